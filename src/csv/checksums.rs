@@ -0,0 +1,69 @@
+// SHA-1 checksums of datastream files, computed at most once per file per run
+// as hashing multi-gigabyte files is slow. Shared by files.csv, the METS
+// documents and the `file_sha1()` script function, so scripts producing their
+// own file-like tables need not hash the files again.
+use super::object::Object;
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+lazy_static! {
+    static ref CHECKSUMS: Mutex<HashMap<PathBuf, String>> = Mutex::new(HashMap::new());
+}
+
+fn hash(path: &Path) -> String {
+    let mut file = File::open(path)
+        .unwrap_or_else(|error| panic!("Failed to open {}: {}", path.display(), error));
+    let mut hasher = Sha1::new();
+    std::io::copy(&mut file, &mut hasher)
+        .unwrap_or_else(|error| panic!("Failed to read {}: {}", path.display(), error));
+    format!("{:x}", hasher.finalize())
+}
+
+pub fn sha1(path: &Path) -> String {
+    if let Some(checksum) = CHECKSUMS.lock().unwrap().get(path) {
+        return checksum.clone();
+    }
+    // Hashed without holding the lock, so other files can be hashed meanwhile.
+    let checksum = hash(path);
+    CHECKSUMS
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), checksum.clone());
+    checksum
+}
+
+// The file of the latest version of the given datastream, if it exists.
+fn file(object: &Object, dsid: &str) -> Option<PathBuf> {
+    object
+        .datastream(dsid)
+        .filter(|version| version.url().is_none())
+        .map(|version| version.path())
+        .filter(|path| path.is_file())
+}
+
+pub fn file_sha1(object: &Object, dsid: &str) -> Option<String> {
+    file(object, dsid).map(|path| sha1(&path))
+}
+
+pub fn file_size(object: &Object, dsid: &str) -> Option<u64> {
+    file(object, dsid).and_then(|path| path.metadata().ok().map(|metadata| metadata.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_checksums() {
+        let path = std::env::temp_dir().join(format!("checksums-{}.txt", std::process::id()));
+        std::fs::write(&path, "abc").unwrap();
+        assert_eq!(sha1(&path), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        // Served from the cache once hashed.
+        std::fs::write(&path, "changed").unwrap();
+        assert_eq!(sha1(&path), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
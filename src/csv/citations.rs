@@ -0,0 +1,645 @@
+// Citations (ir:citationCModel) and theses (ir:thesisCModel) of Islandora
+// Scholar exported from their MODS as CSL-JSON or BibTeX, one file per object
+// in `citations/<pid>.json` or `citations/<pid>.bib`, along with citations.csv
+// listing the fields found, so that the bibliographic fidelity can be
+// validated before feeding Drupal's citation modules.
+// @see https://citeproc-js.readthedocs.io/en/latest/csl-json/markup.html
+use super::issue_dates::edtf;
+use super::object::{Object, ObjectMap};
+use super::output::{Output, Row};
+use super::utils::{finish_partial, partial_path, report_problem};
+use indicatif::ProgressBar;
+use log::{warn, Level};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use rayon::prelude::*;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::io::BufRead;
+use std::path::Path;
+
+static REPORT: &str = "citations.csv";
+
+const MODELS: [&str; 2] = ["ir:citationCModel", "ir:thesisCModel"];
+
+#[derive(Clone, Copy, Debug, EnumString, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum CitationFormat {
+    Csl,    // CSL-JSON, for citeproc based modules.
+    Bibtex, // BibTeX, for reference managers.
+}
+
+impl CitationFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            CitationFormat::Csl => "json",
+            CitationFormat::Bibtex => "bib",
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Person {
+    family: String,
+    given: String,
+}
+
+impl Person {
+    // Names without typed parts are assumed to be 'Family, Given'.
+    fn new(family: Vec<String>, given: Vec<String>, untyped: Vec<String>) -> Self {
+        if family.is_empty() && given.is_empty() {
+            let name = untyped.join(" ");
+            return match name.split_once(',') {
+                Some((family, given)) => Person {
+                    family: family.trim().to_string(),
+                    given: given.trim().to_string(),
+                },
+                None => Person {
+                    family: name.trim().to_string(),
+                    given: String::new(),
+                },
+            };
+        }
+        Person {
+            family: family.join(" "),
+            given: given.join(" "),
+        }
+    }
+
+    fn name(&self) -> String {
+        if self.given.is_empty() {
+            self.family.clone()
+        } else {
+            format!("{}, {}", self.family, self.given)
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Citation {
+    genre: String,
+    title: String,
+    authors: Vec<Person>,
+    editors: Vec<Person>,
+    // Normalized as EDTF if possible.
+    issued: String,
+    container_title: String,
+    volume: String,
+    issue: String,
+    page: String,
+    publisher: String,
+    publisher_place: String,
+    doi: String,
+    isbn: String,
+    issn: String,
+    url: String,
+    r#abstract: String,
+}
+
+// A name being read, its parts by type and its role.
+#[derive(Default)]
+struct Name {
+    family: Vec<String>,
+    given: Vec<String>,
+    untyped: Vec<String>,
+    role: String,
+}
+
+fn attribute(element: &BytesStart, name: &[u8]) -> String {
+    element
+        .attributes()
+        .filter_map(|attribute| attribute.ok())
+        .find(|attribute| attribute.key == name)
+        .map(|attribute| {
+            String::from_utf8_lossy(&attribute.value)
+                .trim()
+                .to_lowercase()
+        })
+        .unwrap_or_default()
+}
+
+fn set_once(field: &mut String, value: &str) {
+    if field.is_empty() {
+        *field = value.trim().to_string();
+    }
+}
+
+fn mods_citation<B: BufRead>(mut reader: Reader<B>) -> Result<Citation, quick_xml::Error> {
+    reader.trim_text(true);
+    let mut buffer = Vec::new();
+    let mut citation = Citation::default();
+    // Local names and the 'type' attribute of the open elements.
+    let mut path: Vec<(String, String)> = Vec::new();
+    let mut name: Option<Name> = None;
+    let mut title = (String::new(), String::new(), String::new());
+    let (mut first_page, mut last_page) = (String::new(), String::new());
+    loop {
+        match reader.read_event(&mut buffer)? {
+            Event::Start(element) => {
+                let local_name = String::from_utf8_lossy(element.local_name()).to_string();
+                let r#type = attribute(&element, b"type");
+                let unit = attribute(&element, b"unit");
+                let names = path.iter().map(|(n, _)| n.as_str()).collect::<Vec<_>>();
+                let parent = names.last().copied().unwrap_or_default();
+                let top_level = path.len() == 1;
+                // Within the host, i.e. the journal or book it was published in.
+                let in_host = path.iter().any(|(n, t)| n == "relatedItem" && t == "host");
+                let parent_type = path.last().map(|(_, t)| t.as_str()).unwrap_or_default();
+                let text =
+                    |reader: &mut Reader<B>| reader.read_text(element.name(), &mut Vec::new());
+                match local_name.as_str() {
+                    "name" if top_level => {
+                        name = Some(Name::default());
+                    }
+                    "namePart" if name.is_some() => {
+                        let part = text(&mut reader)?;
+                        let name = name.as_mut().unwrap();
+                        match r#type.as_str() {
+                            "family" => name.family.push(part),
+                            "given" => name.given.push(part),
+                            "date" | "termsofaddress" => (),
+                            _ => name.untyped.push(part),
+                        }
+                        buffer.clear();
+                        continue;
+                    }
+                    "roleTerm" if name.is_some() => {
+                        let role = text(&mut reader)?;
+                        set_once(&mut name.as_mut().unwrap().role, &role.to_lowercase());
+                        buffer.clear();
+                        continue;
+                    }
+                    "nonSort" | "title" | "subTitle"
+                        if parent == "titleInfo" && parent_type.is_empty() =>
+                    {
+                        let value = text(&mut reader)?;
+                        if names.len() == 2 && citation.title.is_empty() {
+                            match local_name.as_str() {
+                                "nonSort" => set_once(&mut title.0, &value),
+                                "title" => set_once(&mut title.1, &value),
+                                _ => set_once(&mut title.2, &value),
+                            }
+                        } else if in_host && local_name == "title" {
+                            set_once(&mut citation.container_title, &value);
+                        }
+                        buffer.clear();
+                        continue;
+                    }
+                    "genre" if top_level => {
+                        let value = text(&mut reader)?;
+                        set_once(&mut citation.genre, &value.to_lowercase());
+                        buffer.clear();
+                        continue;
+                    }
+                    "abstract" if top_level => {
+                        let value = text(&mut reader)?;
+                        set_once(&mut citation.r#abstract, &value);
+                        buffer.clear();
+                        continue;
+                    }
+                    "identifier" if top_level || in_host => {
+                        let value = text(&mut reader)?;
+                        match r#type.as_str() {
+                            "doi" => set_once(&mut citation.doi, &value),
+                            "isbn" => set_once(&mut citation.isbn, &value),
+                            "issn" => set_once(&mut citation.issn, &value),
+                            _ => (),
+                        }
+                        buffer.clear();
+                        continue;
+                    }
+                    "url" if parent == "location" => {
+                        let value = text(&mut reader)?;
+                        set_once(&mut citation.url, &value);
+                        buffer.clear();
+                        continue;
+                    }
+                    "dateIssued" | "date" if parent == "originInfo" || parent == "part" => {
+                        let value = text(&mut reader)?;
+                        let value = edtf(&value).unwrap_or(value);
+                        // The keyDate is preferred over any others.
+                        if attribute(&element, b"keydate") == "yes" {
+                            citation.issued = value;
+                        } else {
+                            set_once(&mut citation.issued, &value);
+                        }
+                        buffer.clear();
+                        continue;
+                    }
+                    "publisher" if parent == "originInfo" => {
+                        let value = text(&mut reader)?;
+                        set_once(&mut citation.publisher, &value);
+                        buffer.clear();
+                        continue;
+                    }
+                    "placeTerm" if parent == "place" && r#type != "code" => {
+                        let value = text(&mut reader)?;
+                        set_once(&mut citation.publisher_place, &value);
+                        buffer.clear();
+                        continue;
+                    }
+                    "number" if parent == "detail" => {
+                        let value = text(&mut reader)?;
+                        match parent_type {
+                            "volume" => set_once(&mut citation.volume, &value),
+                            "issue" | "number" => set_once(&mut citation.issue, &value),
+                            _ => (),
+                        }
+                        buffer.clear();
+                        continue;
+                    }
+                    "start" | "end" | "list" if parent == "extent" => {
+                        let value = text(&mut reader)?;
+                        match local_name.as_str() {
+                            "start" => set_once(&mut first_page, &value),
+                            "end" => set_once(&mut last_page, &value),
+                            _ => set_once(&mut citation.page, &value),
+                        }
+                        buffer.clear();
+                        continue;
+                    }
+                    _ => (),
+                }
+                let r#type = if local_name == "extent" { unit } else { r#type };
+                path.push((local_name, r#type));
+            }
+            Event::End(_) => {
+                if let Some((local_name, _)) = path.pop() {
+                    if local_name == "name" && path.len() == 1 {
+                        if let Some(name) = name.take() {
+                            let person = Person::new(name.family, name.given, name.untyped);
+                            match name.role.as_str() {
+                                "" | "author" | "aut" | "creator" | "cre" => {
+                                    citation.authors.push(person)
+                                }
+                                "editor" | "edt" => citation.editors.push(person),
+                                "degree grantor" | "dgg" => {
+                                    set_once(&mut citation.publisher, &person.family)
+                                }
+                                _ => (),
+                            }
+                        }
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+        buffer.clear();
+    }
+    citation.title = match (title.0.as_str(), title.1.as_str(), title.2.as_str()) {
+        (non_sort, title, "") => format!("{} {}", non_sort, title),
+        (non_sort, title, sub_title) => format!("{} {}: {}", non_sort, title, sub_title),
+    }
+    .trim()
+    .to_string();
+    if citation.page.is_empty() && !first_page.is_empty() {
+        citation.page = if last_page.is_empty() {
+            first_page
+        } else {
+            format!("{}-{}", first_page, last_page)
+        };
+    }
+    Ok(citation)
+}
+
+impl Citation {
+    // The CSL type, theses are always 'thesis' regardless of their genre.
+    fn csl_type(&self, model: &str) -> &'static str {
+        let genre = self.genre.as_str();
+        if model == "ir:thesisCModel" || genre.contains("thesis") || genre.contains("dissertation")
+        {
+            "thesis"
+        } else if genre.contains("chapter") || genre.contains("section") {
+            "chapter"
+        } else if genre.contains("conference") || genre.contains("proceeding") {
+            "paper-conference"
+        } else if genre.contains("report") {
+            "report"
+        } else if genre.contains("book") {
+            "book"
+        } else if genre.contains("article") || !self.container_title.is_empty() {
+            "article-journal"
+        } else {
+            "document"
+        }
+    }
+
+    fn bibtex_type(&self, model: &str) -> &'static str {
+        match self.csl_type(model) {
+            "thesis" if self.genre.contains("master") => "mastersthesis",
+            "thesis" => "phdthesis",
+            "chapter" => "incollection",
+            "paper-conference" => "inproceedings",
+            "report" => "techreport",
+            "book" => "book",
+            "article-journal" => "article",
+            _ => "misc",
+        }
+    }
+
+    // Required fields that could not be found.
+    fn missing(&self) -> Vec<&'static str> {
+        let mut missing = Vec::new();
+        if self.title.is_empty() {
+            missing.push("title");
+        }
+        if self.authors.is_empty() {
+            missing.push("author");
+        }
+        if self.issued.is_empty() {
+            missing.push("issued");
+        }
+        missing
+    }
+
+    fn csl(&self, pid: &str, model: &str) -> Value {
+        let people = |people: &[Person]| {
+            people
+                .iter()
+                .map(|person| json!({"family": person.family, "given": person.given}))
+                .collect::<Vec<_>>()
+        };
+        let mut csl = Map::new();
+        csl.insert("id".to_string(), pid.into());
+        csl.insert("type".to_string(), self.csl_type(model).into());
+        let mut insert = |key: &str, value: Value| {
+            let empty = match &value {
+                Value::String(value) => value.is_empty(),
+                Value::Array(value) => value.is_empty(),
+                _ => false,
+            };
+            if !empty {
+                csl.insert(key.to_string(), value);
+            }
+        };
+        insert("title", self.title.as_str().into());
+        insert("author", people(&self.authors).into());
+        insert("editor", people(&self.editors).into());
+        if !self.issued.is_empty() {
+            let parts = self
+                .issued
+                .trim_end_matches('?')
+                .split('-')
+                .map(|part| part.parse::<i64>())
+                .collect::<Result<Vec<_>, _>>();
+            insert(
+                "issued",
+                match parts {
+                    Ok(parts) => json!({ "date-parts": [parts] }),
+                    Err(_) => json!({ "raw": self.issued }),
+                },
+            );
+        }
+        insert("container-title", self.container_title.as_str().into());
+        insert("volume", self.volume.as_str().into());
+        insert("issue", self.issue.as_str().into());
+        insert("page", self.page.as_str().into());
+        insert("publisher", self.publisher.as_str().into());
+        insert("publisher-place", self.publisher_place.as_str().into());
+        insert("DOI", self.doi.as_str().into());
+        insert("ISBN", self.isbn.as_str().into());
+        insert("ISSN", self.issn.as_str().into());
+        insert("URL", self.url.as_str().into());
+        insert("abstract", self.r#abstract.as_str().into());
+        Value::Array(vec![Value::Object(csl)])
+    }
+
+    fn bibtex(&self, pid: &str, model: &str) -> String {
+        let escape = |value: &str| {
+            value.chars().fold(String::new(), |mut escaped, c| {
+                if "&%$#_{}".contains(c) {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+                escaped
+            })
+        };
+        let people = |people: &[Person]| {
+            people
+                .iter()
+                .map(Person::name)
+                .collect::<Vec<_>>()
+                .join(" and ")
+        };
+        let year = self.issued.split('-').next().unwrap_or_default();
+        let container = match self.csl_type(model) {
+            "chapter" | "paper-conference" => "booktitle",
+            _ => "journal",
+        };
+        let publisher = match self.csl_type(model) {
+            "thesis" => "school",
+            "report" => "institution",
+            _ => "publisher",
+        };
+        let fields = [
+            ("title", self.title.clone()),
+            ("author", people(&self.authors)),
+            ("editor", people(&self.editors)),
+            ("year", year.trim_end_matches('?').to_string()),
+            (container, self.container_title.clone()),
+            ("volume", self.volume.clone()),
+            ("number", self.issue.clone()),
+            ("pages", self.page.replace('-', "--")),
+            (publisher, self.publisher.clone()),
+            ("address", self.publisher_place.clone()),
+            ("doi", self.doi.clone()),
+            ("isbn", self.isbn.clone()),
+            ("issn", self.issn.clone()),
+            ("url", self.url.clone()),
+            ("abstract", self.r#abstract.clone()),
+        ];
+        let fields = fields
+            .iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(key, value)| format!("  {} = {{{}}}", key, escape(value)))
+            .collect::<Vec<_>>();
+        // Keys can not contain colons.
+        format!(
+            "@{}{{{},\n{}\n}}\n",
+            self.bibtex_type(model),
+            pid.replace(':', "_"),
+            fields.join(",\n")
+        )
+    }
+}
+
+#[derive(Serialize)]
+pub struct CitationRow {
+    pid: String,
+    model: String,
+    r#type: &'static str,
+    title: String,
+    authors: String,
+    issued: String,
+    container_title: String,
+    doi: String,
+    file: String,
+    // Required fields that could not be found in the MODS.
+    missing: String,
+}
+
+impl Row for CitationRow {
+    fn columns() -> &'static [&'static str] {
+        &[
+            "pid",
+            "model",
+            "type",
+            "title",
+            "authors",
+            "issued",
+            "container_title",
+            "doi",
+            "file",
+            "missing",
+        ]
+    }
+}
+
+fn citation(object: &Object, descriptive_metadata: &[String]) -> Option<Citation> {
+    let version = object
+        .descriptive_metadata(descriptive_metadata)
+        .filter(|v| v.path().exists())?;
+    match Reader::from_file(version.path()).and_then(mods_citation) {
+        Ok(citation) => Some(citation),
+        Err(error) => {
+            report_problem(
+                Level::Warn,
+                "unparsable_mods",
+                &format!("Failed to parse MODS of {}: {}", object.pid, error),
+            );
+            None
+        }
+    }
+}
+
+fn write(path: &Path, content: &[u8]) {
+    std::fs::write(partial_path(path), content)
+        .and_then(|_| finish_partial(path))
+        .unwrap_or_else(|error| panic!("Failed to write {}: {}", path.display(), error));
+}
+
+pub fn csv(
+    objects: &ObjectMap,
+    output: &Output,
+    format: CitationFormat,
+    descriptive_metadata: &[String],
+    progress_bar: ProgressBar,
+) {
+    let directory = output.directory().join("citations");
+    std::fs::create_dir_all(&directory)
+        .unwrap_or_else(|error| panic!("Failed to create {}: {}", directory.display(), error));
+    let objects = objects
+        .objects()
+        .filter(|object| MODELS.contains(&object.model.as_str()))
+        .collect::<Vec<_>>();
+    logger::set_length(&progress_bar, objects.len() as u64);
+    let rows = objects
+        .into_par_iter()
+        .filter_map(|object| {
+            progress_bar.inc(1);
+            let citation = citation(object, descriptive_metadata)?;
+            let (pid, model) = (object.pid.0.as_str(), object.model.as_str());
+            let file = format!("{}.{}", pid, format.extension());
+            let content = match format {
+                CitationFormat::Csl => serde_json::to_string_pretty(&citation.csl(pid, model))
+                    .unwrap()
+                    .into_bytes(),
+                CitationFormat::Bibtex => citation.bibtex(pid, model).into_bytes(),
+            };
+            write(&directory.join(&file), &content);
+            Some(CitationRow {
+                pid: pid.to_string(),
+                model: model.to_string(),
+                r#type: citation.csl_type(model),
+                title: citation.title.clone(),
+                authors: citation
+                    .authors
+                    .iter()
+                    .map(Person::name)
+                    .collect::<Vec<_>>()
+                    .join("|"),
+                issued: citation.issued.clone(),
+                container_title: citation.container_title.clone(),
+                doi: citation.doi.clone(),
+                file: format!("citations/{}", file),
+                missing: citation.missing().join("|"),
+            })
+        })
+        .collect::<Vec<_>>();
+    let incomplete = rows.iter().filter(|row| !row.missing.is_empty()).count();
+    if incomplete > 0 {
+        warn!(
+            "{} of {} citations are missing a title, author or date, see {}",
+            incomplete,
+            rows.len(),
+            REPORT
+        );
+    }
+    output
+        .write_rows(&rows, REPORT)
+        .expect("Failed to create citations.csv");
+    progress_bar.finish_with_message("Created citations");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn citation_from_mods() {
+        let content = r#"
+<mods xmlns="http://www.loc.gov/mods/v3">
+  <titleInfo><nonSort>The</nonSort><title>Register</title><subTitle>A history</subTitle></titleInfo>
+  <titleInfo type="alternative"><title>Other</title></titleInfo>
+  <name type="personal">
+    <namePart type="given">Jane</namePart><namePart type="family">Doe</namePart>
+    <role><roleTerm type="text">author</roleTerm></role>
+  </name>
+  <name type="personal"><namePart>Roe, Richard</namePart></name>
+  <name type="personal">
+    <namePart>Smith, Anne</namePart><role><roleTerm>editor</roleTerm></role>
+  </name>
+  <genre>journal article</genre>
+  <originInfo><dateIssued>May 1954</dateIssued></originInfo>
+  <relatedItem type="host">
+    <titleInfo><title>Journal of Denver</title></titleInfo>
+    <part>
+      <detail type="volume"><number>12</number></detail>
+      <detail type="issue"><number>3</number></detail>
+      <extent unit="page"><start>5</start><end>9</end></extent>
+    </part>
+  </relatedItem>
+  <identifier type="doi">10.1000/182</identifier>
+</mods>"#;
+        let citation = mods_citation(Reader::from_str(content)).unwrap();
+        assert_eq!(citation.title, "The Register: A history");
+        assert_eq!(
+            citation
+                .authors
+                .iter()
+                .map(Person::name)
+                .collect::<Vec<_>>(),
+            vec!["Doe, Jane", "Roe, Richard"]
+        );
+        assert_eq!(citation.editors[0].name(), "Smith, Anne");
+        assert_eq!(citation.issued, "1954-05");
+        assert_eq!(citation.container_title, "Journal of Denver");
+        assert_eq!(
+            (
+                citation.volume.as_str(),
+                citation.issue.as_str(),
+                citation.page.as_str()
+            ),
+            ("12", "3", "5-9")
+        );
+        assert!(citation.missing().is_empty());
+        let model = "ir:citationCModel";
+        let csl = citation.csl("archden:1", model);
+        assert_eq!(csl[0]["type"], "article-journal");
+        assert_eq!(csl[0]["issued"], json!({"date-parts": [[1954, 5]]}));
+        let bibtex = citation.bibtex("archden:1", model);
+        assert!(bibtex.starts_with("@article{archden_1,\n  title = {The Register: A history},"));
+        assert!(bibtex.contains("  pages = {5--9},"));
+    }
+}
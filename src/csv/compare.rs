@@ -0,0 +1,276 @@
+// Compares the CSV files of this run to those of a previous run, so that
+// accidental mapping regressions are spotted before re-importing into Drupal.
+// Rows are matched by the value of their first column (usually the PID), and a
+// summary of the added, removed and changed rows of each file is written to
+// comparison.csv.
+use super::output::{Output, OutputFormat, Row};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+static COMPARISON: &str = "comparison.csv";
+
+#[derive(Debug, Default)]
+struct Table {
+    header: Vec<String>,
+    // Rows by key, repeated keys are suffixed by their occurrence e.g. 'key#2'.
+    rows: HashMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ComparisonRow {
+    file: String,
+    previous_rows: usize,
+    rows: usize,
+    // Rows whose key is only found in this run.
+    added: usize,
+    // Rows whose key is only found in the previous run.
+    removed: usize,
+    // Rows found in both runs with at least one differing cell.
+    changed: usize,
+    added_columns: String,
+    removed_columns: String,
+    // Columns with differing cells and the number of rows that differ, e.g.
+    // 'title (12)|date (3)'.
+    changed_columns: String,
+}
+
+impl Row for ComparisonRow {
+    fn columns() -> &'static [&'static str] {
+        &[
+            "file",
+            "previous_rows",
+            "rows",
+            "added",
+            "removed",
+            "changed",
+            "added_columns",
+            "removed_columns",
+            "changed_columns",
+        ]
+    }
+}
+
+impl ComparisonRow {
+    fn differs(&self) -> bool {
+        self.added + self.removed + self.changed > 0
+            || !self.added_columns.is_empty()
+            || !self.removed_columns.is_empty()
+    }
+}
+
+impl Table {
+    fn new(header: Vec<String>, records: Vec<Vec<String>>) -> Self {
+        let mut occurrences = HashMap::new();
+        let rows = records
+            .into_iter()
+            .map(|record| {
+                let key = record.first().cloned().unwrap_or_default();
+                let occurrence = occurrences.entry(key.clone()).or_insert(0);
+                *occurrence += 1;
+                match occurrence {
+                    1 => (key, record),
+                    n => (format!("{}#{}", key, n), record),
+                }
+            })
+            .collect();
+        Table { header, rows }
+    }
+
+    // None if the file does not exist.
+    fn read(path: &Path) -> Result<Option<Self>, csv_other::Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut reader = csv_other::Reader::from_path(path)?;
+        let header = reader.headers()?.iter().map(String::from).collect();
+        let records = reader
+            .records()
+            .map(|record| record.map(|record| record.iter().map(String::from).collect()))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Some(Table::new(header, records)))
+    }
+}
+
+fn compare(file: &str, previous: &Table, current: &Table) -> ComparisonRow {
+    let columns = |table: &Table, other: &Table| {
+        table
+            .header
+            .iter()
+            .filter(|column| !other.header.contains(column))
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("|")
+    };
+    // Columns shared by both and their index in each.
+    let shared = current
+        .header
+        .iter()
+        .enumerate()
+        .filter_map(|(index, column)| {
+            previous
+                .header
+                .iter()
+                .position(|c| c == column)
+                .map(|previous_index| (column, previous_index, index))
+        })
+        .collect::<Vec<_>>();
+    let mut changed = 0;
+    let mut changed_columns = BTreeMap::new();
+    for (key, row) in &current.rows {
+        if let Some(previous_row) = previous.rows.get(key) {
+            let differing = shared
+                .iter()
+                .filter(|(_, previous_index, index)| {
+                    previous_row.get(*previous_index) != row.get(*index)
+                })
+                .map(|(column, _, _)| *column)
+                .collect::<Vec<_>>();
+            if !differing.is_empty() {
+                changed += 1;
+            }
+            for column in differing {
+                *changed_columns.entry(column.clone()).or_insert(0) += 1;
+            }
+        }
+    }
+    ComparisonRow {
+        file: file.to_string(),
+        previous_rows: previous.rows.len(),
+        rows: current.rows.len(),
+        added: current
+            .rows
+            .keys()
+            .filter(|key| !previous.rows.contains_key(*key))
+            .count(),
+        removed: previous
+            .rows
+            .keys()
+            .filter(|key| !current.rows.contains_key(*key))
+            .count(),
+        changed,
+        added_columns: columns(current, previous),
+        removed_columns: columns(previous, current),
+        changed_columns: changed_columns
+            .into_iter()
+            .map(|(column, count)| format!("{} ({})", column, count))
+            .collect::<Vec<_>>()
+            .join("|"),
+    }
+}
+
+// CSV files written by this run or found in the previous run.
+fn files(previous: &Path, output: &Output) -> BTreeSet<String> {
+    let previous_files = std::fs::read_dir(previous)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    output
+        .outputs()
+        .into_iter()
+        .map(|(file, _)| file)
+        .chain(previous_files)
+        .filter(|file| file.ends_with(".csv") && file != COMPARISON)
+        .collect()
+}
+
+// Compares the CSV files of this run to those in the given directory and
+// writes comparison.csv, should be called once all other tables have been
+// written.
+pub fn write_report(previous: &Path, output: &Output) {
+    if output.format() != OutputFormat::Csv {
+        warn!("Only CSV output can be compared to a previous run, skipping comparison");
+        return;
+    }
+    let read = |path: &Path| {
+        Table::read(path)
+            .unwrap_or_else(|error| panic!("Failed to read {}: {}", path.display(), error))
+            .unwrap_or_default()
+    };
+    let rows = files(previous, output)
+        .into_iter()
+        .map(|file| {
+            compare(
+                &file,
+                &read(&previous.join(&file)),
+                &read(&output.directory().join(&file)),
+            )
+        })
+        .collect::<Vec<_>>();
+    let differing = rows.iter().filter(|row| row.differs()).collect::<Vec<_>>();
+    if differing.is_empty() {
+        info!("No differences found compared to {}", previous.display());
+    } else {
+        warn!(
+            "{} of {} files differ from {}, see {}",
+            differing.len(),
+            rows.len(),
+            previous.display(),
+            COMPARISON
+        );
+        for row in differing {
+            warn!(
+                "  {}: {} added, {} removed and {} changed rows",
+                row.file, row.added, row.removed, row.changed
+            );
+        }
+    }
+    output
+        .write_rows(&rows, COMPARISON)
+        .expect("Failed to create comparison.csv");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(header: &[&str], rows: &[&[&str]]) -> Table {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect();
+        Table::new(
+            strings(header),
+            rows.iter().map(|row| strings(row)).collect(),
+        )
+    }
+
+    #[test]
+    fn compare_tables() {
+        let previous = table(
+            &["pid", "title", "date"],
+            &[
+                &["archden:1", "One", "1954"],
+                &["archden:2", "Two", "1955"],
+                &["archden:3", "Three", "1956"],
+            ],
+        );
+        let current = table(
+            &["pid", "title", "weight"],
+            &[
+                &["archden:1", "One", "1"],
+                &["archden:2", "Two.", "2"],
+                &["archden:4", "Four", "3"],
+                &["archden:4", "Four", "4"],
+            ],
+        );
+        let row = compare("nodes.csv", &previous, &current);
+        assert_eq!(
+            row,
+            ComparisonRow {
+                file: "nodes.csv".to_string(),
+                previous_rows: 3,
+                rows: 4,
+                added: 2,
+                removed: 1,
+                changed: 1,
+                added_columns: "weight".to_string(),
+                removed_columns: "date".to_string(),
+                changed_columns: "title (1)".to_string(),
+            }
+        );
+        assert!(!compare("nodes.csv", &current, &current).differs());
+    }
+}
@@ -0,0 +1,96 @@
+// Maps PIDs to the IDs of entities that already exist in Drupal, for second
+// wave migrations into a site that already has some content, so that scripts
+// can reference the existing entities via `drupal_id(pid)` rather than
+// creating duplicates. Read from a CSV file with a `pid` and `id` column,
+// other columns are ignored.
+use rhai::Dynamic;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Clone, Debug, Default)]
+pub struct Crosswalk(HashMap<String, String>);
+
+impl Crosswalk {
+    fn from_reader<R: std::io::Read>(mut reader: csv_other::Reader<R>) -> Result<Self, String> {
+        let headers = reader.headers().map_err(|error| error.to_string())?;
+        let column = |name| {
+            headers
+                .iter()
+                .position(|header| header.trim() == name)
+                .ok_or_else(|| format!("Missing the '{}' column", name))
+        };
+        let (pid, id) = (column("pid")?, column("id")?);
+        let mut map = HashMap::new();
+        for record in reader.records() {
+            let record = record.map_err(|error| error.to_string())?;
+            let (pid, id) = (
+                record.get(pid).unwrap_or_default().trim(),
+                record.get(id).unwrap_or_default().trim(),
+            );
+            if pid.is_empty() || id.is_empty() {
+                continue;
+            }
+            // PIDs are given as they are after `--pid-map`.
+            match map.insert(pid.to_string(), id.to_string()) {
+                Some(previous) if previous != id => {
+                    return Err(format!(
+                        "The PID '{}' is mapped to both '{}' and '{}'",
+                        pid, previous, id
+                    ))
+                }
+                _ => (),
+            }
+        }
+        Ok(Crosswalk(map))
+    }
+
+    pub fn from_path(path: &Path) -> Self {
+        csv_other::Reader::from_path(path)
+            .map_err(|error| error.to_string())
+            .and_then(Self::from_reader)
+            .unwrap_or_else(|error| {
+                panic!("Failed to read crosswalk {}: {}", path.display(), error)
+            })
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    // Numeric IDs (e.g. node or term IDs) are returned as integers, others
+    // (e.g. UUIDs) as strings, or unit if the PID is not mapped.
+    pub fn drupal_id(&self, pid: &str) -> Dynamic {
+        match self.0.get(pid) {
+            Some(id) => id
+                .parse::<i64>()
+                .map_or_else(|_| id.clone().into(), Dynamic::from),
+            None => ().into(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crosswalk(content: &str) -> Result<Crosswalk, String> {
+        Crosswalk::from_reader(csv_other::Reader::from_reader(content.as_bytes()))
+    }
+
+    #[test]
+    fn drupal_id() {
+        let crosswalk = crosswalk(
+            "title,pid,id\nRegister,archden:1,12\nSouthern,archden:2,5c2a-11\n,archden:3,\n",
+        )
+        .unwrap();
+        assert_eq!(crosswalk.len(), 2);
+        assert_eq!(crosswalk.drupal_id("archden:1").as_int(), Ok(12));
+        assert_eq!(
+            crosswalk.drupal_id("archden:2").take_string(),
+            Ok("5c2a-11".into())
+        );
+        assert!(crosswalk.drupal_id("archden:3").is::<()>());
+        assert!(self::crosswalk("pid,nid\narchden:1,12\n").is_err());
+        assert!(self::crosswalk("pid,id\narchden:1,12\narchden:1,13\n").is_err());
+    }
+}
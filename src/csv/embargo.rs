@@ -0,0 +1,170 @@
+// Embargoes as stored by Islandora Scholar, either on the object in RELS-EXT or
+// on individual datastreams in RELS-INT via the `embargo-until` predicate.
+// Some sites instead store them in a custom EMBARGO datastream.
+// @see https://github.com/Islandora/islandora_scholar/tree/7.x/modules/islandora_scholar_embargo
+use super::object::{DatastreamVersion, Object};
+use super::utils::report_problem;
+use chrono::{DateTime, NaiveDate};
+use log::{warn, Level};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use regex::Regex;
+
+lazy_static! {
+    static ref DATE: Regex = Regex::new(r"^\d{4}-\d{2}-\d{2}").unwrap();
+}
+
+const EMBARGO_UNTIL: &[u8] = b"embargo-until";
+const INDEFINITE: &str = "indefinite";
+
+#[derive(Debug, PartialEq)]
+pub struct Embargo {
+    // Empty if the whole object is embargoed.
+    pub dsid: String,
+    // Date formatted as YYYY-MM-DD, or `None` if indefinite.
+    pub expiry_date: Option<String>,
+    // Datastream the embargo was found in.
+    pub source: &'static str,
+}
+
+// Normalizes the value of an embargo, returns `Err` if it cannot be understood.
+fn expiry_date(value: &str) -> Result<Option<String>, ()> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case(INDEFINITE) {
+        return Ok(None);
+    }
+    if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+        return Ok(Some(date.format("%Y-%m-%d").to_string()));
+    }
+    DATE.find(value)
+        .and_then(|date| NaiveDate::parse_from_str(date.as_str(), "%Y-%m-%d").ok())
+        .map(|date| Some(date.format("%Y-%m-%d").to_string()))
+        .ok_or(())
+}
+
+// Finds all `embargo-until` statements, returning the DSID of their subject
+// (empty if it is the object) and their value.
+fn relationships(version: &DatastreamVersion) -> Result<Vec<(String, String)>, quick_xml::Error> {
+    let mut reader = Reader::from_file(version.path())?;
+    reader.trim_text(true);
+    let mut buffer = Vec::new();
+    let mut about = String::new();
+    let mut results = Vec::new();
+    loop {
+        match reader.read_event(&mut buffer)? {
+            Event::Start(element) | Event::Empty(element)
+                if element.local_name() == b"Description" =>
+            {
+                about = element
+                    .attributes()
+                    .filter_map(|attribute| attribute.ok())
+                    .find(|attribute| attribute.key.ends_with(b"about"))
+                    .map(|attribute| String::from_utf8_lossy(&attribute.value).to_string())
+                    .unwrap_or_default();
+            }
+            Event::Start(element) if element.local_name() == EMBARGO_UNTIL => {
+                let name = element.name().to_vec();
+                let value = reader.read_text(&name, &mut Vec::new())?;
+                // Subjects are of the form info:fedora/PID or info:fedora/PID/DSID.
+                let dsid = about
+                    .trim_start_matches("info:fedora/")
+                    .split_once('/')
+                    .map_or("", |(_, dsid)| dsid)
+                    .to_string();
+                results.push((dsid, value));
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+        buffer.clear();
+    }
+    Ok(results)
+}
+
+// The first date (or 'indefinite') in the given datastream.
+fn embargo_datastream(version: &DatastreamVersion) -> Result<Option<String>, quick_xml::Error> {
+    let mut reader = Reader::from_file(version.path())?;
+    reader.trim_text(true);
+    let mut buffer = Vec::new();
+    loop {
+        match reader.read_event(&mut buffer)? {
+            Event::Text(text) => {
+                let text = text.unescape_and_decode(&reader)?;
+                if expiry_date(&text).is_ok() {
+                    return Ok(Some(text));
+                }
+            }
+            Event::Eof => return Ok(None),
+            _ => (),
+        }
+        buffer.clear();
+    }
+}
+
+pub fn embargoes(object: &Object) -> Vec<Embargo> {
+    let mut values = Vec::new();
+    for source in &["RELS-EXT", "RELS-INT"] {
+        if let Some(version) = object.datastream(source).filter(|v| v.path().exists()) {
+            match relationships(version) {
+                Ok(relationships) => values.extend(
+                    relationships
+                        .into_iter()
+                        .map(|(dsid, value)| (dsid, value, *source)),
+                ),
+                Err(error) => report_problem(
+                    Level::Warn,
+                    &format!("unparsable_{}", source.to_lowercase().replace('-', "_")),
+                    &format!("Failed to parse {} of {}: {}", source, object.pid, error),
+                ),
+            }
+        }
+    }
+    if let Some(version) = object.datastream("EMBARGO").filter(|v| v.path().exists()) {
+        match embargo_datastream(version) {
+            Ok(Some(value)) => values.push(("".to_string(), value, "EMBARGO")),
+            Ok(None) => warn!("Failed to find a date in EMBARGO of {}", object.pid),
+            Err(error) => report_problem(
+                Level::Warn,
+                "unparsable_embargo",
+                &format!("Failed to parse EMBARGO of {}: {}", object.pid, error),
+            ),
+        }
+    }
+    values
+        .into_iter()
+        .filter_map(|(dsid, value, source)| match expiry_date(&value) {
+            Ok(expiry_date) => Some(Embargo {
+                dsid,
+                expiry_date,
+                source,
+            }),
+            Err(_) => {
+                warn!(
+                    "Ignoring embargo of {} in {} with unknown date: {}",
+                    object.pid, source, value
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expiry_date() {
+        assert_eq!(expiry_date("indefinite"), Ok(None));
+        assert_eq!(
+            expiry_date("2016-01-01T00:00:00Z"),
+            Ok(Some("2016-01-01".to_string()))
+        );
+        assert!(expiry_date("2016-02-30").is_err());
+        assert_eq!(
+            expiry_date("2020-12-31"),
+            Ok(Some("2020-12-31".to_string()))
+        );
+        assert!(expiry_date("never").is_err());
+    }
+}
@@ -0,0 +1,207 @@
+// Legacy datastreams are not always UTF-8, e.g. MODS exported by a cataloguing
+// system in Latin-1. Valid UTF-8 is always taken as is, otherwise the strings
+// are decoded using the charset declared by the XML declaration, or given
+// `--detect-charset` as Windows-1252 (a superset of the printable Latin-1
+// characters), falling back to replacing the invalid bytes with U+FFFD. Either
+// way the datastream is listed in encoding_problems.csv, so it can be checked.
+use super::object::DatastreamVersion;
+use super::output::{Output, Row};
+use log::warn;
+use quick_xml::events::BytesDecl;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+static REPORT: &str = "encoding_problems.csv";
+
+// Characters given as examples in the report.
+const EXAMPLE_LENGTH: usize = 80;
+
+static DETECT_CHARSET: AtomicBool = AtomicBool::new(false);
+
+// The characters Windows-1252 assigns to 0x80 - 0x9F, the undefined bytes are
+// mapped to the C1 control characters as browsers do.
+const WINDOWS_1252: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Charset {
+    Latin1,
+    Windows1252,
+}
+
+impl Charset {
+    // The charsets which can be decoded, as named by an XML declaration.
+    fn from_label(label: &str) -> Option<Self> {
+        match label.trim().to_lowercase().as_str() {
+            "iso-8859-1" | "iso8859-1" | "iso_8859-1" | "latin1" | "latin-1" | "l1" => {
+                Some(Charset::Latin1)
+            }
+            "windows-1252" | "cp1252" | "x-cp1252" => Some(Charset::Windows1252),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Charset::Latin1 => "ISO-8859-1",
+            Charset::Windows1252 => "Windows-1252",
+        }
+    }
+
+    // Every byte is a character in either charset, so this can not fail.
+    fn decode(&self, bytes: &[u8]) -> String {
+        bytes
+            .iter()
+            .map(|&byte| match (self, byte) {
+                (Charset::Windows1252, 0x80..=0x9F) => WINDOWS_1252[(byte - 0x80) as usize],
+                _ => byte as char,
+            })
+            .collect()
+    }
+}
+
+pub fn set_detect_charset(detect: bool) {
+    DETECT_CHARSET.store(detect, Ordering::Relaxed);
+}
+
+// Decodes the names, attributes and text of a single document, noting any that
+// were not valid UTF-8.
+#[derive(Default)]
+pub struct Decoder {
+    declared: Option<Charset>,
+    // How the invalid strings were decoded, the number of them and the first.
+    problem: Option<(String, usize, String)>,
+}
+
+impl Decoder {
+    pub fn declare(&mut self, declaration: &BytesDecl) {
+        self.declared = declaration
+            .encoding()
+            .and_then(|encoding| encoding.ok())
+            .and_then(|encoding| Charset::from_label(&String::from_utf8_lossy(&encoding)));
+    }
+
+    pub fn decode(&mut self, bytes: &[u8]) -> String {
+        if let Ok(string) = std::str::from_utf8(bytes) {
+            return string.to_string();
+        }
+        let (string, decoded_as) = match self.declared {
+            Some(charset) => (
+                charset.decode(bytes),
+                format!("{} (declared)", charset.name()),
+            ),
+            None if DETECT_CHARSET.load(Ordering::Relaxed) => {
+                let charset = Charset::Windows1252;
+                (
+                    charset.decode(bytes),
+                    format!("{} (detected)", charset.name()),
+                )
+            }
+            None => (
+                String::from_utf8_lossy(bytes).to_string(),
+                "UTF-8 (invalid bytes replaced)".to_string(),
+            ),
+        };
+        match self.problem.as_mut() {
+            Some((_, count, _)) => *count += 1,
+            None => {
+                let example = string.chars().take(EXAMPLE_LENGTH).collect();
+                self.problem = Some((decoded_as, 1, example));
+            }
+        }
+        string
+    }
+
+    // Should be called once the document has been parsed.
+    pub fn record(self, datastream: &DatastreamVersion) {
+        if let Some((decoded_as, values, example)) = self.problem {
+            // Datastreams may be parsed more than once e.g. by several scripts.
+            ENCODING_PROBLEMS.lock().unwrap().insert(
+                (
+                    datastream.pid.clone(),
+                    datastream.dsid.clone(),
+                    datastream.id.clone(),
+                ),
+                EncodingProblemRow {
+                    pid: datastream.pid.clone(),
+                    dsid: datastream.dsid.clone(),
+                    version: datastream.id.clone(),
+                    decoded_as,
+                    values,
+                    example,
+                },
+            );
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EncodingProblemRow {
+    pid: String,
+    dsid: String,
+    version: String,
+    decoded_as: String,
+    // The number of names, attributes and text that were not valid UTF-8.
+    values: usize,
+    example: String,
+}
+
+impl Row for EncodingProblemRow {
+    fn columns() -> &'static [&'static str] {
+        &["pid", "dsid", "version", "decoded_as", "values", "example"]
+    }
+}
+
+lazy_static! {
+    // By pid, dsid and version.
+    static ref ENCODING_PROBLEMS: Mutex<BTreeMap<(String, String, String), EncodingProblemRow>> =
+        Mutex::new(BTreeMap::new());
+}
+
+// Writes encoding_problems.csv, should be called once the datastreams have been
+// parsed.
+pub fn write_report(output: &Output) {
+    let rows = std::mem::take(&mut *ENCODING_PROBLEMS.lock().unwrap())
+        .into_values()
+        .collect::<Vec<_>>();
+    output
+        .write_report(&rows, REPORT)
+        .expect("Failed to create encoding_problems.csv");
+    if !rows.is_empty() {
+        warn!(
+            "{} datastreams were not valid UTF-8, see {}",
+            rows.len(),
+            REPORT
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        // 'café' and the curly quotes around it in Windows-1252.
+        let bytes = b"\x93caf\xe9\x94";
+        let mut decoder = Decoder::default();
+        assert_eq!(decoder.decode("café".as_bytes()), "café");
+        assert!(decoder.problem.is_none());
+        assert_eq!(decoder.decode(bytes), "\u{FFFD}caf\u{FFFD}");
+        set_detect_charset(true);
+        assert_eq!(decoder.decode(bytes), "“café”");
+        set_detect_charset(false);
+        decoder.declared = Charset::from_label("ISO-8859-1");
+        assert_eq!(decoder.decode(bytes), "\u{93}café\u{94}");
+        let (decoded_as, values, example) = decoder.problem.unwrap();
+        assert_eq!(decoded_as, "UTF-8 (invalid bytes replaced)");
+        assert_eq!(values, 3);
+        assert_eq!(example, "\u{FFFD}caf\u{FFFD}");
+    }
+}
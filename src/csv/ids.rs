@@ -0,0 +1,157 @@
+// Some migrate configurations require integer source IDs rather than PIDs.
+// Given `--id-map` each object is assigned a sequential integer ID, recorded in
+// the given file so that later runs (and other exports of the same repository)
+// reuse the same IDs, and every table with a `pid` column gets an `id` column
+// before it holding the ID of that object.
+use super::object::ObjectMap;
+use super::utils::{finish_partial, partial_path};
+use log::info;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+type IdMap = HashMap<String, u64>;
+
+// The map given by `--id-map` if any, and the IDs of objects once assigned.
+pub struct Ids {
+    path: Option<PathBuf>,
+    ids: RwLock<Option<IdMap>>,
+}
+
+fn read_id_map(path: &Path) -> IdMap {
+    if !path.exists() {
+        return IdMap::new();
+    }
+    let mut reader = csv_other::Reader::from_path(path)
+        .unwrap_or_else(|error| panic!("Failed to open {}: {}", path.display(), error));
+    reader
+        .records()
+        .map(|record| {
+            let record = record
+                .unwrap_or_else(|error| panic!("Failed to read {}: {}", path.display(), error));
+            let id = record[1].parse::<u64>().unwrap_or_else(|error| {
+                panic!(
+                    "Invalid ID '{}' for {} in {}: {}",
+                    &record[1],
+                    &record[0],
+                    path.display(),
+                    error
+                )
+            });
+            (record[0].to_string(), id)
+        })
+        .collect()
+}
+
+fn write_id_map(path: &Path, ids: &IdMap) -> Result<(), std::io::Error> {
+    let mut ids = ids.iter().collect::<Vec<_>>();
+    ids.sort_by_key(|(_, &id)| id);
+    let mut writer = csv_other::Writer::from_path(partial_path(path))?;
+    writer.write_record(["pid", "id"])?;
+    for (pid, id) in ids {
+        writer.write_record([pid.as_str(), &id.to_string()])?;
+    }
+    writer.flush()?;
+    finish_partial(path)
+}
+
+// Objects not yet in the map are given the next IDs in PID order, so a fresh
+// export of the same objects always assigns the same IDs.
+fn assign_ids<'a>(ids: &mut IdMap, pids: impl Iterator<Item = &'a str>) -> usize {
+    let mut next = ids.values().max().map_or(1, |id| id + 1);
+    let mut assigned = 0;
+    for pid in pids {
+        if !ids.contains_key(pid) {
+            ids.insert(pid.to_string(), next);
+            next += 1;
+            assigned += 1;
+        }
+    }
+    assigned
+}
+
+impl Ids {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Ids {
+            path,
+            ids: RwLock::new(None),
+        }
+    }
+
+    // Assigns IDs to the given objects, if requested, saving any new ones to
+    // the map unless `save` is false (i.e. when previewing).
+    pub fn assign(&self, objects: &ObjectMap, save: bool) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let mut ids = read_id_map(path);
+        let assigned = assign_ids(&mut ids, objects.inner().keys().map(|pid| pid.0.as_str()));
+        if save && assigned > 0 {
+            write_id_map(path, &ids)
+                .unwrap_or_else(|error| panic!("Failed to write {}: {}", path.display(), error));
+        }
+        info!(
+            "Assigned {} new IDs, {} objects are listed in {}",
+            assigned,
+            ids.len(),
+            path.display()
+        );
+        *self.ids.write().unwrap() = Some(ids);
+    }
+
+    // Inserts an `id` column before the `pid` column of the given header,
+    // unless it already has one, returning its index if IDs were assigned.
+    pub fn insert_column(&self, header: &mut Vec<String>) -> Option<usize> {
+        if self.ids.read().unwrap().is_none() || header.iter().any(|column| column == "id") {
+            return None;
+        }
+        let column = header.iter().position(|column| column == "pid")?;
+        header.insert(column, "id".to_string());
+        Some(column)
+    }
+
+    // Inserts the ID of the object of the given row at the column returned by
+    // `insert_column()`, i.e. before its PID.
+    pub fn insert(&self, column: usize, row: &mut Vec<Value>) {
+        let id = self
+            .ids
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(|ids| ids.get(row.get(column)?.as_str()?))
+            .map_or(Value::Null, |&id| Value::from(id));
+        row.insert(column.min(row.len()), id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_stable() {
+        let path = std::env::temp_dir().join(format!("ids-{}.csv", std::process::id()));
+        let mut ids = read_id_map(&path);
+        assert_eq!(
+            assign_ids(&mut ids, ["test:1", "test:2"].iter().copied()),
+            2
+        );
+        write_id_map(&path, &ids).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "pid,id\ntest:1,1\ntest:2,2\n"
+        );
+        // Existing IDs are kept regardless of order, new ones follow on.
+        let mut ids = read_id_map(&path);
+        assert_eq!(
+            assign_ids(&mut ids, ["test:0", "test:2", "test:1"].iter().copied()),
+            1
+        );
+        assert_eq!(ids["test:0"], 3);
+        assert_eq!(ids["test:1"], 1);
+        assert_eq!(ids["test:2"], 2);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+// An index of the parsed object files, written to `objects.index` at the end of
+// the `migrate` command so that the `csv` and `scripts` commands only parse the
+// object files that changed since, rather than every object file on each run.
+// Each line is JSON, the first describes how the index was built, the rest
+// are the objects along with the size and modified time of their file.
+//
+// The parsed RELS-EXT of every object are likewise kept in
+// `relationships.index`, keyed by the path of the RELS-EXT file, so that the
+// objects which are parsed again (e.g. all of them when using `--triples`) do
+// not parse their RELS-EXT again unless it changed. RELS-EXT are indexed as
+// parsed, so unlike the objects they do not depend on the PID map.
+use super::object::{Object, RelsExt, RelsExtError};
+use super::utils::{finish_partial, partial_path};
+use foxml::pids::PidMap;
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+static INDEX_FILE: &str = "objects.index";
+static RELATIONSHIPS_FILE: &str = "relationships.index";
+
+// Incremented whenever the way objects are parsed changes.
+const INDEX_VERSION: u32 = 4;
+
+#[derive(Deserialize, PartialEq, Serialize)]
+struct Header {
+    version: u32,
+    pid_map: String,
+}
+
+impl Header {
+    fn new(pid_map: &PidMap) -> Self {
+        Header {
+            version: INDEX_VERSION,
+            pid_map: format!("{:?}", pid_map),
+        }
+    }
+}
+
+// Size and modified time (seconds, nanoseconds) of an object file.
+type Stamp = (u64, u64, u32);
+
+fn stamp(path: &Path) -> Option<Stamp> {
+    let metadata = path.metadata().ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), modified.as_secs(), modified.subsec_nanos()))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().to_string()
+}
+
+#[derive(Deserialize, Serialize)]
+struct Entry<O> {
+    file: String,
+    stamp: Stamp,
+    object: O,
+}
+
+type Entries<O> = HashMap<String, Entry<O>>;
+
+// The entries of the given index, `None` if it was built by another version or
+// with another PID map.
+fn read<O: DeserializeOwned>(path: &Path, current: &Header) -> Result<Option<Entries<O>>, String> {
+    let file = File::open(path).map_err(|error| error.to_string())?;
+    let mut lines = BufReader::new(file).lines();
+    let header = match lines.next() {
+        Some(line) => line.map_err(|error| error.to_string())?,
+        None => return Ok(None),
+    };
+    let header: Header = serde_json::from_str(&header).map_err(|error| error.to_string())?;
+    if header != *current {
+        return Ok(None);
+    }
+    let mut entries = HashMap::new();
+    for line in lines {
+        let line = line.map_err(|error| error.to_string())?;
+        let entry: Entry<O> = serde_json::from_str(&line).map_err(|error| error.to_string())?;
+        entries.insert(entry.file.clone(), entry);
+    }
+    Ok(Some(entries))
+}
+
+// Loads the given index, it is ignored if it was built by another version or
+// with another PID map.
+fn load<O: DeserializeOwned>(path: &Path, header: &Header, what: &str) -> Entries<O> {
+    if !path.exists() {
+        return HashMap::new();
+    }
+    match read(path, header) {
+        Ok(Some(entries)) => {
+            info!("Loaded {} {} from {}", entries.len(), what, path.display());
+            entries
+        }
+        Ok(None) => {
+            info!(
+                "Ignoring {} as it was built by another version or with another --pid-map",
+                path.display()
+            );
+            HashMap::new()
+        }
+        Err(error) => {
+            warn!("Ignoring {}: {}", path.display(), error);
+            HashMap::new()
+        }
+    }
+}
+
+// Replaces the given index with the given entries, failures are only logged as
+// the index is an optimization.
+fn write<'a, O, I>(path: &Path, header: &Header, entries: I, what: &str)
+where
+    O: Serialize + 'a,
+    I: Iterator<Item = Entry<&'a O>>,
+{
+    let write = || -> Result<usize, String> {
+        let file = File::create(partial_path(path)).map_err(|error| error.to_string())?;
+        let mut writer = BufWriter::new(file);
+        let mut count = 0;
+        serde_json::to_writer(&mut writer, header).map_err(|error| error.to_string())?;
+        writeln!(writer).map_err(|error| error.to_string())?;
+        for entry in entries {
+            serde_json::to_writer(&mut writer, &entry).map_err(|error| error.to_string())?;
+            writeln!(writer).map_err(|error| error.to_string())?;
+            count += 1;
+        }
+        writer.flush().map_err(|error| error.to_string())?;
+        drop(writer);
+        finish_partial(path).map_err(|error| error.to_string())?;
+        Ok(count)
+    };
+    match write() {
+        Ok(count) => info!("Indexed {} {} in {}", count, what, path.display()),
+        Err(error) => warn!("Failed to write {}: {}", path.display(), error),
+    }
+}
+
+pub struct Index {
+    path: PathBuf,
+    header: Header,
+    entries: Entries<Object>,
+}
+
+impl Index {
+    pub fn load(input: &Path, pid_map: &PidMap) -> Self {
+        let path = input.join(INDEX_FILE);
+        let header = Header::new(pid_map);
+        let entries = load(&path, &header, "objects");
+        Index {
+            path,
+            header,
+            entries,
+        }
+    }
+
+    // Removes the object of the given file from the index, if it has not
+    // changed since the index was built.
+    pub fn take(&mut self, path: &Path) -> Option<Object> {
+        let entry = self.entries.remove(&file_name(path))?;
+        if Some(entry.stamp) == stamp(path) {
+            Some(entry.object)
+        } else {
+            None
+        }
+    }
+
+    // True if objects remain which were not taken, i.e. their files are gone.
+    pub fn has_removed(&self) -> bool {
+        !self.entries.is_empty()
+    }
+
+    // Replaces the index with the given objects.
+    pub fn write<'a, I>(&self, objects: I)
+    where
+        I: Iterator<Item = (&'a Path, &'a Object)>,
+    {
+        let entries = objects.filter_map(|(path, object)| {
+            Some(Entry {
+                file: file_name(path),
+                stamp: stamp(path)?,
+                object,
+            })
+        });
+        write(&self.path, &self.header, entries, "objects");
+    }
+}
+
+#[derive(Default)]
+struct Relationships {
+    entries: Entries<RelsExt>,
+    // Whether any RELS-EXT was parsed since the index was loaded.
+    changed: bool,
+}
+
+lazy_static! {
+    static ref RELATIONSHIPS: Mutex<Relationships> = Mutex::new(Relationships::default());
+}
+
+pub fn load_relationships(input: &Path) {
+    *RELATIONSHIPS.lock().unwrap() = Relationships {
+        entries: load(
+            &input.join(RELATIONSHIPS_FILE),
+            &Header::new(&PidMap::default()),
+            "RELS-EXT",
+        ),
+        changed: false,
+    };
+}
+
+// The RELS-EXT in the given file, parsed unless it has not changed since it
+// was indexed.
+pub fn rels_ext(path: &Path) -> Result<RelsExt, RelsExtError> {
+    let file = path.to_string_lossy().to_string();
+    let stamp = stamp(path);
+    if let Some(entry) = RELATIONSHIPS.lock().unwrap().entries.get(&file) {
+        if Some(entry.stamp) == stamp {
+            return Ok(entry.object.clone());
+        }
+    }
+    let rels_ext = RelsExt::from_path(path)?;
+    if let Some(stamp) = stamp {
+        let mut relationships = RELATIONSHIPS.lock().unwrap();
+        let entry = Entry {
+            file: file.clone(),
+            stamp,
+            object: rels_ext.clone(),
+        };
+        relationships.entries.insert(file, entry);
+        relationships.changed = true;
+    }
+    Ok(rels_ext)
+}
+
+// Replaces the index with every RELS-EXT that is unchanged since it was
+// parsed, if any were parsed.
+pub fn write_relationships(input: &Path) {
+    let relationships = RELATIONSHIPS.lock().unwrap();
+    if !relationships.changed {
+        return;
+    }
+    let entries = relationships
+        .entries
+        .values()
+        .filter(|entry| Some(entry.stamp) == stamp(Path::new(&entry.file)))
+        .map(|entry| Entry {
+            file: entry.file.clone(),
+            stamp: entry.stamp,
+            object: &entry.object,
+        });
+    write(
+        &input.join(RELATIONSHIPS_FILE),
+        &Header::new(&PidMap::default()),
+        entries,
+        "RELS-EXT",
+    );
+}
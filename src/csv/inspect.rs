@@ -0,0 +1,148 @@
+// Prints everything known about a single object, for when one misbehaves among
+// millions: its FOXML properties, relationships and datastream versions (along
+// with whether their files were migrated), the rows each built-in CSV file
+// would include for it, and given `--scripts` the rows of each script.
+use super::object::{Object, RelsExt};
+use super::rows::{self, Table};
+use super::scripts::ScriptOptions;
+use foxml::Foxml;
+use std::path::Path;
+
+// Prints the values aligned after their names.
+fn print_fields<S>(fields: &[(S, String)])
+where
+    S: AsRef<str>,
+{
+    let width = fields
+        .iter()
+        .map(|(name, _)| name.as_ref().chars().count())
+        .max()
+        .unwrap_or(0);
+    for (name, value) in fields {
+        let line = format!("  {:width$}  {}", name.as_ref(), value, width = width);
+        println!("{}", line.trim_end());
+    }
+}
+
+fn print_properties(path: &Path) {
+    let foxml = Foxml::metadata_from_path(path)
+        .unwrap_or_else(|error| panic!("Failed to parse {}: {}", path.display(), error));
+    println!("FOXML properties");
+    let fields = foxml
+        .properties
+        .properties
+        .iter()
+        .chain(foxml.properties.ext_properties.iter())
+        .map(|property| (property.name.as_str(), property.value.clone()))
+        .collect::<Vec<_>>();
+    print_fields(&fields);
+    println!();
+}
+
+fn print_relationships(object: &Object) {
+    println!("RELS-EXT");
+    let mut fields = vec![
+        ("model", object.model.clone()),
+        (
+            "weight",
+            object
+                .weight
+                .map_or_else(String::new, |weight| weight.to_string()),
+        ),
+    ];
+    fields.extend(
+        object
+            .relationships
+            .iter()
+            .map(|relationship| (relationship.predicate.as_str(), relationship.target.clone())),
+    );
+    print_fields(&fields);
+    println!();
+}
+
+fn print_datastreams(object: &Object) {
+    println!("Datastreams");
+    for datastream in &object.datastreams {
+        println!("  {} ({:?})", datastream.id, datastream.state);
+        let fields = datastream
+            .versions
+            .iter()
+            .map(|version| {
+                let file = match version.url() {
+                    Some(url) => format!("{} ({})", url, version.content.remote()),
+                    None => {
+                        let path = version.path();
+                        match path.metadata() {
+                            Ok(metadata) => {
+                                format!("{} ({} bytes)", path.display(), metadata.len())
+                            }
+                            Err(_) => format!("{} (missing)", path.display()),
+                        }
+                    }
+                };
+                (
+                    format!("  {}", version.id),
+                    format!(
+                        "{}  {}  {}",
+                        version.created_date.to_rfc3339(),
+                        version.mime_type,
+                        file
+                    ),
+                )
+            })
+            .collect::<Vec<_>>();
+        print_fields(&fields);
+    }
+    println!();
+}
+
+// Rows are printed one field per line, as most tables are too wide otherwise.
+fn print_rows(name: &str, (header, rows): &Table) {
+    println!("{} ({} rows)", name, rows.len());
+    for (i, row) in rows.iter().enumerate() {
+        if i > 0 {
+            println!("  --");
+        }
+        let fields = header
+            .iter()
+            .zip(row.iter())
+            .map(|(column, value)| (column.as_str(), value.clone()))
+            .collect::<Vec<_>>();
+        print_fields(&fields);
+    }
+    println!();
+}
+
+pub fn inspect(input: &Path, pid: &str, triples: Option<&Path>, options: ScriptOptions) {
+    super::valid_source_directory(input).unwrap_or_else(|error| panic!("{}", error));
+    let path = super::object_file(pid)
+        .filter(|path| path.exists())
+        .unwrap_or_else(|| panic!("No object file found for {} in {}", pid, input.display()));
+    println!("{} ({})", pid, path.display());
+    println!();
+    print_properties(&path);
+    let resource_index = triples.map(|triples| {
+        RelsExt::from_triples(triples).expect("Failed to parse resource index triples")
+    });
+    let object = Object::from_path(&path, resource_index.as_ref(), &options.pid_map)
+        .unwrap_or_else(|| panic!("Failed to parse {}", path.display()));
+    print_relationships(&object);
+    print_datastreams(&object);
+    if object.missing_content_model() || object.is_system_object() || object.is_content_model() {
+        println!("Objects without a content model, system objects and content models are not included in the CSV files.");
+        return;
+    }
+    let descriptive_metadata = options
+        .descriptive_metadata
+        .iter()
+        .map(|dsid| dsid.to_string())
+        .collect::<Vec<_>>();
+    for (name, table) in rows::inspect(&object, &descriptive_metadata, &options.pid_map) {
+        print_rows(name, &table);
+    }
+    // Scripts are previewed against the object given `options.preview`, those
+    // without any rows do not match it.
+    if !options.scripts.is_empty() {
+        super::execute_scripts(input, input, vec![pid.to_string()], triples, options);
+    }
+}
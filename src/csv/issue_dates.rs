@@ -0,0 +1,181 @@
+// Dates of newspaper issues, normalized as EDTF for Drupal's `field_edtf_date`.
+// Islandora records them in the MODS `dateIssued` of the issue, though some
+// sites only have them in the label e.g. 'Denver Catholic Register November
+// 18, 1954'.
+// @see https://www.loc.gov/standards/datetime/
+use super::object::{DatastreamVersion, Object};
+use super::utils::report_problem;
+use chrono::NaiveDate;
+use log::Level;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use regex::{Captures, Regex};
+
+lazy_static! {
+    static ref ISO_DATE: Regex = Regex::new(r"\b(\d{4})-(\d{2})-(\d{2})").unwrap();
+    static ref MONTH_DAY_YEAR: Regex = Regex::new(
+        r"(?i)\b(jan|feb|mar|apr|may|jun|jul|aug|sep|oct|nov|dec)[a-z]*\.?\s+(\d{1,2})(?:st|nd|rd|th)?,?\s+(\d{4})\b"
+    )
+    .unwrap();
+    static ref DAY_MONTH_YEAR: Regex = Regex::new(
+        r"(?i)\b(\d{1,2})(?:st|nd|rd|th)?\s+(jan|feb|mar|apr|may|jun|jul|aug|sep|oct|nov|dec)[a-z]*\.?,?\s+(\d{4})\b"
+    )
+    .unwrap();
+    // Assumed to be month first, as is the convention in the US.
+    static ref US_DATE: Regex = Regex::new(r"\b(\d{1,2})/(\d{1,2})/(\d{4})\b").unwrap();
+    static ref COMPACT_DATE: Regex = Regex::new(r"^(\d{4})(\d{2})(\d{2})$").unwrap();
+    static ref MONTH_YEAR: Regex = Regex::new(
+        r"(?i)\b(jan|feb|mar|apr|may|jun|jul|aug|sep|oct|nov|dec)[a-z]*\.?,?\s+(\d{4})\b"
+    )
+    .unwrap();
+    static ref ISO_MONTH: Regex = Regex::new(r"\b(\d{4})-(\d{2})\b").unwrap();
+    static ref YEAR: Regex = Regex::new(r"\b(\d{4})\b").unwrap();
+}
+
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+// Where to look for the date of an issue, in the order given.
+#[derive(Clone, Copy, Debug, EnumString, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum IssueDateSource {
+    Mods,  // The first dateIssued, preferring those marked as the keyDate.
+    Label, // The first date found in the label.
+}
+
+fn month(name: &str) -> u32 {
+    let name = name.to_ascii_lowercase();
+    MONTHS.iter().position(|month| *month == name).unwrap() as u32 + 1
+}
+
+fn day(year: &str, month: u32, day: &str) -> Option<String> {
+    let date = NaiveDate::from_ymd_opt(year.parse().ok()?, month, day.parse().ok()?)?;
+    Some(date.format("%Y-%m-%d").to_string())
+}
+
+// Normalizes the first date found in the given text as EDTF, i.e. YYYY-MM-DD,
+// YYYY-MM or YYYY, marked as uncertain if the text includes a '?'.
+pub fn edtf(text: &str) -> Option<String> {
+    let text = text.trim();
+    let captures = |regex: &Regex| regex.captures(text);
+    let group = |captures: &Captures, i| captures.get(i).unwrap().as_str().to_string();
+    let date = None
+        .or_else(|| {
+            let c = captures(&ISO_DATE)?;
+            day(&group(&c, 1), group(&c, 2).parse().ok()?, &group(&c, 3))
+        })
+        .or_else(|| {
+            let c = captures(&MONTH_DAY_YEAR)?;
+            day(&group(&c, 3), month(&group(&c, 1)), &group(&c, 2))
+        })
+        .or_else(|| {
+            let c = captures(&DAY_MONTH_YEAR)?;
+            day(&group(&c, 3), month(&group(&c, 2)), &group(&c, 1))
+        })
+        .or_else(|| {
+            let c = captures(&US_DATE)?;
+            day(&group(&c, 3), group(&c, 1).parse().ok()?, &group(&c, 2))
+        })
+        .or_else(|| {
+            let c = captures(&COMPACT_DATE)?;
+            day(&group(&c, 1), group(&c, 2).parse().ok()?, &group(&c, 3))
+        })
+        .or_else(|| {
+            let c = captures(&MONTH_YEAR)?;
+            Some(format!("{}-{:02}", group(&c, 2), month(&group(&c, 1))))
+        })
+        .or_else(|| {
+            let c = captures(&ISO_MONTH)?;
+            let month = group(&c, 2).parse::<u32>().ok()?;
+            if (1..=12).contains(&month) {
+                Some(format!("{}-{:02}", group(&c, 1), month))
+            } else {
+                None
+            }
+        })
+        .or_else(|| captures(&YEAR).map(|c| group(&c, 1)))?;
+    if text.contains('?') {
+        Some(format!("{}?", date))
+    } else {
+        Some(date)
+    }
+}
+
+// The text of each dateIssued, key dates first.
+fn dates_issued(version: &DatastreamVersion) -> Result<Vec<String>, quick_xml::Error> {
+    let mut reader = Reader::from_file(version.path())?;
+    reader.trim_text(true);
+    let mut buffer = Vec::new();
+    let mut dates = Vec::new();
+    loop {
+        match reader.read_event(&mut buffer)? {
+            Event::Start(element) if element.local_name() == b"dateIssued" => {
+                let key_date = element
+                    .attributes()
+                    .filter_map(|attribute| attribute.ok())
+                    .any(|attribute| attribute.key == b"keyDate" && &*attribute.value == b"yes");
+                let name = element.name().to_vec();
+                let value = reader.read_text(&name, &mut Vec::new())?;
+                dates.push((!key_date, value));
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+        buffer.clear();
+    }
+    // Stable, so otherwise in document order.
+    dates.sort_by_key(|(not_key_date, _)| *not_key_date);
+    Ok(dates.into_iter().map(|(_, value)| value).collect())
+}
+
+pub fn issue_date(
+    object: &Object,
+    sources: &[IssueDateSource],
+    descriptive_metadata: &[String],
+) -> Option<String> {
+    sources.iter().find_map(|source| match source {
+        IssueDateSource::Mods => {
+            let version = object
+                .descriptive_metadata(descriptive_metadata)
+                .filter(|v| v.path().exists())?;
+            match dates_issued(version) {
+                Ok(dates) => dates.iter().find_map(|date| edtf(date)),
+                Err(error) => {
+                    report_problem(
+                        Level::Warn,
+                        "unparsable_mods",
+                        &format!("Failed to parse MODS of {}: {}", object.pid, error),
+                    );
+                    None
+                }
+            }
+        }
+        IssueDateSource::Label => edtf(&object.label),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edtf() {
+        let edtf = |text| edtf(text).unwrap_or_default();
+        assert_eq!(edtf("1954-11-18"), "1954-11-18");
+        assert_eq!(edtf("1954-11-18T00:00:00Z"), "1954-11-18");
+        assert_eq!(
+            edtf("Denver Catholic Register November 18, 1954"),
+            "1954-11-18"
+        );
+        assert_eq!(edtf("Register Nov. 3rd 1954"), "1954-11-03");
+        assert_eq!(edtf("18 November 1954"), "1954-11-18");
+        assert_eq!(edtf("11/18/1954"), "1954-11-18");
+        assert_eq!(edtf("19541118"), "1954-11-18");
+        assert_eq!(edtf("Spring issue, March 1954"), "1954-03");
+        assert_eq!(edtf("1954-11"), "1954-11");
+        assert_eq!(edtf("[1954?]"), "1954?");
+        assert_eq!(edtf("February 30, 1954"), "1954");
+        assert_eq!(edtf("Issue 12"), "");
+    }
+}
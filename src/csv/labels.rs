@@ -0,0 +1,265 @@
+// Labels frequently contain HTML entities (e.g. 'Caf&eacute;') or UTF-8 that
+// was decoded as Windows-1252 / Latin-1 and encoded again (e.g. 'CafÃ©') by
+// old ingest tools. With `--clean-labels` the given fixes are applied to the
+// labels of objects and datastream versions, every change is listed in
+// label_corrections.csv. Files on disk keep the name given by the original
+// label, see `DatastreamVersion::file_name()`.
+use super::object::ObjectMap;
+use super::output::{Output, Row};
+use log::info;
+use serde::Serialize;
+
+// Maximum number of times a label is decoded, for labels that were escaped or
+// encoded more than once (e.g. '&amp;eacute;').
+const MAX_PASSES: usize = 3;
+
+#[derive(Clone, Copy, Debug, EnumString, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum LabelFix {
+    // Decode named and numeric HTML entities.
+    Entities,
+    // Repair UTF-8 that was decoded as Windows-1252 or Latin-1.
+    Mojibake,
+    // Remove control characters and collapse runs of whitespace.
+    Whitespace,
+}
+
+// The characters Windows-1252 decodes the bytes 0x80 to 0x9F as, those that
+// are undefined are decoded as the Latin-1 control character.
+static WINDOWS_1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8D}', 'Ž', '\u{8F}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9D}', 'ž', 'Ÿ',
+];
+
+// The byte the character was decoded from, if it can be represented in
+// Windows-1252 or Latin-1.
+fn byte(c: char) -> Option<u8> {
+    match c as u32 {
+        code @ 0..=0xFF => Some(code as u8),
+        _ => WINDOWS_1252
+            .iter()
+            .position(|&other| other == c)
+            .map(|index| 0x80 + index as u8),
+    }
+}
+
+// A UTF-8 sequence of two to four bytes that was decoded as a character per
+// byte, and the number of characters it spans.
+fn mojibake_sequence(chars: &[char]) -> Option<(char, usize)> {
+    let length = match byte(*chars.first()?)? {
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => return None,
+    };
+    let bytes = chars
+        .get(..length)?
+        .iter()
+        .map(|&c| byte(c))
+        .collect::<Option<Vec<_>>>()?;
+    let decoded = std::str::from_utf8(&bytes).ok()?;
+    decoded.chars().next().map(|c| (c, length))
+}
+
+// Replaces every sequence of characters that is valid UTF-8 when re-encoded as
+// the bytes they were wrongly decoded from, which is very unlikely for
+// legitimate text, so labels mixing both are also repaired.
+fn repair_mojibake(label: &str) -> Option<String> {
+    if label.is_ascii() {
+        return None;
+    }
+    let chars = label.chars().collect::<Vec<_>>();
+    let mut repaired = String::with_capacity(label.len());
+    let mut changed = false;
+    let mut index = 0;
+    while index < chars.len() {
+        match mojibake_sequence(&chars[index..]) {
+            Some((c, length)) => {
+                repaired.push(c);
+                index += length;
+                changed = true;
+            }
+            None => {
+                repaired.push(chars[index]);
+                index += 1;
+            }
+        }
+    }
+    if changed {
+        Some(repaired)
+    } else {
+        None
+    }
+}
+
+fn decode_entities(label: &str) -> Option<String> {
+    let decoded = html_escape::decode_html_entities(label);
+    if decoded == label {
+        None
+    } else {
+        Some(decoded.into_owned())
+    }
+}
+
+fn normalize_whitespace(label: &str) -> Option<String> {
+    let normalized = label
+        .split(|c: char| c.is_whitespace() || c.is_control())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if normalized == label {
+        None
+    } else {
+        Some(normalized)
+    }
+}
+
+// The cleaned label and the fixes that changed it, if any.
+pub fn clean(label: &str, fixes: &[LabelFix]) -> Option<(String, Vec<LabelFix>)> {
+    let mut cleaned = label.to_string();
+    let mut applied = Vec::new();
+    // Entities are decoded first, as they may hide mojibake e.g. '&Atilde;&copy;'.
+    for fix in [LabelFix::Entities, LabelFix::Mojibake, LabelFix::Whitespace] {
+        if !fixes.contains(&fix) {
+            continue;
+        }
+        let apply = match fix {
+            LabelFix::Entities => decode_entities,
+            LabelFix::Mojibake => repair_mojibake,
+            LabelFix::Whitespace => normalize_whitespace,
+        };
+        for _ in 0..MAX_PASSES {
+            match apply(&cleaned) {
+                Some(fixed) => {
+                    cleaned = fixed;
+                    if !applied.contains(&fix) {
+                        applied.push(fix);
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+    if applied.is_empty() {
+        None
+    } else {
+        Some((cleaned, applied))
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct LabelCorrectionRow {
+    pid: String,
+    // Empty for the labels of objects.
+    dsid: String,
+    version: String,
+    fixes: String,
+    before: String,
+    after: String,
+}
+
+impl Row for LabelCorrectionRow {
+    fn columns() -> &'static [&'static str] {
+        &["pid", "dsid", "version", "fixes", "before", "after"]
+    }
+}
+
+impl LabelCorrectionRow {
+    fn new(
+        pid: &str,
+        dsid: &str,
+        version: &str,
+        before: &str,
+        after: &str,
+        fixes: &[LabelFix],
+    ) -> Self {
+        LabelCorrectionRow {
+            pid: pid.to_string(),
+            dsid: dsid.to_string(),
+            version: version.to_string(),
+            fixes: fixes
+                .iter()
+                .map(|fix| format!("{:?}", fix).to_lowercase())
+                .collect::<Vec<_>>()
+                .join("|"),
+            before: before.to_string(),
+            after: after.to_string(),
+        }
+    }
+}
+
+// Cleans the labels of all objects and their datastream versions, listing the
+// corrections in label_corrections.csv unless no output is given (e.g.
+// when previewing).
+pub fn apply(objects: &mut ObjectMap, fixes: &[LabelFix], output: Option<&Output>) {
+    if fixes.is_empty() {
+        return;
+    }
+    let mut rows = Vec::new();
+    for object in objects.objects_mut() {
+        if let Some((label, applied)) = clean(&object.label, fixes) {
+            rows.push(LabelCorrectionRow::new(
+                &object.pid.0,
+                "",
+                "",
+                &object.label,
+                &label,
+                &applied,
+            ));
+            object.label = label;
+        }
+    }
+    for version in objects.versions_mut() {
+        if let Some((label, applied)) = clean(&version.label, fixes) {
+            rows.push(LabelCorrectionRow::new(
+                &version.pid,
+                &version.dsid,
+                &version.id,
+                &version.label,
+                &label,
+                &applied,
+            ));
+            let original = std::mem::replace(&mut version.label, label);
+            version.original_label.get_or_insert(original);
+        }
+    }
+    rows.sort_by(|a, b| (&a.pid, &a.dsid, &a.version).cmp(&(&b.pid, &b.dsid, &b.version)));
+    info!("Cleaned {} labels", rows.len());
+    if let Some(output) = output {
+        output
+            .write_report(&rows, "label_corrections.csv")
+            .expect("Failed to create label_corrections.csv");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cleaned_labels() {
+        let all = [LabelFix::Entities, LabelFix::Mojibake, LabelFix::Whitespace];
+        let clean = |label: &str| clean(label, &all).map(|(label, _)| label);
+        assert_eq!(clean("Caf&eacute; &amp; Bar"), Some("Café & Bar".into()));
+        assert_eq!(clean("Caf&amp;eacute;"), Some("Café".into()));
+        assert_eq!(clean("CafÃ©"), Some("Café".into()));
+        // Encoded twice.
+        assert_eq!(clean("CafÃƒÂ©"), Some("Café".into()));
+        assert_eq!(clean("Itâ€™s"), Some("It’s".into()));
+        assert_eq!(clean("Caf&Atilde;&copy;"), Some("Café".into()));
+        assert_eq!(
+            clean("Registerâ€™s Caf&eacute;"),
+            Some("Register’s Café".into())
+        );
+        assert_eq!(clean(" Two\t words\n"), Some("Two words".into()));
+        // Legitimate non-ASCII text is left as is.
+        assert_eq!(clean("Café"), None);
+        assert_eq!(clean("Ñandú"), None);
+        assert_eq!(clean("日本語"), None);
+        assert_eq!(clean("Plain"), None);
+        assert_eq!(
+            super::clean("CafÃ© &amp;", &[LabelFix::Mojibake]),
+            Some(("Café &amp;".to_string(), vec![LabelFix::Mojibake]))
+        );
+    }
+}
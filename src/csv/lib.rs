@@ -8,25 +8,84 @@ extern crate strum_macros;
 #[macro_use]
 extern crate maplit;
 
+mod checksums;
+mod citations;
+mod compare;
+mod crosswalk;
+mod embargo;
+mod encoding;
+mod ids;
+mod index;
+mod inspect;
+mod issue_dates;
+mod labels;
+mod limits;
 mod map;
+mod mets;
+mod network;
 mod object;
+mod output;
+mod pages;
+mod parse_errors;
+mod pid_query;
+mod profile;
 mod rows;
+mod run;
+mod schema;
 mod scripts;
+mod shards;
+mod tiff;
+mod timings;
 mod utils;
+mod vocabularies;
+mod warnings;
+mod workbench;
+mod workbook;
 mod xml;
 
-pub use scripts::ScriptError;
+pub use citations::CitationFormat;
+pub use encoding::set_detect_charset;
+pub use inspect::inspect;
+pub use issue_dates::IssueDateSource;
+pub use labels::LabelFix;
+pub use limits::{truncate, FieldLimit, FieldLimits, OversizedValuePolicy};
+pub use output::{OutputFormat, OutputOptions};
+pub use pages::PageDelimiter;
+pub use pid_query::query_pids;
+pub use rows::{
+    DanglingParentPolicy, FileRow, InvalidOwnerPolicy, MediaRow, MissingFilePolicy, NodeRow,
+    UnknownModelPolicy,
+};
+pub use run::Run;
+pub use scripts::{ScriptError, ScriptOptions};
+pub use shards::{merge_shards, shard_directory};
+pub use timings::set_slowest_objects;
+pub use utils::set_fail_fast;
+pub use xml::XmlLimits;
 
+use foxml::pids::PidMap;
+use foxml::VersionPolicy;
+use indicatif::ProgressBar;
 use log::info;
+use logger::webhook;
 use object::ObjectMap;
-use rows::{FileRow, MediaRow, NodeRow};
+use output::Output;
+use rows::{
+    DanglingParentRow, DcRow, DeletedRow, EmbargoRow, FileCollisionRow, InvalidOwnerRow,
+    MimeTypeRow, MissingFileRow, RelationshipRow, SystemObjectRow, ThumbnailRow, UnknownModelRow,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
 use std::sync::RwLock;
+use utils::{is_fail_fast, notify_outputs, remove_completion_marker, write_completion_marker};
 
 lazy_static! {
     static ref OBJECTS_DIRECTORY: RwLock<Option<Box<Path>>> = RwLock::new(None);
     static ref DATASTREAMS_DIRECTORY: RwLock<Option<Box<Path>>> = RwLock::new(None);
+    // Volume name and directory by PID, when the `migrate` command spread the
+    // datastreams across multiple volumes.
+    static ref VOLUMES: RwLock<HashMap<String, (String, PathBuf)>> = RwLock::new(HashMap::new());
 }
 
 fn set_objects_directory(path: &PathBuf) {
@@ -39,6 +98,38 @@ fn set_datastreams_directory(path: &PathBuf) {
     *lock = Some(path.clone().into_boxed_path());
 }
 
+fn set_volumes(manifest: &Path) -> Result<(), String> {
+    let read = || -> Result<HashMap<String, (String, PathBuf)>, csv_other::Error> {
+        let mut volumes = HashMap::new();
+        for record in csv_other::Reader::from_path(manifest)?.records() {
+            let record = record?;
+            volumes.insert(
+                record[0].to_string(),
+                (record[1].to_string(), PathBuf::from(&record[2])),
+            );
+        }
+        Ok(volumes)
+    };
+    let volumes =
+        read().map_err(|error| format!("Failed to read {}: {}", manifest.display(), error))?;
+    *VOLUMES.write().unwrap() = volumes;
+    Ok(())
+}
+
+// The object file of the given object, once the source directory is validated.
+fn object_file(pid: &str) -> Option<PathBuf> {
+    OBJECTS_DIRECTORY
+        .read()
+        .unwrap()
+        .as_ref()
+        .map(|directory| directory.join(format!("{}.xml", pid)))
+}
+
+// The volume the datastreams of the given object were spread to if any.
+fn volume(pid: &str) -> Option<(String, PathBuf)> {
+    VOLUMES.read().unwrap().get(pid).cloned()
+}
+
 pub fn valid_source_directory(path: &Path) -> Result<(), String> {
     fn valid_directory(path: &Path) -> Result<(), String> {
         if path.is_dir() {
@@ -52,57 +143,367 @@ pub fn valid_source_directory(path: &Path) -> Result<(), String> {
     valid_directory(&objects)?;
     set_objects_directory(&objects);
     let datastreams = path.join("datastreams");
-    valid_directory(&datastreams)?;
+    let volumes = path.join("volumes.csv");
+    if volumes.exists() {
+        set_volumes(&volumes)?;
+    } else {
+        valid_directory(&datastreams)?;
+    }
     set_datastreams_directory(&datastreams);
     Ok(())
 }
 
-pub fn generate_csvs(input: &Path, dest: &Path, pids: Vec<&str>) {
+// Creates the output directory, unless told not to in which case it must
+// already exist.
+pub fn create_output_directory(path: &Path, create: bool) {
+    if !create && !path.is_dir() {
+        panic!(
+            "The directory '{}' does not exist, and --no-create was given",
+            path.display()
+        );
+    }
+    std::fs::create_dir_all(path)
+        .unwrap_or_else(|error| panic!("Failed to create {}: {}", path.display(), error));
+}
+
+// Generates a single CSV file.
+type Task = Box<dyn FnOnce(&ObjectMap, &Output) + Send>;
+
+// Options which control how the built-in CSV files are generated.
+pub struct CsvOptions {
+    pub unknown_models: UnknownModelPolicy,
+    pub disambiguate_file_names: bool,
+    pub dangling_parents: DanglingParentPolicy,
+    pub invalid_owners: InvalidOwnerPolicy,
+    pub missing_files: MissingFilePolicy,
+    // Leave the objects and datastreams in the Deleted state out of every CSV.
+    pub exclude_deleted: bool,
+    // Also write a METS document per object for preservation.
+    pub mets: bool,
+    // Also write an inventory of the system objects & content models.
+    pub system_objects: bool,
+    // Also write the subjects and agents found across all objects.
+    pub vocabularies: bool,
+    // Also write the CSVs of an Islandora Workbench create and add_media task.
+    pub workbench: bool,
+    // Also export the MODS of citations & theses in the given format.
+    pub citations: Option<CitationFormat>,
+    // Add a `thumbnail` column to nodes.csv, see missing_thumbnails.csv.
+    pub thumbnail_column: bool,
+    // Add a column to nodes.csv per kind of relationship to the parents, see
+    // relationships.csv.
+    pub typed_parents: bool,
+    // Where to find the date of newspaper issues for nodes.csv.
+    pub issue_date_sources: Vec<IssueDateSource>,
+    // Priority list of DSIDs that may hold the MODS.
+    pub descriptive_metadata: Vec<String>,
+    // Nest the output in a unique directory for this run.
+    pub run: Option<Run>,
+    // The output directory of a previous run to compare this run to.
+    pub compare_to: Option<PathBuf>,
+    // Fixes applied to the labels of objects and datastream versions.
+    pub clean_labels: Vec<LabelFix>,
+    // Which datastream versions are included in files.csv, media.csv, etc.
+    pub versions: VersionPolicy,
+    // Rewrites the PIDs of objects, given `--pid-map`.
+    pub pid_map: PidMap,
+    // How the tables are written.
+    pub output: OutputOptions,
+}
+
+// Parses every object file in the output of the `migrate` command, so that
+// subsequent commands can load them from the index rather than parse them.
+pub fn index_objects(input: &Path, pid_map: &PidMap) {
+    webhook::phase_started("index objects");
+    valid_source_directory(input).unwrap_or_else(|error| panic!("{}", error));
+    let objects = ObjectMap::from_path(input, vec![], None, pid_map);
+    parse_errors::write_report(&Output::new(input, OutputOptions::default()));
+    webhook::phase_finished("index objects", &[("objects", objects.inner().len())]);
+}
+
+// Splits the given datastream of objects with pages into a datastream per page
+// object, see `pages::split_pages()`.
+pub fn split_pages(input: &Path, dsid: &str, delimiter: &PageDelimiter, pid_map: &PidMap) {
+    webhook::phase_started("split pages");
+    valid_source_directory(input).unwrap_or_else(|error| panic!("{}", error));
+    let objects = ObjectMap::from_path(input, vec![], None, pid_map);
+    let count = pages::split_pages(&objects, input, dsid, delimiter);
+    webhook::phase_finished("split pages", &[("pages", count)]);
+}
+
+pub fn generate_csvs(
+    input: &Path,
+    dest: &Path,
+    pids: Vec<String>,
+    triples: Option<&Path>,
+    options: CsvOptions,
+) {
     info!("Generating csv files");
 
-    let objects = Arc::new(ObjectMap::from_path(&input, pids));
-    let dest = Arc::new(dest.to_path_buf());
+    let run_directory = options.run.as_ref().map(|run| run.directory(dest));
+    let dest = run_directory.as_deref().unwrap_or(dest);
+    let output = Output::new(dest, options.output);
+
+    // Remove the marker from any previous run, it is written once all files are complete.
+    let marker = dest.join("csv.complete");
+    remove_completion_marker(&marker);
+
+    webhook::phase_started("objects");
+    let mut objects = ObjectMap::from_path(&input, pids, triples, &options.pid_map);
+    pages::apply(&mut objects, input);
+    objects.apply_version_policy(options.versions);
+    labels::apply(&mut objects, &options.clean_labels, Some(&output));
+    UnknownModelRow::apply(&mut objects, options.unknown_models, &output)
+        .unwrap_or_else(|error| panic!("{}", error));
+    DeletedRow::apply(&mut objects, options.exclude_deleted, &output);
+    DanglingParentRow::apply(&mut objects, &options.dangling_parents, &output);
+    InvalidOwnerRow::apply(&mut objects, &options.invalid_owners, &output);
+    MissingFileRow::apply(&objects, options.missing_files, &output);
+    FileCollisionRow::apply(&mut objects, options.disambiguate_file_names, &output);
+    output.ids().assign(&objects, true);
+    webhook::phase_finished("objects", &[("objects", objects.inner().len())]);
+    if let Some(run) = &options.run {
+        run.write_metadata(dest, &[], objects.inner().len());
+    }
+
+    webhook::phase_started("tables");
 
-    let multi = Arc::new(logger::multi_progress());
+    let multi = logger::multi_progress();
     let count = 10000; // Just set the progress bars to arbitrary length until actual length can be calculated.
+    let missing_files = options.missing_files;
 
-    let _objects = objects.clone();
-    let _dest = dest.clone();
-    let progress_bar = multi.add(logger::progress_bar(count));
-    rayon::spawn(move || {
-        FileRow::csv(&_objects, &_dest, progress_bar);
-    });
+    // The progress bars of each task are finished should it fail, as otherwise
+    // waiting on them would never return.
+    let progress_bars = RefCell::new(Vec::new());
+    let add_progress_bar = || {
+        let progress_bar = multi.add(logger::progress_bar(count));
+        progress_bars.borrow_mut().push(progress_bar.clone());
+        progress_bar
+    };
+    let with_progress_bars = |name: &'static str, task: Task| (name, progress_bars.take(), task);
+    let mut tasks: Vec<(&str, Vec<ProgressBar>, Task)> = vec![
+        with_progress_bars("files.csv", {
+            let progress_bar = add_progress_bar();
+            let hash_progress_bar = add_progress_bar();
+            Box::new(move |objects: &ObjectMap, output: &Output| {
+                FileRow::csv(
+                    objects,
+                    output,
+                    progress_bar,
+                    hash_progress_bar,
+                    missing_files,
+                )
+            })
+        }),
+        with_progress_bars("media.csv", {
+            let progress_bar = add_progress_bar();
+            Box::new(move |objects: &ObjectMap, output: &Output| {
+                MediaRow::csv(objects, output, progress_bar, missing_files)
+            })
+        }),
+        with_progress_bars("media_revisions.csv", {
+            let progress_bar = add_progress_bar();
+            Box::new(move |objects: &ObjectMap, output: &Output| {
+                MediaRow::revisions_csv(objects, output, progress_bar, missing_files)
+            })
+        }),
+        with_progress_bars("nodes.csv", {
+            let progress_bar = add_progress_bar();
+            let thumbnail_column = options.thumbnail_column;
+            let typed_parents = options.typed_parents;
+            let issue_date_sources = options.issue_date_sources.clone();
+            let descriptive_metadata = options.descriptive_metadata.clone();
+            Box::new(move |objects: &ObjectMap, output: &Output| {
+                NodeRow::csv(
+                    objects,
+                    output,
+                    progress_bar,
+                    thumbnail_column,
+                    typed_parents,
+                    &issue_date_sources,
+                    &descriptive_metadata,
+                )
+            })
+        }),
+        with_progress_bars("relationships.csv", {
+            let progress_bar = add_progress_bar();
+            Box::new(move |objects: &ObjectMap, output: &Output| {
+                RelationshipRow::csv(objects, output, progress_bar)
+            })
+        }),
+        with_progress_bars("dc.csv", {
+            let progress_bar = add_progress_bar();
+            Box::new(move |objects: &ObjectMap, output: &Output| {
+                DcRow::csv(objects, output, progress_bar)
+            })
+        }),
+        with_progress_bars("embargoes.csv", {
+            let progress_bar = add_progress_bar();
+            Box::new(move |objects: &ObjectMap, output: &Output| {
+                EmbargoRow::csv(objects, output, progress_bar)
+            })
+        }),
+        with_progress_bars("mime_types.csv", {
+            let progress_bar = add_progress_bar();
+            Box::new(move |objects: &ObjectMap, output: &Output| {
+                MimeTypeRow::csv(objects, output, progress_bar)
+            })
+        }),
+        with_progress_bars("missing_thumbnails.csv", {
+            let progress_bar = add_progress_bar();
+            Box::new(move |objects: &ObjectMap, output: &Output| {
+                ThumbnailRow::csv(objects, output, progress_bar)
+            })
+        }),
+        with_progress_bars("multipage_tiffs.csv", {
+            let progress_bar = add_progress_bar();
+            Box::new(move |objects: &ObjectMap, output: &Output| {
+                tiff::csv(objects, output, progress_bar)
+            })
+        }),
+    ];
+    if options.mets {
+        let progress_bar = add_progress_bar();
+        tasks.push(with_progress_bars(
+            "mets",
+            Box::new(move |objects: &ObjectMap, output: &Output| {
+                mets::write_mets(objects, output.directory(), progress_bar)
+            }),
+        ));
+    }
+    if options.workbench {
+        let progress_bar = add_progress_bar();
+        tasks.push(with_progress_bars(
+            "workbench_create.csv and workbench_add_media.csv",
+            Box::new(move |objects: &ObjectMap, output: &Output| {
+                workbench::csv(objects, output, progress_bar)
+            }),
+        ));
+    }
+    if options.vocabularies {
+        let progress_bar = add_progress_bar();
+        let descriptive_metadata = options.descriptive_metadata.clone();
+        tasks.push(with_progress_bars(
+            "subjects.csv and agents.csv",
+            Box::new(move |objects: &ObjectMap, output: &Output| {
+                vocabularies::csv(objects, output, &descriptive_metadata, progress_bar)
+            }),
+        ));
+    }
+    if let Some(format) = options.citations {
+        let progress_bar = add_progress_bar();
+        let descriptive_metadata = options.descriptive_metadata.clone();
+        tasks.push(with_progress_bars(
+            "citations.csv",
+            Box::new(move |objects: &ObjectMap, output: &Output| {
+                citations::csv(objects, output, format, &descriptive_metadata, progress_bar)
+            }),
+        ));
+    }
+    if options.system_objects {
+        let progress_bar = add_progress_bar();
+        tasks.push(with_progress_bars(
+            "system_objects.csv",
+            Box::new(move |objects: &ObjectMap, output: &Output| {
+                SystemObjectRow::csv(objects, output, progress_bar)
+            }),
+        ));
+    }
 
-    let _objects = objects.clone();
-    let _dest = dest.clone();
-    let progress_bar = multi.add(logger::progress_bar(count));
-    rayon::spawn(move || {
-        MediaRow::csv(&_objects, &_dest, progress_bar);
-    });
+    // Tasks report their result rather than exiting the program should they
+    // fail, so that all failures can be summarized once the others complete,
+    // unless failing fast. Only panics on the threads of the pool the tasks
+    // are installed in are caught, any other still exits the program.
+    let fail_fast = is_fail_fast();
+    let pool = rayon::ThreadPoolBuilder::new()
+        .thread_name(|index| format!("csv-{}", index))
+        .start_handler(|_| logger::set_catching_panics(true))
+        .build()
+        .expect("Failed to create thread pool");
+    let results = std::thread::scope(|scope| {
+        let handles = tasks
+            .into_iter()
+            .map(|(name, progress_bars, task)| {
+                let (objects, output, pool) = (&objects, &output, &pool);
+                let handle = scope.spawn(move || {
+                    if fail_fast {
+                        task(objects, output);
+                        return Ok(());
+                    }
+                    let result = pool.install(|| logger::catch_panic(|| task(objects, output)));
+                    if result.is_err() {
+                        for progress_bar in progress_bars {
+                            progress_bar.finish_and_clear();
+                        }
+                    }
+                    result
+                });
+                (name, handle)
+            })
+            .collect::<Vec<_>>();
 
-    let _objects = objects.clone();
-    let _dest = dest.clone();
-    let progress_bar = multi.add(logger::progress_bar(count));
-    rayon::spawn(move || {
-        MediaRow::revisions_csv(&_objects, &_dest, progress_bar);
-    });
+        // Wait for progress to finish and update the progress bar display.
+        multi.join_and_clear().unwrap();
 
-    let progress_bar = multi.add(logger::progress_bar(count));
-    rayon::spawn(move || {
-        NodeRow::csv(&objects, &dest, progress_bar);
+        handles
+            .into_iter()
+            .map(|(name, handle)| (name, handle.join().unwrap()))
+            .collect::<Vec<_>>()
     });
 
-    // Wait for progress to finish and update the progress bar display.
-    multi.join_and_clear().unwrap();
+    let mut failures = results
+        .into_iter()
+        .filter_map(|(name, result)| result.err().map(|error| format!("{}: {}", name, error)))
+        .collect::<Vec<_>>();
+    if !failures.is_empty() {
+        failures.sort();
+        panic!(
+            "Failed to generate {} file(s):\n\t{}",
+            failures.len(),
+            failures.join("\n\t")
+        );
+    }
+
+    parse_errors::write_report(&output);
+    encoding::write_report(&output);
+    limits::write_report(&output);
+    profile::write_report(&output);
+    timings::write_report(&objects, &output);
+    warnings::write_report(&output);
+    if let Some(previous) = &options.compare_to {
+        compare::write_report(previous, &output);
+    }
+    schema::write(&output);
+    workbook::write(&output);
+    write_completion_marker(&marker, &output.outputs());
+    notify_outputs("tables", &output.outputs());
 }
 
 pub fn execute_scripts(
     input: &Path,
     dest: &Path,
-    scripts: Vec<&Path>,
-    modules: Vec<&Path>,
-    pids: Vec<&str>,
+    pids: Vec<String>,
+    triples: Option<&Path>,
+    mut options: ScriptOptions,
 ) {
-    let objects = ObjectMap::from_path(&input, pids);
-    scripts::run_scripts(objects, scripts, modules, dest);
+    webhook::phase_started("objects");
+    let mut objects = ObjectMap::from_path(&input, pids, triples, &options.pid_map);
+    pages::apply(&mut objects, input);
+    objects.apply_version_policy(options.versions);
+    webhook::phase_finished("objects", &[("objects", objects.inner().len())]);
+    // Nothing is written when previewing.
+    let run_directory = match (&options.run, options.preview) {
+        (Some(run), None) => {
+            let directory = run.directory(dest);
+            run.write_metadata(&directory, &options.scripts, objects.inner().len());
+            Some(directory)
+        }
+        _ => None,
+    };
+    let dest = run_directory.as_deref().unwrap_or(dest);
+    let output = Output::new(dest, std::mem::take(&mut options.output));
+    let report = options.preview.map_or(Some(&output), |_| None);
+    labels::apply(&mut objects, &options.clean_labels, report);
+    output.ids().assign(&objects, report.is_some());
+    scripts::run_scripts(objects, options, &output);
 }
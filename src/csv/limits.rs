@@ -0,0 +1,213 @@
+// Drupal restricts the length of many fields (e.g. titles to 255 characters),
+// values exceeding the configured limits are caught when the tables are
+// written rather than when they are imported.
+use super::output::{Output, Row};
+use log::warn;
+use serde::Serialize;
+use serde_json::Value;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+const ELLIPSIS: char = '…';
+
+// How to handle values which exceed the limit of their column.
+#[derive(Clone, Copy, Debug, Display, EnumString, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum OversizedValuePolicy {
+    Report,   // Leave the value as is.
+    Truncate, // Shorten the value and end it with an ellipsis.
+    Fail,     // Stop once all tables have been written.
+}
+
+// Maximum number of characters of a column, given as `column=N` to apply to
+// every table with that column, or `table.column=N` to apply to a single table.
+#[derive(Debug)]
+pub struct FieldLimit {
+    table: Option<String>,
+    column: String,
+    limit: usize,
+}
+
+impl FromStr for FieldLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+        match (parts.next(), parts.next().map(str::parse::<usize>)) {
+            (Some(key), Some(Ok(limit))) if !key.is_empty() && limit > 0 => {
+                let mut parts = key.rsplitn(2, '.');
+                let column = parts.next().unwrap().to_string();
+                let table = parts.next().map(str::to_string);
+                Ok(FieldLimit {
+                    table,
+                    column,
+                    limit,
+                })
+            }
+            _ => Err(format!(
+                "The field limit '{}' is not of the form column=N or table.column=N",
+                s
+            )),
+        }
+    }
+}
+
+// The limits applied to every table written, and the values found exceeding
+// them.
+pub struct FieldLimits {
+    limits: Vec<FieldLimit>,
+    policy: OversizedValuePolicy,
+    oversized: Mutex<Vec<OversizedValueRow>>,
+}
+
+impl Default for FieldLimits {
+    fn default() -> Self {
+        FieldLimits::new(Vec::new(), OversizedValuePolicy::Report)
+    }
+}
+
+impl FieldLimits {
+    pub fn new(limits: Vec<FieldLimit>, policy: OversizedValuePolicy) -> Self {
+        FieldLimits {
+            limits,
+            policy,
+            oversized: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Table specific limits take precedence.
+    fn limit(&self, table: &str, column: &str) -> Option<usize> {
+        let limits = self.limits.iter().filter(|limit| limit.column == column);
+        let (specific, general): (Vec<_>, Vec<_>) = limits.partition(|limit| limit.table.is_some());
+        specific
+            .into_iter()
+            .find(|limit| limit.table.as_deref() == Some(table))
+            .or_else(|| general.into_iter().next())
+            .map(|limit| limit.limit)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct OversizedValueRow {
+    file: String,
+    row: usize,
+    column: String,
+    length: usize,
+    limit: usize,
+    action: String,
+}
+
+impl Row for OversizedValueRow {
+    fn columns() -> &'static [&'static str] {
+        &["file", "row", "column", "length", "limit", "action"]
+    }
+}
+
+// The limits of the columns of a single table, against which each row is
+// checked as it is written. The table is identified by its file name without
+// extension e.g. `nodes`.
+pub struct TableLimits<'a> {
+    limits: &'a FieldLimits,
+    file: String,
+    // The index, name and limit of each limited column.
+    columns: Vec<(usize, String, usize)>,
+}
+
+impl<'a> TableLimits<'a> {
+    pub fn new(limits: &'a FieldLimits, dest: &Path, header: &[String]) -> Self {
+        let table = dest.file_stem().unwrap().to_string_lossy();
+        TableLimits {
+            limits,
+            file: dest.file_name().unwrap().to_string_lossy().to_string(),
+            columns: header
+                .iter()
+                .enumerate()
+                .filter_map(|(index, column)| {
+                    let limit = limits.limit(&table, column)?;
+                    Some((index, column.clone(), limit))
+                })
+                .collect(),
+        }
+    }
+
+    // Checks the values of the given row (numbered from 1) against the
+    // limits, truncating them if required.
+    pub fn apply(&self, number: usize, row: &mut [Value]) {
+        for (index, column, limit) in &self.columns {
+            if let Some(Value::String(value)) = row.get_mut(*index) {
+                let length = value.chars().count();
+                if length <= *limit {
+                    continue;
+                }
+                let policy = self.limits.policy;
+                if policy == OversizedValuePolicy::Truncate {
+                    *value = truncate(value, *limit);
+                }
+                self.limits
+                    .oversized
+                    .lock()
+                    .unwrap()
+                    .push(OversizedValueRow {
+                        file: self.file.clone(),
+                        row: number,
+                        column: column.clone(),
+                        length,
+                        limit: *limit,
+                        action: policy.to_string(),
+                    });
+            }
+        }
+    }
+}
+
+pub fn truncate(value: &str, limit: usize) -> String {
+    value
+        .chars()
+        .take(limit - 1)
+        .chain(std::iter::once(ELLIPSIS))
+        .collect()
+}
+
+// Writes oversized_values.csv listing all values which exceeded their limit,
+// should be called once all other tables have been written.
+pub fn write_report(output: &Output) {
+    let limits = output.limits();
+    let mut rows = std::mem::take(&mut *limits.oversized.lock().unwrap());
+    rows.sort_by(|a, b| (&a.file, a.row).cmp(&(&b.file, b.row)));
+    output
+        .write_report(&rows, "oversized_values.csv")
+        .expect("Failed to create oversized_values.csv");
+    if rows.is_empty() {
+        return;
+    }
+    warn!(
+        "Found {} values exceeding their field limits, see oversized_values.csv",
+        rows.len()
+    );
+    if limits.policy == OversizedValuePolicy::Fail {
+        panic!(
+            "{} values exceed their field limits, see oversized_values.csv",
+            rows.len()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn field_limits() {
+        let limits = FieldLimits::new(
+            vec!["label=5".parse().unwrap(), "nodes.label=3".parse().unwrap()],
+            OversizedValuePolicy::Truncate,
+        );
+        assert_eq!(limits.limit("nodes", "label"), Some(3));
+        assert_eq!(limits.limit("media", "label"), Some(5));
+        assert_eq!(limits.limit("nodes", "title"), None);
+        assert!("label=0".parse::<FieldLimit>().is_err());
+        assert!("label".parse::<FieldLimit>().is_err());
+        assert_eq!(truncate("Ünïcödé", 4), "Ünï…");
+    }
+}
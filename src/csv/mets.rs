@@ -0,0 +1,199 @@
+// METS documents for preservation, one per object in `mets/<pid>.xml`. The
+// fileSec lists every version of every datastream as migrated, and the
+// structMap lists the latest versions followed by any pages (children with a
+// page / sequence number) in order, which refer to their own METS documents.
+// @see https://www.loc.gov/standards/mets/
+use super::checksums;
+use super::object::{Object, ObjectMap};
+use super::utils::{finish_partial, partial_path};
+use indicatif::ProgressBar;
+use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, Event};
+use quick_xml::Writer;
+use rayon::prelude::*;
+use std::io::Write;
+use std::path::Path;
+use strum::AsStaticRef;
+
+const METS_NS: &str = "http://www.loc.gov/METS/";
+const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+
+type XmlWriter = Writer<Vec<u8>>;
+
+// METS identifiers must be valid XML IDs, which PIDs and version ids are not
+// e.g. 'DC.0', so prefix and replace any invalid characters.
+fn file_id(id: &str) -> String {
+    let id = id
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    format!("FILE_{}", id)
+}
+
+fn start(writer: &mut XmlWriter, name: &str, attributes: &[(&str, &str)]) -> quick_xml::Result<()> {
+    let element =
+        BytesStart::borrowed_name(name.as_bytes()).with_attributes(attributes.iter().copied());
+    writer.write_event(Event::Start(element))?;
+    Ok(())
+}
+
+fn empty(writer: &mut XmlWriter, name: &str, attributes: &[(&str, &str)]) -> quick_xml::Result<()> {
+    let element =
+        BytesStart::borrowed_name(name.as_bytes()).with_attributes(attributes.iter().copied());
+    writer.write_event(Event::Empty(element))?;
+    Ok(())
+}
+
+fn end(writer: &mut XmlWriter, name: &str) -> quick_xml::Result<()> {
+    writer.write_event(Event::End(BytesEnd::borrowed(name.as_bytes())))?;
+    Ok(())
+}
+
+fn file_sec(writer: &mut XmlWriter, object: &Object) -> quick_xml::Result<()> {
+    start(writer, "mets:fileSec", &[])?;
+    for datastream in &object.datastreams {
+        start(writer, "mets:fileGrp", &[("USE", &datastream.id)])?;
+        for version in &datastream.versions {
+            let path = version.path();
+            let id = file_id(&version.id);
+            let created = version.created_date.to_rfc3339();
+            let mut attributes = vec![
+                ("ID", id.as_str()),
+                ("MIMETYPE", version.mime_type.as_str()),
+                ("CREATED", created.as_str()),
+            ];
+            // Missing files are still listed so they can be accounted for.
+            let (size, checksum) = match std::fs::metadata(&path) {
+                Ok(metadata) => (metadata.len().to_string(), checksums::sha1(&path)),
+                Err(_) => (String::new(), String::new()),
+            };
+            if !size.is_empty() {
+                attributes.push(("SIZE", &size));
+                attributes.push(("CHECKSUM", &checksum));
+                attributes.push(("CHECKSUMTYPE", "SHA-1"));
+            }
+            start(writer, "mets:file", &attributes)?;
+            empty(
+                writer,
+                "mets:FLocat",
+                &[
+                    ("LOCTYPE", "OTHER"),
+                    ("OTHERLOCTYPE", "SYSTEM"),
+                    ("xlink:href", &path.to_string_lossy()),
+                ],
+            )?;
+            end(writer, "mets:file")?;
+        }
+        end(writer, "mets:fileGrp")?;
+    }
+    end(writer, "mets:fileSec")
+}
+
+fn struct_map(writer: &mut XmlWriter, object: &Object, pages: &[&Object]) -> quick_xml::Result<()> {
+    start(writer, "mets:structMap", &[("TYPE", "physical")])?;
+    start(
+        writer,
+        "mets:div",
+        &[("TYPE", &object.model), ("LABEL", &object.label)],
+    )?;
+    for datastream in &object.datastreams {
+        empty(
+            writer,
+            "mets:fptr",
+            &[("FILEID", &file_id(&datastream.latest().id))],
+        )?;
+    }
+    for page in pages {
+        let order = page.weight.unwrap().to_string();
+        start(
+            writer,
+            "mets:div",
+            &[
+                ("TYPE", &page.model),
+                ("ORDER", &order),
+                ("LABEL", &page.label),
+            ],
+        )?;
+        empty(
+            writer,
+            "mets:mptr",
+            &[
+                ("LOCTYPE", "OTHER"),
+                ("OTHERLOCTYPE", "SYSTEM"),
+                ("xlink:href", &format!("{}.xml", page.pid)),
+            ],
+        )?;
+        end(writer, "mets:div")?;
+    }
+    end(writer, "mets:div")?;
+    end(writer, "mets:structMap")
+}
+
+fn document(object: &Object, pages: &[&Object]) -> quick_xml::Result<Vec<u8>> {
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 2);
+    writer.write_event(Event::Decl(BytesDecl::new(b"1.0", Some(b"UTF-8"), None)))?;
+    let pid = object.pid.to_string();
+    start(
+        &mut writer,
+        "mets:mets",
+        &[
+            ("xmlns:mets", METS_NS),
+            ("xmlns:xlink", XLINK_NS),
+            ("OBJID", &pid),
+            ("LABEL", &object.label),
+            ("TYPE", &object.model),
+        ],
+    )?;
+    empty(
+        &mut writer,
+        "mets:metsHdr",
+        &[
+            ("CREATEDATE", &object.created_date.to_rfc3339()),
+            ("LASTMODDATE", &object.modified_date.to_rfc3339()),
+            ("RECORDSTATUS", object.state.as_static()),
+        ],
+    )?;
+    file_sec(&mut writer, object)?;
+    struct_map(&mut writer, object, pages)?;
+    end(&mut writer, "mets:mets")?;
+    Ok(writer.into_inner())
+}
+
+pub fn write_mets(objects: &ObjectMap, dest: &Path, progress_bar: ProgressBar) {
+    let directory = dest.join("mets");
+    std::fs::create_dir_all(&directory)
+        .unwrap_or_else(|error| panic!("Failed to create {}: {}", directory.display(), error));
+    let pages = objects.pages();
+    logger::set_length(&progress_bar, objects.inner().len() as u64);
+    objects.objects().for_each(|object| {
+        let pages = pages
+            .get(object.pid.0.as_str())
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let path = directory.join(format!("{}.xml", object.pid));
+        let write = || -> Result<(), String> {
+            let document = document(object, pages).map_err(|error| error.to_string())?;
+            let mut file =
+                std::fs::File::create(partial_path(&path)).map_err(|error| error.to_string())?;
+            file.write_all(&document)
+                .map_err(|error| error.to_string())?;
+            finish_partial(&path).map_err(|error| error.to_string())
+        };
+        write().unwrap_or_else(|error| panic!("Failed to write {}: {}", path.display(), error));
+        progress_bar.inc(1);
+    });
+    progress_bar.finish_with_message("Created METS documents");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_id() {
+        assert_eq!(file_id("DC.0"), "FILE_DC_0");
+        assert_eq!(file_id("RELS-EXT.12"), "FILE_RELS-EXT_12");
+    }
+}
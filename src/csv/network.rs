@@ -0,0 +1,152 @@
+// Optional network access for scripts that need external lookups, such as
+// resolving handles or querying a names authority. Requests are limited to an
+// allow-list of hosts and cached for the duration of the run, as many objects
+// typically refer to the same resources.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use url::Url;
+
+// As many as ureq would follow by default.
+const MAX_REDIRECTS: usize = 5;
+
+pub struct HttpClient {
+    agent: ureq::Agent,
+    hosts: Vec<String>,
+    // Responses by URL, `None` if the resource was not found.
+    cache: Mutex<HashMap<String, Option<String>>>,
+}
+
+impl HttpClient {
+    pub fn new<S: AsRef<str>>(hosts: &[S], timeout: Duration) -> Self {
+        HttpClient {
+            // Redirects are followed by `get()`, so that every host visited
+            // is checked against the allow-list.
+            agent: ureq::AgentBuilder::new()
+                .timeout(timeout)
+                .redirects(0)
+                .build(),
+            hosts: hosts
+                .iter()
+                .map(|host| host.as_ref().to_lowercase())
+                .collect(),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn allowed(&self, url: &Url) -> bool {
+        match url.host_str() {
+            Some(host) => self.hosts.contains(&host.to_lowercase()),
+            None => false,
+        }
+    }
+
+    fn check(&self, url: &Url) -> Result<(), String> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("Unsupported URL scheme: {}", url));
+        }
+        if !self.allowed(url) {
+            return Err(format!(
+                "The host of {} is not allowed, add it to --allow-network",
+                url
+            ));
+        }
+        Ok(())
+    }
+
+    // Fetches the given URL, following redirects to allowed hosts only.
+    fn fetch(&self, mut url: Url) -> Result<Option<String>, String> {
+        for _ in 0..=MAX_REDIRECTS {
+            let response = match self.agent.get(url.as_str()).call() {
+                Ok(response) => response,
+                Err(ureq::Error::Status(404, _)) | Err(ureq::Error::Status(410, _)) => {
+                    return Ok(None)
+                }
+                Err(error) => return Err(format!("Failed to fetch {}: {}", url, error)),
+            };
+            if !(300..400).contains(&response.status()) {
+                return response
+                    .into_string()
+                    .map(Some)
+                    .map_err(|error| format!("Failed to read {}: {}", url, error));
+            }
+            let location = response
+                .header("Location")
+                .ok_or_else(|| format!("Redirect from {} has no location", url))?;
+            url = url
+                .join(location)
+                .map_err(|error| format!("Invalid redirect from {}: {}", url, error))?;
+            self.check(&url)?;
+        }
+        Err(format!("Too many redirects fetching {}", url))
+    }
+
+    // Returns the body of the response, or `None` if the resource does not
+    // exist. Any other failure is an error.
+    pub fn get(&self, url: &str) -> Result<Option<String>, String> {
+        let parsed = Url::parse(url).map_err(|error| format!("Invalid URL {}: {}", url, error))?;
+        self.check(&parsed)?;
+        if let Some(response) = self.cache.lock().unwrap().get(url) {
+            return Ok(response.clone());
+        }
+        let response = self.fetch(parsed)?;
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(url.to_string(), response.clone());
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowed_hosts() {
+        let client = HttpClient::new(&["id.loc.gov"], Duration::from_secs(1));
+        assert!(client.allowed(&Url::parse("https://ID.loc.gov/authorities/names").unwrap()));
+        assert!(!client.allowed(&Url::parse("https://example.com/id.loc.gov").unwrap()));
+        assert!(client.get("https://example.com/").is_err());
+        assert!(client.get("file:///etc/passwd").is_err());
+    }
+
+    // Serves the given responses to successive requests.
+    fn serve(responses: Vec<String>) -> String {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut buffer = [0; 1024];
+                let _ = stream.read(&mut buffer);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    fn redirect(location: &str) -> String {
+        format!(
+            "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+            location
+        )
+    }
+
+    #[test]
+    fn redirects() {
+        let ok = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok".to_string();
+        let client = HttpClient::new(&["127.0.0.1"], Duration::from_secs(5));
+        // Redirects to allowed hosts are followed.
+        let base = serve(vec![redirect("/moved"), ok]);
+        assert_eq!(
+            client.get(&format!("{}/original", base)),
+            Ok(Some("ok".to_string()))
+        );
+        // Those to any other host are not.
+        let base = serve(vec![redirect("http://example.com/")]);
+        let error = client.get(&format!("{}/original", base)).unwrap_err();
+        assert!(error.contains("example.com"), "{}", error);
+    }
+}
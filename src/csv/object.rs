@@ -1,18 +1,24 @@
 #![allow(non_snake_case)]
 #![allow(non_camel_case_types)]
 
+use super::index::{self, Index};
+use super::parse_errors;
+use super::timings::{self, Stage};
 use super::utils::*;
 use chrono::{DateTime, FixedOffset};
+use foxml::mime_types::normalize_mime_type;
+use foxml::pids::PidMap;
 use foxml::*;
-use log::{error, info};
+use log::{info, warn, Level};
 use quick_xml::events::attributes::Attribute;
 use quick_xml::events::{BytesStart, Event};
 use quick_xml::Reader;
 use rayon::prelude::*;
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use std::boxed::Box;
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
 use std::fs::File;
 use std::hash::{Hash, Hasher};
@@ -26,9 +32,23 @@ lazy_static! {
         m.insert("fedoraAdmin", "admin");
         m
     };
+    // Matches a single N-Triples statement, capturing the subject, predicate & object.
+    // Objects are either a resource (IRI) or a literal, datatypes & language tags are ignored.
+    static ref TRIPLE: Regex = Regex::new(
+        r#"^<info:fedora/([^>/]+)>\s+<([^>]+)>\s+(?:<(?:info:fedora/)?([^>]*)>|"((?:[^"\\]|\\.)*)"(?:\^\^<[^>]*>|@[A-Za-z0-9-]+)?)\s*\.\s*$"#
+    )
+    .unwrap();
 }
 
-#[derive(Clone, Debug, Eq)]
+// Maps the given Fedora user to the appropriate Drupal user if applicable.
+pub fn map_user(user: &str) -> String {
+    USER_MAP
+        .get(user)
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| user.to_string())
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Serialize)]
 pub struct Pid(pub String);
 
 impl Pid {
@@ -36,6 +56,22 @@ impl Pid {
         // Only use for Foxml files expected. eg. 'namespace:123.xml'
         Pid(path.file_stem().unwrap().to_string_lossy().to_string())
     }
+
+    // The namespace e.g. 'archden' of 'archden:463'.
+    pub fn namespace(&self) -> &str {
+        self.0.split(':').next().unwrap_or_default()
+    }
+
+    // Everything after the namespace e.g. '463' of 'archden:463', which may
+    // itself contain colons.
+    pub fn local_id(&self) -> &str {
+        self.0.split_once(':').map_or("", |(_, id)| id)
+    }
+
+    // The local id if it is numeric.
+    pub fn number(&self) -> Option<i64> {
+        self.local_id().parse().ok()
+    }
 }
 
 impl Hash for Pid {
@@ -74,7 +110,7 @@ impl PartialEq for Pid {
     }
 }
 
-#[derive(AsStaticStr, Clone, Debug, Display, Eq, PartialEq)]
+#[derive(AsStaticStr, Clone, Debug, Deserialize, Display, Eq, PartialEq, Serialize)]
 pub enum ObjectState {
     Active,
     Inactive,
@@ -91,7 +127,7 @@ impl From<FoxmlObjectState> for ObjectState {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
 pub enum DatastreamState {
     Active,
     Inactive,
@@ -108,35 +144,140 @@ impl From<FoxmlDatastreamState> for DatastreamState {
     }
 }
 
-#[derive(Clone, Debug, Eq)]
+// Where the content of a datastream version is kept, Fedora stores Managed (M)
+// and Inline XML (X) content, whereas External (E) and Redirect (R) content is
+// only referenced by URL.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum DatastreamContent {
+    #[default]
+    Stored,
+    External(String),
+    Redirect(String),
+}
+
+impl DatastreamContent {
+    pub fn new(control_group: &FoxmlControlGroup, version: &FoxmlDatastreamVersion) -> Self {
+        let url = || {
+            version
+                .content_location()
+                .map_or_else(String::new, |location| location.r#ref.clone())
+        };
+        match control_group {
+            FoxmlControlGroup::E => DatastreamContent::External(url()),
+            FoxmlControlGroup::R => DatastreamContent::Redirect(url()),
+            FoxmlControlGroup::M | FoxmlControlGroup::X => DatastreamContent::Stored,
+        }
+    }
+
+    // Empty for stored content, as used by the 'remote' column of files.csv
+    // and media.csv.
+    pub fn remote(&self) -> &'static str {
+        match self {
+            DatastreamContent::Stored => "",
+            DatastreamContent::External(_) => "external",
+            DatastreamContent::Redirect(_) => "redirect",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Serialize)]
 pub struct DatastreamVersion {
     pub pid: String,
     pub dsid: String,
     pub id: String,
     pub label: String,
     pub created_date: DateTime<FixedOffset>,
+    // Normalized, see `foxml::mime_types`.
     pub mime_type: String,
+    // As given in the FOXML if it differs from the normalized mime type.
+    pub original_mime_type: Option<String>,
+    pub renamed: Option<String>,
+    // As given in the FOXML if the label was cleaned, see `labels::apply()`.
+    #[serde(default)]
+    pub original_label: Option<String>,
+    #[serde(default)]
+    pub content: DatastreamContent,
+    // Format registry identifier e.g. 'info:pronom/fmt/18', if any.
+    #[serde(default)]
+    pub format_uri: Option<String>,
+    #[serde(default)]
+    pub alt_ids: Vec<String>,
 }
 
 impl DatastreamVersion {
     pub fn new(pid: String, dsid: String, version: FoxmlDatastreamVersion) -> Self {
+        let alt_ids = version
+            .alternate_ids()
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        let (mime_type, _) = normalize_mime_type(&version.mime_type);
+        let original_mime_type = if mime_type != version.mime_type {
+            Some(version.mime_type)
+        } else {
+            None
+        };
         DatastreamVersion {
             pid,
             dsid,
             id: version.id,
             label: version.label,
             created_date: version.created,
-            mime_type: version.mime_type,
+            mime_type,
+            original_mime_type,
+            renamed: None,
+            original_label: None,
+            content: DatastreamContent::Stored,
+            format_uri: version.format,
+            alt_ids,
         }
     }
 
+    // The URL of External and Redirect content, which has no file on disk.
+    pub fn url(&self) -> Option<&str> {
+        match &self.content {
+            DatastreamContent::Stored => None,
+            DatastreamContent::External(url) | DatastreamContent::Redirect(url) => Some(url),
+        }
+    }
+
+    // The name of the file on disk, labels that are file names are used as
+    // given in the FOXML, as the `migrate` command did.
     pub fn file_name(&self) -> String {
-        foxml::extensions::version_file_name(&self.pid, &self.id, &self.label, &self.mime_type)
+        let label = self.original_label.as_ref().unwrap_or(&self.label);
+        foxml::extensions::version_file_name(&self.pid, &self.id, label, &self.mime_type)
+    }
+
+    // The name presented to Drupal, may differ from the file name on disk if
+    // it was renamed to avoid a collision or its label was cleaned.
+    pub fn name(&self) -> String {
+        self.renamed.clone().unwrap_or_else(|| {
+            foxml::extensions::version_file_name(&self.pid, &self.id, &self.label, &self.mime_type)
+        })
+    }
+
+    // The name split into its basename and extension e.g. ('scan', 'pdf'), the
+    // extension is empty if it has none.
+    pub fn basename_and_extension(&self) -> (String, String) {
+        let name = self.name();
+        match name.rsplit_once('.') {
+            Some((basename, extension)) if !basename.is_empty() => {
+                (basename.to_string(), extension.to_string())
+            }
+            _ => (name, String::new()),
+        }
     }
 
     pub fn path(&self) -> PathBuf {
-        let lock = super::DATASTREAMS_DIRECTORY.read().unwrap();
-        let root = lock.as_ref().unwrap();
+        let root = match super::volume(&self.pid) {
+            Some((_, volume)) => volume.join("datastreams"),
+            None => super::DATASTREAMS_DIRECTORY
+                .read()
+                .unwrap()
+                .as_ref()
+                .unwrap()
+                .to_path_buf(),
+        };
         root.join(&self.pid)
             .join(&self.dsid)
             .join(&self.id)
@@ -144,9 +285,26 @@ impl DatastreamVersion {
     }
 }
 
+impl DatastreamVersion {
+    // Orders versions by when they were created, falling back to the version
+    // id should they share the same timestamp.
+    pub fn cmp_by_created(&self, other: &Self) -> Ordering {
+        self.created_date
+            .cmp(&other.created_date)
+            .then_with(|| self.cmp_by_id(other))
+    }
+
+    // Orders versions alphanumerically by their id e.g. DSID.9 before DSID.10.
+    pub fn cmp_by_id(&self, other: &Self) -> Ordering {
+        alphanumeric_sort::compare_str(&self.id, &other.id)
+    }
+}
+
+// Revisions are created in Drupal in this order so it must reflect when the
+// versions were created, not how they were named.
 impl Ord for DatastreamVersion {
     fn cmp(&self, other: &Self) -> Ordering {
-        alphanumeric_sort::compare_str(&self.id, &other.id)
+        self.cmp_by_created(other)
     }
 }
 
@@ -164,7 +322,7 @@ impl PartialEq for DatastreamVersion {
     }
 }
 
-#[derive(Clone, Debug, Eq)]
+#[derive(Clone, Debug, Deserialize, Eq, Serialize)]
 pub struct Datastream {
     pub id: String,
     pub state: DatastreamState,
@@ -213,7 +371,7 @@ impl From<quick_xml::Error> for RelsExtError {
     }
 }
 
-#[derive(Debug, Default, PartialEq)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
 pub struct RelsExt {
     pub about: String,
     // Fedora Model Rels-Ext Ontology
@@ -257,6 +415,8 @@ pub struct RelsExt {
 impl RelsExt {
     // Strip the prefix off of applicable values.
     const PREFIX_LENGTH: usize = "info:fedora/".len();
+    // Compound predicates are suffixed with the parent PID e.g. isSequenceNumberOfnamespace_123.
+    const SEQUENCE_NUMBER_OF: &'static str = "isSequenceNumberOf";
 
     pub fn from_reader<B>(mut reader: Reader<B>) -> Result<Self, RelsExtError>
     where
@@ -292,169 +452,195 @@ impl RelsExt {
         Ok(RelsExt::from_reader(reader)?)
     }
 
+    // Parses an N-Triples dump of the Fedora resource index (Mulgara) into
+    // relationships grouped by the subject PID. Statements about datastreams
+    // or with unknown predicates are ignored.
+    pub fn from_triples(path: &Path) -> Result<Triples, RelsExtError> {
+        let file = File::open(path)?;
+        Self::from_triples_reader(BufReader::new(file))
+    }
+
+    fn from_triples_reader<B>(reader: B) -> Result<Triples, RelsExtError>
+    where
+        B: BufRead,
+    {
+        let mut triples = Triples::new();
+        for line in reader.lines() {
+            let line = line?;
+            if let Some(captures) = TRIPLE.captures(line.trim()) {
+                let pid = &captures[1];
+                let predicate = &captures[2];
+                let predicate = predicate
+                    .rsplit(&['#', '/'][..])
+                    .next()
+                    .unwrap_or(predicate);
+                let value = match (captures.get(3), captures.get(4)) {
+                    (Some(resource), _) => resource.as_str().to_string(),
+                    (_, Some(literal)) => Self::unescape_literal(literal.as_str()),
+                    _ => continue,
+                };
+                // Every object has this model, it is never the one we are interested in.
+                if predicate == "hasModel" && value == "fedora-system:FedoraObject-3.0" {
+                    continue;
+                }
+                let rels_ext = triples.entry(pid.to_string()).or_insert_with(|| RelsExt {
+                    about: pid.to_string(),
+                    ..RelsExt::default()
+                });
+                rels_ext.insert(predicate, value);
+            }
+        }
+        Ok(triples)
+    }
+
+    // Merges the given relationships into this one, multi-valued
+    // relationships are combined, single valued ones are overridden.
+    pub fn merge(&mut self, other: &RelsExt) {
+        fn extend(to: &mut Vec<String>, from: &[String]) {
+            for value in from {
+                if !to.contains(value) {
+                    to.push(value.clone());
+                }
+            }
+        }
+        extend(&mut self.hasModel, &other.hasModel);
+        extend(&mut self.fedoraRelationship, &other.fedoraRelationship);
+        extend(&mut self.hasAnnotation, &other.hasAnnotation);
+        extend(&mut self.hasCollectionMember, &other.hasCollectionMember);
+        extend(&mut self.hasConstituent, &other.hasConstituent);
+        extend(&mut self.hasDependent, &other.hasDependent);
+        extend(&mut self.hasDerivation, &other.hasDerivation);
+        extend(&mut self.hasDescription, &other.hasDescription);
+        extend(&mut self.hasEquivalent, &other.hasEquivalent);
+        extend(&mut self.hasMember, &other.hasMember);
+        extend(&mut self.hasMetadata, &other.hasMetadata);
+        extend(&mut self.hasPart, &other.hasPart);
+        extend(&mut self.hasSubset, &other.hasSubset);
+        extend(&mut self.isAnnotationOf, &other.isAnnotationOf);
+        extend(&mut self.isConstituentOf, &other.isConstituentOf);
+        extend(&mut self.isDependentOf, &other.isDependentOf);
+        extend(&mut self.isDerivationOf, &other.isDerivationOf);
+        extend(&mut self.isDescriptionOf, &other.isDescriptionOf);
+        extend(&mut self.isMemberOf, &other.isMemberOf);
+        extend(&mut self.isMemberOfCollection, &other.isMemberOfCollection);
+        extend(&mut self.isMetadataFor, &other.isMetadataFor);
+        extend(&mut self.isPartOf, &other.isPartOf);
+        extend(&mut self.isSubsetOf, &other.isSubsetOf);
+        self.deferDerivatives = other.deferDerivatives.or(self.deferDerivatives);
+        self.generateHOCR = other.generateHOCR.or(self.generateHOCR);
+        self.generateOCR = other.generateOCR.or(self.generateOCR);
+        self.isPageNumber = other.isPageNumber.or(self.isPageNumber);
+        self.isPageOf = other.isPageOf.clone().or_else(|| self.isPageOf.take());
+        self.isSection = other.isSection.or(self.isSection);
+        self.isSequenceNumber = other.isSequenceNumber.or(self.isSequenceNumber);
+        for (pid, weight) in &other.isSequenceNumberOf {
+            self.isSequenceNumberOf
+                .retain(|(existing, _)| existing != pid);
+            self.isSequenceNumberOf.push((pid.clone(), *weight));
+        }
+    }
+
+    fn unescape_literal(literal: &str) -> String {
+        literal
+            .replace("\\\"", "\"")
+            .replace("\\n", "\n")
+            .replace("\\t", "\t")
+            .replace("\\\\", "\\")
+    }
+
     fn process_element<B>(rels_ext: &mut RelsExt, mut reader: &mut Reader<B>, element: &BytesStart)
     where
         B: BufRead,
     {
+        let predicate = std::str::from_utf8(element.local_name())
+            .unwrap()
+            .to_string();
         match element.name() {
+            b"rdf:RDF" => (),
             b"rdf:Description" => {
                 rels_ext.about = Self::get_attribute_without_prefix(&element, b"rdf:about");
             }
-            // Fedora Model Rels-Ext Ontology
-            b"fedora-model:hasModel" => {
-                rels_ext
-                    .hasModel
-                    .push(Self::get_resource_attribute(&element));
-            }
-            // Fedora Rels-Ext Ontology
-            b"fedora:fedoraRelationship" => {
-                rels_ext
-                    .fedoraRelationship
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:isPartOf" => {
-                rels_ext
-                    .isPartOf
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:hasPart" => {
-                rels_ext
-                    .hasPart
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:isConstituentOf" => {
-                rels_ext
-                    .isConstituentOf
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:hasConstituent" => {
-                rels_ext
-                    .hasConstituent
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:isMemberOf" => {
-                rels_ext
-                    .isMemberOf
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:hasMember" => {
-                rels_ext
-                    .hasMember
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:isSubsetOf" => {
-                rels_ext
-                    .isSubsetOf
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:hasSubset" => {
-                rels_ext
-                    .hasSubset
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:isMemberOfCollection" => {
-                rels_ext
-                    .isMemberOfCollection
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:hasCollectionMember" => {
-                rels_ext
-                    .hasCollectionMember
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:isDerivationOf" => {
-                rels_ext
-                    .isDerivationOf
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:hasDerivation" => {
-                rels_ext
-                    .hasDerivation
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:isDependentOf" => {
-                rels_ext
-                    .isDependentOf
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:hasDependent" => {
-                rels_ext
-                    .hasDependent
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:isDescriptionOf" => {
-                rels_ext
-                    .isDescriptionOf
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:hasDescription" => {
-                rels_ext
-                    .hasDescription
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:isMetadataFor" => {
-                rels_ext
-                    .isMetadataFor
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:hasMetadata" => {
-                rels_ext
-                    .hasMetadata
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:isAnnotationOf" => {
-                rels_ext
-                    .isAnnotationOf
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:hasAnnotation" => {
-                rels_ext
-                    .hasAnnotation
-                    .push(Self::get_resource_attribute(&element));
-            }
-            b"fedora:hasEquivalent" => {
-                rels_ext
-                    .hasEquivalent
-                    .push(Self::get_resource_attribute(&element));
-            }
-            // Islandora Rels-Ext Ontology
-            b"islandora:deferDerivatives" => {
-                let text = Self::get_text(&mut reader).to_lowercase();
-                rels_ext.deferDerivatives = Some(text.parse().unwrap());
-            }
-            b"islandora:generate_hocr" => {
-                let text = Self::get_text(&mut reader).to_lowercase();
-                rels_ext.generateHOCR = Some(text.parse().unwrap());
-            }
-            b"islandora:generate_ocr" => {
-                let text = Self::get_text(&mut reader).to_lowercase();
-                rels_ext.generateOCR = Some(text.parse().unwrap());
-            }
-            b"islandora:isPageNumber" => {
-                let text = Self::get_text(&mut reader);
-                rels_ext.isPageNumber = Self::parse_integer(text);
-            }
-            b"islandora:isPageOf" => {
-                let attribute = Self::get_resource_attribute(&element);
-                rels_ext.isPageOf = Some(attribute);
-            }
-            b"islandora:isSection" => {
-                let text = Self::get_text(&mut reader);
-                rels_ext.isSection = Self::parse_integer(text);
-            }
-            b"islandora:isSequenceNumber" => {
+            // Islandora Rels-Ext Ontology literals.
+            b"islandora:deferDerivatives"
+            | b"islandora:generate_hocr"
+            | b"islandora:generate_ocr"
+            | b"islandora:isPageNumber"
+            | b"islandora:isSection"
+            | b"islandora:isSequenceNumber" => {
                 let text = Self::get_text(&mut reader);
-                rels_ext.isSequenceNumber = Self::parse_integer(text);
+                rels_ext.insert(&predicate, text);
             }
             _ => {
-                // Compounds are weird.
-                if let Some(sequence_number) = Self::is_sequence_number_of(&mut reader, &element) {
-                    rels_ext.isSequenceNumberOf.push(sequence_number);
+                if Self::get_attribute(&element, b"rdf:resource").is_some() {
+                    rels_ext.insert(&predicate, Self::get_resource_attribute(&element));
+                } else if predicate.starts_with(Self::SEQUENCE_NUMBER_OF) {
+                    // Compounds are weird.
+                    let text = Self::get_text(&mut reader);
+                    rels_ext.insert(&predicate, text);
                 }
             }
         };
     }
 
+    // Adds the value of the given predicate (local-name only) to the
+    // appropriate field, unknown predicates are ignored.
+    fn insert(&mut self, predicate: &str, value: String) {
+        match predicate {
+            // Fedora Model Rels-Ext Ontology
+            "hasModel" => self.hasModel.push(value),
+            // Fedora Rels-Ext Ontology
+            "fedoraRelationship" => self.fedoraRelationship.push(value),
+            "isPartOf" => self.isPartOf.push(value),
+            "hasPart" => self.hasPart.push(value),
+            "isConstituentOf" => self.isConstituentOf.push(value),
+            "hasConstituent" => self.hasConstituent.push(value),
+            "isMemberOf" => self.isMemberOf.push(value),
+            "hasMember" => self.hasMember.push(value),
+            "isSubsetOf" => self.isSubsetOf.push(value),
+            "hasSubset" => self.hasSubset.push(value),
+            "isMemberOfCollection" => self.isMemberOfCollection.push(value),
+            "hasCollectionMember" => self.hasCollectionMember.push(value),
+            "isDerivationOf" => self.isDerivationOf.push(value),
+            "hasDerivation" => self.hasDerivation.push(value),
+            "isDependentOf" => self.isDependentOf.push(value),
+            "hasDependent" => self.hasDependent.push(value),
+            "isDescriptionOf" => self.isDescriptionOf.push(value),
+            "hasDescription" => self.hasDescription.push(value),
+            "isMetadataFor" => self.isMetadataFor.push(value),
+            "hasMetadata" => self.hasMetadata.push(value),
+            "isAnnotationOf" => self.isAnnotationOf.push(value),
+            "hasAnnotation" => self.hasAnnotation.push(value),
+            "hasEquivalent" => self.hasEquivalent.push(value),
+            // Islandora Rels-Ext Ontology
+            "deferDerivatives" => self.deferDerivatives = self.parse_boolean(predicate, value),
+            "generate_hocr" => self.generateHOCR = self.parse_boolean(predicate, value),
+            "generate_ocr" => self.generateOCR = self.parse_boolean(predicate, value),
+            "isPageNumber" => self.isPageNumber = Self::parse_integer(value),
+            "isPageOf" => self.isPageOf = Some(value),
+            "isSection" => self.isSection = Self::parse_integer(value),
+            "isSequenceNumber" => self.isSequenceNumber = Self::parse_integer(value),
+            _ => {
+                if let Some(pid) = predicate.strip_prefix(Self::SEQUENCE_NUMBER_OF) {
+                    let pid = pid.replacen("_", ":", 1);
+                    let weight = Self::parse_integer(value).unwrap_or(0);
+                    self.isSequenceNumberOf.push((pid, weight));
+                }
+            }
+        }
+    }
+
+    // Invalid values are skipped rather than failing the whole object.
+    fn parse_boolean(&self, predicate: &str, text: String) -> Option<bool> {
+        let boolean = text.trim().to_lowercase().parse().ok();
+        if boolean.is_none() {
+            warn!(
+                "Ignoring invalid {} value '{}' in the RELS-EXT of {}",
+                predicate, text, self.about
+            );
+        }
+        boolean
+    }
+
     fn parse_integer(text: String) -> Option<isize> {
         let re = Regex::new(r"[^0-9]").unwrap();
         re.replace_all(&text, "").parse().ok()
@@ -494,31 +680,20 @@ impl RelsExt {
             }
         }
     }
+}
 
-    // Compounds.
-    fn is_sequence_number_of<B>(
-        mut reader: &mut Reader<B>,
-        element: &BytesStart,
-    ) -> Option<(String, isize)>
-    where
-        B: BufRead,
-    {
-        let name = std::str::from_utf8(element.local_name())
-            .unwrap()
-            .to_string();
-        let predicate = "isSequenceNumberOf";
-        if name.starts_with(predicate) {
-            let pid = &name[predicate.len()..];
-            let pid = pid.replacen("_", ":", 1);
-            let text = Self::get_text(&mut reader);
-            Some((pid, Self::parse_integer(text).unwrap_or(0)))
-        } else {
-            None
-        }
-    }
+// Relationships from the resource index grouped by subject PID.
+pub type Triples = HashMap<String, RelsExt>;
+
+// A relationship to a parent object, as the parents alone do not distinguish
+// collection membership from compound constituency, etc.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct Relationship {
+    pub predicate: String,
+    pub target: String,
 }
 
-#[derive(Clone, Debug, Eq)]
+#[derive(Clone, Debug, Deserialize, Eq, Serialize)]
 pub struct Object {
     pub pid: Pid,
     pub state: ObjectState,
@@ -526,6 +701,7 @@ pub struct Object {
     pub label: String,
     pub model: String,
     pub parents: Vec<String>,
+    pub relationships: Vec<Relationship>,
     pub created_date: DateTime<FixedOffset>,
     pub modified_date: DateTime<FixedOffset>,
     pub datastreams: Vec<Datastream>,
@@ -533,61 +709,77 @@ pub struct Object {
 }
 
 impl Object {
-    pub fn new(foxml: Foxml) -> Self {
-        let pid = foxml.pid.clone();
+    // The PID map is applied here to the object and the targets of its
+    // relationships, the FOXML is parsed as is.
+    pub fn new(
+        foxml: Foxml,
+        triples: Option<&RelsExt>,
+        pid_map: &PidMap,
+    ) -> Result<Self, FoxmlError> {
+        let pid = pid_map.rewrite(&foxml.pid);
         let mut object = Object {
-            pid: Pid(foxml.pid.to_owned()),
-            // Map to the appropriate Drupal user if applicable.
-            owner: USER_MAP
-                .get(&foxml.properties.owner_id().as_str())
-                .map(|s| s.to_string())
-                .unwrap_or_else(|| foxml.properties.owner_id()),
-            label: foxml.properties.label(),
+            pid: Pid(pid.clone()),
+            owner: map_user(&foxml.properties.owner_id()?),
+            label: foxml.properties.label()?,
             model: "".to_string(),
             parents: vec![],
+            relationships: vec![],
             weight: None,
-            created_date: foxml.properties.created_date(),
-            modified_date: foxml.properties.modified_date(),
-            state: foxml.properties.state().into(),
+            created_date: foxml.properties.created_date()?,
+            modified_date: foxml.properties.modified_date()?,
+            state: foxml.properties.state()?.into(),
             datastreams: {
                 let mut datastreams = foxml
                     .datastreams
                     .into_iter()
-                    .map(move |datastream| match datastream.control_group {
-                        FoxmlControlGroup::E | FoxmlControlGroup::R => unimplemented!(),
-                        FoxmlControlGroup::M | FoxmlControlGroup::X => {
-                            Object::create_datastream(&pid, datastream)
-                        }
-                    })
+                    .map(move |datastream| Object::create_datastream(&pid, datastream))
                     .collect::<Vec<Datastream>>();
                 datastreams.sort_by(|a, b| a.partial_cmp(b).unwrap());
                 datastreams
             },
         };
-        if let Some(rels_ext) = object.rels_ext() {
+        let rels_ext = match (object.rels_ext(), triples) {
+            (Some(mut rels_ext), Some(triples)) => {
+                rels_ext.merge(triples);
+                Some(rels_ext)
+            }
+            (None, Some(triples)) => Some(triples.clone()),
+            (rels_ext, None) => rels_ext,
+        };
+        if let Some(rels_ext) = rels_ext {
             object.model = Object::model(&rels_ext);
-            object.parents = Object::parents(&rels_ext);
+            object.relationships = Object::relationships(&rels_ext, pid_map);
+            object.parents = Object::parents(&object.relationships);
             object.weight = Object::weight(&rels_ext);
         } else {
             // No RELS-EXT.
             object.model = String::from("");
             object.parents = vec![];
+            object.relationships = vec![];
             object.weight = None;
         }
-        object
+        Ok(object)
     }
 
-    pub fn from_path(path: &Path) -> Option<Self> {
-        let foxml = std::fs::read_to_string(&path)
-            .unwrap_or_else(|_| panic!("Failed to read file: {}", &path.to_string_lossy()));
-        let result = Foxml::new(&foxml);
+    // Only the metadata is read, the content of inline datastreams is taken from
+    // the migrated files, so large objects need not be held in memory.
+    // Resource index triples are keyed by the PID before it is mapped.
+    pub fn from_path(path: &Path, triples: Option<&Triples>, pid_map: &PidMap) -> Option<Self> {
+        let result = Foxml::metadata_from_path(path).and_then(|foxml| {
+            let triples = triples.and_then(|triples| triples.get(&foxml.pid));
+            Object::new(foxml, triples, pid_map)
+        });
         match result {
-            Ok(foxml) => Some(Object::new(foxml)),
+            Ok(object) => Some(object),
             Err(err) => {
-                error!(
-                    "Failed to parse file: {}, with error: {}",
-                    &path.to_string_lossy(),
-                    err
+                parse_errors::record(path, &err);
+                report_listed_problem(
+                    Level::Error,
+                    &format!(
+                        "Failed to parse file: {}, with error: {}",
+                        &path.to_string_lossy(),
+                        err
+                    ),
                 );
                 None
             }
@@ -606,6 +798,12 @@ impl Object {
         self.model == "fedora-system:ContentModel-3.0"
     }
 
+    // Service definitions & deployments, i.e. disseminators.
+    pub fn is_service_object(&self) -> bool {
+        self.model == "fedora-system:ServiceDefinition-3.0"
+            || self.model == "fedora-system:ServiceDeployment-3.0"
+    }
+
     fn model(rels_ext: &RelsExt) -> String {
         if rels_ext.hasModel.is_empty() {
             dbg!(&rels_ext);
@@ -613,29 +811,35 @@ impl Object {
         rels_ext.hasModel.first().unwrap().into()
     }
 
-    fn parents(rels_ext: &RelsExt) -> Vec<String> {
+    fn relationships(rels_ext: &RelsExt, pid_map: &PidMap) -> Vec<Relationship> {
         // isSequenceNumberOf relationship is covered by isConstituentOf.
-        let parents = vec![
-            &rels_ext.isPartOf,
-            &rels_ext.isConstituentOf,
-            &rels_ext.isMemberOf,
-            &rels_ext.isSubsetOf,
-            &rels_ext.isMemberOfCollection,
-            &rels_ext.isDerivationOf,
-            &rels_ext.isDependentOf,
-            &rels_ext.isDescriptionOf,
-            &rels_ext.isMetadataFor,
-            &rels_ext.isAnnotationOf,
+        let relationships = vec![
+            ("isPartOf", &rels_ext.isPartOf),
+            ("isConstituentOf", &rels_ext.isConstituentOf),
+            ("isMemberOf", &rels_ext.isMemberOf),
+            ("isSubsetOf", &rels_ext.isSubsetOf),
+            ("isMemberOfCollection", &rels_ext.isMemberOfCollection),
+            ("isDerivationOf", &rels_ext.isDerivationOf),
+            ("isDependentOf", &rels_ext.isDependentOf),
+            ("isDescriptionOf", &rels_ext.isDescriptionOf),
+            ("isMetadataFor", &rels_ext.isMetadataFor),
+            ("isAnnotationOf", &rels_ext.isAnnotationOf),
         ];
-        let size = parents.iter().fold(0, |a, b| a + b.len());
-        let mut parents = parents
+        relationships
             .into_iter()
-            .fold(Vec::with_capacity(size), |mut acc, v| {
-                acc.extend(v.clone());
-                acc
+            .flat_map(|(predicate, targets)| {
+                targets.iter().map(move |target| Relationship {
+                    predicate: predicate.to_string(),
+                    target: pid_map.rewrite(target),
+                })
             })
-            .into_iter()
-            .map(|parent| parent)
+            .collect()
+    }
+
+    fn parents(relationships: &[Relationship]) -> Vec<String> {
+        let mut parents = relationships
+            .iter()
+            .map(|relationship| relationship.target.clone())
             .collect::<Vec<String>>();
         parents.sort_by(|a, b| alphanumeric_sort::compare_str(&a, &b));
         parents
@@ -667,6 +871,23 @@ impl Object {
         }
     }
 
+    // Gets every version of the requested datastream, oldest first.
+    pub fn datastream_versions<'a>(&'a self, datastream_id: &str) -> &'a [DatastreamVersion] {
+        self.datastreams
+            .iter()
+            .find(|datastream| datastream.id == datastream_id)
+            .map_or(&[], |datastream| datastream.versions.as_slice())
+    }
+
+    // Gets the latest version of the first datastream found in the given
+    // priority list, as some repositories store MODS under other DSIDs.
+    pub fn descriptive_metadata<'a, S>(&'a self, dsids: &[S]) -> Option<&'a DatastreamVersion>
+    where
+        S: AsRef<str>,
+    {
+        dsids.iter().find_map(|dsid| self.datastream(dsid.as_ref()))
+    }
+
     fn rels_ext(&self) -> Option<RelsExt> {
         let rels_ext = self
             .datastreams
@@ -674,7 +895,7 @@ impl Object {
             .find(|&datastream| datastream.id == "RELS-EXT");
         if let Some(datastream) = rels_ext {
             let latest_version = datastream.versions.last().unwrap();
-            Some(RelsExt::from_path(&latest_version.path()).expect("Failed to parse RELS-EXT"))
+            Some(index::rels_ext(&latest_version.path()).expect("Failed to parse RELS-EXT"))
         } else {
             None
         }
@@ -682,6 +903,7 @@ impl Object {
 
     fn create_datastream(pid: &str, datastream: FoxmlDatastream) -> Datastream {
         let dsid = datastream.id.clone();
+        let control_group = datastream.control_group;
         Datastream {
             id: datastream.id,
             state: datastream.state.into(),
@@ -690,7 +912,11 @@ impl Object {
                     .versions
                     .into_iter()
                     .map(move |version| {
-                        DatastreamVersion::new(pid.to_string(), dsid.clone(), version)
+                        let content = DatastreamContent::new(&control_group, &version);
+                        DatastreamVersion {
+                            content,
+                            ..DatastreamVersion::new(pid.to_string(), dsid.clone(), version)
+                        }
                     })
                     .collect::<Vec<DatastreamVersion>>();
                 result.sort_by(|a, b| a.partial_cmp(b).unwrap());
@@ -718,9 +944,11 @@ impl PartialEq for Object {
     }
 }
 
-// Sorted map of pids to objects.
+// Sorted map of pids to objects, along with the system objects & content
+// models which are not migrated, the pids of objects in other shards, and the
+// PID map the objects were loaded with.
 pub type ObjectMapInner = BTreeMap<Pid, Object>;
-pub struct ObjectMap(ObjectMapInner);
+pub struct ObjectMap(ObjectMapInner, ObjectMapInner, HashSet<String>, PidMap);
 
 pub trait VersionIterator<'a>:
     ParallelIterator<Item = (&'a Object, &'a Datastream, &'a DatastreamVersion)>
@@ -733,41 +961,164 @@ impl<'a, T: ParallelIterator<Item = (&'a Object, &'a Datastream, &'a DatastreamV
 }
 
 impl ObjectMap {
-    pub fn from_path(input: &Path, limit_to_pids: Vec<&str>) -> Self {
-        let object_paths = Self::object_files(&input, limit_to_pids);
-        info!("Parsing object files");
-        let progress_bar = logger::progress_bar(object_paths.len() as u64);
-        let inner = object_paths
-            .par_iter()
-            .filter_map(|path| {
-                progress_bar.inc(1);
-                match Object::from_path(&path) {
-                    Some(object) => {
-                        // Ignore system objects & content models.
-                        if !(object.is_system_object()
-                            || object.is_content_model()
-                            || object.missing_content_model())
-                        {
-                            Some((object.pid.clone(), object))
-                        } else {
-                            None
-                        }
-                    }
-                    None => None,
-                }
+    pub fn from_path(
+        input: &Path,
+        limit_to_pids: Vec<String>,
+        triples: Option<&Path>,
+        pid_map: &PidMap,
+    ) -> Self {
+        let triples = triples.map(|path| {
+            info!("Parsing resource index triples");
+            RelsExt::from_triples(path).expect("Failed to parse resource index triples")
+        });
+        // The index only ever covers every object file.
+        let limited = !limit_to_pids.is_empty() || foxml::pids::shard().is_some();
+        let (object_paths, not_loaded) =
+            Self::partition_object_files(Self::object_files(input), &limit_to_pids);
+        // Relationships in the resource index are merged as objects are parsed,
+        // so the index cannot be used with them.
+        let mut index = match triples {
+            Some(_) => None,
+            None => Some(Index::load(input, pid_map)),
+        };
+        let object_paths = object_paths
+            .into_iter()
+            .map(|path| {
+                let object = index.as_mut().and_then(|index| index.take(&path));
+                (path, object)
+            })
+            .collect::<Vec<_>>();
+        let indexed = object_paths.iter().filter(|(_, o)| o.is_some()).count();
+        info!("Parsing {} object files", object_paths.len() - indexed);
+        if object_paths.len() > indexed {
+            index::load_relationships(input);
+        }
+        let progress_bar = logger::progress_bar((object_paths.len() - indexed) as u64);
+        let objects = object_paths
+            .into_par_iter()
+            .filter_map(|(path, object)| {
+                let object = object.or_else(|| {
+                    progress_bar.inc(1);
+                    let _processing =
+                        logger::state::processing(&path.file_name()?.to_string_lossy());
+                    timings::time(Stage::Parse, &Pid::from_path(&path).0, || {
+                        Object::from_path(&path, triples.as_ref(), pid_map)
+                    })
+                })?;
+                Some((path, object))
             })
-            .collect::<ObjectMapInner>();
-        Self(inner)
+            .collect::<Vec<_>>();
+        if let Some(index) = index {
+            if !limited && (objects.len() != indexed || index.has_removed()) {
+                index.write(objects.iter().map(|(path, object)| (path.as_ref(), object)));
+            }
+        }
+        if !limited {
+            index::write_relationships(input);
+        }
+        // Ignore system objects & content models.
+        let (system, inner): (Vec<_>, Vec<_>) = objects
+            .into_par_iter()
+            .filter(|(_, object)| !object.missing_content_model())
+            .map(|(_, object)| (object.pid.clone(), object))
+            .partition(|(_, object)| object.is_system_object() || object.is_content_model());
+        Self(
+            inner.into_iter().collect(),
+            system.into_iter().collect(),
+            not_loaded,
+            pid_map.clone(),
+        )
     }
 
     pub fn inner(&self) -> &ObjectMapInner {
         &self.0
     }
 
+    // System objects & content models, which are excluded from all other
+    // functions.
+    pub fn system_objects(&self) -> &ObjectMapInner {
+        &self.1
+    }
+
+    // Objects that were migrated but not loaded, as they are processed by
+    // another shard or were excluded by `--pids`, etc.
+    pub fn not_loaded(&self) -> &HashSet<String> {
+        &self.2
+    }
+
+    // Also applied to references in the content of datastreams, e.g. DC.
+    pub fn pid_map(&self) -> &PidMap {
+        &self.3
+    }
+
+    pub fn get(&self, pid: &str) -> Option<&Object> {
+        self.0.get(&Pid(pid.to_string()))
+    }
+
+    pub fn get_mut(&mut self, pid: &str) -> Option<&mut Object> {
+        self.0.get_mut(&Pid(pid.to_string()))
+    }
+
+    // Children with a page or sequence number by parent, in order.
+    pub fn pages(&self) -> HashMap<&str, Vec<&Object>> {
+        let mut pages = HashMap::new();
+        for object in self.0.values().filter(|object| object.weight.is_some()) {
+            for parent in &object.parents {
+                pages
+                    .entry(parent.as_str())
+                    .or_insert_with(Vec::new)
+                    .push(object);
+            }
+        }
+        for children in pages.values_mut() {
+            children.sort_by(|a, b| (a.weight, &a.pid).cmp(&(b.weight, &b.pid)));
+        }
+        pages
+    }
+
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: Fn(&Object) -> bool,
+    {
+        self.0.retain(|_, object| f(object));
+    }
+
     pub fn objects(&self) -> impl ParallelIterator<Item = &Object> {
         self.0.par_iter().map(|(_, v)| v)
     }
 
+    // Drops the previous versions of every datastream, given
+    // `VersionPolicy::LatestOnly`.
+    pub fn apply_version_policy(&mut self, policy: VersionPolicy) {
+        if policy == VersionPolicy::All {
+            return;
+        }
+        let mut dropped = 0;
+        for datastream in self
+            .0
+            .values_mut()
+            .flat_map(|object| object.datastreams.iter_mut())
+        {
+            let previous = datastream.versions.len().saturating_sub(1);
+            datastream.versions.drain(..previous);
+            dropped += previous;
+        }
+        info!("Dropped {} previous datastream versions", dropped);
+    }
+
+    pub fn objects_mut(&mut self) -> impl Iterator<Item = &mut Object> {
+        self.0.values_mut()
+    }
+
+    pub fn versions_mut(&mut self) -> impl Iterator<Item = &mut DatastreamVersion> {
+        self.0.values_mut().flat_map(|object| {
+            object
+                .datastreams
+                .iter_mut()
+                .flat_map(|datastream| datastream.versions.iter_mut())
+        })
+    }
+
     fn datastreams(&self) -> impl ParallelIterator<Item = (&Object, &Datastream)> {
         self.objects().flat_map(|object| {
             object
@@ -805,17 +1156,24 @@ impl ObjectMap {
         })
     }
 
-    // Enumerate object files, if limit_to_pids is non-empty restrict the files to just those whose PID matches entries in the given list.
-    fn object_files(directory: &Path, limit_to_pids: Vec<&str>) -> Vec<Box<Path>> {
-        let files = files(&directory.join("objects"));
-        if limit_to_pids.is_empty() {
-            files
-        } else {
-            files
-                .into_par_iter()
-                .filter(|path| limit_to_pids.contains(&Pid::from_path(&path).0.as_str()))
-                .collect()
-        }
+    fn object_files(directory: &Path) -> Vec<Box<Path>> {
+        files(&directory.join("objects"))
+    }
+
+    // Splits the object files into those to load, i.e. those in this shard and
+    // if limit_to_pids is non-empty whose PID matches entries in the given
+    // list, and the PIDs of the rest.
+    fn partition_object_files(
+        files: Vec<Box<Path>>,
+        limit_to_pids: &[String],
+    ) -> (Vec<Box<Path>>, HashSet<String>) {
+        let (files, rest): (Vec<_>, Vec<_>) = files.into_par_iter().partition(|path| {
+            let pid = Pid::from_path(path).0;
+            (limit_to_pids.is_empty() || limit_to_pids.contains(&pid))
+                && foxml::pids::in_shard(&pid)
+        });
+        let rest = rest.iter().map(|path| Pid::from_path(path).0).collect();
+        (files, rest)
     }
 }
 
@@ -867,6 +1225,217 @@ xmlns:islandora="http://islandora.ca/ontology/relsext#">
         };
         let result = RelsExt::from_string(&content);
         assert!(result.is_ok());
-        assert_eq!(result.unwrap(), expected);
+        let rels_ext = result.unwrap();
+        assert_eq!(rels_ext, expected);
+        let relationship = |predicate: &str, target: &str| Relationship {
+            predicate: predicate.to_string(),
+            target: target.to_string(),
+        };
+        let relationships = Object::relationships(&rels_ext, &PidMap::default());
+        assert_eq!(
+            relationships,
+            vec![
+                relationship("isMemberOf", "namespace:111"),
+                relationship("isMemberOfCollection", "namespace:456"),
+                relationship("isMemberOfCollection", "namespace:789"),
+            ]
+        );
+        assert_eq!(
+            Object::parents(&relationships),
+            vec!["namespace:111", "namespace:456", "namespace:789"]
+        );
+    }
+
+    #[test]
+    fn invalid_boolean_rels_ext() {
+        let content = r#"
+<rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#"
+xmlns:islandora="http://islandora.ca/ontology/relsext#">
+    <rdf:Description rdf:about="info:fedora/namespace:123">
+        <islandora:deferDerivatives>yes</islandora:deferDerivatives>
+        <islandora:generate_ocr>False</islandora:generate_ocr>
+        <islandora:generate_hocr>1</islandora:generate_hocr>
+    </rdf:Description>
+</rdf:RDF>
+"#;
+        let rels_ext = RelsExt::from_string(content).unwrap();
+        assert_eq!(rels_ext.deferDerivatives, None);
+        assert_eq!(rels_ext.generateOCR, Some(false));
+        assert_eq!(rels_ext.generateHOCR, None);
+    }
+
+    #[test]
+    fn merge_triples() {
+        let content = r#"
+<info:fedora/namespace:123> <info:fedora/fedora-system:def/model#hasModel> <info:fedora/fedora-system:FedoraObject-3.0> .
+<info:fedora/namespace:123> <info:fedora/fedora-system:def/model#hasModel> <info:fedora/islandora:pageCModel> .
+<info:fedora/namespace:123> <info:fedora/fedora-system:def/relations-external#isMemberOf> <info:fedora/namespace:222> .
+<info:fedora/namespace:123> <http://islandora.ca/ontology/relsext#isPageNumber> "5" .
+<info:fedora/namespace:123/OBJ> <info:fedora/fedora-system:def/view#mimeType> "image/tiff" .
+"#;
+        let triples = RelsExt::from_triples_reader(content.as_bytes()).unwrap();
+        assert_eq!(triples.len(), 1);
+        let mut rels_ext = RelsExt {
+            about: "namespace:123".to_string(),
+            hasModel: vec!["islandora:pageCModel".to_string()],
+            isMemberOf: vec!["namespace:111".to_string()],
+            isPageNumber: Some(2),
+            ..RelsExt::default()
+        };
+        rels_ext.merge(&triples["namespace:123"]);
+        let expected = RelsExt {
+            about: "namespace:123".to_string(),
+            hasModel: vec!["islandora:pageCModel".to_string()],
+            isMemberOf: vec!["namespace:111".to_string(), "namespace:222".to_string()],
+            isPageNumber: Some(5),
+            ..RelsExt::default()
+        };
+        assert_eq!(rels_ext, expected);
+    }
+
+    #[test]
+    fn object_files_not_loaded() {
+        let files = || {
+            ["a:1.xml", "a:2.xml", "a:3.xml"]
+                .iter()
+                .map(|name| Path::new("objects").join(name).into_boxed_path())
+                .collect::<Vec<_>>()
+        };
+        let (loaded, not_loaded) = ObjectMap::partition_object_files(files(), &[]);
+        assert_eq!(loaded.len(), 3);
+        assert!(not_loaded.is_empty());
+        let (loaded, not_loaded) = ObjectMap::partition_object_files(files(), &["a:2".to_string()]);
+        assert_eq!(loaded, vec![Path::new("objects/a:2.xml").into()]);
+        assert_eq!(
+            not_loaded,
+            ["a:1", "a:3"].iter().map(|pid| pid.to_string()).collect()
+        );
+    }
+
+    #[test]
+    fn pid_parts() {
+        let pid = Pid("archden:463".to_string());
+        assert_eq!(
+            (pid.namespace(), pid.local_id(), pid.number()),
+            ("archden", "463", Some(463))
+        );
+        let pid = Pid("archden:a:b".to_string());
+        assert_eq!(
+            (pid.namespace(), pid.local_id(), pid.number()),
+            ("archden", "a:b", None)
+        );
+        let pid = Pid("archden".to_string());
+        assert_eq!(
+            (pid.namespace(), pid.local_id(), pid.number()),
+            ("archden", "", None)
+        );
+    }
+
+    #[test]
+    fn version_order() {
+        let version = |id: &str, created: &str| DatastreamVersion {
+            pid: "namespace:123".to_string(),
+            dsid: "OBJ".to_string(),
+            id: id.to_string(),
+            label: "".to_string(),
+            created_date: DateTime::parse_from_rfc3339(created).unwrap(),
+            mime_type: "image/tiff".to_string(),
+            original_mime_type: None,
+            renamed: None,
+            original_label: None,
+            content: DatastreamContent::Stored,
+            format_uri: None,
+            alt_ids: vec![],
+        };
+        let mut versions = [
+            version("OBJ.10", "2020-01-02T00:00:00+00:00"),
+            version("OBJ.9", "2020-01-03T00:00:00Z"),
+            version("OBJ.2", "2020-01-02T00:00:00Z"),
+        ];
+        versions.sort();
+        let ids = versions.iter().map(|v| v.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["OBJ.2", "OBJ.10", "OBJ.9"]);
+        versions.sort_by(|a, b| a.cmp_by_id(b));
+        let ids = versions.iter().map(|v| v.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["OBJ.2", "OBJ.9", "OBJ.10"]);
+    }
+
+    #[test]
+    fn basename_and_extension() {
+        let split = |name: &str| {
+            DatastreamVersion {
+                pid: "namespace:123".to_string(),
+                dsid: "OBJ".to_string(),
+                id: "OBJ.0".to_string(),
+                label: "".to_string(),
+                created_date: DateTime::parse_from_rfc3339("2020-01-02T00:00:00Z").unwrap(),
+                mime_type: "application/pdf".to_string(),
+                original_mime_type: None,
+                renamed: Some(name.to_string()),
+                original_label: None,
+                content: DatastreamContent::Stored,
+                format_uri: None,
+                alt_ids: vec![],
+            }
+            .basename_and_extension()
+        };
+        let pair = |a: &str, b: &str| (a.to_string(), b.to_string());
+        assert_eq!(split("scan_1.pdf"), pair("scan_1", "pdf"));
+        assert_eq!(split("data.tar.gz"), pair("data.tar", "gz"));
+        assert_eq!(split("README"), pair("README", ""));
+        assert_eq!(split(".hidden"), pair(".hidden", ""));
+    }
+
+    #[test]
+    fn remote_content() {
+        let version = FoxmlDatastreamVersion {
+            id: "OBJ.0".to_string(),
+            label: "".to_string(),
+            created: DateTime::parse_from_rfc3339("2020-01-02T00:00:00Z").unwrap(),
+            mime_type: "video/mp4".to_string(),
+            size: None,
+            format: None,
+            alt_ids: None,
+            content: vec![FoxmlDatastreamContent::ContentLocation(
+                FoxmlDatastreamContentLocation {
+                    r#type: "URL".to_string(),
+                    r#ref: "https://example.com/video.mp4".to_string(),
+                },
+            )],
+        };
+        let content = |control_group| DatastreamContent::new(&control_group, &version);
+        let url = "https://example.com/video.mp4".to_string();
+        assert_eq!(
+            content(FoxmlControlGroup::E),
+            DatastreamContent::External(url.clone())
+        );
+        assert_eq!(
+            content(FoxmlControlGroup::R),
+            DatastreamContent::Redirect(url)
+        );
+        assert_eq!(content(FoxmlControlGroup::M), DatastreamContent::Stored);
+        let version = DatastreamVersion {
+            content: content(FoxmlControlGroup::R),
+            ..DatastreamVersion::new("archden:1".to_string(), "OBJ".to_string(), version)
+        };
+        assert_eq!(version.url(), Some("https://example.com/video.mp4"));
+        assert_eq!(version.content.remote(), "redirect");
+    }
+
+    #[test]
+    fn format_and_alternate_ids() {
+        let version = FoxmlDatastreamVersion {
+            id: "OBJ.0".to_string(),
+            label: "".to_string(),
+            created: DateTime::parse_from_rfc3339("2020-01-02T00:00:00Z").unwrap(),
+            mime_type: "application/pdf".to_string(),
+            size: None,
+            format: Some("info:pronom/fmt/18".to_string()),
+            alt_ids: Some(" hdl:1234/5  doi:10.1000/1 ".to_string()),
+            content: vec![],
+        };
+        let version = DatastreamVersion::new("archden:1".to_string(), "OBJ".to_string(), version);
+        assert_eq!(version.format_uri.as_deref(), Some("info:pronom/fmt/18"));
+        assert_eq!(version.alt_ids, vec!["hdl:1234/5", "doi:10.1000/1"]);
     }
 }
@@ -0,0 +1,509 @@
+// Tables are written as CSV by default, or optionally as Parquet so that very
+// large inventories can be analyzed with tools like DuckDB or Spark.
+use super::ids::Ids;
+use super::limits::{FieldLimits, TableLimits};
+use super::profile::Profile;
+use super::schema::{Schema, TableSchema};
+use super::utils::*;
+use super::workbook::Workbook;
+use parquet::basic::{Compression, ConvertedType, Repetition, Type as PhysicalType};
+use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::Type;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Number of rows per Parquet row group, each is held in memory until written.
+const ROW_GROUP_SIZE: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, Default, EnumString, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Csv,
+    Parquet,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Csv => "csv",
+            OutputFormat::Parquet => "parquet",
+        }
+    }
+}
+
+// Options which control how the tables of the `csv` and `scripts` commands
+// are written.
+#[derive(Default)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    // Add an `id` column to every table with a `pid` column, from the given
+    // map of PIDs to integer IDs.
+    pub id_map: Option<PathBuf>,
+    // Checked against every value written, see oversized_values.csv.
+    pub field_limits: FieldLimits,
+    // Also write column_profile.csv.
+    pub profile: bool,
+    // Also write every table as a sheet of the given workbook.
+    pub workbook: Option<PathBuf>,
+}
+
+// The directory the tables of a command are written to, shared by every task
+// that writes them, along with what is recorded of each table as it is
+// written for the reports written once all others are complete.
+pub struct Output {
+    directory: PathBuf,
+    format: OutputFormat,
+    ids: Ids,
+    limits: FieldLimits,
+    schema: Schema,
+    profile: Option<Profile>,
+    workbook: Option<Workbook>,
+    // Files written so far and their number of rows.
+    written: Mutex<BTreeMap<String, usize>>,
+}
+
+// A row of a generated table, whose columns are the fields it serializes.
+pub trait Row: Serialize {
+    // Every column in the order written, including optional columns which are
+    // only written if the first row has them, e.g. `thumbnail` of nodes.csv.
+    fn columns() -> &'static [&'static str];
+}
+
+// The columns of the given rows, all of them if there are none.
+fn header<S: Row>(rows: &[S]) -> Result<Vec<String>, serde_json::Error> {
+    let first = match rows.first().map(serde_json::to_value).transpose()? {
+        Some(Value::Object(first)) => Some(first),
+        _ => None,
+    };
+    Ok(S::columns()
+        .iter()
+        .filter(|column| {
+            first
+                .as_ref()
+                .is_none_or(|first| first.contains_key(**column))
+        })
+        .map(|column| column.to_string())
+        .collect())
+}
+
+// The values of the given row in the order of the given header.
+fn row_values<S: Row>(header: &[String], row: &S) -> Result<Vec<Value>, serde_json::Error> {
+    let mut row = match serde_json::to_value(row)? {
+        Value::Object(row) => row,
+        _ => return Ok(Vec::new()),
+    };
+    debug_assert!(
+        row.keys().all(|key| header.contains(key)),
+        "The columns of the row are not all in the header"
+    );
+    Ok(header
+        .iter()
+        .map(|column| row.remove(column).unwrap_or(Value::Null))
+        .collect())
+}
+
+// The header and values of the given rows as `Output::write_rows()` would
+// write them to a CSV file.
+pub fn to_table<S: Row>(rows: &[S]) -> Result<(Vec<String>, Vec<Vec<String>>), serde_json::Error> {
+    if rows.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
+    let header = header(rows)?;
+    let values = rows
+        .iter()
+        .map(|row| Ok(row_values(&header, row)?.iter().map(csv_value).collect()))
+        .collect::<Result<Vec<Vec<String>>, serde_json::Error>>()?;
+    Ok((header, values))
+}
+
+enum TableWriter {
+    Csv(csv_other::Writer<File>),
+    Parquet(ParquetWriter),
+}
+
+impl Output {
+    pub fn new(directory: &Path, options: OutputOptions) -> Self {
+        Output {
+            directory: directory.to_path_buf(),
+            format: options.format,
+            ids: Ids::new(options.id_map),
+            limits: options.field_limits,
+            schema: Schema::default(),
+            profile: options.profile.then(Profile::default),
+            workbook: options.workbook.map(Workbook::new),
+            written: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        self.format
+    }
+
+    pub fn ids(&self) -> &Ids {
+        &self.ids
+    }
+
+    pub fn limits(&self) -> &FieldLimits {
+        &self.limits
+    }
+
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    pub fn workbook(&self) -> Option<&Workbook> {
+        self.workbook.as_ref()
+    }
+
+    // The path of the given table e.g. `nodes.csv`, with the extension
+    // replaced to match the output format.
+    fn path(&self, file: &str) -> PathBuf {
+        self.directory
+            .join(file)
+            .with_extension(self.format.extension())
+    }
+
+    // All files written so far and their number of rows.
+    pub fn outputs(&self) -> Vec<(String, usize)> {
+        self.written
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, rows)| (name.clone(), *rows))
+            .collect()
+    }
+
+    // Writes the rows to the given file with their columns as the header, for
+    // CSV the header is omitted if there are no rows.
+    pub fn write_rows<S: Row>(&self, rows: &[S], file: &str) -> Result<(), std::io::Error> {
+        let mut header = header(rows)?;
+        // Parquet files always have a schema.
+        if rows.is_empty() && self.format == OutputFormat::Csv {
+            header.clear();
+        }
+        self.write_values(
+            &header,
+            rows.iter().map(|row| row_values(&header, row)),
+            file,
+        )
+    }
+
+    // Writes a report of problems, which if not empty is listed in the summary
+    // written on exit, with its rows counted under the report's name e.g.
+    // 'dangling_parents'.
+    pub fn write_report<S: Row>(&self, rows: &[S], file: &str) -> Result<(), std::io::Error> {
+        self.write_rows(rows, file)?;
+        if !rows.is_empty() {
+            logger::summary::add_report(&self.path(file));
+            let header = header(rows)?;
+            let examples = rows
+                .iter()
+                .take(logger::summary::EXAMPLES)
+                .filter_map(|row| row_values(&header, row).ok())
+                .map(|row| row.iter().map(csv_value).collect::<Vec<_>>().join(", "))
+                .collect::<Vec<_>>();
+            let category = Path::new(file).file_stem().unwrap().to_string_lossy();
+            logger::summary::warnings(&category, rows.len(), &examples);
+        }
+        Ok(())
+    }
+
+    // Writes the given header and rows to the given file, the header is always
+    // included.
+    pub fn write_table(
+        &self,
+        header: &[String],
+        rows: &[Vec<String>],
+        file: &str,
+    ) -> Result<(), std::io::Error> {
+        let values = rows
+            .iter()
+            .map(|row| Ok(row.iter().cloned().map(Value::String).collect()));
+        self.write_values(header, values, file)
+    }
+
+    // Each row is converted, checked against the limits and written in turn, so
+    // the values of the whole table are only held in memory when they are
+    // needed at once, i.e. by `--profile` or `--xlsx`. The column types of
+    // Parquet files are found by converting the rows once beforehand.
+    fn write_values<I>(&self, header: &[String], rows: I, file: &str) -> Result<(), std::io::Error>
+    where
+        I: Iterator<Item = Result<Vec<Value>, serde_json::Error>> + Clone,
+    {
+        let dest = self.path(file);
+        let mut header = header.to_vec();
+        let id_column = self.ids.insert_column(&mut header);
+        let header = header.as_slice();
+        let rows = rows.map(|row| {
+            let mut row = row?;
+            if let Some(column) = id_column {
+                self.ids.insert(column, &mut row);
+            }
+            Ok::<_, serde_json::Error>(row)
+        });
+        let limits = TableLimits::new(&self.limits, &dest, header);
+        let mut schema = TableSchema::new(header);
+        let buffered = self.profile.is_some() || self.workbook.is_some();
+        let mut values = Vec::new();
+        let mut writer = match self.format {
+            OutputFormat::Csv => {
+                let mut writer = csv_other::WriterBuilder::new().from_path(partial_path(&dest))?;
+                if !header.is_empty() {
+                    writer.write_record(header)?;
+                }
+                TableWriter::Csv(writer)
+            }
+            OutputFormat::Parquet => {
+                let integers = integer_columns(header.len(), rows.clone())?;
+                let writer = ParquetWriter::new(&partial_path(&dest), header, integers)
+                    .map_err(parquet_error)?;
+                TableWriter::Parquet(writer)
+            }
+        };
+        let mut count = 0;
+        for row in rows {
+            let mut row = row?;
+            count += 1;
+            limits.apply(count, &mut row);
+            schema.add(&row);
+            match &mut writer {
+                TableWriter::Csv(writer) => writer.write_record(row.iter().map(csv_value))?,
+                TableWriter::Parquet(writer) => writer.write(&row).map_err(parquet_error)?,
+            }
+            if buffered {
+                values.push(row);
+            }
+        }
+        match writer {
+            TableWriter::Csv(mut writer) => writer.flush()?,
+            TableWriter::Parquet(writer) => writer.close().map_err(parquet_error)?,
+        }
+        if let Some(profile) = &self.profile {
+            profile.record(&dest, header, &values);
+        }
+        schema.record(&self.schema, &dest);
+        if let Some(workbook) = &self.workbook {
+            workbook.record(&dest, header, &values);
+        }
+        finish_partial(&dest)?;
+        let name = dest.file_name().unwrap().to_string_lossy().to_string();
+        self.written.lock().unwrap().insert(name, count);
+        Ok(())
+    }
+}
+
+pub fn csv_value(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        Value::Null => "".to_string(),
+        value => value.to_string(),
+    }
+}
+
+fn parquet_error(error: parquet::errors::ParquetError) -> std::io::Error {
+    std::io::Error::other(error)
+}
+
+// Columns in which every value is an integer are stored as such, all others
+// are stored as strings.
+fn integer_columns<I>(width: usize, rows: I) -> Result<Vec<bool>, serde_json::Error>
+where
+    I: Iterator<Item = Result<Vec<Value>, serde_json::Error>>,
+{
+    let mut integers = vec![false; width];
+    for (index, row) in rows.enumerate() {
+        let row = row?;
+        for (column, integer) in integers.iter_mut().enumerate() {
+            let value = row.get(column).and_then(Value::as_i64);
+            *integer = (index == 0 || *integer) && value.is_some();
+        }
+    }
+    Ok(integers)
+}
+
+// Writes the rows given to it in row groups of `ROW_GROUP_SIZE`.
+struct ParquetWriter {
+    writer: SerializedFileWriter<File>,
+    integers: Vec<bool>,
+    rows: Vec<Vec<Value>>,
+    row_group_size: usize,
+}
+
+impl ParquetWriter {
+    fn new(dest: &Path, header: &[String], integers: Vec<bool>) -> parquet::errors::Result<Self> {
+        let fields = header
+            .iter()
+            .zip(integers.iter())
+            .map(|(name, integer)| {
+                let field = if *integer {
+                    Type::primitive_type_builder(name, PhysicalType::INT64)
+                } else {
+                    Type::primitive_type_builder(name, PhysicalType::BYTE_ARRAY)
+                        .with_converted_type(ConvertedType::UTF8)
+                };
+                Ok(Arc::new(
+                    field.with_repetition(Repetition::REQUIRED).build()?,
+                ))
+            })
+            .collect::<parquet::errors::Result<Vec<_>>>()?;
+        let schema = Arc::new(
+            Type::group_type_builder("schema")
+                .with_fields(fields)
+                .build()?,
+        );
+        let properties = Arc::new(
+            WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .build(),
+        );
+        Ok(ParquetWriter {
+            writer: SerializedFileWriter::new(File::create(dest)?, schema, properties)?,
+            integers,
+            rows: Vec::new(),
+            row_group_size: ROW_GROUP_SIZE,
+        })
+    }
+
+    fn write(&mut self, row: &[Value]) -> parquet::errors::Result<()> {
+        self.rows.push(row.to_vec());
+        if self.rows.len() >= self.row_group_size {
+            self.write_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn write_row_group(&mut self) -> parquet::errors::Result<()> {
+        let rows = std::mem::take(&mut self.rows);
+        let mut row_group = self.writer.next_row_group()?;
+        let mut column = 0;
+        while let Some(mut column_writer) = row_group.next_column()? {
+            let values = rows
+                .iter()
+                .map(|row| row.get(column).unwrap_or(&Value::Null));
+            if self.integers[column] {
+                let values = values
+                    .map(|value| value.as_i64().unwrap())
+                    .collect::<Vec<_>>();
+                column_writer
+                    .typed::<Int64Type>()
+                    .write_batch(&values, None, None)?;
+            } else {
+                let values = values
+                    .map(|value| ByteArray::from(csv_value(value).as_str()))
+                    .collect::<Vec<_>>();
+                column_writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, None, None)?;
+            }
+            column_writer.close()?;
+            column += 1;
+        }
+        row_group.close()?;
+        Ok(())
+    }
+
+    fn close(mut self) -> parquet::errors::Result<()> {
+        if !self.rows.is_empty() {
+            self.write_row_group()?;
+        }
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+
+    #[test]
+    fn parquet_column_types() {
+        let dest = std::env::temp_dir().join("migration_parquet_column_types.parquet");
+        let header = vec!["pid".to_string(), "size".to_string()];
+        let rows = vec![
+            vec![Value::from("namespace:1"), Value::from(10)],
+            vec![Value::from("namespace:2"), Value::from(20)],
+        ];
+        let integers = integer_columns(header.len(), rows.iter().cloned().map(Ok)).unwrap();
+        let mut writer = ParquetWriter::new(&dest, &header, integers).unwrap();
+        for row in &rows {
+            writer.write(row).unwrap();
+        }
+        writer.close().unwrap();
+        let reader = SerializedFileReader::new(File::open(&dest).unwrap()).unwrap();
+        let metadata = reader.metadata();
+        assert_eq!(metadata.file_metadata().num_rows(), 2);
+        let schema = metadata.file_metadata().schema_descr();
+        assert_eq!(schema.column(0).physical_type(), PhysicalType::BYTE_ARRAY);
+        assert_eq!(schema.column(1).physical_type(), PhysicalType::INT64);
+        std::fs::remove_file(&dest).unwrap();
+    }
+
+    #[test]
+    fn parquet_row_groups() {
+        let dest = std::env::temp_dir().join("migration_parquet_row_groups.parquet");
+        let header = vec!["pid".to_string()];
+        let mut writer = ParquetWriter::new(&dest, &header, vec![false]).unwrap();
+        writer.row_group_size = 1;
+        writer.write(&[Value::from("namespace:1")]).unwrap();
+        writer.write(&[Value::from("namespace:2")]).unwrap();
+        writer.close().unwrap();
+        let reader = SerializedFileReader::new(File::open(&dest).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+        std::fs::remove_file(&dest).unwrap();
+    }
+
+    #[derive(Serialize)]
+    struct TestRow<'a> {
+        pid: &'a str,
+        #[serde(rename = "type")]
+        kind: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        thumbnail: Option<String>,
+    }
+
+    impl Row for TestRow<'_> {
+        fn columns() -> &'static [&'static str] {
+            &["pid", "type", "thumbnail"]
+        }
+    }
+
+    #[test]
+    fn columns_of_rows() {
+        let row = |thumbnail: Option<&str>| TestRow {
+            pid: "namespace:1",
+            kind: "image".to_string(),
+            thumbnail: thumbnail.map(str::to_string),
+        };
+        assert_eq!(
+            header::<TestRow>(&[]).unwrap(),
+            vec!["pid", "type", "thumbnail"]
+        );
+        let rows = vec![row(None)];
+        assert_eq!(
+            to_table(&rows).unwrap(),
+            (
+                vec!["pid".to_string(), "type".to_string()],
+                vec![vec!["namespace:1".to_string(), "image".to_string()]]
+            )
+        );
+        let rows = vec![row(Some("ok"))];
+        assert_eq!(to_table(&rows).unwrap().1[0][2], "ok");
+    }
+}
@@ -0,0 +1,271 @@
+// Splits a single large text datastream (e.g. the OCR of a whole newspaper
+// issue) into one file per page, stored as a datastream of the corresponding
+// page object, i.e. the child whose page / sequence number matches. The split
+// pages are recorded in split_pages.csv so that the `csv` and `scripts`
+// commands include them as datastreams of the page objects.
+use super::object::{Datastream, DatastreamContent, DatastreamState, DatastreamVersion, ObjectMap};
+use log::{info, warn};
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::str::FromStr;
+
+static MANIFEST: &str = "split_pages.csv";
+
+const MIME_TYPE: &str = "text/plain";
+
+// How pages are separated within the datastream.
+#[derive(Debug, PartialEq)]
+pub enum PageDelimiter {
+    // Literal text, e.g. the form feed `\f` output by pdftotext.
+    Text(String),
+    // Each `Page` element of an ALTO document, as plain text.
+    Alto,
+}
+
+impl FromStr for PageDelimiter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("alto") {
+            return Ok(PageDelimiter::Alto);
+        }
+        let delimiter = s
+            .replace("\\f", "\u{c}")
+            .replace("\\n", "\n")
+            .replace("\\t", "\t");
+        if delimiter.is_empty() {
+            Err("The page delimiter must not be empty".to_string())
+        } else {
+            Ok(PageDelimiter::Text(delimiter))
+        }
+    }
+}
+
+impl PageDelimiter {
+    fn split(&self, content: &str) -> Result<Vec<String>, quick_xml::Error> {
+        match self {
+            PageDelimiter::Text(delimiter) => {
+                let mut pages = content
+                    .split(delimiter.as_str())
+                    .map(str::to_string)
+                    .collect::<Vec<_>>();
+                // Delimiters usually follow every page including the last.
+                if pages.last().is_some_and(|page| page.trim().is_empty()) {
+                    pages.pop();
+                }
+                Ok(pages)
+            }
+            PageDelimiter::Alto => alto_pages(content),
+        }
+    }
+}
+
+// The text of each page, with a line per TextLine.
+fn alto_pages(content: &str) -> Result<Vec<String>, quick_xml::Error> {
+    let mut reader = Reader::from_str(content);
+    let mut buffer = Vec::new();
+    let mut pages = Vec::new();
+    let mut line = Vec::new();
+    loop {
+        match reader.read_event(&mut buffer)? {
+            Event::Start(element) | Event::Empty(element) => match element.local_name() {
+                b"Page" => pages.push(String::new()),
+                b"String" => {
+                    let content = element
+                        .attributes()
+                        .filter_map(|attribute| attribute.ok())
+                        .find(|attribute| attribute.key == b"CONTENT")
+                        .map(|attribute| attribute.unescape_and_decode_value(&reader))
+                        .transpose()?;
+                    line.extend(content);
+                }
+                _ => (),
+            },
+            Event::End(element) if element.local_name() == b"TextLine" => {
+                if let Some(page) = pages.last_mut() {
+                    page.push_str(&line.join(" "));
+                    page.push('\n');
+                }
+                line.clear();
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+        buffer.clear();
+    }
+    Ok(pages)
+}
+
+// The datastream given to a page, dated as the version it was split from.
+fn page_version(page: &str, version: &DatastreamVersion) -> DatastreamVersion {
+    DatastreamVersion {
+        pid: page.to_string(),
+        dsid: version.dsid.clone(),
+        id: format!("{}.0", version.dsid),
+        label: version.dsid.clone(),
+        created_date: version.created_date,
+        mime_type: MIME_TYPE.to_string(),
+        original_mime_type: None,
+        renamed: None,
+        original_label: None,
+        content: DatastreamContent::Stored,
+        format_uri: None,
+        alt_ids: vec![],
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct SplitPageRow {
+    pid: String,
+    dsid: String,
+    version: String,
+    page: usize,
+    page_pid: String,
+}
+
+// Splits the latest version of the given datastream of every object with
+// pages, pages that already have the datastream are left as is. Returns the
+// number of pages written.
+pub fn split_pages(
+    objects: &ObjectMap,
+    dest: &Path,
+    dsid: &str,
+    delimiter: &PageDelimiter,
+) -> usize {
+    let pages = objects.pages();
+    let progress_bar = logger::progress_bar(pages.len() as u64);
+    let mut rows = pages
+        .par_iter()
+        .flat_map_iter(|(parent, children)| {
+            progress_bar.inc(1);
+            let version = objects
+                .get(parent)
+                .and_then(|object| object.datastream(dsid))
+                .filter(|version| version.path().exists());
+            let version = match version {
+                Some(version) => version,
+                None => return Vec::new(),
+            };
+            let content = std::fs::read_to_string(version.path()).unwrap_or_else(|error| {
+                panic!("Failed to read {}: {}", version.path().display(), error)
+            });
+            let texts = match delimiter.split(&content) {
+                Ok(texts) => texts,
+                Err(error) => {
+                    warn!("Failed to split {} of {}: {}", dsid, parent, error);
+                    return Vec::new();
+                }
+            };
+            if texts.len() != children.len() {
+                warn!(
+                    "Found {} pages in {} of {} but it has {} page objects",
+                    texts.len(),
+                    dsid,
+                    parent,
+                    children.len()
+                );
+            }
+            texts
+                .iter()
+                .zip(children.iter())
+                .enumerate()
+                .filter(|(_, (_, page))| page.datastream(dsid).is_none())
+                .map(|(index, (text, page))| {
+                    let path = page_version(&page.pid.0, version).path();
+                    std::fs::create_dir_all(path.parent().unwrap())
+                        .and_then(|_| std::fs::write(&path, text))
+                        .unwrap_or_else(|error| {
+                            panic!("Failed to write {}: {}", path.display(), error)
+                        });
+                    SplitPageRow {
+                        pid: parent.to_string(),
+                        dsid: dsid.to_string(),
+                        version: version.id.clone(),
+                        page: index + 1,
+                        page_pid: page.pid.0.clone(),
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    progress_bar.finish_and_clear();
+    rows.sort_by(|a, b| (&a.pid, a.page).cmp(&(&b.pid, b.page)));
+    let manifest = dest.join(MANIFEST);
+    let write = || -> Result<(), csv_other::Error> {
+        let mut writer = csv_other::Writer::from_path(&manifest)?;
+        for row in &rows {
+            writer.serialize(row)?;
+        }
+        writer.flush()?;
+        Ok(())
+    };
+    write().unwrap_or_else(|error| panic!("Failed to write {}: {}", manifest.display(), error));
+    info!("Split {} into {} pages, see {}", dsid, rows.len(), MANIFEST);
+    rows.len()
+}
+
+// Adds the split pages recorded by the `migrate` command as datastreams of the
+// page objects.
+pub fn apply(objects: &mut ObjectMap, input: &Path) {
+    let manifest = input.join(MANIFEST);
+    if !manifest.exists() {
+        return;
+    }
+    let read = || -> Result<Vec<SplitPageRow>, csv_other::Error> {
+        csv_other::Reader::from_path(&manifest)?
+            .deserialize()
+            .collect()
+    };
+    let rows =
+        read().unwrap_or_else(|error| panic!("Failed to read {}: {}", manifest.display(), error));
+    let mut added = 0;
+    for row in rows {
+        let version = objects
+            .get(&row.pid)
+            .and_then(|object| object.datastream(&row.dsid))
+            .map(|version| page_version(&row.page_pid, version));
+        let page = objects.get_mut(&row.page_pid);
+        if let (Some(version), Some(page)) = (version, page) {
+            if page.datastream(&row.dsid).is_none() {
+                page.datastreams.push(Datastream {
+                    id: row.dsid.clone(),
+                    state: DatastreamState::Active,
+                    versions: vec![version],
+                });
+                page.datastreams.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                added += 1;
+            }
+        }
+    }
+    info!("Added {} pages split by the migrate command", added);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let delimiter = "\\f".parse::<PageDelimiter>().unwrap();
+        assert_eq!(
+            delimiter.split("one\u{c}two\u{c}\n").unwrap(),
+            vec!["one".to_string(), "two".to_string()]
+        );
+        let alto = r#"<alto><Layout>
+            <Page ID="P1"><PrintSpace><TextBlock>
+                <TextLine><String CONTENT="Hello"/><SP/><String CONTENT="world"/></TextLine>
+                <TextLine><String CONTENT="&amp;c"/></TextLine>
+            </TextBlock></PrintSpace></Page>
+            <Page ID="P2"><PrintSpace><TextBlock>
+                <TextLine><String CONTENT="Two"/></TextLine>
+            </TextBlock></PrintSpace></Page>
+        </Layout></alto>"#;
+        assert_eq!(
+            PageDelimiter::Alto.split(alto).unwrap(),
+            vec!["Hello world\n&c\n".to_string(), "Two\n".to_string()]
+        );
+    }
+}
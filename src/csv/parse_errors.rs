@@ -0,0 +1,52 @@
+// Object files that could not be parsed, categorized so that corrupt files
+// (empty, or not XML such as an HTML error page) can be distinguished from
+// those that do not match the FOXML schema.
+use super::output::{Output, Row};
+use foxml::FoxmlError;
+use log::warn;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Mutex;
+
+static REPORT: &str = "parse_errors.csv";
+
+#[derive(Debug, Serialize)]
+pub struct ParseErrorRow {
+    file: String,
+    category: &'static str,
+    error: String,
+}
+
+impl Row for ParseErrorRow {
+    fn columns() -> &'static [&'static str] {
+        &["file", "category", "error"]
+    }
+}
+
+lazy_static! {
+    static ref PARSE_ERRORS: Mutex<Vec<ParseErrorRow>> = Mutex::new(Vec::new());
+}
+
+pub fn record(path: &Path, error: &FoxmlError) {
+    PARSE_ERRORS.lock().unwrap().push(ParseErrorRow {
+        file: path.to_string_lossy().to_string(),
+        category: error.category(),
+        error: error.to_string(),
+    });
+}
+
+// Writes parse_errors.csv, should be called once the objects have been parsed.
+pub fn write_report(output: &Output) {
+    let mut rows = std::mem::take(&mut *PARSE_ERRORS.lock().unwrap());
+    rows.sort_by(|a, b| a.file.cmp(&b.file));
+    output
+        .write_report(&rows, REPORT)
+        .expect("Failed to create parse_errors.csv");
+    if !rows.is_empty() {
+        warn!(
+            "Failed to parse {} object files, see {}",
+            rows.len(),
+            REPORT
+        );
+    }
+}
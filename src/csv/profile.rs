@@ -0,0 +1,160 @@
+// Statistics for each column of the generated tables, so that mapping problems
+// (e.g. 40% of titles being empty) can be spotted before importing into Drupal.
+use super::limits;
+use super::output::{csv_value, Output, Row};
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+// Excluded from profiling itself.
+static REPORT: &str = "column_profile";
+
+// Number of the most frequent values listed per column.
+const TOP_VALUES: usize = 5;
+
+// Values listed are shortened to this many characters.
+const TOP_VALUE_LENGTH: usize = 50;
+
+#[derive(Debug, Serialize)]
+pub struct ColumnProfileRow {
+    file: String,
+    column: String,
+    rows: usize,
+    empty: usize,
+    empty_rate: String,
+    distinct: usize,
+    min_length: usize,
+    max_length: usize,
+    top_values: String,
+}
+
+impl Row for ColumnProfileRow {
+    fn columns() -> &'static [&'static str] {
+        &[
+            "file",
+            "column",
+            "rows",
+            "empty",
+            "empty_rate",
+            "distinct",
+            "min_length",
+            "max_length",
+            "top_values",
+        ]
+    }
+}
+
+// The profiles of the columns of the tables written so far, given
+// `--profile`.
+#[derive(Default)]
+pub struct Profile {
+    profiles: Mutex<Vec<ColumnProfileRow>>,
+}
+
+fn top_value(value: &str) -> String {
+    if value.chars().count() > TOP_VALUE_LENGTH {
+        limits::truncate(value, TOP_VALUE_LENGTH)
+    } else {
+        value.to_string()
+    }
+}
+
+fn profile_column(file: &str, column: &str, values: Vec<String>) -> ColumnProfileRow {
+    let rows = values.len();
+    let mut counts = HashMap::new();
+    let mut empty = 0;
+    let (mut min_length, mut max_length) = (usize::MAX, 0);
+    for value in &values {
+        if value.trim().is_empty() {
+            empty += 1;
+            continue;
+        }
+        let length = value.chars().count();
+        min_length = min_length.min(length);
+        max_length = max_length.max(length);
+        *counts.entry(value.as_str()).or_insert(0) += 1;
+    }
+    let mut top = counts.iter().collect::<Vec<_>>();
+    top.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+    let top_values = top
+        .into_iter()
+        .take(TOP_VALUES)
+        .map(|(value, count)| format!("{} ({})", top_value(value), count))
+        .collect::<Vec<_>>()
+        .join("; ");
+    ColumnProfileRow {
+        file: file.to_string(),
+        column: column.to_string(),
+        rows,
+        empty,
+        empty_rate: if rows == 0 {
+            "".to_string()
+        } else {
+            format!("{:.1}%", empty as f64 * 100.0 / rows as f64)
+        },
+        distinct: counts.len(),
+        min_length: if counts.is_empty() { 0 } else { min_length },
+        max_length,
+        top_values,
+    }
+}
+
+impl Profile {
+    // Profiles every column of the given table.
+    pub fn record(&self, dest: &Path, header: &[String], rows: &[Vec<Value>]) {
+        if dest.file_stem().unwrap() == REPORT {
+            return;
+        }
+        let file = dest.file_name().unwrap().to_string_lossy();
+        let profiles = header
+            .iter()
+            .enumerate()
+            .map(|(index, column)| {
+                let values = rows
+                    .iter()
+                    .map(|row| row.get(index).map(csv_value).unwrap_or_default())
+                    .collect();
+                profile_column(&file, column, values)
+            })
+            .collect::<Vec<_>>();
+        self.profiles.lock().unwrap().extend(profiles);
+    }
+}
+
+// Writes column_profile.csv if enabled, should be called once all other tables
+// have been written.
+pub fn write_report(output: &Output) {
+    let profile = match output.profile() {
+        Some(profile) => profile,
+        None => return,
+    };
+    let mut rows = std::mem::take(&mut *profile.profiles.lock().unwrap());
+    // Columns are kept in the order they appear in each file.
+    rows.sort_by(|a, b| a.file.cmp(&b.file));
+    output
+        .write_rows(&rows, &format!("{}.csv", REPORT))
+        .expect("Failed to create column_profile.csv");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_column() {
+        let values = vec!["a", "", "bb", "a", " "]
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+        let profile = profile_column("nodes.csv", "title", values);
+        assert_eq!(profile.rows, 5);
+        assert_eq!(profile.empty, 2);
+        assert_eq!(profile.empty_rate, "40.0%");
+        assert_eq!(profile.distinct, 2);
+        assert_eq!(profile.min_length, 1);
+        assert_eq!(profile.max_length, 2);
+        assert_eq!(profile.top_values, "a (2); bb (1)");
+    }
+}
@@ -1,13 +1,26 @@
 extern crate chrono;
 extern crate serde;
 
+use super::checksums;
+use super::embargo;
+use super::issue_dates::{self, IssueDateSource};
+use super::map::CustomMap;
 use super::object::*;
+use super::output::{self, Output, Row};
+use super::timings::{self, Stage};
+use super::utils::{is_fail_fast, report_listed_problem, report_problem};
+use super::xml;
 use chrono::{DateTime, FixedOffset};
+use foxml::mime_types::{normalize_mime_type, MimeTypeStatus};
+use foxml::pids::PidMap;
 use indicatif::ProgressBar;
+use log::{info, warn, Level};
+use quick_xml::events::Event;
+use quick_xml::Reader;
 use rayon::prelude::*;
-use serde::Serialize;
-use sha1::{Digest, Sha1};
-use std::collections::HashMap;
+use rhai::{Array, ImmutableString};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryFrom;
 use std::path::{Path, PathBuf};
 use strum::AsStaticRef;
@@ -33,7 +46,6 @@ lazy_static! {
         m.insert("image/jp2", "file");
         m.insert("image/tiff", "file");
         m.insert("image/jpeg", "image");
-        m.insert("image/jpg", "image");
         m.insert("image/png", "image");
         m.insert("text/plain", "document");
         m.insert("text/xml", "file");
@@ -63,6 +75,45 @@ lazy_static! {
     };
 }
 
+// How to handle objects whose content model is not in MODEL_MAP.
+#[derive(Clone, Copy, Debug, Display, EnumString, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum UnknownModelPolicy {
+    Skip,   // Exclude the object from all generated CSVs.
+    Binary, // Migrate the object as if it were a Binary.
+    Fail,   // Stop the migration.
+}
+
+// How to handle datastream versions whose file is not present, e.g. when
+// running locally without them.
+#[derive(Clone, Copy, Debug, Display, EnumString, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum MissingFilePolicy {
+    Skip,        // Omit the version from files.csv and media.csv.
+    Placeholder, // Include the version, with the `missing` column set.
+    Fail,        // Stop the migration.
+}
+
+// How to handle parents which are referenced but were not migrated e.g.
+// system objects, objects in other namespaces or missing objects.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DanglingParentPolicy {
+    Keep,                // Leave the reference as is.
+    Blank,               // Remove the reference.
+    Placeholder(String), // Replace the reference with the given collection.
+    Fail,                // Stop the migration.
+}
+
+// How to handle objects whose ownerId is empty or lists multiple (comma
+// separated) users.
+#[derive(Clone, Debug, PartialEq)]
+pub enum InvalidOwnerPolicy {
+    Report,          // Leave the owner as is.
+    First,           // Take the first of multiple owners, empty owners are left as is.
+    Default(String), // Replace the owner with the given user.
+    Fail,            // Stop the migration.
+}
+
 #[derive(Clone)]
 enum Model {
     Audio,
@@ -115,25 +166,63 @@ impl Model {
     }
 }
 
-#[derive(Serialize)]
+// The identifier of the given content model, unknown models are treated as
+// binaries, see `UnknownModelRow::apply()`.
+pub fn model_identifier(model: &str) -> &'static str {
+    Model::try_from(model).unwrap_or(Model::Binary).identifier()
+}
+
+// Also read back by the `push` command.
+#[derive(Deserialize, Serialize)]
 pub struct MediaRow<'a> {
-    pid: &'a str,
-    dsid: &'a str,
-    version: &'a str,
-    bundle: String,
-    created_date: i64,
+    pub pid: &'a str,
+    pub dsid: &'a str,
+    pub version: &'a str,
+    pub bundle: String,
+    pub created_date: i64,
     file_size: u64,
-    label: &'a str,
+    pub label: &'a str,
     mime_type: &'a str,
-    name: String,
+    pub name: String,
     user: &'a str,
+    // The file was not present, so the file_size is a placeholder.
+    pub missing: bool,
+    // Either 'external' or 'redirect' if the content is only referenced by
+    // URL, see `DatastreamContent`.
+    remote: &'a str,
+    url: &'a str,
+    format_uri: &'a str,
+    // Separated by '|'.
+    alt_ids: String,
+}
+
+impl Row for MediaRow<'_> {
+    fn columns() -> &'static [&'static str] {
+        &[
+            "pid",
+            "dsid",
+            "version",
+            "bundle",
+            "created_date",
+            "file_size",
+            "label",
+            "mime_type",
+            "name",
+            "user",
+            "missing",
+            "remote",
+            "url",
+            "format_uri",
+            "alt_ids",
+        ]
+    }
 }
 
 impl<'a> MediaRow<'a> {
     fn new(tuple: (&'a Object, &'a Datastream, &'a DatastreamVersion)) -> Self {
         let (object, datastream, version) = tuple;
         let version_path = version.path();
-        let version_exists = version_path.exists();
+        let version_exists = version.url().is_none() && version_path.exists();
         MediaRow {
             pid: &object.pid.0,
             dsid: &datastream.id,
@@ -149,13 +238,13 @@ impl<'a> MediaRow<'a> {
             },
             label: &version.label,
             mime_type: &version.mime_type,
-            name: version
-                .path()
-                .file_name()
-                .unwrap()
-                .to_string_lossy()
-                .to_string(),
+            name: version.name(),
             user: &object.owner,
+            missing: !version_exists && version.url().is_none(),
+            remote: version.content.remote(),
+            url: version.url().unwrap_or_default(),
+            format_uri: version.format_uri.as_deref().unwrap_or_default(),
+            alt_ids: version.alt_ids.join("|"),
         }
     }
 
@@ -169,114 +258,208 @@ impl<'a> MediaRow<'a> {
         }
     }
 
-    pub fn csv(objects: &ObjectMap, dest: &Path, progress_bar: ProgressBar) {
-        progress_bar.set_length(objects.latest_versions().count() as u64);
+    pub fn csv(
+        objects: &ObjectMap,
+        output: &Output,
+        progress_bar: ProgressBar,
+        missing_files: MissingFilePolicy,
+    ) {
+        logger::set_length(&progress_bar, objects.latest_versions().count() as u64);
         let rows = objects
             .latest_versions()
+            .filter(|row| MissingFileRow::include(row, missing_files))
             .map(|row| {
                 progress_bar.inc(1);
                 MediaRow::new(row)
             })
             .collect::<Vec<_>>();
-        create_csv(&rows, &dest.join("media.csv")).expect("Failed to create media.csv");
+        output
+            .write_rows(&rows, "media.csv")
+            .expect("Failed to create media.csv");
         progress_bar.finish_with_message("Created media.csv");
     }
 
-    pub fn revisions_csv(objects: &ObjectMap, dest: &Path, progress_bar: ProgressBar) {
-        progress_bar.set_length(objects.previous_versions().count() as u64);
+    pub fn revisions_csv(
+        objects: &ObjectMap,
+        output: &Output,
+        progress_bar: ProgressBar,
+        missing_files: MissingFilePolicy,
+    ) {
+        logger::set_length(&progress_bar, objects.previous_versions().count() as u64);
         let rows = objects
             .previous_versions()
+            .filter(|row| MissingFileRow::include(row, missing_files))
             .map(|row| {
                 progress_bar.inc(1);
                 MediaRow::new(row)
             })
             .collect::<Vec<_>>();
-        create_csv(&rows, &dest.join("media_revisions.csv"))
+        output
+            .write_rows(&rows, "media_revisions.csv")
             .expect("Failed to create media_revisions.csv");
         progress_bar.finish_with_message("Created media_revisions.csv");
     }
 }
 
-#[derive(Serialize)]
+// Also read back by the `push` command.
+#[derive(Deserialize, Serialize)]
 pub struct FileRow<'a> {
-    pid: &'a str,
-    dsid: &'a str,
-    version: &'a str,
+    pub pid: &'a str,
+    pub dsid: &'a str,
+    pub version: &'a str,
     created_date: i64,
     mime_type: &'a str,
     name: String,
-    path: String,
+    basename: String,
+    extension: String,
+    pub path: String,
+    // The path without the file name.
+    directory: String,
     user: &'a str,
     sha1: String,
     size: u64,
+    // The file was not present, so the sha1 and size are placeholders.
+    missing: bool,
+    // Either 'external' or 'redirect' if the content is only referenced by
+    // URL, in which case the path is the URL and the sha1 and size are empty.
+    remote: &'a str,
+    format_uri: &'a str,
+    // Separated by '|'.
+    alt_ids: String,
+    // Only set if the file exists, hashed separately from building the row.
+    #[serde(skip)]
+    source: Option<PathBuf>,
+}
+
+impl Row for FileRow<'_> {
+    fn columns() -> &'static [&'static str] {
+        &[
+            "pid",
+            "dsid",
+            "version",
+            "created_date",
+            "mime_type",
+            "name",
+            "basename",
+            "extension",
+            "path",
+            "directory",
+            "user",
+            "sha1",
+            "size",
+            "missing",
+            "remote",
+            "format_uri",
+            "alt_ids",
+        ]
+    }
 }
 
 impl<'a> FileRow<'a> {
     fn new(tuple: (&'a Object, &'a Datastream, &'a DatastreamVersion)) -> Self {
         let (object, datastream, version) = tuple;
         let version_path = version.path();
-        let version_exists = version_path.exists();
-        let relative_path = version_path
-            .components()
-            .rev()
-            .take(5)
-            .collect::<Vec<_>>()
-            .iter()
-            .rev()
-            .collect::<PathBuf>()
-            .into_boxed_path();
-        // Assume all files are in the private://fedora folder for now.
-        let mut path = "private://fedora/".to_string();
-        path.push_str(&relative_path.to_str().unwrap());
+        let version_exists = version.url().is_none() && version_path.exists();
+        let path = match version.url() {
+            Some(url) => url.to_string(),
+            None => Self::stream_wrapper_path(&version.pid, &version_path),
+        };
+        let (basename, extension) = version.basename_and_extension();
         FileRow {
             pid: &object.pid.0,
             dsid: &datastream.id,
             version: &version.id,
             created_date: format_date(&version.created_date),
             mime_type: &version.mime_type,
-            name: version
-                .path()
-                .file_name()
-                .unwrap()
-                .to_string_lossy()
-                .to_string(),
+            name: version.name(),
+            basename,
+            extension,
             user: &object.owner,
+            directory: path
+                .rsplit_once('/')
+                .map_or("", |(directory, _)| directory)
+                .to_string(),
             path,
             // When running locally we may not actually have the files,
             // in which case just do not generate a sha-1 or calculate the file size.
-            sha1: if version_exists {
-                Self::sha1(&version_path)
-            } else {
-                "".to_string()
-            },
+            sha1: "".to_string(),
             size: if version_exists {
                 version_path.metadata().unwrap().len()
             } else {
                 0
             },
+            missing: !version_exists && version.url().is_none(),
+            remote: version.content.remote(),
+            format_uri: version.format_uri.as_deref().unwrap_or_default(),
+            alt_ids: version.alt_ids.join("|"),
+            source: if version_exists {
+                Some(version_path)
+            } else {
+                None
+            },
         }
     }
 
-    fn sha1(path: &Path) -> String {
-        let mut file = std::fs::File::open(&path).unwrap();
-        let mut hasher = Sha1::new();
-        std::io::copy(&mut file, &mut hasher).unwrap();
-        let hash = hasher.finalize();
-        format!("{:x}", hash)
+    fn stream_wrapper_path(pid: &str, version_path: &Path) -> String {
+        let components = version_path.components().rev().take(5).collect::<Vec<_>>();
+        // Stream wrapper URIs always use forward slashes, even when generated
+        // on Windows.
+        let relative_path = components
+            .iter()
+            .rev()
+            .map(|component| component.as_os_str().to_str().unwrap())
+            .collect::<Vec<_>>()
+            .join("/");
+        // Assume all files are in the private://fedora folder for now, with a
+        // sub-folder per volume if they were spread across volumes.
+        match super::volume(pid) {
+            Some((volume, _)) => format!("private://fedora/{}/{}", volume, relative_path),
+            None => format!("private://fedora/{}", relative_path),
+        }
     }
 
-    pub fn csv(objects: &ObjectMap, dest: &Path, progress_bar: ProgressBar) {
-        progress_bar.set_length(objects.versions().count() as u64);
-        let rows = objects
+    pub fn csv(
+        objects: &ObjectMap,
+        output: &Output,
+        progress_bar: ProgressBar,
+        hash_progress_bar: ProgressBar,
+        missing_files: MissingFilePolicy,
+    ) {
+        logger::set_length(&progress_bar, objects.versions().count() as u64);
+        let mut rows = objects
             .versions()
+            .filter(|row| MissingFileRow::include(row, missing_files))
             .map(|row| {
                 progress_bar.inc(1);
                 FileRow::new(row)
             })
             .collect::<Vec<_>>();
-        create_csv(&rows, &dest.join("files.csv")).expect("Failed to create files.csv");
+        progress_bar.set_message("Hashing files");
+        Self::hash(&mut rows, hash_progress_bar);
+        output
+            .write_rows(&rows, "files.csv")
+            .expect("Failed to create files.csv");
         progress_bar.finish_with_message("Created files.csv");
     }
+
+    // Hashing is CPU bound and file sizes vary greatly, so each file is its
+    // own task, allowing idle threads to steal work rather than waiting on
+    // the largest file in a batch.
+    fn hash(rows: &mut [FileRow], progress_bar: ProgressBar) {
+        logger::set_length(
+            &progress_bar,
+            rows.iter().filter(|row| row.source.is_some()).count() as u64,
+        );
+        rows.par_iter_mut()
+            .with_max_len(1)
+            .filter(|row| row.source.is_some())
+            .for_each(|row| {
+                let source = row.source.as_ref().unwrap();
+                row.sha1 = timings::time(Stage::Hash, row.pid, || checksums::sha1(source));
+                progress_bar.inc(1);
+            });
+        progress_bar.finish_with_message("Hashed files");
+    }
 }
 
 enum DisplayHint {
@@ -307,25 +490,107 @@ impl From<Model> for DisplayHint {
     }
 }
 
-#[derive(Serialize)]
+// Also read back by the `push` command.
+#[derive(Deserialize, Serialize)]
 pub struct NodeRow<'a> {
-    pid: &'a str,
-    created_date: i64,
-    label: &'a str,
+    pub pid: &'a str,
+    pub created_date: i64,
+    pub label: &'a str,
     weight: String,
-    model: &'a str,
+    pub model: &'a str,
     modified_date: i64,
-    state: &'a str,
+    pub state: &'a str,
     user: &'a str,
-    display_hint: &'a str,
-    parents: String,
+    pub display_hint: &'a str,
+    pub parents: String,
+    // EDTF date of newspaper issues, empty for other models.
+    edtf_date: String,
+    // Computed from the datastreams of the object, so that large or anomalous
+    // objects can be prioritized for review.
+    datastream_count: usize,
+    // Of every migrated version, missing and remote files count as nothing.
+    total_bytes: u64,
+    has_ocr: bool,
+    has_preservation_master: bool,
+    // Only with `--thumbnail-column`, see `ThumbnailRow`.
+    #[serde(borrow, skip_serializing_if = "Option::is_none")]
+    thumbnail: Option<&'a str>,
+    // Only with `--typed-parents`, the parents split by relationship, see
+    // `RelationshipRow`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    member_of_collection: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    member_of: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    constituent_of: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    part_of: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    other_parents: Option<String>,
 }
 
+impl Row for NodeRow<'_> {
+    fn columns() -> &'static [&'static str] {
+        &[
+            "pid",
+            "created_date",
+            "label",
+            "weight",
+            "model",
+            "modified_date",
+            "state",
+            "user",
+            "display_hint",
+            "parents",
+            "edtf_date",
+            "datastream_count",
+            "total_bytes",
+            "has_ocr",
+            "has_preservation_master",
+            "thumbnail",
+            "member_of_collection",
+            "member_of",
+            "constituent_of",
+            "part_of",
+            "other_parents",
+        ]
+    }
+}
+
+// Datastreams holding the OCR of an object.
+const OCR_DSIDS: [&str; 2] = ["OCR", "HOCR"];
+
+// The datastream holding the original file, i.e. the preservation master.
+const PRESERVATION_MASTER_DSID: &str = "OBJ";
+
+// Predicates of the typed parent columns in nodes.csv, any others are given in
+// `other_parents`.
+const TYPED_PARENTS: [(&str, &[&str]); 4] = [
+    ("member_of_collection", &["isMemberOfCollection"]),
+    ("member_of", &["isMemberOf", "isSubsetOf"]),
+    ("constituent_of", &["isConstituentOf"]),
+    ("part_of", &["isPartOf"]),
+];
+
 impl<'a> NodeRow<'a> {
-    fn new(object: &'a Object) -> Self {
-        // Can panic but we shouldn't have any unknown content models in the
-        // dataset, so just die here if the unlikely case occurs.
-        let model = Model::try_from(object.model.as_str()).unwrap();
+    fn new(
+        object: &'a Object,
+        thumbnail_column: bool,
+        typed_parents: bool,
+        issue_date_sources: &[IssueDateSource],
+        descriptive_metadata: &[String],
+    ) -> Self {
+        // Unknown content models have already been handled by
+        // `UnknownModelRow::apply()`, any that remain are to be treated as
+        // binaries.
+        let model = Model::try_from(object.model.as_str()).unwrap_or(Model::Binary);
+        let edtf_date = match model {
+            Model::NewspaperIssue => {
+                issue_dates::issue_date(object, issue_date_sources, descriptive_metadata)
+                    .unwrap_or_default()
+            }
+            _ => String::new(),
+        };
 
         NodeRow {
             pid: &object.pid.0,
@@ -338,35 +603,1284 @@ impl<'a> NodeRow<'a> {
             state: &object.state.as_static(),
             display_hint: DisplayHint::from(model).as_str(),
             parents: object.parents.join("|"),
+            edtf_date,
+            datastream_count: object.datastreams.len(),
+            total_bytes: Self::total_bytes(object),
+            has_ocr: object
+                .datastreams
+                .iter()
+                .any(|datastream| OCR_DSIDS.contains(&datastream.id.as_str())),
+            has_preservation_master: object
+                .datastreams
+                .iter()
+                .any(|datastream| datastream.id == PRESERVATION_MASTER_DSID),
+            thumbnail: if thumbnail_column {
+                Some(ThumbnailRow::status(object))
+            } else {
+                None
+            },
+            member_of_collection: typed_parents.then(|| Self::typed_parents(object, 0)),
+            member_of: typed_parents.then(|| Self::typed_parents(object, 1)),
+            constituent_of: typed_parents.then(|| Self::typed_parents(object, 2)),
+            part_of: typed_parents.then(|| Self::typed_parents(object, 3)),
+            other_parents: typed_parents.then(|| Self::other_parents(object)),
         }
     }
 
-    pub fn csv(objects: &ObjectMap, dest: &Path, progress_bar: ProgressBar) {
-        progress_bar.set_length(objects.objects().count() as u64);
+    fn total_bytes(object: &Object) -> u64 {
+        object
+            .datastreams
+            .iter()
+            .flat_map(|datastream| &datastream.versions)
+            .filter(|version| version.url().is_none())
+            .filter_map(|version| version.path().metadata().ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+
+    // Parents related by the predicates of the given column of `TYPED_PARENTS`.
+    fn typed_parents(object: &Object, column: usize) -> String {
+        let (_, predicates) = TYPED_PARENTS[column];
+        Self::join_parents(object, |predicate| predicates.contains(&predicate))
+    }
+
+    fn other_parents(object: &Object) -> String {
+        Self::join_parents(object, |predicate| {
+            !TYPED_PARENTS
+                .iter()
+                .any(|(_, predicates)| predicates.contains(&predicate))
+        })
+    }
+
+    fn join_parents<F>(object: &Object, filter: F) -> String
+    where
+        F: Fn(&str) -> bool,
+    {
+        let mut parents = object
+            .relationships
+            .iter()
+            .filter(|relationship| filter(&relationship.predicate))
+            .map(|relationship| relationship.target.as_str())
+            .collect::<Vec<_>>();
+        parents.sort_by(|a, b| alphanumeric_sort::compare_str(a, b));
+        parents.dedup();
+        parents.join("|")
+    }
+
+    pub fn csv(
+        objects: &ObjectMap,
+        output: &Output,
+        progress_bar: ProgressBar,
+        thumbnail_column: bool,
+        typed_parents: bool,
+        issue_date_sources: &[IssueDateSource],
+        descriptive_metadata: &[String],
+    ) {
+        logger::set_length(&progress_bar, objects.objects().count() as u64);
         let rows: Vec<_> = objects
             .objects()
             .map(|row| {
                 progress_bar.inc(1);
-                NodeRow::new(row)
+                NodeRow::new(
+                    row,
+                    thumbnail_column,
+                    typed_parents,
+                    issue_date_sources,
+                    descriptive_metadata,
+                )
             })
             .collect();
-        create_csv(&rows, &dest.join("nodes.csv")).expect("Failed to create media_revisions.csv");
+        let undated = rows
+            .iter()
+            .filter(|row| {
+                row.model == Model::NewspaperIssue.identifier() && row.edtf_date.is_empty()
+            })
+            .count();
+        if undated > 0 {
+            warn!(
+                "Failed to find the date of {} newspaper issues, their edtf_date is empty in nodes.csv",
+                undated
+            );
+        }
+        output
+            .write_rows(&rows, "nodes.csv")
+            .expect("Failed to create nodes.csv");
         progress_bar.finish_with_message("Created nodes.csv");
     }
 }
 
-pub fn create_csv<S>(rows: &[S], dest: &Path) -> Result<(), std::io::Error>
-where
-    S: Serialize,
-{
-    let builder = csv_other::WriterBuilder::new();
-    let mut writer = builder.from_path(&dest)?;
-    for row in rows {
-        writer.serialize(row)?;
+// Every relationship to a parent object, the parents column of nodes.csv does
+// not distinguish between them.
+#[derive(Serialize)]
+pub struct RelationshipRow<'a> {
+    pid: &'a str,
+    predicate: &'a str,
+    target: &'a str,
+}
+
+impl Row for RelationshipRow<'_> {
+    fn columns() -> &'static [&'static str] {
+        &["pid", "predicate", "target"]
+    }
+}
+
+impl<'a> RelationshipRow<'a> {
+    pub fn csv(objects: &ObjectMap, output: &Output, progress_bar: ProgressBar) {
+        logger::set_length(&progress_bar, objects.objects().count() as u64);
+        let rows = objects
+            .objects()
+            .flat_map_iter(|object| {
+                progress_bar.inc(1);
+                object
+                    .relationships
+                    .iter()
+                    .map(move |relationship| RelationshipRow {
+                        pid: &object.pid.0,
+                        predicate: &relationship.predicate,
+                        target: &relationship.target,
+                    })
+            })
+            .collect::<Vec<_>>();
+        output
+            .write_rows(&rows, "relationships.csv")
+            .expect("Failed to create relationships.csv");
+        progress_bar.finish_with_message("Created relationships.csv");
+    }
+}
+
+// Objects without a usable thumbnail (TN datastream), which will need one
+// generated in Drupal after import.
+#[derive(Serialize)]
+pub struct ThumbnailRow<'a> {
+    pid: &'a str,
+    model: &'a str,
+    label: &'a str,
+    status: &'static str,
+}
+
+impl Row for ThumbnailRow<'_> {
+    fn columns() -> &'static [&'static str] {
+        &["pid", "model", "label", "status"]
+    }
+}
+
+impl<'a> ThumbnailRow<'a> {
+    // One of 'ok', 'missing' (no TN datastream), 'missing_file' (its file was
+    // not migrated) or 'empty' (a zero-byte file).
+    fn status(object: &Object) -> &'static str {
+        match object.datastream("TN") {
+            None => "missing",
+            Some(version) => match version.path().metadata() {
+                Err(_) => "missing_file",
+                Ok(metadata) if metadata.len() == 0 => "empty",
+                Ok(_) => "ok",
+            },
+        }
+    }
+
+    fn new(object: &'a Object) -> Option<Self> {
+        match Self::status(object) {
+            "ok" => None,
+            status => Some(ThumbnailRow {
+                pid: &object.pid.0,
+                model: &object.model,
+                label: &object.label,
+                status,
+            }),
+        }
+    }
+
+    pub fn csv(objects: &ObjectMap, output: &Output, progress_bar: ProgressBar) {
+        logger::set_length(&progress_bar, objects.objects().count() as u64);
+        let rows = objects
+            .objects()
+            .filter_map(|object| {
+                progress_bar.inc(1);
+                ThumbnailRow::new(object)
+            })
+            .collect::<Vec<_>>();
+        output
+            .write_report(&rows, "missing_thumbnails.csv")
+            .expect("Failed to create missing_thumbnails.csv");
+        progress_bar.finish_with_message("Created missing_thumbnails.csv");
+    }
+}
+
+// Datastream versions whose mime type was normalized, or is not registered
+// with IANA, so that typos can be corrected in Fedora if need be.
+#[derive(Serialize)]
+pub struct MimeTypeRow<'a> {
+    pid: &'a str,
+    dsid: &'a str,
+    version: &'a str,
+    original: &'a str,
+    normalized: &'a str,
+    // One of 'normalized', 'unregistered' or 'invalid'.
+    status: &'static str,
+}
+
+impl Row for MimeTypeRow<'_> {
+    fn columns() -> &'static [&'static str] {
+        &["pid", "dsid", "version", "original", "normalized", "status"]
+    }
+}
+
+impl<'a> MimeTypeRow<'a> {
+    fn new(version: &'a DatastreamVersion) -> Option<Self> {
+        let original = version
+            .original_mime_type
+            .as_deref()
+            .unwrap_or(&version.mime_type);
+        match normalize_mime_type(original).1 {
+            MimeTypeStatus::Valid => None,
+            status => Some(MimeTypeRow {
+                pid: &version.pid,
+                dsid: &version.dsid,
+                version: &version.id,
+                original,
+                normalized: &version.mime_type,
+                status: status.as_str(),
+            }),
+        }
+    }
+
+    pub fn csv(objects: &ObjectMap, output: &Output, progress_bar: ProgressBar) {
+        logger::set_length(&progress_bar, objects.versions().count() as u64);
+        let rows = objects
+            .versions()
+            .filter_map(|(_, _, version)| {
+                progress_bar.inc(1);
+                MimeTypeRow::new(version)
+            })
+            .collect::<Vec<_>>();
+        let unknown = rows
+            .iter()
+            .filter(|row| row.status != MimeTypeStatus::Normalized.as_str())
+            .count();
+        if !rows.is_empty() {
+            warn!(
+                "{} mime types were normalized and {} are unregistered or invalid, see mime_types.csv",
+                rows.len() - unknown,
+                unknown
+            );
+        }
+        output
+            .write_report(&rows, "mime_types.csv")
+            .expect("Failed to create mime_types.csv");
+        progress_bar.finish_with_message("Created mime_types.csv");
+    }
+}
+
+// Long format export of the DC datastream, one row per element value.
+#[derive(Serialize)]
+pub struct DcRow<'a> {
+    pid: &'a str,
+    element: String,
+    qualifier: String,
+    value: String,
+}
+
+impl Row for DcRow<'_> {
+    fn columns() -> &'static [&'static str] {
+        &["pid", "element", "qualifier", "value"]
+    }
+}
+
+impl<'a> DcRow<'a> {
+    fn new(object: &'a Object, pid_map: &PidMap) -> Vec<Self> {
+        let dc = match object
+            .datastream("DC")
+            .map(|version| xml::parse(version, xml::XmlLimits::default(), pid_map))
+        {
+            Some(Some(Ok(dc))) => dc,
+            Some(Some(Err(err))) => {
+                report_problem(
+                    Level::Error,
+                    "unparsable_dc",
+                    &format!("Failed to parse DC of {}, with error: {}", object.pid, err),
+                );
+                return vec![];
+            }
+            _ => return vec![],
+        };
+        let mut elements = dc
+            .keys()
+            .filter(|key| !(key.starts_with('@') || key.starts_with('#')))
+            .cloned()
+            .collect::<Vec<_>>();
+        elements.sort();
+        elements
+            .into_iter()
+            .flat_map(|element| {
+                let values = dc.get(&element).unwrap().clone().cast::<Array>();
+                values
+                    .into_iter()
+                    .map(|value| value.cast::<CustomMap>())
+                    .map(move |value| DcRow {
+                        pid: &object.pid.0,
+                        element: element.to_string(),
+                        // Qualified DC denotes refinements / encoding schemes via xsi:type.
+                        qualifier: Self::text(&value, "@xsi:type"),
+                        value: Self::text(&value, "#text"),
+                    })
+            })
+            .filter(|row| !row.value.is_empty())
+            .collect()
+    }
+
+    fn text(element: &CustomMap, key: &str) -> String {
+        element
+            .get(key)
+            .map(|value| value.clone().cast::<ImmutableString>().trim().to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn csv(objects: &ObjectMap, output: &Output, progress_bar: ProgressBar) {
+        logger::set_length(&progress_bar, objects.objects().count() as u64);
+        let rows = objects
+            .objects()
+            .flat_map_iter(|object| {
+                progress_bar.inc(1);
+                DcRow::new(object, objects.pid_map())
+            })
+            .collect::<Vec<_>>();
+        output
+            .write_rows(&rows, "dc.csv")
+            .expect("Failed to create dc.csv");
+        progress_bar.finish_with_message("Created dc.csv");
+    }
+}
+
+// Reports objects and datastreams in the Deleted state, so that it can be
+// signed off whether they were carried over. The dsid is empty for objects.
+#[derive(Serialize)]
+pub struct DeletedRow {
+    pid: String,
+    dsid: String,
+    modified_date: i64,
+    // Either "exclude" given `--exclude-deleted`, or "keep".
+    action: String,
+}
+
+impl Row for DeletedRow {
+    fn columns() -> &'static [&'static str] {
+        &["pid", "dsid", "modified_date", "action"]
+    }
+}
+
+impl DeletedRow {
+    fn new(object: &Object, action: &str) -> Vec<Self> {
+        let mut rows = Vec::new();
+        if object.state == ObjectState::Deleted {
+            rows.push(DeletedRow {
+                pid: object.pid.to_string(),
+                dsid: String::new(),
+                modified_date: format_date(&object.modified_date),
+                action: action.to_string(),
+            });
+        }
+        rows.extend(
+            object
+                .datastreams
+                .iter()
+                .filter(|datastream| datastream.state == DatastreamState::Deleted)
+                .map(|datastream| DeletedRow {
+                    pid: object.pid.to_string(),
+                    dsid: datastream.id.clone(),
+                    modified_date: format_date(&datastream.latest().created_date),
+                    action: action.to_string(),
+                }),
+        );
+        rows
+    }
+
+    // Reports all deleted objects and datastreams in deleted.csv, removing them
+    // if required so they are left out of every other CSV.
+    pub fn apply(objects: &mut ObjectMap, exclude: bool, output: &Output) {
+        let action = if exclude { "exclude" } else { "keep" };
+        let rows = objects
+            .objects()
+            .flat_map_iter(|object| DeletedRow::new(object, action))
+            .collect::<Vec<_>>();
+        output
+            .write_report(&rows, "deleted.csv")
+            .expect("Failed to create deleted.csv");
+        if !exclude || rows.is_empty() {
+            return;
+        }
+        let pids = rows
+            .iter()
+            .filter(|row| row.dsid.is_empty())
+            .map(|row| &row.pid)
+            .collect::<HashSet<_>>();
+        objects.retain(|object| !pids.contains(&object.pid.0));
+        for object in objects.objects_mut() {
+            object
+                .datastreams
+                .retain(|datastream| datastream.state != DatastreamState::Deleted);
+        }
+        info!(
+            "Excluded {} deleted objects and {} deleted datastreams, see deleted.csv",
+            pids.len(),
+            rows.len() - pids.len()
+        );
+    }
+}
+
+// Inventory of the system objects, content models and service definitions /
+// deployments, for auditing which models and services existed before the
+// migration. Content models list the datastreams (and their mime types) from
+// their DS-COMPOSITE-MODEL.
+#[derive(Serialize)]
+pub struct SystemObjectRow<'a> {
+    pid: &'a str,
+    model: &'a str,
+    label: &'a str,
+    state: &'static str,
+    datastreams: String,
+    composite_model: String,
+}
+
+impl Row for SystemObjectRow<'_> {
+    fn columns() -> &'static [&'static str] {
+        &[
+            "pid",
+            "model",
+            "label",
+            "state",
+            "datastreams",
+            "composite_model",
+        ]
+    }
+}
+
+impl<'a> SystemObjectRow<'a> {
+    fn new(object: &'a Object) -> Self {
+        let composite_model = object
+            .datastream("DS-COMPOSITE-MODEL")
+            .and_then(|version| std::fs::read_to_string(version.path()).ok())
+            .map(|content| {
+                Self::composite_model(&content).unwrap_or_else(|error| {
+                    warn!(
+                        "Failed to parse DS-COMPOSITE-MODEL of {}: {}",
+                        object.pid, error
+                    );
+                    String::new()
+                })
+            })
+            .unwrap_or_default();
+        SystemObjectRow {
+            pid: &object.pid.0,
+            model: &object.model,
+            label: &object.label,
+            state: object.state.as_static(),
+            datastreams: object
+                .datastreams
+                .iter()
+                .map(|datastream| datastream.id.as_str())
+                .collect::<Vec<_>>()
+                .join("; "),
+            composite_model,
+        }
+    }
+
+    // Lists each dsTypeModel as 'DSID (mime/type, ...)'.
+    fn composite_model(content: &str) -> Result<String, quick_xml::Error> {
+        let mut reader = Reader::from_str(content);
+        let mut buffer = Vec::new();
+        let mut models: Vec<(String, Vec<String>)> = Vec::new();
+        loop {
+            match reader.read_event(&mut buffer)? {
+                Event::Start(element) | Event::Empty(element) => {
+                    let attribute = |name: &[u8]| -> Result<Option<String>, quick_xml::Error> {
+                        element
+                            .attributes()
+                            .filter_map(|attribute| attribute.ok())
+                            .find(|attribute| attribute.key == name)
+                            .map(|attribute| attribute.unescape_and_decode_value(&reader))
+                            .transpose()
+                    };
+                    match element.local_name() {
+                        b"dsTypeModel" => {
+                            models.push((attribute(b"ID")?.unwrap_or_default(), Vec::new()))
+                        }
+                        b"form" => {
+                            if let (Some(mime), Some((_, mimes))) =
+                                (attribute(b"MIME")?, models.last_mut())
+                            {
+                                mimes.push(mime);
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                Event::Eof => break,
+                _ => (),
+            }
+            buffer.clear();
+        }
+        Ok(models
+            .into_iter()
+            .map(|(dsid, mimes)| {
+                if mimes.is_empty() {
+                    dsid
+                } else {
+                    format!("{} ({})", dsid, mimes.join(", "))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("; "))
+    }
+
+    pub fn csv(objects: &ObjectMap, output: &Output, progress_bar: ProgressBar) {
+        // Service objects are not excluded from the migration by default, as
+        // they have an unknown content model they are also reported as such.
+        let system = objects
+            .system_objects()
+            .values()
+            .chain(objects.inner().values().filter(|o| o.is_service_object()))
+            .collect::<Vec<_>>();
+        logger::set_length(&progress_bar, system.len() as u64);
+        let mut rows = system
+            .into_par_iter()
+            .map(|object| {
+                progress_bar.inc(1);
+                SystemObjectRow::new(object)
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| a.pid.cmp(b.pid));
+        output
+            .write_rows(&rows, "system_objects.csv")
+            .expect("Failed to create system_objects.csv");
+        progress_bar.finish_with_message("Created system_objects.csv");
+    }
+}
+
+// Embargoes to populate the Drupal embargo module with, the type is either
+// `node` for the whole object, or `file` for a single datastream. The
+// expiry_date is empty if the embargo is indefinite.
+#[derive(Serialize)]
+pub struct EmbargoRow<'a> {
+    pid: &'a str,
+    dsid: String,
+    #[serde(rename = "type")]
+    embargo_type: &'static str,
+    expiration_type: &'static str,
+    expiry_date: String,
+    source: &'static str,
+}
+
+impl Row for EmbargoRow<'_> {
+    fn columns() -> &'static [&'static str] {
+        &[
+            "pid",
+            "dsid",
+            "type",
+            "expiration_type",
+            "expiry_date",
+            "source",
+        ]
+    }
+}
+
+impl<'a> EmbargoRow<'a> {
+    fn new(object: &'a Object) -> Vec<Self> {
+        embargo::embargoes(object)
+            .into_iter()
+            .map(|embargo| EmbargoRow {
+                pid: &object.pid.0,
+                embargo_type: if embargo.dsid.is_empty() {
+                    "node"
+                } else {
+                    "file"
+                },
+                dsid: embargo.dsid,
+                expiration_type: if embargo.expiry_date.is_some() {
+                    "scheduled"
+                } else {
+                    "indefinite"
+                },
+                expiry_date: embargo.expiry_date.unwrap_or_default(),
+                source: embargo.source,
+            })
+            .collect()
+    }
+
+    pub fn csv(objects: &ObjectMap, output: &Output, progress_bar: ProgressBar) {
+        logger::set_length(&progress_bar, objects.objects().count() as u64);
+        let mut rows = objects
+            .objects()
+            .flat_map_iter(|object| {
+                progress_bar.inc(1);
+                EmbargoRow::new(object)
+            })
+            .collect::<Vec<_>>();
+        rows.sort_by(|a, b| (a.pid, &a.dsid).cmp(&(b.pid, &b.dsid)));
+        output
+            .write_rows(&rows, "embargoes.csv")
+            .expect("Failed to create embargoes.csv");
+        progress_bar.finish_with_message("Created embargoes.csv");
+    }
+}
+
+// Reports datastream versions whose name or path is shared with another version.
+#[derive(Serialize)]
+pub struct FileCollisionRow {
+    pid: String,
+    dsid: String,
+    version: String,
+    column: &'static str,
+    value: String,
+    renamed: String,
+}
+
+impl Row for FileCollisionRow {
+    fn columns() -> &'static [&'static str] {
+        &["pid", "dsid", "version", "column", "value", "renamed"]
+    }
+}
+
+impl FileCollisionRow {
+    // Finds colliding file names / paths and reports them in
+    // file_collisions.csv, if requested the names of all but the first version
+    // in a collision are made unique, paths cannot be changed as they refer to
+    // the files on disk.
+    pub fn apply(objects: &mut ObjectMap, disambiguate: bool, output: &Output) {
+        let mut names = BTreeMap::<String, Vec<&mut DatastreamVersion>>::new();
+        let mut paths = BTreeMap::<String, Vec<(String, String, String)>>::new();
+        for version in objects.versions_mut() {
+            let path = FileRow::stream_wrapper_path(&version.pid, &version.path());
+            let key = (
+                version.pid.clone(),
+                version.dsid.clone(),
+                version.id.clone(),
+            );
+            paths.entry(path).or_default().push(key);
+            names.entry(version.name()).or_default().push(version);
+        }
+        let mut taken = names.keys().cloned().collect::<HashSet<_>>();
+        let mut rows = Vec::new();
+        for (path, versions) in paths.into_iter().filter(|(_, v)| v.len() > 1) {
+            for (pid, dsid, version) in versions {
+                rows.push(FileCollisionRow {
+                    pid,
+                    dsid,
+                    version,
+                    column: "path",
+                    value: path.clone(),
+                    renamed: "".to_string(),
+                });
+            }
+        }
+        for (name, versions) in names.into_iter().filter(|(_, v)| v.len() > 1) {
+            for (index, version) in versions.into_iter().enumerate() {
+                let renamed = if disambiguate && index > 0 {
+                    let renamed = Self::unique_name(&name, &taken);
+                    taken.insert(renamed.clone());
+                    version.renamed = Some(renamed.clone());
+                    renamed
+                } else {
+                    "".to_string()
+                };
+                rows.push(FileCollisionRow {
+                    pid: version.pid.clone(),
+                    dsid: version.dsid.clone(),
+                    version: version.id.clone(),
+                    column: "name",
+                    value: name.clone(),
+                    renamed,
+                });
+            }
+        }
+        output
+            .write_report(&rows, "file_collisions.csv")
+            .expect("Failed to create file_collisions.csv");
+        if !rows.is_empty() {
+            warn!(
+                "Found {} datastream versions with colliding file names or paths, see file_collisions.csv",
+                rows.len()
+            );
+        }
+    }
+
+    // Appends the lowest available counter to the file stem, e.g. 'scan_1.pdf'.
+    fn unique_name(name: &str, taken: &HashSet<String>) -> String {
+        let path = Path::new(name);
+        let stem = path.file_stem().unwrap().to_string_lossy();
+        let extension = path
+            .extension()
+            .map_or("".to_string(), |e| format!(".{}", e.to_string_lossy()));
+        (1..)
+            .map(|i| format!("{}_{}{}", stem, i, extension))
+            .find(|name| !taken.contains(name))
+            .unwrap()
     }
-    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct UnknownModelRow {
+    pid: String,
+    model: String,
+    action: String,
+}
+
+impl Row for UnknownModelRow {
+    fn columns() -> &'static [&'static str] {
+        &["pid", "model", "action"]
+    }
+}
+
+impl UnknownModelRow {
+    // Applies the given policy to all objects with an unknown content model,
+    // and reports them in unknown_models.csv, returning an error if required.
+    pub fn apply(
+        objects: &mut ObjectMap,
+        policy: UnknownModelPolicy,
+        output: &Output,
+    ) -> Result<(), String> {
+        let policy = if is_fail_fast() {
+            UnknownModelPolicy::Fail
+        } else {
+            policy
+        };
+        let rows = objects
+            .objects()
+            .filter(|object| Model::try_from(object.model.as_str()).is_err())
+            .map(|object| UnknownModelRow {
+                pid: object.pid.to_string(),
+                model: object.model.clone(),
+                action: policy.to_string(),
+            })
+            .collect::<Vec<_>>();
+        output
+            .write_report(&rows, "unknown_models.csv")
+            .expect("Failed to create unknown_models.csv");
+        if rows.is_empty() {
+            return Ok(());
+        }
+        warn!(
+            "Found {} objects with unknown content models, see unknown_models.csv:\n\t{}",
+            rows.len(),
+            rows.iter()
+                .map(|row| format!("{} ({})", row.pid, row.model))
+                .collect::<Vec<_>>()
+                .join("\n\t")
+        );
+        match policy {
+            UnknownModelPolicy::Skip => {
+                let pids = rows.iter().map(|row| &row.pid).collect::<HashSet<_>>();
+                objects.retain(|object| !pids.contains(&object.pid.0));
+            }
+            UnknownModelPolicy::Binary => (),
+            UnknownModelPolicy::Fail => {
+                return Err(format!(
+                    "Found {} objects with unknown content models, see unknown_models.csv",
+                    rows.len()
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+pub struct MissingFileRow<'a> {
+    pid: &'a str,
+    dsid: &'a str,
+    version: &'a str,
+    path: String,
+    action: String,
+}
+
+impl Row for MissingFileRow<'_> {
+    fn columns() -> &'static [&'static str] {
+        &["pid", "dsid", "version", "path", "action"]
+    }
+}
+
+impl<'a> MissingFileRow<'a> {
+    // Reports all datastream versions whose file is not present in
+    // missing_files.csv, failing if required.
+    pub fn apply(objects: &ObjectMap, policy: MissingFilePolicy, output: &Output) {
+        let policy = if is_fail_fast() {
+            MissingFilePolicy::Fail
+        } else {
+            policy
+        };
+        let rows = objects
+            .versions()
+            .filter_map(|(object, datastream, version)| {
+                let path = version.path();
+                if version.url().is_some() || path.exists() {
+                    return None;
+                }
+                Some(MissingFileRow {
+                    pid: &object.pid.0,
+                    dsid: &datastream.id,
+                    version: &version.id,
+                    path: path.to_string_lossy().to_string(),
+                    action: policy.to_string(),
+                })
+            })
+            .collect::<Vec<_>>();
+        output
+            .write_report(&rows, "missing_files.csv")
+            .expect("Failed to create missing_files.csv");
+        if rows.is_empty() {
+            return;
+        }
+        warn!(
+            "Found {} datastream versions whose file is missing, see missing_files.csv",
+            rows.len()
+        );
+        if policy == MissingFilePolicy::Fail {
+            let row = &rows[0];
+            report_listed_problem(
+                Level::Error,
+                &format!(
+                    "Missing file {} of {} {} {}",
+                    row.path, row.pid, row.dsid, row.version
+                ),
+            );
+            panic!(
+                "Found {} datastream versions whose file is missing.",
+                rows.len()
+            );
+        }
+    }
+
+    // False if the version should be omitted from files.csv and media.csv.
+    fn include(
+        (_, _, version): &(&Object, &Datastream, &DatastreamVersion),
+        policy: MissingFilePolicy,
+    ) -> bool {
+        policy != MissingFilePolicy::Skip || version.url().is_some() || version.path().exists()
+    }
+}
+
+#[derive(Serialize)]
+pub struct DanglingParentRow {
+    pid: String,
+    parent: String,
+    action: String,
+}
+
+impl Row for DanglingParentRow {
+    fn columns() -> &'static [&'static str] {
+        &["pid", "parent", "action"]
+    }
+}
+
+impl DanglingParentRow {
+    // Applies the given policy to all parents that reference objects which
+    // were not migrated, and reports them in dangling_parents.csv.
+    pub fn apply(objects: &mut ObjectMap, policy: &DanglingParentPolicy, output: &Output) {
+        // Parents processed by another shard, or excluded by `--pids`, etc.
+        // were still migrated.
+        let pids = objects
+            .inner()
+            .keys()
+            .map(|pid| pid.0.clone())
+            .chain(objects.not_loaded().iter().cloned())
+            .collect::<HashSet<_>>();
+        let action = match policy {
+            DanglingParentPolicy::Placeholder(placeholder) => {
+                format!("Placeholder ({})", placeholder)
+            }
+            policy => format!("{:?}", policy),
+        };
+        let rows = objects
+            .objects()
+            .flat_map_iter(|object| {
+                object
+                    .parents
+                    .iter()
+                    .filter(|parent| !pids.contains(*parent))
+                    .map(|parent| DanglingParentRow {
+                        pid: object.pid.to_string(),
+                        parent: parent.clone(),
+                        action: action.clone(),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        output
+            .write_report(&rows, "dangling_parents.csv")
+            .expect("Failed to create dangling_parents.csv");
+        if rows.is_empty() {
+            return;
+        }
+        warn!(
+            "Found {} references to parents that were not migrated, see dangling_parents.csv",
+            rows.len()
+        );
+        let dangling = |parent: &String| !pids.contains(parent);
+        match policy {
+            DanglingParentPolicy::Keep => (),
+            DanglingParentPolicy::Blank => {
+                for object in objects.objects_mut() {
+                    object.parents.retain(|parent| !dangling(parent));
+                    object
+                        .relationships
+                        .retain(|relationship| !dangling(&relationship.target));
+                }
+            }
+            DanglingParentPolicy::Placeholder(placeholder) => {
+                for object in objects.objects_mut() {
+                    if object.parents.iter().any(dangling) {
+                        object.parents.retain(|parent| !dangling(parent));
+                        if !object.parents.contains(placeholder) {
+                            object.parents.push(placeholder.clone());
+                        }
+                        // Keeping the predicate of the dangling relationship.
+                        for relationship in object.relationships.iter_mut() {
+                            if dangling(&relationship.target) {
+                                relationship.target = placeholder.clone();
+                            }
+                        }
+                        let mut seen = HashSet::new();
+                        object
+                            .relationships
+                            .retain(|relationship| seen.insert(relationship.clone()));
+                    }
+                }
+            }
+            DanglingParentPolicy::Fail => panic!(
+                "Found {} references to parents that were not migrated.",
+                rows.len()
+            ),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct InvalidOwnerRow {
+    pid: String,
+    owner: String,
+    action: String,
+    // The owner once the policy has been applied.
+    replacement: String,
+}
+
+impl Row for InvalidOwnerRow {
+    fn columns() -> &'static [&'static str] {
+        &["pid", "owner", "action", "replacement"]
+    }
+}
+
+impl InvalidOwnerRow {
+    // Users listed in the given ownerId, none if it is empty.
+    fn owners(owner: &str) -> Vec<&str> {
+        owner
+            .split(',')
+            .map(str::trim)
+            .filter(|owner| !owner.is_empty())
+            .collect()
+    }
+
+    fn replacement(owner: &str, policy: &InvalidOwnerPolicy) -> String {
+        match policy {
+            InvalidOwnerPolicy::First => Self::owners(owner)
+                .first()
+                .map_or_else(|| owner.to_string(), |first| map_user(first)),
+            InvalidOwnerPolicy::Default(user) => user.clone(),
+            InvalidOwnerPolicy::Report | InvalidOwnerPolicy::Fail => owner.to_string(),
+        }
+    }
+
+    // Applies the given policy to all objects whose ownerId is empty or lists
+    // multiple users, and reports them in invalid_owners.csv.
+    pub fn apply(objects: &mut ObjectMap, policy: &InvalidOwnerPolicy, output: &Output) {
+        let action = match policy {
+            InvalidOwnerPolicy::Default(user) => format!("Default ({})", user),
+            policy => format!("{:?}", policy),
+        };
+        let rows = objects
+            .objects()
+            .filter(|object| Self::owners(&object.owner).len() != 1)
+            .map(|object| InvalidOwnerRow {
+                pid: object.pid.to_string(),
+                owner: object.owner.clone(),
+                action: action.clone(),
+                replacement: Self::replacement(&object.owner, policy),
+            })
+            .collect::<Vec<_>>();
+        output
+            .write_report(&rows, "invalid_owners.csv")
+            .expect("Failed to create invalid_owners.csv");
+        if rows.is_empty() {
+            return;
+        }
+        warn!(
+            "Found {} objects with an empty or multi-valued owner, see invalid_owners.csv",
+            rows.len()
+        );
+        if let InvalidOwnerPolicy::Fail = policy {
+            panic!(
+                "Found {} objects with an empty or multi-valued owner.",
+                rows.len()
+            );
+        }
+        let replacements = rows
+            .into_iter()
+            .map(|row| (row.pid, row.replacement))
+            .collect::<HashMap<_, _>>();
+        for object in objects.objects_mut() {
+            if let Some(replacement) = replacements.get(&object.pid.0) {
+                object.owner = replacement.clone();
+            }
+        }
+    }
+}
+
+// The header and rows of a CSV file.
+pub type Table = (Vec<String>, Vec<Vec<String>>);
+
+// The rows each built-in CSV file would include for the given object, see the
+// `inspect` command. Those only written given an option (e.g. `--mets`) are
+// omitted.
+pub fn inspect(
+    object: &Object,
+    descriptive_metadata: &[String],
+    pid_map: &PidMap,
+) -> Vec<(&'static str, Table)> {
+    let versions = object
+        .datastreams
+        .iter()
+        .flat_map(|datastream| {
+            datastream
+                .versions
+                .iter()
+                .map(move |version| (object, datastream, version))
+        })
+        .collect::<Vec<_>>();
+    let is_latest = |(_, datastream, version): &&(&Object, &Datastream, &DatastreamVersion)| {
+        std::ptr::eq(*version, datastream.latest())
+    };
+    let mut files = versions
+        .iter()
+        .map(|&row| FileRow::new(row))
+        .collect::<Vec<_>>();
+    for row in files.iter_mut() {
+        if let Some(source) = &row.source {
+            row.sha1 = checksums::sha1(source);
+        }
+    }
+    let table = |rows: Result<Table, serde_json::Error>| {
+        rows.expect("Failed to serialize the rows of the object")
+    };
+    vec![
+        (
+            "nodes.csv",
+            table(output::to_table(&[NodeRow::new(
+                object,
+                false,
+                false,
+                &[IssueDateSource::Mods, IssueDateSource::Label],
+                descriptive_metadata,
+            )])),
+        ),
+        (
+            "media.csv",
+            table(output::to_table(
+                &versions
+                    .iter()
+                    .filter(is_latest)
+                    .map(|&row| MediaRow::new(row))
+                    .collect::<Vec<_>>(),
+            )),
+        ),
+        (
+            "media_revisions.csv",
+            table(output::to_table(
+                &versions
+                    .iter()
+                    .filter(|row| !is_latest(row))
+                    .map(|&row| MediaRow::new(row))
+                    .collect::<Vec<_>>(),
+            )),
+        ),
+        ("files.csv", table(output::to_table(&files))),
+        (
+            "relationships.csv",
+            table(output::to_table(
+                &object
+                    .relationships
+                    .iter()
+                    .map(|relationship| RelationshipRow {
+                        pid: &object.pid.0,
+                        predicate: &relationship.predicate,
+                        target: &relationship.target,
+                    })
+                    .collect::<Vec<_>>(),
+            )),
+        ),
+        (
+            "dc.csv",
+            table(output::to_table(&DcRow::new(object, pid_map))),
+        ),
+        (
+            "deleted.csv",
+            table(output::to_table(&DeletedRow::new(object, "keep"))),
+        ),
+        (
+            "embargoes.csv",
+            table(output::to_table(&EmbargoRow::new(object))),
+        ),
+        (
+            "mime_types.csv",
+            table(output::to_table(
+                &versions
+                    .iter()
+                    .filter_map(|(_, _, version)| MimeTypeRow::new(version))
+                    .collect::<Vec<_>>(),
+            )),
+        ),
+        (
+            "missing_thumbnails.csv",
+            table(output::to_table(
+                &ThumbnailRow::new(object).into_iter().collect::<Vec<_>>(),
+            )),
+        ),
+    ]
 }
 
 fn format_date(date_time: &DateTime<FixedOffset>) -> i64 {
     date_time.timestamp()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn unique_name() {
+        let taken = hashset! {
+            "scan.pdf".to_string(),
+            "scan_1.pdf".to_string(),
+            "README".to_string(),
+        };
+        assert_eq!(
+            FileCollisionRow::unique_name("scan.pdf", &taken),
+            "scan_2.pdf"
+        );
+        assert_eq!(FileCollisionRow::unique_name("README", &taken), "README_1");
+    }
+
+    #[test]
+    fn composite_model() {
+        let content = r#"<dsCompositeModel xmlns="info:fedora/fedora-system:def/dsCompositeModel#">
+            <dsTypeModel ID="DC"><form FORMAT_URI="http://www.openarchives.org/OAI/2.0/oai_dc/" MIME="text/xml"/></dsTypeModel>
+            <dsTypeModel ID="OBJ" optional="false"><form MIME="image/tiff"/><form MIME="image/jp2"/></dsTypeModel>
+            <dsTypeModel ID="TN" optional="true"/>
+        </dsCompositeModel>"#;
+        assert_eq!(
+            SystemObjectRow::composite_model(content).unwrap(),
+            "DC (text/xml); OBJ (image/tiff, image/jp2); TN"
+        );
+    }
+
+    #[test]
+    fn node_inventory() {
+        let directory = std::env::temp_dir().join("csv_node_inventory_test");
+        crate::set_datastreams_directory(&directory);
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("../../assets/fixtures/valid.foxml.xml");
+        let foxml = foxml::Foxml::from_path(&path).unwrap();
+        let datastream = |dsid: &str| Datastream {
+            id: dsid.to_string(),
+            state: DatastreamState::Active,
+            versions: foxml::Foxml::from_path(&path)
+                .unwrap()
+                .datastreams
+                .remove(0)
+                .versions
+                .into_iter()
+                .map(|version| DatastreamVersion::new(foxml.pid.clone(), dsid.to_string(), version))
+                .collect(),
+        };
+        let mut object = Object {
+            pid: Pid(foxml.pid.clone()),
+            state: ObjectState::Active,
+            owner: String::new(),
+            label: String::new(),
+            model: String::new(),
+            parents: vec![],
+            relationships: vec![],
+            created_date: foxml.properties.created_date().unwrap(),
+            modified_date: foxml.properties.modified_date().unwrap(),
+            datastreams: vec![datastream("OBJ"), datastream("TN")],
+            weight: None,
+        };
+        // Only the one version has been migrated.
+        let version = object.datastreams[0].latest().path();
+        std::fs::create_dir_all(version.parent().unwrap()).unwrap();
+        std::fs::write(&version, "abc").unwrap();
+        let row = NodeRow::new(&object, false, false, &[], &[]);
+        assert_eq!(row.datastream_count, 2);
+        assert_eq!(row.total_bytes, 3);
+        assert!(!row.has_ocr);
+        assert!(row.has_preservation_master);
+        object.datastreams = vec![datastream("HOCR")];
+        let row = NodeRow::new(&object, false, false, &[], &[]);
+        assert!(row.has_ocr);
+        assert!(!row.has_preservation_master);
+        // As printed by the `inspect` command.
+        let versions = object.datastreams[0].versions.len();
+        let tables = inspect(&object, &[], &PidMap::default())
+            .into_iter()
+            .map(|(name, (header, rows))| (name, (header, rows.len())))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(tables["nodes.csv"].0[0], "pid");
+        assert_eq!(tables["nodes.csv"].1, 1);
+        assert_eq!(tables["media.csv"].1, 1);
+        assert_eq!(tables["media_revisions.csv"].1, versions - 1);
+        assert_eq!(tables["files.csv"].1, versions);
+        assert_eq!(tables["relationships.csv"].1, 0);
+        assert_eq!(tables["deleted.csv"].1, 0);
+        // Listed in deleted.csv along with its deleted datastreams.
+        object.state = ObjectState::Deleted;
+        object.datastreams[0].state = DatastreamState::Deleted;
+        let rows = DeletedRow::new(&object, "exclude");
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].dsid, "");
+        assert_eq!(rows[1].dsid, "HOCR");
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn stream_wrapper_path() {
+        let path = Path::new("/data/datastreams/archden:463/OBJ/OBJ.0/scan.pdf");
+        assert_eq!(
+            FileRow::stream_wrapper_path("archden:463", path),
+            "private://fedora/datastreams/archden:463/OBJ/OBJ.0/scan.pdf"
+        );
+    }
+
+    #[test]
+    fn invalid_owners() {
+        assert_eq!(InvalidOwnerRow::owners("jdoe"), vec!["jdoe"]);
+        assert_eq!(InvalidOwnerRow::owners(" , "), Vec::<&str>::new());
+        let replacement = |owner, policy| InvalidOwnerRow::replacement(owner, &policy);
+        let first = InvalidOwnerPolicy::First;
+        assert_eq!(replacement("fedoraAdmin, jdoe", first.clone()), "admin");
+        assert_eq!(replacement("", first), "");
+        let default = InvalidOwnerPolicy::Default("migration".to_string());
+        assert_eq!(replacement("", default), "migration");
+        assert_eq!(replacement("a,b", InvalidOwnerPolicy::Report), "a,b");
+    }
+
+    #[test]
+    fn policy_actions() {
+        // The `action` column matches the values given on the command line.
+        for policy in &["skip", "binary", "fail"] {
+            let action = UnknownModelPolicy::from_str(policy).unwrap().to_string();
+            assert_eq!(&action, policy);
+        }
+        for policy in &["skip", "placeholder", "fail"] {
+            let action = MissingFilePolicy::from_str(policy).unwrap().to_string();
+            assert_eq!(&action, policy);
+        }
+    }
+}
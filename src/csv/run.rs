@@ -0,0 +1,80 @@
+// Repeated experiments can be kept apart by nesting their output in a unique
+// directory per run, along with a record of how the output was produced.
+use chrono::{DateTime, Local};
+use log::info;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub struct Run {
+    id: String,
+    version: &'static str,
+    started: DateTime<Local>,
+}
+
+#[derive(Serialize)]
+struct RunMetadata<'a> {
+    id: &'a str,
+    started: String,
+    version: &'a str,
+    arguments: Vec<String>,
+    // `git describe` of each scripts directory, if under version control.
+    scripts: BTreeMap<String, Option<String>>,
+    objects: usize,
+}
+
+impl Run {
+    // The version is that of the binary rather than this library.
+    pub fn new(id: &str, version: &'static str) -> Self {
+        Run {
+            id: id.to_string(),
+            version,
+            started: Local::now(),
+        }
+    }
+
+    // Creates the directory for this run within the given destination.
+    pub fn directory(&self, dest: &Path) -> PathBuf {
+        let directory = dest.join(format!(
+            "{}_{}",
+            self.started.format("%Y%m%dT%H%M%S"),
+            self.id
+        ));
+        std::fs::create_dir_all(&directory)
+            .unwrap_or_else(|error| panic!("Failed to create {}: {}", directory.display(), error));
+        info!("Writing output to: {}", directory.display());
+        directory
+    }
+
+    pub fn write_metadata(&self, directory: &Path, scripts: &[&Path], objects: usize) {
+        let metadata = RunMetadata {
+            id: &self.id,
+            started: self.started.to_rfc3339(),
+            version: self.version,
+            arguments: std::env::args().collect(),
+            scripts: scripts
+                .iter()
+                .map(|path| (path.display().to_string(), git_describe(path)))
+                .collect(),
+            objects,
+        };
+        let dest = directory.join("run.json");
+        File::create(&dest)
+            .map_err(serde_json::Error::io)
+            .and_then(|file| serde_json::to_writer_pretty(file, &metadata))
+            .unwrap_or_else(|error| panic!("Failed to write {}: {}", dest.display(), error));
+    }
+}
+
+fn git_describe(path: &Path) -> Option<String> {
+    Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .args(["describe", "--always", "--dirty", "--tags"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
@@ -0,0 +1,164 @@
+// Describes the shape of every generated table in schema.json, so that the
+// Drupal migration YAML and validation tooling can be generated from, or
+// checked against, the actual output. For example:
+// {"format":"csv","tables":[{"file":"nodes.csv","script":null,"sort_by":null,
+//  "rows":1,"columns":[{"name":"pid","type":"string","nullable":false},...]}]}
+use super::output::Output;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+static SCHEMA: &str = "schema.json";
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Column {
+    name: String,
+    // One of 'integer', 'number', 'boolean' or 'string', the most specific
+    // type shared by every non-empty value.
+    r#type: &'static str,
+    // Some values are empty.
+    nullable: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Table {
+    file: String,
+    // The script that generated the table, none for those built in.
+    script: Option<String>,
+    // Column the rows are sorted by, if known.
+    sort_by: Option<String>,
+    rows: usize,
+    // Empty if a built in table has no rows, as its header is then unknown.
+    columns: Vec<Column>,
+}
+
+// The tables written so far by file stem, which is the same regardless of
+// output format.
+#[derive(Default)]
+pub struct Schema {
+    tables: Mutex<BTreeMap<String, Table>>,
+}
+
+impl Schema {
+    // Records the script that generates the given table and its sort column.
+    pub fn describe(&self, dest: &Path, script: &Path, sort_by: &str) {
+        let mut tables = self.tables.lock().unwrap();
+        let table = tables.entry(stem(dest)).or_default();
+        table.script = Some(script.to_string_lossy().to_string());
+        table.sort_by = Some(sort_by.to_string());
+    }
+}
+
+fn stem(dest: &Path) -> String {
+    dest.file_stem().unwrap().to_string_lossy().to_string()
+}
+
+fn value_type(value: &Value) -> Option<&'static str> {
+    match value {
+        Value::Null => None,
+        Value::String(value) if value.is_empty() => None,
+        Value::Bool(_) => Some("boolean"),
+        Value::Number(number) if number.is_f64() => Some("number"),
+        Value::Number(_) => Some("integer"),
+        _ => Some("string"),
+    }
+}
+
+// The shape of a table, built up as each row is written.
+pub struct TableSchema {
+    header: Vec<String>,
+    // The type shared by the values seen so far in each column, if any, and
+    // whether any were empty.
+    columns: Vec<(Option<&'static str>, bool)>,
+    rows: usize,
+}
+
+impl TableSchema {
+    pub fn new(header: &[String]) -> Self {
+        TableSchema {
+            header: header.to_vec(),
+            columns: vec![(None, false); header.len()],
+            rows: 0,
+        }
+    }
+
+    pub fn add(&mut self, row: &[Value]) {
+        self.rows += 1;
+        for (index, (r#type, nullable)) in self.columns.iter_mut().enumerate() {
+            *r#type = match (*r#type, value_type(row.get(index).unwrap_or(&Value::Null))) {
+                (r#type, None) => {
+                    *nullable = true;
+                    r#type
+                }
+                (None, value_type) => value_type,
+                (Some(a), Some(b)) if a == b => Some(a),
+                (Some("integer"), Some("number")) | (Some("number"), Some("integer")) => {
+                    Some("number")
+                }
+                _ => Some("string"),
+            };
+        }
+    }
+
+    fn columns(&self) -> Vec<Column> {
+        self.header
+            .iter()
+            .zip(&self.columns)
+            .map(|(name, (r#type, nullable))| Column {
+                name: name.clone(),
+                r#type: r#type.unwrap_or("string"),
+                nullable: *nullable,
+            })
+            .collect()
+    }
+
+    // Records the shape of the table once it has been written.
+    pub fn record(self, schema: &Schema, dest: &Path) {
+        let columns = self.columns();
+        let mut tables = schema.tables.lock().unwrap();
+        let table = tables.entry(stem(dest)).or_default();
+        table.file = dest.file_name().unwrap().to_string_lossy().to_string();
+        table.rows = self.rows;
+        table.columns = columns;
+    }
+}
+
+// Writes schema.json, should be called once all other tables have been written.
+pub fn write(output: &Output) {
+    let tables = std::mem::take(&mut *output.schema().tables.lock().unwrap());
+    let schema = serde_json::json!({
+        "format": output.format().extension(),
+        "tables": tables.into_values().collect::<Vec<_>>(),
+    });
+    let path = output.directory().join(SCHEMA);
+    std::fs::write(&path, serde_json::to_string_pretty(&schema).unwrap())
+        .unwrap_or_else(|error| panic!("Failed to write {}: {}", path.display(), error));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn column_types() {
+        let rows = vec![
+            vec![json!("archden:1"), json!(10), json!(true), json!(1)],
+            vec![json!("archden:2"), json!(null), json!(false), json!(1.5)],
+            vec![json!("archden:3"), json!(""), json!("yes"), json!(2)],
+        ];
+        let mut schema = TableSchema::new(&vec![String::new(); 5]);
+        for row in &rows {
+            schema.add(row);
+        }
+        let columns = schema.columns();
+        let column = |index: usize| (columns[index].r#type, columns[index].nullable);
+        assert_eq!(column(0), ("string", false));
+        assert_eq!(column(1), ("integer", true));
+        assert_eq!(column(2), ("string", false));
+        assert_eq!(column(3), ("number", false));
+        assert_eq!(column(4), ("string", true));
+    }
+}
@@ -1,20 +1,35 @@
+use super::checksums;
+use super::crosswalk::Crosswalk;
+use super::encoding;
+use super::limits;
 use super::map::CustomMap;
+use super::network::HttpClient;
 use super::object::{Object, ObjectMap};
+use super::output::Output;
+use super::parse_errors;
+use super::profile;
+use super::schema;
+use super::timings::{self, Stage};
 use super::utils::*;
-use super::xml;
+use super::warnings;
+use super::workbook;
+use super::xml::{self, XmlLimits};
 use chrono::{DateTime, NaiveDate};
+use foxml::pids::PidMap;
 use indicatif::ProgressBar;
-use log::info;
+use log::{error, info, warn};
 use rayon::prelude::*;
 use regex::Regex;
 use rhai::module_resolvers::{FileModuleResolver, ModuleResolversCollection};
 use rhai::*;
+use std::cell::RefCell;
 use std::collections::hash_map::DefaultHasher;
-use std::collections::{BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
 #[derive(Debug)]
 pub struct ScriptError(Box<Path>, Box<EvalAltResult>);
@@ -40,12 +55,105 @@ impl fmt::Display for ScriptError {
 }
 
 type Script = (Box<Path>, AST);
-type Scripts = HashMap<Box<Path>, AST>;
 type Row = Vec<String>;
 type Header = Vec<String>;
 type Rows = Vec<Row>;
 type ProgressBars = HashMap<Box<Path>, ProgressBar>;
 
+// Options which control how scripts are executed.
+pub struct ScriptOptions<'a> {
+    pub scripts: Vec<&'a Path>,
+    pub modules: Vec<&'a Path>,
+    // Given on the command line via `--script-arg key=value`.
+    pub params: Vec<(&'a str, &'a str)>,
+    // DSIDs checked in order by `descriptive_metadata()`.
+    pub descriptive_metadata: Vec<&'a str>,
+    // Limit to the given number of objects and print the results rather than
+    // writing CSV files.
+    pub preview: Option<usize>,
+    // Hosts `http_get()` may fetch from, scripts have no network access
+    // unless given.
+    pub allow_network: Option<Vec<&'a str>>,
+    pub http_timeout: Duration,
+    // Applied when parsing datastreams for `datastream()` and
+    // `descriptive_metadata()`.
+    pub xml_limits: XmlLimits,
+    // Maps PIDs to existing Drupal entities for `drupal_id()`.
+    pub crosswalk: Option<&'a Path>,
+    // Rewrites the PIDs of objects, given `--pid-map`.
+    pub pid_map: PidMap,
+    // Nest the output in a unique directory for this run.
+    pub run: Option<super::Run>,
+    // Fixes applied to the labels of objects and datastream versions.
+    pub clean_labels: Vec<super::LabelFix>,
+    // Which datastream versions are given to the scripts.
+    pub versions: foxml::VersionPolicy,
+    // How the tables are written.
+    pub output: super::OutputOptions,
+}
+
+// How a script extends one of the same name in an earlier `--scripts`
+// directory, as declared by 'layer' in the map returned by `fn headers()`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Layer {
+    // Rows are appended to those of the earlier scripts, given the same columns.
+    Append,
+    // The earlier scripts are not executed.
+    Override,
+}
+
+// The scripts whose rows are written to the same CSV file, in the order of the
+// directories they were found in.
+struct Table {
+    scripts: Vec<Script>,
+    header: Header,
+    sort_by_column: usize,
+}
+
+// Row built by scripts via `row().set("column", value)`, validated against the
+// scripts headers when the rows are aggregated.
+#[derive(Clone, Debug, Default)]
+pub struct NamedRow(BTreeMap<String, String>);
+
+impl NamedRow {
+    fn set(&mut self, column: ImmutableString, value: Dynamic) {
+        self.0.insert(column.into(), value.to_string());
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.values().all(|value| value.trim().is_empty())
+    }
+
+    // Orders the values as given by the header, every column must be set
+    // and no others.
+    fn into_row(mut self, header: &[String]) -> Result<Row, String> {
+        let missing: Vec<&str> = header
+            .iter()
+            .filter(|column| !self.0.contains_key(*column))
+            .map(|column| column.as_str())
+            .collect();
+        let unknown: Vec<&str> = self
+            .0
+            .keys()
+            .filter(|column| !header.contains(column))
+            .map(|column| column.as_str())
+            .collect();
+        if !missing.is_empty() || !unknown.is_empty() {
+            return Err(format!(
+                "Row is missing column(s): [{}], sets unknown column(s): [{}], expected: [{}]",
+                missing.join(", "),
+                unknown.join(", "),
+                header.join(", ")
+            ));
+        }
+        let row: Row = header
+            .iter()
+            .map(|column| self.0.remove(column).unwrap())
+            .collect();
+        Ok(row)
+    }
+}
+
 fn edtf(value: ImmutableString) -> String {
     if let Ok(date) = DateTime::parse_from_rfc2822(&value) {
         return date.to_rfc3339();
@@ -61,22 +169,341 @@ fn edtf(value: ImmutableString) -> String {
     "".to_string()
 }
 
+// Removes duplicate values keeping the first occurrence, optionally ignoring
+// case.
+fn unique(array: &Array, case_insensitive: bool) -> Array {
+    let mut seen = HashSet::new();
+    array
+        .iter()
+        .filter(|value| {
+            let value = value.to_string();
+            seen.insert(if case_insensitive {
+                value.to_lowercase()
+            } else {
+                value
+            })
+        })
+        .cloned()
+        .collect()
+}
+
+// Sorts alphanumerically ignoring case, so that 'page 2' comes before 'Page 10'.
+fn sort(array: &Array) -> Array {
+    let mut array = array
+        .iter()
+        .map(|v| (v.to_string(), v.clone()))
+        .collect::<Vec<_>>();
+    array.sort_by(|(a, _), (b, _)| {
+        alphanumeric_sort::compare_str(a.to_lowercase(), b.to_lowercase()).then_with(|| a.cmp(b))
+    });
+    array.into_iter().map(|(_, value)| value).collect()
+}
+
+// Nested arrays are replaced by their values, at any depth.
+fn flatten(array: &Array) -> Array {
+    let mut flattened = Array::with_capacity(array.len());
+    for value in array {
+        if value.is::<Array>() {
+            flattened.extend(flatten(&value.clone().cast::<Array>()));
+        } else {
+            flattened.push(value.clone());
+        }
+    }
+    flattened
+}
+
+// Counts over the whole repository, computed once so that scripts can derive
+// values such as "page N of M" without preprocessing the objects themselves.
+#[derive(Default)]
+struct RepositoryCounts {
+    objects: usize,
+    models: HashMap<String, usize>,
+    // Objects that list the given PID among their parents, e.g. the members of
+    // a collection or the pages of a book.
+    children: HashMap<String, usize>,
+}
+
+impl RepositoryCounts {
+    fn new<'a, I>(objects: I) -> Self
+    where
+        I: Iterator<Item = (&'a str, &'a [String])>,
+    {
+        let mut counts = RepositoryCounts::default();
+        for (model, parents) in objects {
+            counts.objects += 1;
+            *counts.models.entry(model.to_string()).or_default() += 1;
+            for parent in parents {
+                *counts.children.entry(parent.clone()).or_default() += 1;
+            }
+        }
+        counts
+    }
+
+    // Models may be given as URIs e.g. 'info:fedora/islandora:sp_basic_image'.
+    fn objects_with_model(&self, model: &str) -> usize {
+        let model = model.trim_start_matches("info:fedora/");
+        self.models.get(model).copied().unwrap_or_default()
+    }
+
+    fn children(&self, pid: &str) -> usize {
+        self.children.get(pid).copied().unwrap_or_default()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn repository_counts() {
+        let parents = |pids: &[&str]| pids.iter().map(|pid| pid.to_string()).collect::<Vec<_>>();
+        let (collection, book) = (parents(&["demo:collection"]), parents(&["demo:book"]));
+        let objects = vec![
+            ("islandora:bookCModel", collection.as_slice()),
+            ("islandora:pageCModel", book.as_slice()),
+            ("islandora:pageCModel", book.as_slice()),
+            ("islandora:sp_basic_image", collection.as_slice()),
+        ];
+        let counts = RepositoryCounts::new(objects.into_iter());
+        assert_eq!(counts.objects, 4);
+        assert_eq!(counts.objects_with_model("islandora:pageCModel"), 2);
+        assert_eq!(
+            counts.objects_with_model("info:fedora/islandora:bookCModel"),
+            1
+        );
+        assert_eq!(counts.objects_with_model("islandora:videoCModel"), 0);
+        assert_eq!(counts.children("demo:collection"), 2);
+        assert_eq!(counts.children("demo:book"), 2);
+        assert_eq!(counts.children("demo:missing"), 0);
+    }
+
+    #[test]
+    fn array_helpers() {
+        let array: Array = vec!["b", "A", "page 10", "a", "page 2", "b"]
+            .into_iter()
+            .map(|value| value.into())
+            .collect();
+        let strings = |array: Array| array.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            strings(unique(&array, false)),
+            vec!["b", "A", "page 10", "a", "page 2"]
+        );
+        assert_eq!(
+            strings(unique(&array, true)),
+            vec!["b", "A", "page 10", "page 2"]
+        );
+        assert_eq!(
+            strings(sort(&unique(&array, true))),
+            vec!["A", "b", "page 2", "page 10"]
+        );
+        let nested: Array = vec![
+            "a".into(),
+            Dynamic::from(vec![
+                "b".into(),
+                Dynamic::from(vec![Dynamic::from("c".to_string())]),
+            ]),
+        ];
+        assert_eq!(strings(flatten(&nested)), vec!["a", "b", "c"]);
+    }
+
     #[test]
     fn test_edtf() {
         assert_eq!(edtf("1900-01-01".into()), "1900-01-01".to_string(), "Dates equal");
     }
+
+    #[test]
+    fn named_row() {
+        let header = vec!["pid".to_string(), "label".to_string()];
+        let mut row = NamedRow::default();
+        row.set("label".into(), "Title".into());
+        row.set("pid".into(), "namespace:123".into());
+        assert_eq!(
+            row.clone().into_row(&header),
+            Ok(vec!["namespace:123".to_string(), "Title".to_string()])
+        );
+        row.set("lable".into(), "Title".into());
+        assert!(row.into_row(&header).is_err());
+    }
+    #[test]
+    fn headers() {
+        let path = Path::new("nodes.rhai");
+        let parse = |columns: Vec<Dynamic>, sort_by: Option<&str>| {
+            let mut result = Map::new();
+            result.insert("columns".into(), columns.into());
+            if let Some(sort_by) = sort_by {
+                result.insert("sort_by".into(), sort_by.into());
+            }
+            parse_headers(path, result)
+        };
+        let strings = |columns: &[&str]| {
+            columns
+                .iter()
+                .map(|c| Dynamic::from(c.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            parse(strings(&["pid", "title"]), Some("title")),
+            Ok((vec!["pid".to_string(), "title".to_string()], 1))
+        );
+        let error = parse(strings(&["pid", "title"]), Some("label")).unwrap_err();
+        assert!(
+            error.contains("nodes.rhai")
+                && error.contains("'label'")
+                && error.contains("pid, title")
+        );
+        assert!(parse(strings(&["pid", "title"]), None)
+            .unwrap_err()
+            .contains("pid, title"));
+        assert!(parse(strings(&["pid", "title", "pid"]), Some("pid"))
+            .unwrap_err()
+            .contains("duplicate columns: pid"));
+        assert!(parse(vec![Dynamic::from(1_i64)], Some("pid")).is_err());
+        assert!(parse(vec![], Some("pid")).is_err());
+        assert!(parse_headers(path, Map::new()).is_err());
+    }
+
+    #[test]
+    fn layers() {
+        let engine = Engine::new();
+        let script = |path: &str, layer: &str, columns: &str| -> Script {
+            let source = format!(
+                r#"fn headers() {{ #{{ columns: [{}], sort_by: "pid"{} }} }}"#,
+                columns, layer
+            );
+            (
+                Path::new(path).into(),
+                engine.compile(&source).unwrap(),
+            )
+        };
+        let append = r#", layer: "append""#;
+        let names = |table: &Table| {
+            table
+                .scripts
+                .iter()
+                .map(|(path, _)| path.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        };
+        let tables = layer_scripts(
+            &engine,
+            vec![
+                script("base/nodes.rhai", "", r#""pid", "title""#),
+                script("base/media.rhai", "", r#""pid""#),
+                script("site/nodes.rhai", append, r#""pid", "title""#),
+                script("site/media.rhai", r#", layer: "override""#, r#""pid", "file""#),
+            ],
+            &Map::new(),
+        );
+        assert_eq!(names(&tables[0]), vec!["base/nodes.rhai", "site/nodes.rhai"]);
+        assert_eq!(names(&tables[1]), vec!["site/media.rhai"]);
+        assert_eq!(tables[1].header, vec!["pid", "file"]);
+        // Undeclared conflicts override the earlier script.
+        let tables = layer_scripts(
+            &engine,
+            vec![
+                script("base/nodes.rhai", "", r#""pid""#),
+                script("site/nodes.rhai", "", r#""pid""#),
+            ],
+            &Map::new(),
+        );
+        assert_eq!(names(&tables[0]), vec!["site/nodes.rhai"]);
+        // Appended rows must have the same columns.
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            layer_scripts(
+                &engine,
+                vec![
+                    script("base/nodes.rhai", "", r#""pid""#),
+                    script("site/nodes.rhai", append, r#""pid", "title""#),
+                ],
+                &Map::new(),
+            )
+        }));
+        assert!(result.is_err());
+        let mut result = Map::new();
+        result.insert("layer".into(), "merge".into());
+        assert!(parse_layer(Path::new("nodes.rhai"), &mut result).is_err());
+    }
+}
+
+thread_local! {
+    // The script and PID being executed on this thread, attached to messages
+    // logged by scripts, as each script is executed serially on one thread.
+    static CONTEXT: RefCell<(String, String)> = const { RefCell::new((String::new(), String::new())) };
+}
+
+lazy_static! {
+    // Number of warnings and errors logged per script.
+    static ref MESSAGES: Mutex<BTreeMap<String, (usize, usize)>> = Mutex::new(BTreeMap::new());
+}
+
+fn set_context(path: &Path, pid: &str) {
+    CONTEXT.with(|context| {
+        *context.borrow_mut() = (
+            path.file_name().unwrap().to_string_lossy().to_string(),
+            pid.to_string(),
+        )
+    });
 }
 
-fn create_engine(objects: Arc<RwLock<ObjectMap>>, modules: Vec<&Path>) -> Engine {
+fn log_message(level: log::Level, msg: &str) {
+    CONTEXT.with(|context| {
+        let (script, pid) = &*context.borrow();
+        let mut messages = MESSAGES.lock().unwrap();
+        let (warnings, errors) = messages.entry(script.clone()).or_default();
+        if level == log::Level::Error {
+            *errors += 1;
+            report_problem(
+                level,
+                "script_errors",
+                &format!("{} ({}): {}", script, pid, msg),
+            );
+        } else {
+            *warnings += 1;
+            warn!("{} ({}): {}", script, pid, msg);
+        }
+    });
+}
+
+// Summarizes the number of messages logged by each script.
+fn log_message_summary() {
+    for (script, (warnings, errors)) in std::mem::take(&mut *MESSAGES.lock().unwrap()) {
+        let summary = format!("{} logged {} warnings and {} errors", script, warnings, errors);
+        if errors > 0 {
+            error!("{}", summary);
+        } else {
+            warn!("{}", summary);
+        }
+    }
+}
+
+fn create_engine(
+    objects: Arc<RwLock<ObjectMap>>,
+    modules: Vec<&Path>,
+    params: &Map,
+    descriptive_metadata: Vec<String>,
+    http: Option<HttpClient>,
+    xml_limits: XmlLimits,
+    crosswalk: Crosswalk,
+) -> Engine {
     let mut engine = Engine::new();
 
     // Custom types.
     engine.register_type::<Object>();
     engine.register_type::<CustomMap>();
+    engine.register_type::<NamedRow>();
+
+    // Repository-level context, counted once before any script is run.
+    let counts = Arc::new(RepositoryCounts::new(
+        objects
+            .read()
+            .unwrap()
+            .inner()
+            .values()
+            .map(|object| (object.model.as_str(), object.parents.as_slice())),
+    ));
+
+    // References in the content of datastreams are rewritten like the PIDs of
+    // the objects.
+    let pid_map = objects.read().unwrap().pid_map().clone();
 
     // Custom functions.
     engine.register_result_fn(
@@ -95,11 +522,92 @@ fn create_engine(objects: Arc<RwLock<ObjectMap>>, modules: Vec<&Path>) -> Engine
         },
     );
 
+    let datastream_pid_map = pid_map.clone();
     engine.register_result_fn(
         "datastream",
-        |object: &mut Object, dsid: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+        move |object: &mut Object, dsid: &str| -> Result<Dynamic, Box<EvalAltResult>> {
             match object.datastream(dsid) {
-                Some(datastream) => match xml::parse(datastream) {
+                Some(datastream) => match xml::parse(datastream, xml_limits, &datastream_pid_map) {
+                    Some(result) => match result {
+                        Ok(map) => Ok(Dynamic::from(map)),
+                        Err(e) => Err(e.to_string().into()),
+                    },
+                    None => Ok(().into()),
+                },
+                None => Ok(().into()),
+            }
+        },
+    );
+
+    // Every version of a datastream oldest first, for revision aware scripts.
+    let versions_pid_map = pid_map.clone();
+    engine.register_result_fn(
+        "datastream_versions",
+        move |object: &mut Object, dsid: &str| -> Result<Dynamic, Box<EvalAltResult>> {
+            object
+                .datastream_versions(dsid)
+                .iter()
+                .map(|version| {
+                    let content: Dynamic = match xml::parse(version, xml_limits, &versions_pid_map)
+                    {
+                        Some(Ok(map)) => Dynamic::from(map),
+                        Some(Err(e)) => {
+                            return Err(format!("{} ({}): {}", dsid, version.id, e).into())
+                        }
+                        None => ().into(),
+                    };
+                    let mut map = Map::new();
+                    map.insert("id".into(), version.id.clone().into());
+                    map.insert("label".into(), version.label.clone().into());
+                    map.insert("created".into(), version.created_date.to_rfc3339().into());
+                    map.insert("mime".into(), version.mime_type.clone().into());
+                    map.insert("content".into(), content);
+                    Ok(Dynamic::from(map))
+                })
+                .collect::<Result<Array, _>>()
+                .map(Dynamic::from)
+        },
+    );
+
+    let total = counts.objects as INT;
+    engine.register_fn("total_objects", move || total);
+    let models = counts.clone();
+    engine.register_fn("objects_with_model", move |model: &str| -> INT {
+        models.objects_with_model(model) as INT
+    });
+    // Direct children only, i.e. not the pages of the books in a collection.
+    engine.register_fn("collection_size", move |pid: &str| -> INT {
+        counts.children(pid) as INT
+    });
+
+    // Checks for datastreams without reading their content.
+    engine.register_fn("has_datastream", |object: &mut Object, dsid: &str| -> bool {
+        object.datastream(dsid).is_some()
+    });
+
+    engine.register_fn(
+        "datastream_mime",
+        |object: &mut Object, dsid: &str| -> Dynamic {
+            object
+                .datastream(dsid)
+                .map_or_else(|| ().into(), |version| version.mime_type.clone().into())
+        },
+    );
+
+    // Checksums are cached, so files are hashed at most once per run.
+    engine.register_fn("file_sha1", |object: &mut Object, dsid: &str| -> Dynamic {
+        checksums::file_sha1(object, dsid).map_or_else(|| ().into(), Dynamic::from)
+    });
+
+    engine.register_fn("file_size", |object: &mut Object, dsid: &str| -> Dynamic {
+        checksums::file_size(object, dsid).map_or_else(|| ().into(), |size| (size as i64).into())
+    });
+
+    engine.register_result_fn(
+        "descriptive_metadata",
+        move |object: &mut Object| -> Result<Dynamic, Box<EvalAltResult>> {
+            match object.descriptive_metadata(&descriptive_metadata) {
+                Some(datastream) => match xml::parse(datastream, xml_limits, &pid_map) {
                     Some(result) => match result {
                         Ok(map) => Ok(Dynamic::from(map)),
                         Err(e) => Err(e.to_string().into()),
@@ -117,6 +625,14 @@ fn create_engine(objects: Arc<RwLock<ObjectMap>>, modules: Vec<&Path>) -> Engine
         format!("{:X}", s.finish())
     });
 
+    // Array helpers for multi-valued fields, e.g. `names.unique().sort().join("|")`.
+    engine.register_fn("unique", |array: &mut Array| unique(array, false));
+    engine.register_fn("unique", |array: &mut Array, case_insensitive: bool| {
+        unique(array, case_insensitive)
+    });
+    engine.register_fn("sort", |array: &mut Array| sort(array));
+    engine.register_fn("flatten", |array: &mut Array| flatten(array));
+
     engine.register_fn(
         "join",
         |array: &mut Array, delimiter: &str| -> ImmutableString {
@@ -132,12 +648,111 @@ fn create_engine(objects: Arc<RwLock<ObjectMap>>, modules: Vec<&Path>) -> Engine
 
     engine.register_fn("edtf", edtf);
 
+    // Parameters given on the command line via `--script-arg key=value`.
+    let _params = params.clone();
+    engine.register_fn("param", move |key: ImmutableString| -> Dynamic {
+        _params.get(&key).cloned().unwrap_or_else(|| ().into())
+    });
+
+    engine.register_fn("env", |key: ImmutableString| -> Dynamic {
+        std::env::var(key.as_str()).map_or_else(|_| ().into(), |value| value.into())
+    });
+
+    // Data-quality messages, logged along with the script and PID.
+    engine.register_fn("warn", |msg: ImmutableString| {
+        log_message(log::Level::Warn, &msg)
+    });
+
+    engine.register_fn("error", |msg: ImmutableString| {
+        log_message(log::Level::Error, &msg)
+    });
+
+    // Only available when enabled via `--allow-network`.
+    if let Some(http) = http {
+        engine.register_result_fn(
+            "http_get",
+            move |url: ImmutableString| -> Result<Dynamic, Box<EvalAltResult>> {
+                match http.get(&url) {
+                    Ok(Some(body)) => Ok(body.into()),
+                    Ok(None) => Ok(().into()),
+                    Err(e) => Err(e.into()),
+                }
+            },
+        );
+    }
+
+    // Unit unless the PID is listed in the `--crosswalk`.
+    engine.register_fn("drupal_id", move |pid: ImmutableString| -> Dynamic {
+        crosswalk.drupal_id(&pid)
+    });
+
+    // Rows with named columns.
+    engine.register_fn("row", NamedRow::default);
+
+    // Dynamic arguments are not matched by the engine so register each value type.
+    engine.register_fn(
+        "set",
+        |row: &mut NamedRow, column: ImmutableString, value: ImmutableString| -> NamedRow {
+            row.set(column, value.into());
+            row.clone()
+        },
+    );
+
+    engine.register_fn(
+        "set",
+        |row: &mut NamedRow, column: ImmutableString, value: INT| -> NamedRow {
+            row.set(column, value.into());
+            row.clone()
+        },
+    );
+
+    engine.register_fn(
+        "set",
+        |row: &mut NamedRow, column: ImmutableString, value: bool| -> NamedRow {
+            row.set(column, value.into());
+            row.clone()
+        },
+    );
+
+    engine.register_fn("push", |list: &mut Array, row: NamedRow| {
+        list.push(Dynamic::from(row));
+    });
+
+    // Like arrays, rows in which every column is empty are ignored.
+    engine.register_fn("+=", |array: &mut Array, row: NamedRow| {
+        if !row.is_empty() {
+            array.push(Dynamic::from(row));
+        }
+    });
+
     // Object properties.
     engine.register_get("pid", |object: &mut Object| object.pid.0.clone());
     engine.register_get("state", |object: &mut Object| object.state.to_string());
     engine.register_get("label", |object: &mut Object| object.label.clone());
     engine.register_get("model", |object: &mut Object| object.model.clone());
     engine.register_get("parents", |object: &mut Object| object.parents.clone());
+    engine.register_get("pid_namespace", |object: &mut Object| {
+        object.pid.namespace().to_string()
+    });
+    engine.register_get("pid_local_id", |object: &mut Object| {
+        object.pid.local_id().to_string()
+    });
+    engine.register_get("pid_number", |object: &mut Object| -> Dynamic {
+        object.pid.number().map_or_else(|| ().into(), Dynamic::from)
+    });
+
+    // The same for PIDs given as strings e.g. parents.
+    engine.register_fn("pid_namespace", |pid: ImmutableString| {
+        super::object::Pid(pid.into()).namespace().to_string()
+    });
+    engine.register_fn("pid_local_id", |pid: ImmutableString| {
+        super::object::Pid(pid.into()).local_id().to_string()
+    });
+    engine.register_fn("pid_number", |pid: ImmutableString| -> Dynamic {
+        super::object::Pid(pid.into())
+            .number()
+            .map_or_else(|| ().into(), Dynamic::from)
+    });
 
     // CustomMap functions (custom type is required to override indexing behavior on maps).
     engine.register_fn("print", |map: &mut CustomMap| -> ImmutableString {
@@ -237,23 +852,38 @@ fn parse_script(path: Box<Path>, engine: &Engine) -> Result<Script, ScriptError>
     Ok((path, ast))
 }
 
-// Parse the script files in the script folder.
-fn parse_scripts(paths: Vec<&Path>, engine: &Engine) -> Scripts {
+// Parse the script files in the script folders, in the order the folders were
+// given.
+fn parse_scripts(paths: Vec<&Path>, engine: &Engine) -> Vec<Script> {
     info!("Parsing Scripts");
     paths
-        .into_par_iter()
-        .flat_map(|path| files(&path))
+        .into_iter()
+        .flat_map(|path| {
+            let mut files = files(&path);
+            files.sort();
+            files
+        })
+        .collect::<Vec<_>>()
         .into_par_iter()
         .filter(|path| is_script(&path))
         .map(|path| parse_script(path, engine))
-        .collect::<Result<Scripts, ScriptError>>()
+        .collect::<Result<Vec<_>, ScriptError>>()
         .unwrap()
 }
 
+// Scope shared by all script function calls, exposes the command line
+// parameters as the constant `PARAMS`.
+fn scope(params: &Map) -> Scope<'static> {
+    let mut scope = Scope::new();
+    scope.push_constant("PARAMS", params.clone());
+    scope
+}
+
 // Call `headers()` function in the given script.
-fn call_headers(engine: &Engine, script: &Script) -> (Header, usize) {
+fn call_headers(engine: &Engine, script: &Script, params: &Map) -> (Header, usize, Option<Layer>) {
     let (path, ast) = script;
-    let mut scope = Scope::new();
+    let mut scope = scope(params);
+    set_context(path, "");
     let mut result: Map = engine
         .call_fn(&mut scope, &ast, "headers", ())
         .unwrap_or_else(|error| {
@@ -263,19 +893,164 @@ fn call_headers(engine: &Engine, script: &Script) -> (Header, usize) {
                 error
             )
         });
+    let layer = parse_layer(path, &mut result).unwrap_or_else(|error| panic!("{}", error));
+    let (header, sort_by_column) =
+        parse_headers(path, result).unwrap_or_else(|error| panic!("{}", error));
+    (header, sort_by_column, layer)
+}
+
+// The optional 'layer' returned by 'fn headers()'.
+fn parse_layer(path: &Path, result: &mut Map) -> Result<Option<Layer>, String> {
+    match result.remove("layer") {
+        None => Ok(None),
+        Some(layer) => match layer.try_cast::<String>().as_deref() {
+            Some("append") => Ok(Some(Layer::Append)),
+            Some("override") => Ok(Some(Layer::Override)),
+            _ => Err(format!(
+                "'layer' in {} must be either 'append' or 'override'",
+                path.display()
+            )),
+        },
+    }
+}
+
+// Groups the scripts by the CSV file they write to. Scripts of the same name in
+// later directories either append rows to or override the earlier scripts, as
+// declared by their 'layer'. Those which declare neither override them as well,
+// but are reported as conflicts.
+fn layer_scripts(engine: &Engine, scripts: Vec<Script>, params: &Map) -> Vec<Table> {
+    let mut tables: Vec<(String, Table)> = Vec::new();
+    let mut conflicts = Vec::new();
+    for script in scripts {
+        let (header, sort_by_column, layer) = call_headers(engine, &script, params);
+        let name = script.0.file_stem().unwrap().to_string_lossy().to_string();
+        let table = match tables.iter_mut().find(|(other, _)| *other == name) {
+            Some((_, table)) => table,
+            None => {
+                tables.push((
+                    name,
+                    Table {
+                        scripts: vec![script],
+                        header,
+                        sort_by_column,
+                    },
+                ));
+                continue;
+            }
+        };
+        let earlier = table
+            .scripts
+            .iter()
+            .map(|(path, _)| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        match layer {
+            Some(Layer::Append) => {
+                if header != table.header {
+                    panic!(
+                        "'layer' in {} is 'append' but its columns ({}) differ from those of {} ({})",
+                        script.0.display(),
+                        header.join(", "),
+                        earlier,
+                        table.header.join(", ")
+                    );
+                }
+                info!("{} appends rows to {}", script.0.display(), earlier);
+                table.scripts.push(script);
+            }
+            Some(Layer::Override) | None => {
+                if layer.is_some() {
+                    info!("{} overrides {}", script.0.display(), earlier);
+                } else {
+                    conflicts.push(format!("{} overrides {}", script.0.display(), earlier));
+                }
+                *table = Table {
+                    scripts: vec![script],
+                    header,
+                    sort_by_column,
+                };
+            }
+        }
+    }
+    if !conflicts.is_empty() {
+        for conflict in &conflicts {
+            logger::summary::warning("script_conflicts", conflict);
+        }
+        warn!(
+            "The following scripts share their name with those of an earlier directory without declaring their 'layer' as 'append' or 'override', and override them:\n\t{}",
+            conflicts.join("\n\t")
+        );
+    }
+    tables.into_iter().map(|(_, table)| table).collect()
+}
+
+// Validates the map returned by 'fn headers()', returning the columns and the
+// index of the column to sort by.
+fn parse_headers(path: &Path, mut result: Map) -> Result<(Header, usize), String> {
+    let script = path.display();
+    let columns: Array = result
+        .remove("columns")
+        .and_then(|columns| columns.try_cast())
+        .ok_or_else(|| {
+            format!(
+                "'fn headers()' in {} must return an array of strings as 'columns'",
+                script
+            )
+        })?;
     // Consume results and convert to a list of strings.
-    let columns: Header = {
-        let columns: Array = result.remove("columns").unwrap().cast();
-        columns
-            .into_iter()
-            .map(|d| d.take_string().unwrap())
-            .collect()
-    };
-    let sort_by_column: usize = {
-        let sort_by: String = result.remove("sort_by").unwrap().cast();
-        columns.iter().position(|r| r.eq(&sort_by)).unwrap()
-    };
-    (columns, sort_by_column)
+    let columns: Header = columns
+        .into_iter()
+        .enumerate()
+        .map(|(index, column)| {
+            column.take_string().map_err(|type_name| {
+                format!(
+                    "Column {} returned by 'fn headers()' in {} is a {} rather than a string",
+                    index, script, type_name
+                )
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    if columns.is_empty() {
+        return Err(format!(
+            "'fn headers()' in {} must return at least one column",
+            script
+        ));
+    }
+    let mut seen = HashSet::new();
+    let duplicates = columns
+        .iter()
+        .filter(|column| !seen.insert(column.as_str()))
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    if !duplicates.is_empty() {
+        return Err(format!(
+            "'fn headers()' in {} declares duplicate columns: {}",
+            script,
+            duplicates.into_iter().collect::<Vec<_>>().join(", ")
+        ));
+    }
+    let sort_by: String = result
+        .remove("sort_by")
+        .and_then(|sort_by| sort_by.try_cast())
+        .ok_or_else(|| {
+            format!(
+                "'fn headers()' in {} must return the column to sort by as 'sort_by', one of: {}",
+                script,
+                columns.join(", ")
+            )
+        })?;
+    let sort_by_column = columns
+        .iter()
+        .position(|column| *column == sort_by)
+        .ok_or_else(|| {
+            format!(
+                "'sort_by' in {} is '{}', which is not one of the declared columns: {}",
+                script,
+                sort_by,
+                columns.join(", ")
+            )
+        })?;
+    Ok((columns, sort_by_column))
 }
 
 fn call_rows(
@@ -283,14 +1058,20 @@ fn call_rows(
     script: &Script,
     object: &Object,
     progress_bars: &ProgressBars,
+    params: &Map,
+    header: &[String],
 ) -> Rows {
     // Serially in alphanumeric order.
     let (path, ast) = script;
-    let mut scope = Scope::new();
-    let result: Array = engine
-        .call_fn(&mut scope, &ast, "rows", (object.pid.to_string(),))
-        .map_err(|error| ScriptError(path.clone(), error))
-        .unwrap();
+    let mut scope = scope(params);
+    set_context(path, &object.pid.0);
+    let processing = logger::state::processing(&object.pid.0);
+    let result: Array = timings::time(Stage::Script, &object.pid.0, || {
+        engine.call_fn(&mut scope, &ast, "rows", (object.pid.to_string(),))
+    })
+    .map_err(|error| ScriptError(path.clone(), error))
+    .unwrap();
+    drop(processing);
     // Update progress.
     let progress_bar = progress_bars.get(path).unwrap();
     progress_bar.inc(1);
@@ -300,23 +1081,49 @@ fn call_rows(
     // Consume result and convert to a list of lists of strings.
     result
         .into_iter()
-        .map(|d| d.cast::<rhai::Array>())
-        .map(|a| a.into_iter().map(|v| v.to_string()).collect())
+        .map(|d| {
+            if d.is::<NamedRow>() {
+                d.cast::<NamedRow>()
+                    .into_row(header)
+                    .unwrap_or_else(|error| {
+                        panic!(
+                            "Invalid row for {} in {}.\nError: {}",
+                            object.pid,
+                            path.display(),
+                            error
+                        )
+                    })
+            } else {
+                d.cast::<rhai::Array>()
+                    .into_iter()
+                    .map(|v| v.to_string())
+                    .collect()
+            }
+        })
         .collect()
 }
 
 fn aggregate_rows(
     engine: &Engine,
-    script: &Script,
-    objects: &ObjectMap,
+    table: &Table,
+    objects: &[&Object],
     progress_bars: &ProgressBars,
-    sort_by_column: usize,
+    params: &Map,
 ) -> Rows {
+    let Table {
+        scripts,
+        header,
+        sort_by_column,
+    } = table;
+    let sort_by_column = *sort_by_column;
     // Execute scripts and aggregate the results.
-    let rows: Rows = objects
-        .inner()
-        .values()
-        .flat_map(|object| call_rows(&engine, &script, &object, &progress_bars))
+    let rows: Rows = scripts
+        .iter()
+        .flat_map(|script| {
+            objects.iter().flat_map(move |object| {
+                call_rows(&engine, &script, &object, &progress_bars, params, header)
+            })
+        })
         .collect();
     // Filter identical rows / collect into
     let mut rows: Rows = rows
@@ -330,83 +1137,195 @@ fn aggregate_rows(
     rows
 }
 
-fn execute_script(
-    engine: &Engine,
-    script: &Script,
-    objects: &ObjectMap,
-    progress_bars: &ProgressBars,
-) -> (Header, Rows) {
-    let header = call_headers(&engine, &script);
-    (
-        header.0,
-        aggregate_rows(&engine, &script, &objects, &progress_bars, header.1),
-    )
-}
 
-fn csv_destination(script: &Script, dest: &Path) -> Box<Path> {
+fn csv_destination(script: &Script) -> String {
     let (path, _) = script;
-    dest.join(format!(
-        "{}.{}",
-        path.file_stem().unwrap().to_string_lossy(),
-        "csv"
-    ))
-    .into_boxed_path()
+    format!("{}.{}", path.file_stem().unwrap().to_string_lossy(), "csv")
 }
 
-fn create_csv(header: Header, rows: Rows, dest: Box<Path>) {
-    let mut wtr = csv_other::WriterBuilder::new()
-        .from_path(&dest)
+fn create_csv(script: &Script, header: Header, sort_by_column: usize, rows: Rows, output: &Output) {
+    let (path, _) = script;
+    let file = csv_destination(script);
+    output
+        .schema()
+        .describe(Path::new(&file), path, &header[sort_by_column]);
+    output
+        .write_table(&header, &rows, &file)
         .expect("Failed to create CSV");
+}
 
-    wtr.write_record(header)
-        .expect("Failed to write header to csv");
-
-    for row in rows {
-        wtr.write_record(row).expect("Failed to row header to csv");
+// Prints the header and rows as a table with aligned columns.
+fn print_table(script: &Script, header: &[String], rows: &[Row]) {
+    // Long values are truncated to keep the table readable.
+    const MAX_WIDTH: usize = 40;
+    let truncate = |value: &str| -> String {
+        if value.chars().count() > MAX_WIDTH {
+            let mut value = value.chars().take(MAX_WIDTH - 3).collect::<String>();
+            value.push_str("...");
+            value
+        } else {
+            value.to_string()
+        }
+    };
+    let header: Row = header.iter().map(|column| truncate(column)).collect();
+    let rows: Rows = rows
+        .iter()
+        .map(|row| row.iter().map(|value| truncate(value)).collect())
+        .collect();
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| {
+            std::iter::once(&header)
+                .chain(rows.iter())
+                .filter_map(|row| row.get(column))
+                .map(|value| value.chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    let format_row = |row: &Row| -> String {
+        row.iter()
+            .zip(widths.iter())
+            .map(|(value, width)| format!("{:width$}", value, width = width))
+            .collect::<Vec<_>>()
+            .join(" | ")
+            .trim_end()
+            .to_string()
+    };
+    let (path, _) = script;
+    println!("{}", path.display());
+    println!("{}", format_row(&header));
+    println!(
+        "{}",
+        widths
+            .iter()
+            .map(|width| "-".repeat(*width))
+            .collect::<Vec<_>>()
+            .join("-+-")
+    );
+    for row in &rows {
+        println!("{}", format_row(row));
     }
+    println!();
 }
 
-pub fn run_scripts(objects: ObjectMap, scripts: Vec<&Path>, modules: Vec<&Path>, dest: &Path) {
+pub fn run_scripts(objects: ObjectMap, options: ScriptOptions, output: &Output) {
+    let ScriptOptions {
+        scripts,
+        modules,
+        params,
+        descriptive_metadata,
+        preview,
+        allow_network,
+        http_timeout,
+        xml_limits,
+        crosswalk,
+        ..
+    } = options;
+    let limit = preview.unwrap_or_else(|| objects.inner().len());
+
+    // Remove the marker from any previous run, it is written once all files are complete.
+    let marker = output.directory().join("scripts.complete");
+    if preview.is_none() {
+        remove_completion_marker(&marker);
+    }
+
     // Track our progress per script, against the total number of objects.
-    let count = objects.inner().len() as u64;
+    let count = objects.inner().len().min(limit) as u64;
 
     // Wrap such that it can be shared across script invocations.
     // RHAI assumes ownership so we need a type that can be cloned.
     // Should be fairly fast as it will only increment a counter per clone,
     // and allows for concurrent reads.
     let arc = Arc::new(RwLock::new(objects));
-    let engine = create_engine(arc.clone(), modules);
+    let params: Map = params
+        .into_iter()
+        .map(|(key, value)| (key.into(), value.to_string().into()))
+        .collect();
+    let descriptive_metadata = descriptive_metadata
+        .into_iter()
+        .map(|dsid| dsid.to_string())
+        .collect();
+    let http = allow_network.map(|hosts| HttpClient::new(&hosts, http_timeout));
+    let crosswalk = crosswalk.map_or_else(Crosswalk::default, |path| {
+        let crosswalk = Crosswalk::from_path(path);
+        info!(
+            "Found {} PIDs in crosswalk {}",
+            crosswalk.len(),
+            path.display()
+        );
+        crosswalk
+    });
+    let engine = create_engine(
+        arc.clone(),
+        modules,
+        &params,
+        descriptive_metadata,
+        http,
+        xml_limits,
+        crosswalk,
+    );
 
     let scripts = parse_scripts(scripts, &engine);
+    let tables = layer_scripts(&engine, scripts, &params);
 
-    let (multi, bars) = logger::progress_bars(count, scripts.keys().cloned());
+    logger::webhook::phase_started("scripts");
+    let (multi, bars) = logger::progress_bars(
+        count,
+        tables
+            .iter()
+            .flat_map(|table| table.scripts.iter().map(|(path, _)| path.clone())),
+    );
 
     // Create a thread to run the scripts in the background so we can update the
     // progress bars in this thread.
-    let dest = dest.to_path_buf();
+    let objects = arc.clone();
     let thread = std::thread::spawn(move || {
         info!("Executing scripts");
-        let results: Vec<_> = scripts
+        tables
             .into_par_iter()
-            .map(|script| match arc.read() {
-                Ok(objects) => (
-                    script.clone(),
-                    execute_script(&engine, &script, &objects, &bars),
-                ),
+            .map(|table| match arc.read() {
+                Ok(objects) => {
+                    // Scripts can still look up any object, but rows are only
+                    // generated for those within the limit.
+                    let objects = objects.inner().values().take(limit).collect::<Vec<_>>();
+                    let rows = aggregate_rows(&engine, &table, &objects, &bars, &params);
+                    (table, rows)
+                }
                 Err(_) => panic!("Failed to get read access to objects"),
             })
-            .collect();
-        // Create CSV files.
-        info!("Writing CSV files");
-        results
-            .into_par_iter()
-            .for_each(|(script, (header, rows))| {
-                create_csv(header, rows, csv_destination(&script, &dest));
-            });
+            .collect::<Vec<_>>()
     });
 
     // Wait for progress to finish and update the progress bar display.
     multi.join_and_clear().unwrap();
     // Process can still continue after the progress bars have finished, make sure the thread is joined.
-    thread.join().unwrap();
+    let mut results = thread.join().unwrap();
+    log_message_summary();
+
+    if preview.is_some() {
+        // Nothing is written, the results are only displayed.
+        results.sort_by(|(a, _), (b, _)| a.scripts[0].0.cmp(&b.scripts[0].0));
+        for (table, rows) in results {
+            print_table(&table.scripts[0], &table.header, &rows);
+        }
+    } else {
+        // Create CSV files.
+        info!("Writing CSV files");
+        results
+            .into_par_iter()
+            .for_each(|(table, rows)| {
+                let script = &table.scripts[0];
+                create_csv(script, table.header, table.sort_by_column, rows, output);
+            });
+        parse_errors::write_report(output);
+        encoding::write_report(output);
+        limits::write_report(output);
+        profile::write_report(output);
+        timings::write_report(&objects.read().unwrap(), output);
+        warnings::write_report(output);
+        schema::write(output);
+        workbook::write(output);
+        write_completion_marker(&marker, &output.outputs());
+        notify_outputs("scripts", &output.outputs());
+    }
 }
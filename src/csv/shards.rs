@@ -0,0 +1,188 @@
+// Commands given `--shard i/n` only process the objects whose PID hashes to
+// that shard, so that they can be run concurrently on multiple machines. The
+// `csv` and `scripts` commands nest their output in a directory per shard
+// (e.g. `shard-2-of-4`), whose CSV files are concatenated by `merge_shards()`
+// once every shard has completed.
+use super::utils::{finish_partial, partial_path};
+use foxml::pids::Shard;
+use log::{info, warn};
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    static ref SHARD_DIRECTORY_REGEX: Regex = Regex::new(r"^shard-(\d+)-of-(\d+)$").unwrap();
+}
+
+// The directory the output of this shard is nested in, if any.
+pub fn shard_directory(output: &Path) -> PathBuf {
+    match foxml::pids::shard() {
+        Some(shard) => output.join(shard.to_string()),
+        None => output.to_path_buf(),
+    }
+}
+
+// Shard directories in the given directory by their index, all of which must
+// be present and belong to the same number of shards.
+fn shard_directories(input: &Path) -> Result<BTreeMap<u64, PathBuf>, String> {
+    let entries = std::fs::read_dir(input)
+        .map_err(|error| format!("Failed to read {}: {}", input.display(), error))?;
+    let mut count = None;
+    let mut directories = BTreeMap::new();
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let shard = match SHARD_DIRECTORY_REGEX.captures(&name) {
+            Some(captures) => Shard {
+                index: captures[1].parse().unwrap(),
+                count: captures[2].parse().unwrap(),
+            },
+            None => continue,
+        };
+        if *count.get_or_insert(shard.count) != shard.count {
+            return Err(format!(
+                "Found shards of differing counts in {}",
+                input.display()
+            ));
+        }
+        directories.insert(shard.index, entry.path());
+    }
+    let count = count.ok_or_else(|| format!("Found no shards in {}", input.display()))?;
+    let missing = (1..=count)
+        .filter(|index| !directories.contains_key(index))
+        .map(|index| index.to_string())
+        .collect::<Vec<_>>();
+    if !missing.is_empty() {
+        return Err(format!(
+            "Missing shards {} of {} in {}",
+            missing.join(", "),
+            count,
+            input.display()
+        ));
+    }
+    Ok(directories)
+}
+
+fn csv_files(directory: &Path) -> Vec<String> {
+    std::fs::read_dir(directory)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| name.ends_with(".csv"))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Concatenates the given file of each shard, in order of the shards, the header
+// of every shard must match.
+fn merge_file(name: &str, shards: &[&PathBuf], dest: &Path) -> Result<usize, csv_other::Error> {
+    let path = dest.join(name);
+    let mut writer = csv_other::Writer::from_path(partial_path(&path))?;
+    let mut header: Option<csv_other::StringRecord> = None;
+    let mut rows = 0;
+    for shard in shards {
+        let source = shard.join(name);
+        if !source.exists() {
+            continue;
+        }
+        let mut reader = csv_other::Reader::from_path(&source)?;
+        let shard_header = reader.headers()?.clone();
+        // Reports without rows are written without a header.
+        if shard_header.is_empty() {
+            continue;
+        }
+        match &header {
+            None => {
+                writer.write_record(&shard_header)?;
+                header = Some(shard_header);
+            }
+            Some(header) if *header != shard_header => panic!(
+                "The header of {} differs from that of the other shards",
+                source.display()
+            ),
+            _ => (),
+        }
+        for record in reader.records() {
+            writer.write_record(&record?)?;
+            rows += 1;
+        }
+    }
+    writer.flush()?;
+    finish_partial(&path)?;
+    Ok(rows)
+}
+
+// Merges the CSV files written to each shard directory in the given directory
+// into the destination.
+pub fn merge_shards(input: &Path, dest: &Path) {
+    let directories = shard_directories(input).unwrap_or_else(|error| panic!("{}", error));
+    let shards = directories.values().collect::<Vec<_>>();
+    let files = shards
+        .iter()
+        .flat_map(|directory| csv_files(directory))
+        .collect::<BTreeSet<_>>();
+    if files.is_empty() {
+        warn!("Found no CSV files to merge in {}", input.display());
+    }
+    for file in &files {
+        let rows = merge_file(file, &shards, dest)
+            .unwrap_or_else(|error| panic!("Failed to merge {}: {}", file, error));
+        info!(
+            "Merged {} rows of {} from {} shards",
+            rows,
+            file,
+            shards.len()
+        );
+    }
+    info!(
+        "Merged {} files from {} shards into {}",
+        files.len(),
+        shards.len(),
+        dest.display()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge() {
+        let input = std::env::temp_dir().join(format!("shards-{}", std::process::id()));
+        let write = |shard: &str, file: &str, content: &str| {
+            let directory = input.join(shard);
+            std::fs::create_dir_all(&directory).unwrap();
+            std::fs::write(directory.join(file), content).unwrap();
+        };
+        write("shard-1-of-2", "nodes.csv", "pid,title\narchden:2,Two\n");
+        write("shard-1-of-2", "dangling_parents.csv", "");
+        write("shard-3-of-3", "nodes.csv", "");
+        assert!(shard_directories(&input).is_err());
+        std::fs::remove_dir_all(input.join("shard-3-of-3")).unwrap();
+        assert!(shard_directories(&input).is_err());
+        write(
+            "shard-2-of-2",
+            "nodes.csv",
+            "pid,title\narchden:1,One\narchden:3,Three\n",
+        );
+        write(
+            "shard-2-of-2",
+            "dangling_parents.csv",
+            "pid,parent\narchden:1,archden:4\n",
+        );
+        let dest = input.join("merged");
+        std::fs::create_dir_all(&dest).unwrap();
+        merge_shards(&input, &dest);
+        let read = |file: &str| std::fs::read_to_string(dest.join(file)).unwrap();
+        assert_eq!(
+            read("nodes.csv"),
+            "pid,title\narchden:2,Two\narchden:1,One\narchden:3,Three\n"
+        );
+        assert_eq!(
+            read("dangling_parents.csv"),
+            "pid,parent\narchden:1,archden:4\n"
+        );
+        std::fs::remove_dir_all(&input).unwrap();
+    }
+}
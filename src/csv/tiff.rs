@@ -0,0 +1,275 @@
+// Some legacy objects store every page of a document as a single multi-page
+// TIFF OBJ, which Drupal displays as a single image. These are listed in
+// multipage_tiffs.csv so they can be restructured into paged content before or
+// after import. Pages are counted by walking the chain of image file
+// directories (IFD) in the file header, without decoding any images.
+// @see https://www.itu.int/itudoc/itu-t/com16/tiff-fx/docs/tiff6.pdf
+use super::object::{Object, ObjectMap};
+use super::output::{Output, Row};
+use indicatif::ProgressBar;
+use log::{debug, warn};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const NEW_SUBFILE_TYPE: u16 = 254;
+// NewSubfileType flags of images that are not pages, i.e. reduced resolution
+// copies (thumbnails, pyramid levels) and transparency masks.
+const NOT_A_PAGE: u64 = 0b101;
+// Guards against corrupt files whose directories form a very long chain.
+const MAX_DIRECTORIES: usize = 100_000;
+
+// Reads integers in the byte order given by the header, with offsets of 4
+// bytes for TIFF and 8 bytes for BigTIFF.
+struct Header {
+    big_endian: bool,
+    big_tiff: bool,
+}
+
+impl Header {
+    fn read_u16<R: Read>(&self, reader: &mut R) -> io::Result<u16> {
+        let mut bytes = [0; 2];
+        reader.read_exact(&mut bytes)?;
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn read_u32<R: Read>(&self, reader: &mut R) -> io::Result<u32> {
+        let mut bytes = [0; 4];
+        reader.read_exact(&mut bytes)?;
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn read_u64<R: Read>(&self, reader: &mut R) -> io::Result<u64> {
+        let mut bytes = [0; 8];
+        reader.read_exact(&mut bytes)?;
+        Ok(if self.big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        })
+    }
+
+    // Either the number of entries in a directory or an offset.
+    fn read_offset<R: Read>(&self, reader: &mut R) -> io::Result<u64> {
+        if self.big_tiff {
+            self.read_u64(reader)
+        } else {
+            self.read_u32(reader).map(u64::from)
+        }
+    }
+
+    fn read_count<R: Read>(&self, reader: &mut R) -> io::Result<u64> {
+        if self.big_tiff {
+            self.read_u64(reader)
+        } else {
+            self.read_u16(reader).map(u64::from)
+        }
+    }
+
+    // The header and the offset of the first directory, `None` if the content
+    // is not a TIFF.
+    fn read<R: Read>(reader: &mut R) -> io::Result<Option<(Self, u64)>> {
+        let mut order = [0; 2];
+        reader.read_exact(&mut order)?;
+        let big_endian = match &order {
+            b"II" => false,
+            b"MM" => true,
+            _ => return Ok(None),
+        };
+        let mut header = Header {
+            big_endian,
+            big_tiff: false,
+        };
+        match header.read_u16(reader)? {
+            42 => (),
+            // BigTIFF, followed by the offset size (8) and a reserved 0.
+            43 => {
+                header.big_tiff = true;
+                header.read_u32(reader)?;
+            }
+            _ => return Ok(None),
+        }
+        let offset = header.read_offset(reader)?;
+        Ok(Some((header, offset)))
+    }
+
+    // The NewSubfileType of the directory at the current position and the
+    // offset of the next directory, zero if it is the last.
+    fn read_directory<R: Read + Seek>(&self, reader: &mut R) -> io::Result<(u64, u64)> {
+        let entries = self.read_count(reader)?;
+        let entry_length = if self.big_tiff { 20 } else { 12 };
+        let mut subfile_type = 0;
+        let start = reader.stream_position()?;
+        for index in 0..entries {
+            reader.seek(SeekFrom::Start(start + index * entry_length))?;
+            let tag = self.read_u16(reader)?;
+            if tag != NEW_SUBFILE_TYPE {
+                continue;
+            }
+            let field_type = self.read_u16(reader)?;
+            // The count, the value follows as it fits in the entry.
+            self.read_offset(reader)?;
+            subfile_type = match field_type {
+                3 => u64::from(self.read_u16(reader)?),
+                _ => u64::from(self.read_u32(reader)?),
+            };
+        }
+        reader.seek(SeekFrom::Start(start + entries * entry_length))?;
+        Ok((subfile_type, self.read_offset(reader)?))
+    }
+}
+
+// The number of pages of the given TIFF, `None` if it is not a TIFF.
+pub fn page_count<R: Read + Seek>(reader: &mut R) -> io::Result<Option<usize>> {
+    let (header, mut offset) = match Header::read(reader)? {
+        Some(header) => header,
+        None => return Ok(None),
+    };
+    let mut visited = HashSet::new();
+    let mut pages = 0;
+    // Offsets that were already visited would loop forever.
+    while offset != 0 && visited.len() < MAX_DIRECTORIES && visited.insert(offset) {
+        reader.seek(SeekFrom::Start(offset))?;
+        let (subfile_type, next) = header.read_directory(reader)?;
+        if subfile_type & NOT_A_PAGE == 0 {
+            pages += 1;
+        }
+        offset = next;
+    }
+    Ok(Some(pages))
+}
+
+fn file_page_count(path: &Path) -> io::Result<Option<usize>> {
+    page_count(&mut BufReader::new(File::open(path)?))
+}
+
+#[derive(Debug, Serialize)]
+struct MultipageTiffRow<'a> {
+    pid: &'a str,
+    model: &'a str,
+    label: &'a str,
+    dsid: &'a str,
+    version: &'a str,
+    pages: usize,
+}
+
+impl Row for MultipageTiffRow<'_> {
+    fn columns() -> &'static [&'static str] {
+        &["pid", "model", "label", "dsid", "version", "pages"]
+    }
+}
+
+impl<'a> MultipageTiffRow<'a> {
+    fn new(object: &'a Object) -> Option<Self> {
+        let version = object
+            .datastream("OBJ")
+            .filter(|version| version.mime_type == "image/tiff")?;
+        let path = version.path();
+        match file_page_count(&path) {
+            Ok(Some(pages)) if pages > 1 => Some(MultipageTiffRow {
+                pid: &object.pid.0,
+                model: &object.model,
+                label: &object.label,
+                dsid: &version.dsid,
+                version: &version.id,
+                pages,
+            }),
+            Ok(_) => None,
+            // Missing files are reported in missing_files.csv.
+            Err(error) => {
+                debug!("Failed to count the pages of {}: {}", path.display(), error);
+                None
+            }
+        }
+    }
+}
+
+pub fn csv(objects: &ObjectMap, output: &Output, progress_bar: ProgressBar) {
+    logger::set_length(&progress_bar, objects.objects().count() as u64);
+    let mut rows = objects
+        .objects()
+        .filter_map(|object| {
+            progress_bar.inc(1);
+            MultipageTiffRow::new(object)
+        })
+        .collect::<Vec<_>>();
+    rows.sort_by(|a, b| a.pid.cmp(b.pid));
+    output
+        .write_report(&rows, "multipage_tiffs.csv")
+        .expect("Failed to create multipage_tiffs.csv");
+    if !rows.is_empty() {
+        warn!(
+            "Found {} objects whose OBJ is a multi-page TIFF, see multipage_tiffs.csv",
+            rows.len()
+        );
+    }
+    progress_bar.finish_with_message("Created multipage_tiffs.csv");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // A little-endian TIFF with a directory per given NewSubfileType, each
+    // with a single entry, chained in order.
+    fn tiff(subfile_types: &[u32]) -> Vec<u8> {
+        let mut bytes = b"II".to_vec();
+        bytes.extend(&42u16.to_le_bytes());
+        bytes.extend(&8u32.to_le_bytes());
+        for (index, subfile_type) in subfile_types.iter().enumerate() {
+            bytes.extend(&1u16.to_le_bytes());
+            bytes.extend(&NEW_SUBFILE_TYPE.to_le_bytes());
+            bytes.extend(&4u16.to_le_bytes());
+            bytes.extend(&1u32.to_le_bytes());
+            bytes.extend(&subfile_type.to_le_bytes());
+            let next = if index + 1 < subfile_types.len() {
+                bytes.len() as u32 + 4
+            } else {
+                0
+            };
+            bytes.extend(&next.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn pages(bytes: Vec<u8>) -> Option<usize> {
+        page_count(&mut Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn count_pages() {
+        assert_eq!(pages(tiff(&[0])), Some(1));
+        assert_eq!(pages(tiff(&[0, 2, 2])), Some(3));
+        // A reduced resolution thumbnail and a transparency mask.
+        assert_eq!(pages(tiff(&[0, 1, 4])), Some(1));
+        assert_eq!(pages(b"%PDF-1.4".to_vec()), None);
+        // The last directory points back at the first.
+        let mut looped = tiff(&[0, 0]);
+        let length = looped.len();
+        looped[length - 4..].copy_from_slice(&8u32.to_le_bytes());
+        assert_eq!(pages(looped), Some(2));
+        // Big-endian BigTIFF with two directories without entries.
+        let mut big = b"MM".to_vec();
+        big.extend(&43u16.to_be_bytes());
+        big.extend(&8u16.to_be_bytes());
+        big.extend(&0u16.to_be_bytes());
+        big.extend(&16u64.to_be_bytes());
+        big.extend(&0u64.to_be_bytes());
+        big.extend(&32u64.to_be_bytes());
+        big.extend(&0u64.to_be_bytes());
+        big.extend(&0u64.to_be_bytes());
+        assert_eq!(pages(big), Some(2));
+    }
+}
@@ -0,0 +1,207 @@
+// Time spent on each object, so that the few pathological objects (e.g. a 40GB
+// object file or one with 10k versions) that dominate the runtime of the `csv`
+// and `scripts` commands can be found. When enabled the slowest are logged and
+// listed in slowest_objects.csv, along with their sizes.
+use super::object::{Object, ObjectMap};
+use super::output::{Output, Row};
+use log::info;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy)]
+pub enum Stage {
+    Parse,  // Parsing the object file, objects read from the index take none.
+    Script, // Calling `rows()`, summed across all scripts.
+    Hash,   // Hashing the files of every datastream version.
+}
+
+#[derive(Default)]
+struct Timing {
+    parse: Duration,
+    script: Duration,
+    hash: Duration,
+}
+
+impl Timing {
+    fn total(&self) -> Duration {
+        self.parse + self.script + self.hash
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SlowObjectRow {
+    pid: String,
+    model: String,
+    seconds: String,
+    parse_seconds: String,
+    script_seconds: String,
+    hash_seconds: String,
+    object_size: u64,
+    datastreams: usize,
+    versions: usize,
+    datastreams_size: u64,
+}
+
+impl Row for SlowObjectRow {
+    fn columns() -> &'static [&'static str] {
+        &[
+            "pid",
+            "model",
+            "seconds",
+            "parse_seconds",
+            "script_seconds",
+            "hash_seconds",
+            "object_size",
+            "datastreams",
+            "versions",
+            "datastreams_size",
+        ]
+    }
+}
+
+// Number of objects to report, none if zero.
+static SLOWEST_OBJECTS: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    static ref TIMINGS: Mutex<HashMap<String, Timing>> = Mutex::new(HashMap::new());
+}
+
+pub fn set_slowest_objects(count: usize) {
+    SLOWEST_OBJECTS.store(count, Ordering::Relaxed);
+}
+
+// Times the given function, attributing it to the given object.
+pub fn time<F, T>(stage: Stage, pid: &str, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    if SLOWEST_OBJECTS.load(Ordering::Relaxed) == 0 {
+        return f();
+    }
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    let mut timings = TIMINGS.lock().unwrap();
+    let timing = timings.entry(pid.to_string()).or_default();
+    match stage {
+        Stage::Parse => timing.parse += elapsed,
+        Stage::Script => timing.script += elapsed,
+        Stage::Hash => timing.hash += elapsed,
+    }
+    result
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.3}", duration.as_secs_f64())
+}
+
+// Largest first, ties by PID so the report is stable.
+fn slowest(timings: &HashMap<String, Timing>, count: usize) -> Vec<(&str, &Timing)> {
+    let mut slowest = timings
+        .iter()
+        .map(|(pid, timing)| (pid.as_str(), timing))
+        .collect::<Vec<_>>();
+    slowest.sort_by(|(a, a_timing), (b, b_timing)| {
+        b_timing
+            .total()
+            .cmp(&a_timing.total())
+            .then_with(|| a.cmp(b))
+    });
+    slowest.truncate(count);
+    slowest
+}
+
+impl SlowObjectRow {
+    fn new(pid: &str, timing: &Timing, object: Option<&Object>) -> Self {
+        let versions = object
+            .map(|object| {
+                object
+                    .datastreams
+                    .iter()
+                    .flat_map(|datastream| datastream.versions.iter())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        SlowObjectRow {
+            pid: pid.to_string(),
+            model: object
+                .map(|object| object.model.clone())
+                .unwrap_or_default(),
+            seconds: seconds(timing.total()),
+            parse_seconds: seconds(timing.parse),
+            script_seconds: seconds(timing.script),
+            hash_seconds: seconds(timing.hash),
+            object_size: super::object_file(pid)
+                .and_then(|path| path.metadata().ok())
+                .map_or(0, |metadata| metadata.len()),
+            datastreams: object.map_or(0, |object| object.datastreams.len()),
+            versions: versions.len(),
+            datastreams_size: versions
+                .iter()
+                .filter_map(|version| version.path().metadata().ok())
+                .map(|metadata| metadata.len())
+                .sum(),
+        }
+    }
+}
+
+// Logs the slowest objects and writes slowest_objects.csv if enabled, should be
+// called once all other tables have been written.
+pub fn write_report(objects: &ObjectMap, output: &Output) {
+    let count = SLOWEST_OBJECTS.load(Ordering::Relaxed);
+    if count == 0 {
+        return;
+    }
+    let timings = std::mem::take(&mut *TIMINGS.lock().unwrap());
+    let rows = slowest(&timings, count)
+        .into_iter()
+        .map(|(pid, timing)| SlowObjectRow::new(pid, timing, objects.get(pid)))
+        .collect::<Vec<_>>();
+    info!("The {} slowest objects were:", rows.len());
+    for row in &rows {
+        info!(
+            "  {}: {}s (parse {}s, scripts {}s, hash {}s), {} datastreams with {} versions",
+            row.pid,
+            row.seconds,
+            row.parse_seconds,
+            row.script_seconds,
+            row.hash_seconds,
+            row.datastreams,
+            row.versions
+        );
+    }
+    output
+        .write_rows(&rows, "slowest_objects.csv")
+        .expect("Failed to create slowest_objects.csv");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slowest_objects() {
+        let timing = |parse, script, hash| Timing {
+            parse: Duration::from_millis(parse),
+            script: Duration::from_millis(script),
+            hash: Duration::from_millis(hash),
+        };
+        let mut timings = HashMap::new();
+        timings.insert("archden:1".to_string(), timing(10, 0, 0));
+        timings.insert("archden:2".to_string(), timing(5, 2000, 40));
+        timings.insert("archden:3".to_string(), timing(0, 0, 10));
+        timings.insert("archden:4".to_string(), timing(1, 0, 900));
+        let slowest = slowest(&timings, 3)
+            .into_iter()
+            .map(|(pid, _)| pid)
+            .collect::<Vec<_>>();
+        assert_eq!(slowest, vec!["archden:2", "archden:4", "archden:1"]);
+        let row = SlowObjectRow::new("archden:2", &timings["archden:2"], None);
+        assert_eq!(row.seconds, "2.045");
+        assert_eq!(row.script_seconds, "2.000");
+        assert_eq!(row.versions, 0);
+    }
+}
@@ -1,34 +1,131 @@
+use jwalk::{Parallelism, WalkDir};
 use log::info;
-use rayon::prelude::*;
-use std::path::Path;
-use std::sync::atomic;
-use walkdir::WalkDir;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::{atomic, Arc};
 
-// Find all files recursively in the given folder.
+static FAIL_FAST: atomic::AtomicBool = atomic::AtomicBool::new(false);
+
+pub fn set_fail_fast(enabled: bool) {
+    FAIL_FAST.store(enabled, atomic::Ordering::Relaxed);
+}
+
+pub fn is_fail_fast() -> bool {
+    FAIL_FAST.load(atomic::Ordering::Relaxed)
+}
+
+// Data problems (missing files, unparsable metadata, etc) are logged, counted
+// by category in the summary and skipped, unless failing fast in which case the
+// first one stops the program.
+pub fn report_problem(level: log::Level, category: &str, message: &str) {
+    logger::summary::warning(category, message);
+    report_listed_problem(level, message);
+}
+
+// As above for problems listed in a report, which are counted by the report
+// once it is written, see `output::write_report()`.
+pub fn report_listed_problem(level: log::Level, message: &str) {
+    if is_fail_fast() {
+        panic!("{} (--fail-fast)", message);
+    }
+    log::log!(level, "{}", message);
+}
+
+// Number of files found between updates of the spinner.
+const PROGRESS_INTERVAL: usize = 10_000;
+
+lazy_static! {
+    // Directories are read on a pool of their own, as files are often found
+    // from within tasks running on the global pool, which may be busy. The
+    // walk stalls with a single thread, so at least two are used.
+    static ref WALK_POOL: Arc<ThreadPool> = Arc::new(
+        ThreadPoolBuilder::new()
+            .num_threads(rayon::current_num_threads().max(2))
+            .thread_name(|index| format!("walk-{}", index))
+            .build()
+            .expect("Failed to create thread pool")
+    );
+}
+
+// Find all files recursively in the given folder, sorted by path so the order
+// is the same on every run. Directories are read in parallel and entries are
+// yielded in order as they are read, so only the directories being read are
+// held in memory besides the paths found.
 pub fn files(path: &Path) -> Vec<Box<Path>> {
     let spinner = logger::spinner();
-    let count = atomic::AtomicUsize::new(0);
     info!("Enumerating files at: {}", path.display());
-    WalkDir::new(&path)
+    let error = |error: &dyn std::fmt::Display| -> ! {
+        panic!(
+            "Failed to find files in path: {}. Error: {}",
+            &path.to_string_lossy(),
+            error
+        )
+    };
+    // Symbolic links are not followed, so every path found under the
+    // canonical root is also canonical.
+    let root = path.canonicalize().unwrap_or_else(|e| error(&e));
+    let walk = WalkDir::new(&root)
         .follow_links(false)
-        .into_iter()
-        .par_bridge()
-        .filter(|entry| {
-            entry
-                .as_ref()
-                .map_or(false, |e| e.metadata().map_or(false, |m| m.is_file()))
-        })
-        .map(|entry| {
-            count.fetch_add(1, atomic::Ordering::Relaxed);
-            spinner.set_message(&format!("Found: {}", count.load(atomic::Ordering::Relaxed)));
-            Ok(entry?.path().canonicalize()?.into_boxed_path())
-        })
-        .collect::<Result<Vec<_>, std::io::Error>>()
-        .unwrap_or_else(|error| {
-            panic!(
-                "Failed to find files in path: {}. Error: {}",
-                &path.to_string_lossy(),
-                error
-            )
-        })
+        .skip_hidden(false)
+        .sort(true)
+        .parallelism(Parallelism::RayonExistingPool {
+            pool: WALK_POOL.clone(),
+            busy_timeout: None,
+        });
+    let mut files = Vec::new();
+    for entry in walk {
+        let entry = entry.unwrap_or_else(|e| error(&e));
+        if entry.file_type().is_file() {
+            files.push(entry.path().into_boxed_path());
+            if files.len() % PROGRESS_INTERVAL == 0 {
+                spinner.set_message(&format!("Found: {}", files.len()));
+            }
+        }
+    }
+    spinner.finish_and_clear();
+    files
+}
+
+// CSV files are written to `<name>.csv.partial` and renamed once complete, so
+// that a truncated file is never mistaken for a complete one.
+pub fn partial_path(dest: &Path) -> PathBuf {
+    let mut name = OsString::from(dest.file_name().unwrap());
+    name.push(".partial");
+    dest.with_file_name(name)
+}
+
+pub fn finish_partial(dest: &Path) -> Result<(), std::io::Error> {
+    std::fs::rename(partial_path(dest), dest)
+}
+
+pub fn remove_completion_marker(dest: &Path) {
+    if dest.exists() {
+        std::fs::remove_file(dest)
+            .unwrap_or_else(|error| panic!("Failed to remove {}: {}", dest.display(), error));
+    }
+}
+
+// Written last to signal all files are complete, listing the number of rows in
+// each so wrapper scripts can verify the output.
+pub fn write_completion_marker(dest: &Path, outputs: &[(String, usize)]) {
+    let write = || -> Result<(), csv_other::Error> {
+        let mut writer = csv_other::Writer::from_path(partial_path(dest))?;
+        writer.write_record(["file", "rows"])?;
+        for (file, rows) in outputs {
+            writer.write_record([file, &rows.to_string()])?;
+        }
+        writer.flush()?;
+        Ok(finish_partial(dest)?)
+    };
+    write().unwrap_or_else(|error| panic!("Failed to write {}: {}", dest.display(), error));
+}
+
+// Reports the files written so far and their number of rows to the webhook.
+pub fn notify_outputs(phase: &str, outputs: &[(String, usize)]) {
+    let counts = outputs
+        .iter()
+        .map(|(file, rows)| (file.as_str(), *rows))
+        .collect::<Vec<_>>();
+    logger::webhook::phase_finished(phase, &counts);
 }
@@ -0,0 +1,465 @@
+// Subjects, genres and names aggregated across the repository, deduplicated
+// into subjects.csv and agents.csv, to seed the Drupal taxonomies and let
+// librarians review the vocabulary before importing. Terms are taken from the
+// MODS of each object, or its DC if it has no MODS. Terms that differ only by
+// case, whitespace or trailing punctuation are considered the same, the most
+// frequent spelling is used and the others are listed as variants.
+use super::object::{Object, ObjectMap};
+use super::output::{Output, Row};
+use super::utils::report_problem;
+use indicatif::ProgressBar;
+use log::Level;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::BufRead;
+
+// Elements of a MODS subject that are listed in subjects.csv, genre is also
+// taken from the top level.
+const SUBJECT_ELEMENTS: [&str; 5] = ["topic", "geographic", "temporal", "genre", "occupation"];
+
+#[derive(Debug, PartialEq)]
+struct Subject {
+    // The element it was taken from e.g. 'topic'.
+    vocabulary: &'static str,
+    term: String,
+    authority: String,
+}
+
+#[derive(Debug, PartialEq)]
+struct Agent {
+    name: String,
+    // The MODS name type e.g. 'personal' or 'corporate'.
+    r#type: String,
+    // 'subject' if the name is the subject of the object.
+    role: String,
+}
+
+#[derive(Default)]
+struct Terms {
+    subjects: Vec<Subject>,
+    agents: Vec<Agent>,
+}
+
+fn attribute(element: &BytesStart, name: &[u8]) -> String {
+    element
+        .attributes()
+        .filter_map(|attribute| attribute.ok())
+        .find(|attribute| attribute.key == name)
+        .map(|attribute| String::from_utf8_lossy(&attribute.value).trim().to_string())
+        .unwrap_or_default()
+}
+
+fn local_name(element: &BytesStart) -> String {
+    String::from_utf8_lossy(element.local_name()).to_string()
+}
+
+fn mods_terms<B: BufRead>(mut reader: Reader<B>) -> Result<Terms, quick_xml::Error> {
+    reader.trim_text(true);
+    let mut buffer = Vec::new();
+    let mut terms = Terms::default();
+    // Local names of the open elements.
+    let mut path: Vec<String> = Vec::new();
+    // Authority of the enclosing subject, if any.
+    let mut subject_authority = String::new();
+    // Name being read, along with its parts.
+    let mut agent: Option<(Agent, Vec<String>)> = None;
+    loop {
+        match reader.read_event(&mut buffer)? {
+            Event::Start(element) => {
+                let name = local_name(&element);
+                let parent = path.last().map(String::as_str).unwrap_or_default();
+                let in_subject = parent == "subject";
+                if name == "subject" {
+                    subject_authority = attribute(&element, b"authority");
+                } else if name == "name" && (in_subject || path.len() == 1) {
+                    let role = if in_subject { "subject" } else { "" };
+                    let agent_type = attribute(&element, b"type");
+                    agent = Some((
+                        Agent {
+                            name: String::new(),
+                            r#type: agent_type,
+                            role: role.to_string(),
+                        },
+                        Vec::new(),
+                    ));
+                } else if let Some((agent, parts)) = agent.as_mut() {
+                    let element_name = element.name().to_vec();
+                    match name.as_str() {
+                        "namePart" => {
+                            parts.push(reader.read_text(&element_name, &mut Vec::new())?);
+                            buffer.clear();
+                            continue;
+                        }
+                        "roleTerm" if agent.role.is_empty() => {
+                            agent.role = reader.read_text(&element_name, &mut Vec::new())?;
+                            buffer.clear();
+                            continue;
+                        }
+                        _ => (),
+                    }
+                } else if SUBJECT_ELEMENTS.contains(&name.as_str())
+                    && (in_subject || (name == "genre" && path.len() == 1))
+                {
+                    let authority = match attribute(&element, b"authority") {
+                        authority if authority.is_empty() && in_subject => {
+                            subject_authority.clone()
+                        }
+                        authority => authority,
+                    };
+                    let vocabulary = SUBJECT_ELEMENTS.iter().find(|e| **e == name).unwrap();
+                    let term = reader.read_text(element.name(), &mut Vec::new())?;
+                    terms.subjects.push(Subject {
+                        vocabulary,
+                        term,
+                        authority,
+                    });
+                    buffer.clear();
+                    continue;
+                }
+                path.push(name);
+            }
+            Event::End(element) => {
+                if element.local_name() == b"name" && path.last().is_some_and(|n| n == "name") {
+                    if let Some((mut agent, parts)) = agent.take() {
+                        agent.name = parts.join(", ");
+                        terms.agents.push(agent);
+                    }
+                }
+                path.pop();
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+        buffer.clear();
+    }
+    Ok(terms)
+}
+
+fn dc_terms<B: BufRead>(mut reader: Reader<B>) -> Result<Terms, quick_xml::Error> {
+    reader.trim_text(true);
+    let mut buffer = Vec::new();
+    let mut terms = Terms::default();
+    loop {
+        match reader.read_event(&mut buffer)? {
+            Event::Start(element) => {
+                let name = local_name(&element);
+                if ["subject", "creator", "contributor"].contains(&name.as_str()) {
+                    let text = reader.read_text(element.name(), &mut Vec::new())?;
+                    if name == "subject" {
+                        terms.subjects.push(Subject {
+                            vocabulary: "topic",
+                            term: text,
+                            authority: String::new(),
+                        });
+                    } else {
+                        terms.agents.push(Agent {
+                            name: text,
+                            r#type: String::new(),
+                            role: name,
+                        });
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+        buffer.clear();
+    }
+    Ok(terms)
+}
+
+// Terms are taken from the first of the given DSIDs holding the MODS, or DC if
+// the object has none of them.
+fn object_terms(object: &Object, descriptive_metadata: &[String]) -> Terms {
+    let (mods, version) = match object.descriptive_metadata(descriptive_metadata) {
+        Some(version) => (true, Some(version)),
+        None => (false, object.datastream("DC")),
+    };
+    let version = match version.filter(|v| v.path().exists()) {
+        Some(version) => version,
+        None => return Terms::default(),
+    };
+    let terms = Reader::from_file(version.path()).and_then(|reader| {
+        if mods {
+            mods_terms(reader)
+        } else {
+            dc_terms(reader)
+        }
+    });
+    match terms {
+        Ok(terms) => terms,
+        Err(error) => {
+            let format = if mods { "mods" } else { "dc" };
+            report_problem(
+                Level::Warn,
+                &format!("unparsable_{}", format),
+                &format!(
+                    "Failed to parse {} of {}: {}",
+                    version.dsid, object.pid, error
+                ),
+            );
+            Terms::default()
+        }
+    }
+}
+
+// Terms that differ only by case, whitespace or trailing punctuation.
+fn normalize(term: &str) -> String {
+    term.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_end_matches(|c: char| ".,;:".contains(c))
+        .to_lowercase()
+}
+
+#[derive(Default)]
+struct Entry {
+    // Spellings and the number of times each was used.
+    spellings: HashMap<String, usize>,
+    // Authorities of subjects, types ('type:personal') and roles
+    // ('role:editor') of agents.
+    qualifiers: BTreeSet<String>,
+    pids: BTreeSet<String>,
+}
+
+impl Entry {
+    fn add<I>(&mut self, term: &str, qualifiers: I, pid: &str)
+    where
+        I: IntoIterator<Item = String>,
+    {
+        *self.spellings.entry(term.to_string()).or_insert(0) += 1;
+        self.qualifiers.extend(qualifiers);
+        self.pids.insert(pid.to_string());
+    }
+
+    // Most frequent spelling first, ties alphabetically.
+    fn spellings(&self) -> Vec<&str> {
+        let mut spellings = self.spellings.iter().collect::<Vec<_>>();
+        spellings.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+        spellings
+            .into_iter()
+            .map(|(term, _)| term.as_str())
+            .collect()
+    }
+}
+
+#[derive(Serialize)]
+pub struct SubjectRow {
+    vocabulary: String,
+    term: String,
+    authority: String,
+    count: usize,
+    variants: String,
+    pids: String,
+}
+
+impl Row for SubjectRow {
+    fn columns() -> &'static [&'static str] {
+        &[
+            "vocabulary",
+            "term",
+            "authority",
+            "count",
+            "variants",
+            "pids",
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct AgentRow {
+    name: String,
+    r#type: String,
+    roles: String,
+    count: usize,
+    variants: String,
+    pids: String,
+}
+
+impl Row for AgentRow {
+    fn columns() -> &'static [&'static str] {
+        &["name", "type", "roles", "count", "variants", "pids"]
+    }
+}
+
+type Entries = BTreeMap<(String, String), Entry>;
+
+fn aggregate(objects: Vec<(&str, Terms)>) -> (Entries, Entries) {
+    let (mut subjects, mut agents) = (Entries::new(), Entries::new());
+    for (pid, terms) in objects {
+        for subject in terms.subjects.iter().filter(|s| !s.term.trim().is_empty()) {
+            let term = subject.term.trim();
+            let authority = Some(subject.authority.clone()).filter(|a| !a.is_empty());
+            subjects
+                .entry((subject.vocabulary.to_string(), normalize(term)))
+                .or_default()
+                .add(term, authority, pid);
+        }
+        for agent in terms.agents.iter().filter(|a| !a.name.trim().is_empty()) {
+            let name = agent.name.trim();
+            let qualifiers = [("type", agent.r#type.trim()), ("role", agent.role.trim())]
+                .iter()
+                .filter(|(_, value)| !value.is_empty())
+                .map(|(prefix, value)| format!("{}:{}", prefix, value))
+                .collect::<Vec<_>>();
+            agents
+                .entry((String::new(), normalize(name)))
+                .or_default()
+                .add(name, qualifiers, pid);
+        }
+    }
+    (subjects, agents)
+}
+
+fn qualifiers(entry: &Entry, prefix: &str) -> String {
+    entry
+        .qualifiers
+        .iter()
+        .filter_map(|qualifier| qualifier.strip_prefix(prefix))
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+fn rows(subjects: Entries, agents: Entries) -> (Vec<SubjectRow>, Vec<AgentRow>) {
+    let join = |values: &[&str]| values.join("|");
+    let pids = |entry: &Entry| entry.pids.iter().cloned().collect::<Vec<_>>().join("|");
+    let subjects = subjects
+        .into_iter()
+        .map(|((vocabulary, _), entry)| {
+            let spellings = entry.spellings();
+            SubjectRow {
+                vocabulary,
+                term: spellings[0].to_string(),
+                authority: qualifiers(&entry, ""),
+                count: entry.pids.len(),
+                variants: join(&spellings[1..]),
+                pids: pids(&entry),
+            }
+        })
+        .collect();
+    let agents = agents
+        .into_values()
+        .map(|entry| {
+            let spellings = entry.spellings();
+            AgentRow {
+                name: spellings[0].to_string(),
+                r#type: qualifiers(&entry, "type:"),
+                roles: qualifiers(&entry, "role:"),
+                count: entry.pids.len(),
+                variants: join(&spellings[1..]),
+                pids: pids(&entry),
+            }
+        })
+        .collect();
+    (subjects, agents)
+}
+
+pub fn csv(
+    objects: &ObjectMap,
+    output: &Output,
+    descriptive_metadata: &[String],
+    progress_bar: ProgressBar,
+) {
+    logger::set_length(&progress_bar, objects.objects().count() as u64);
+    let terms = objects
+        .objects()
+        .map(|object| {
+            progress_bar.inc(1);
+            (
+                object.pid.0.as_str(),
+                object_terms(object, descriptive_metadata),
+            )
+        })
+        .collect::<Vec<_>>();
+    let (subjects, agents) = aggregate(terms);
+    let (subjects, agents) = rows(subjects, agents);
+    output
+        .write_rows(&subjects, "subjects.csv")
+        .expect("Failed to create subjects.csv");
+    output
+        .write_rows(&agents, "agents.csv")
+        .expect("Failed to create agents.csv");
+    progress_bar.finish_with_message("Created subjects.csv and agents.csv");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static MODS: &str = r#"<mods:mods xmlns:mods="http://www.loc.gov/mods/v3">
+        <mods:name type="personal">
+            <mods:namePart>Casey, A. J.</mods:namePart>
+            <mods:role><mods:roleTerm type="text">editor</mods:roleTerm></mods:role>
+        </mods:name>
+        <mods:genre authority="aat">newspapers</mods:genre>
+        <mods:subject authority="lcsh">
+            <mods:topic>Catholic News</mods:topic>
+            <mods:geographic>Colorado</mods:geographic>
+        </mods:subject>
+        <mods:subject>
+            <mods:name type="corporate"><mods:namePart>Archdiocese of Denver</mods:namePart></mods:name>
+        </mods:subject>
+        <mods:relatedItem><mods:name><mods:namePart>Ignored</mods:namePart></mods:name></mods:relatedItem>
+    </mods:mods>"#;
+
+    #[test]
+    fn mods_subjects_and_agents() {
+        let terms = mods_terms(Reader::from_str(MODS)).unwrap();
+        let subjects = terms
+            .subjects
+            .iter()
+            .map(|s| (s.vocabulary, s.term.as_str(), s.authority.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            subjects,
+            vec![
+                ("genre", "newspapers", "aat"),
+                ("topic", "Catholic News", "lcsh"),
+                ("geographic", "Colorado", "lcsh"),
+            ]
+        );
+        let agents = terms
+            .agents
+            .iter()
+            .map(|a| (a.name.as_str(), a.r#type.as_str(), a.role.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            agents,
+            vec![
+                ("Casey, A. J.", "personal", "editor"),
+                ("Archdiocese of Denver", "corporate", "subject"),
+            ]
+        );
+    }
+
+    #[test]
+    fn deduplicated() {
+        let subject = |term: &str| Subject {
+            vocabulary: "topic",
+            term: term.to_string(),
+            authority: String::new(),
+        };
+        let terms = |subjects| Terms {
+            subjects,
+            agents: vec![],
+        };
+        let objects = vec![
+            ("archden:1", terms(vec![subject("Catholic News")])),
+            ("archden:2", terms(vec![subject("catholic  news.")])),
+            (
+                "archden:3",
+                terms(vec![subject("Catholic News"), subject(" ")]),
+            ),
+        ];
+        let (subjects, agents) = aggregate(objects);
+        let (subjects, _) = rows(subjects, agents);
+        assert_eq!(subjects.len(), 1);
+        assert_eq!(subjects[0].term, "Catholic News");
+        assert_eq!(subjects[0].count, 3);
+        assert_eq!(subjects[0].variants, "catholic  news.");
+        assert_eq!(subjects[0].pids, "archden:1|archden:2|archden:3");
+    }
+}
@@ -0,0 +1,37 @@
+// The problems counted by category so far, e.g. 'missing_files' or
+// 'unparsable_dc', along with a few examples of each, so that it is known at
+// a glance how many of each kind of problem occurred.
+use super::output::{Output, Row};
+use serde::Serialize;
+
+static REPORT: &str = "warnings.csv";
+
+#[derive(Debug, Serialize)]
+pub struct WarningRow {
+    category: String,
+    count: usize,
+    examples: String,
+}
+
+impl Row for WarningRow {
+    fn columns() -> &'static [&'static str] {
+        &["category", "count", "examples"]
+    }
+}
+
+// Writes warnings.csv, should be called once all other tables have been
+// written.
+pub fn write_report(output: &Output) {
+    let rows = logger::summary::categories()
+        .into_iter()
+        .map(|(category, count, examples)| WarningRow {
+            category,
+            count,
+            examples: examples.join("|"),
+        })
+        .collect::<Vec<_>>();
+    // Not written as a report, as the rows would then be counted themselves.
+    output
+        .write_rows(&rows, REPORT)
+        .expect("Failed to create warnings.csv");
+}
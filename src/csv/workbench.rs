@@ -0,0 +1,160 @@
+// Islandora Workbench CSVs for sites that import with Workbench in two passes,
+// first creating the nodes and then adding their media as a secondary task.
+// Both are keyed by PID, the `id` of workbench_create.csv is the `node_id` of
+// workbench_add_media.csv, which is to be replaced by the node ids Workbench
+// assigned once the create task has run.
+// @see https://mjordan.github.io/islandora_workbench_docs/
+use super::object::{Object, ObjectMap, ObjectState};
+use super::output::{Output, Row};
+use super::rows::model_identifier;
+use indicatif::ProgressBar;
+use log::info;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+
+lazy_static! {
+    // Islandora media use terms by DSID, datastreams not listed are not added.
+    static ref MEDIA_USE_MAP: HashMap<&'static str, &'static str> = {
+        let mut m = HashMap::new();
+        m.insert("OBJ", "http://pcdm.org/use#OriginalFile");
+        m.insert("TN", "http://pcdm.org/use#ThumbnailImage");
+        m.insert("OCR", "http://pcdm.org/use#ExtractedText");
+        m.insert("HOCR", "http://pcdm.org/use#ExtractedText");
+        m.insert("FULL_TEXT", "http://pcdm.org/use#ExtractedText");
+        m.insert("TRANSCRIPT", "http://pcdm.org/use#Transcript");
+        m.insert("JP2", "http://pcdm.org/use#ServiceFile");
+        m.insert("JPG", "http://pcdm.org/use#ServiceFile");
+        m.insert("MEDIUM_SIZE", "http://pcdm.org/use#ServiceFile");
+        m.insert("PREVIEW", "http://pcdm.org/use#ServiceFile");
+        m.insert("PROXY_MP3", "http://pcdm.org/use#ServiceFile");
+        m.insert("MP4", "http://pcdm.org/use#ServiceFile");
+        m
+    };
+}
+
+#[derive(Serialize)]
+pub struct CreateRow<'a> {
+    id: &'a str,
+    title: &'a str,
+    field_model: &'static str,
+    // The first parent that was migrated, which precedes this row.
+    parent_id: &'a str,
+    field_weight: String,
+    published: u8,
+}
+
+impl Row for CreateRow<'_> {
+    fn columns() -> &'static [&'static str] {
+        &[
+            "id",
+            "title",
+            "field_model",
+            "parent_id",
+            "field_weight",
+            "published",
+        ]
+    }
+}
+
+#[derive(Serialize)]
+pub struct AddMediaRow<'a> {
+    node_id: &'a str,
+    file: String,
+    media_use_tid: &'static str,
+}
+
+impl Row for AddMediaRow<'_> {
+    fn columns() -> &'static [&'static str] {
+        &["node_id", "file", "media_use_tid"]
+    }
+}
+
+// Number of migrated ancestors, as Workbench requires parents to be created
+// before their children.
+fn depth<'a>(
+    object: &'a Object,
+    objects: &'a ObjectMap,
+    depths: &mut HashMap<&'a str, usize>,
+) -> usize {
+    if let Some(depth) = depths.get(object.pid.0.as_str()) {
+        return *depth;
+    }
+    // Guards against cycles.
+    depths.insert(&object.pid.0, 0);
+    let depth = parent(object, objects).map_or(0, |parent| depth(parent, objects, depths) + 1);
+    depths.insert(&object.pid.0, depth);
+    depth
+}
+
+fn parent<'a>(object: &Object, objects: &'a ObjectMap) -> Option<&'a Object> {
+    object.parents.iter().find_map(|parent| objects.get(parent))
+}
+
+impl<'a> CreateRow<'a> {
+    fn new(object: &'a Object, objects: &'a ObjectMap) -> Self {
+        CreateRow {
+            id: &object.pid.0,
+            title: &object.label,
+            field_model: model_identifier(&object.model),
+            parent_id: parent(object, objects).map_or("", |parent| &parent.pid.0),
+            field_weight: object.weight.map_or("".to_string(), |w| w.to_string()),
+            published: (object.state == ObjectState::Active) as u8,
+        }
+    }
+}
+
+fn create_rows(objects: &ObjectMap) -> Vec<CreateRow<'_>> {
+    let mut depths = HashMap::new();
+    let mut objects_by_depth = objects
+        .inner()
+        .values()
+        .map(|object| (depth(object, objects, &mut depths), object))
+        .collect::<Vec<_>>();
+    // Stable, so objects of the same depth remain ordered by PID.
+    objects_by_depth.sort_by_key(|(depth, _)| *depth);
+    objects_by_depth
+        .into_iter()
+        .map(|(_, object)| CreateRow::new(object, objects))
+        .collect()
+}
+
+pub fn csv(objects: &ObjectMap, output: &Output, progress_bar: ProgressBar) {
+    logger::set_length(&progress_bar, objects.latest_versions().count() as u64);
+    let create = create_rows(objects);
+    output
+        .write_rows(&create, "workbench_create.csv")
+        .expect("Failed to create workbench_create.csv");
+    let add_media = objects
+        .latest_versions()
+        .filter_map(|(object, datastream, version)| {
+            progress_bar.inc(1);
+            let media_use = MEDIA_USE_MAP.get(datastream.id.as_str())?;
+            // Workbench fetches remote content from its URL.
+            let file = match version.url() {
+                Some(url) => url.to_string(),
+                None => {
+                    let path = version.path();
+                    if !path.exists() {
+                        return None;
+                    }
+                    path.to_string_lossy().to_string()
+                }
+            };
+            Some(AddMediaRow {
+                node_id: &object.pid.0,
+                file,
+                media_use_tid: media_use,
+            })
+        })
+        .collect::<Vec<_>>();
+    info!(
+        "Found {} nodes and {} media for Islandora Workbench",
+        create.len(),
+        add_media.len()
+    );
+    output
+        .write_rows(&add_media, "workbench_add_media.csv")
+        .expect("Failed to create workbench_add_media.csv");
+    progress_bar.finish_with_message("Created workbench_create.csv and workbench_add_media.csv");
+}
@@ -0,0 +1,203 @@
+// Additionally writes every generated table as a sheet of a single Excel
+// workbook, for QA reviewers who prefer one spreadsheet to many CSV files.
+// Rows are recorded as they are written, after sorting and field limits have
+// been applied, so the sheets match the tables exactly.
+use super::output::{csv_value, Output};
+use log::{info, warn};
+use rust_xlsxwriter::{Format, Workbook as Xlsx, XlsxError};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Limits imposed by Excel.
+const MAX_ROWS: usize = 1_048_576;
+const MAX_COLUMNS: usize = 16_384;
+const MAX_CELL_LENGTH: usize = 32_767;
+const MAX_SHEET_NAME_LENGTH: usize = 31;
+
+// Header and rows of each table by file stem.
+type Sheets = BTreeMap<String, (Vec<String>, Vec<Vec<Value>>)>;
+
+// The workbook given by `--xlsx` and the tables recorded so far.
+pub struct Workbook {
+    path: PathBuf,
+    sheets: Mutex<Sheets>,
+}
+
+impl Workbook {
+    pub fn new(path: PathBuf) -> Self {
+        Workbook {
+            path,
+            sheets: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // Records the given table as it is written.
+    pub fn record(&self, dest: &Path, header: &[String], rows: &[Vec<Value>]) {
+        // Reports without rows have no header and are left out.
+        if header.is_empty() {
+            return;
+        }
+        let stem = dest.file_stem().unwrap().to_string_lossy().to_string();
+        self.sheets
+            .lock()
+            .unwrap()
+            .insert(stem, (header.to_vec(), rows.to_vec()));
+    }
+}
+
+// Sheet names are limited in length, must not contain some characters and
+// must be unique regardless of case.
+fn sheet_names<'a>(stems: impl Iterator<Item = &'a String>) -> Vec<String> {
+    let mut used = HashSet::new();
+    stems
+        .map(|stem| {
+            let name = stem
+                .chars()
+                .map(|c| match c {
+                    '[' | ']' | ':' | '*' | '?' | '/' | '\\' => '_',
+                    c => c,
+                })
+                .take(MAX_SHEET_NAME_LENGTH)
+                .collect::<String>();
+            let mut unique = name.clone();
+            let mut suffix = 1;
+            while !used.insert(unique.to_lowercase()) {
+                suffix += 1;
+                let suffix = format!("~{}", suffix);
+                let prefix = name
+                    .chars()
+                    .take(MAX_SHEET_NAME_LENGTH - suffix.len())
+                    .collect::<String>();
+                unique = prefix + &suffix;
+            }
+            unique
+        })
+        .collect()
+}
+
+fn write_sheets(sheets: &Sheets, path: &Path) -> Result<(), XlsxError> {
+    let mut workbook = Xlsx::new();
+    let bold = Format::new().set_bold();
+    for (name, (stem, (header, rows))) in sheet_names(sheets.keys()).into_iter().zip(sheets) {
+        let worksheet = workbook.add_worksheet();
+        worksheet.set_name(&name)?;
+        if header.len() > MAX_COLUMNS {
+            warn!(
+                "Only the first {} of {} columns of {} were written to the workbook",
+                MAX_COLUMNS,
+                header.len(),
+                stem
+            );
+        }
+        // One row is taken by the header.
+        if rows.len() >= MAX_ROWS {
+            warn!(
+                "Only the first {} of {} rows of {} were written to the workbook",
+                MAX_ROWS - 1,
+                rows.len(),
+                stem
+            );
+        }
+        for (column, title) in header.iter().take(MAX_COLUMNS).enumerate() {
+            worksheet.write_string_with_format(0, column as u16, title, &bold)?;
+        }
+        let mut truncated = 0;
+        for (index, row) in rows.iter().take(MAX_ROWS - 1).enumerate() {
+            let index = index as u32 + 1;
+            for (column, value) in row.iter().take(MAX_COLUMNS).enumerate() {
+                let column = column as u16;
+                match value {
+                    Value::Bool(value) => {
+                        worksheet.write_boolean(index, column, *value)?;
+                    }
+                    Value::Number(number) if number.as_f64().is_some() => {
+                        worksheet.write_number(index, column, number.as_f64().unwrap())?;
+                    }
+                    value => {
+                        let value = csv_value(value);
+                        if value.chars().count() > MAX_CELL_LENGTH {
+                            truncated += 1;
+                            let value = value.chars().take(MAX_CELL_LENGTH).collect::<String>();
+                            worksheet.write_string(index, column, value)?;
+                        } else if !value.is_empty() {
+                            worksheet.write_string(index, column, value)?;
+                        }
+                    }
+                }
+            }
+        }
+        if truncated > 0 {
+            warn!(
+                "Truncated {} values of {} to the {} characters allowed in a cell of the workbook",
+                truncated, stem, MAX_CELL_LENGTH
+            );
+        }
+        worksheet.set_freeze_panes(1, 0)?;
+    }
+    workbook.save(path)
+}
+
+// Writes the workbook if requested, should be called once all other tables
+// have been written.
+pub fn write(output: &Output) {
+    let (path, sheets) = match output.workbook() {
+        Some(workbook) => (&workbook.path, &workbook.sheets),
+        None => return,
+    };
+    let sheets = std::mem::take(&mut *sheets.lock().unwrap());
+    if sheets.is_empty() {
+        warn!("No tables were written, so {} is empty", path.display());
+    }
+    write_sheets(&sheets, path)
+        .unwrap_or_else(|error| panic!("Failed to write {}: {}", path.display(), error));
+    info!("Wrote {} sheets to {}", sheets.len(), path.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn unique_sheet_names() {
+        let stems = [
+            "nodes".to_string(),
+            "NODES".to_string(),
+            "media:files?".to_string(),
+            "a_very_long_table_name_that_is_truncated".to_string(),
+            "a_very_long_table_name_that_is_truncated_too".to_string(),
+        ];
+        assert_eq!(
+            sheet_names(stems.iter()),
+            vec![
+                "nodes",
+                "NODES~2",
+                "media_files_",
+                "a_very_long_table_name_that_is_",
+                "a_very_long_table_name_that_i~2",
+            ]
+        );
+    }
+
+    #[test]
+    fn write_workbook() {
+        let path = std::env::temp_dir().join(format!("workbook-{}.xlsx", std::process::id()));
+        let mut sheets = BTreeMap::new();
+        sheets.insert(
+            "nodes".to_string(),
+            (
+                vec!["pid".to_string(), "weight".to_string()],
+                vec![
+                    vec![json!("archden:1"), json!(1)],
+                    vec![json!("archden:2"), json!(null)],
+                ],
+            ),
+        );
+        write_sheets(&sheets, &path).unwrap();
+        // Workbooks are zip archives.
+        assert_eq!(&std::fs::read(&path).unwrap()[..2], b"PK");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,18 +1,109 @@
+use super::encoding::Decoder;
 use super::map::CustomMap;
 use super::object::*;
-use quick_xml::events::{BytesStart, Event};
+use foxml::pids::PidMap;
+use quick_xml::events::{BytesStart, BytesText, Event};
 use quick_xml::Reader;
 use rhai::{Array, Dynamic, ImmutableString};
 use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 
 type Element = (ImmutableString, CustomMap);
 
+// Limits on the documents given to scripts, so that a pathological or malicious
+// datastream results in a script error rather than exhausting the stack or
+// memory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct XmlLimits {
+    // Nesting of elements.
+    pub max_depth: usize,
+    // Elements in the whole document.
+    pub max_elements: usize,
+    // Bytes of text in a single element.
+    pub max_text: usize,
+}
+
+impl Default for XmlLimits {
+    fn default() -> Self {
+        XmlLimits {
+            max_depth: 256,
+            max_elements: 1_000_000,
+            max_text: 16 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum XmlError {
+    Xml(quick_xml::Error),
+    // Which limit was exceeded, and the argument used to raise it.
+    Limit(&'static str, usize, &'static str),
+}
+
+impl fmt::Display for XmlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XmlError::Xml(error) => write!(f, "{}", error),
+            XmlError::Limit(limit, value, arg) => write!(
+                f,
+                "Exceeded the maximum {} of {}, see {}",
+                limit, value, arg
+            ),
+        }
+    }
+}
+
+impl From<quick_xml::Error> for XmlError {
+    fn from(error: quick_xml::Error) -> Self {
+        XmlError::Xml(error)
+    }
+}
+
+// Tracks the document parsed so far against the limits.
+struct Budget {
+    limits: XmlLimits,
+    elements: usize,
+}
+
+impl Budget {
+    fn element(&mut self, depth: usize) -> Result<(), XmlError> {
+        self.elements += 1;
+        if depth > self.limits.max_depth {
+            Err(XmlError::Limit(
+                "depth",
+                self.limits.max_depth,
+                "--xml-max-depth",
+            ))
+        } else if self.elements > self.limits.max_elements {
+            Err(XmlError::Limit(
+                "number of elements",
+                self.limits.max_elements,
+                "--xml-max-elements",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn text(&self, text: &[u8]) -> Result<(), XmlError> {
+        if text.len() > self.limits.max_text {
+            Err(XmlError::Limit(
+                "text size (bytes)",
+                self.limits.max_text,
+                "--xml-max-text",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
+
 // Returns optional namespace and local-name portions of the given element.
 // If the namespace is not part of the name it will be set to an empty string.
-fn name(element: &BytesStart) -> (ImmutableString, ImmutableString) {
-    let name = unsafe { std::str::from_utf8_unchecked(element.name()).to_string() };
+fn name(element: &BytesStart, decoder: &mut Decoder) -> (ImmutableString, ImmutableString) {
+    let name = decoder.decode(element.name());
     let parts: Vec<_> = name.split(':').collect();
     if parts.len() == 2 {
         (parts[0].into(), parts[1].into())
@@ -21,26 +112,31 @@ fn name(element: &BytesStart) -> (ImmutableString, ImmutableString) {
     }
 }
 
-fn attributes(element: &BytesStart) -> CustomMap {
+fn attributes(element: &BytesStart, decoder: &mut Decoder, pid_map: &PidMap) -> CustomMap {
     element
         .attributes()
         .filter_map(|x| x.ok())
-        .map(|attribute| unsafe {
-            let key = ImmutableString::from(format!(
-                "@{}",
-                std::str::from_utf8_unchecked(&attribute.key)
-            ));
-            let value = Dynamic::from(std::str::from_utf8_unchecked(&attribute.value).to_string());
+        .map(|attribute| {
+            let key = ImmutableString::from(format!("@{}", decoder.decode(attribute.key)));
+            let value = pid_map.rewrite_reference(&decoder.decode(&attribute.value));
+            let value = Dynamic::from(value);
             (key, value)
         })
         .collect()
 }
 
-fn element<B>(reader: &mut Reader<B>, e: &BytesStart) -> Result<Element, quick_xml::Error>
+fn element<B>(
+    reader: &mut Reader<B>,
+    e: &BytesStart,
+    budget: &mut Budget,
+    decoder: &mut Decoder,
+    pid_map: &PidMap,
+    depth: usize,
+) -> Result<Element, XmlError>
 where
     B: BufRead,
 {
-    let mut properties = attributes(&e);
+    let mut properties = attributes(&e, decoder, pid_map);
     let mut children: Vec<Element> = Vec::new();
     let mut text = ImmutableString::from("".to_string());
     let mut buffer = Vec::new();
@@ -48,14 +144,17 @@ where
         match reader.read_event(&mut buffer)? {
             // Opening tag of child.
             Event::Start(e) => {
-                children.push(element(reader, &e)?); // Recurse.
+                budget.element(depth + 1)?;
+                children.push(element(reader, &e, budget, decoder, pid_map, depth + 1)?);
+                // Recurse.
             }
             // Closing current tag.
             Event::End(_) => break,
             // Tag of childless with no child.
             Event::Empty(e) => {
-                let (namespace, local_name) = name(&e);
-                let mut properties = attributes(&e);
+                budget.element(depth + 1)?;
+                let (namespace, local_name) = name(&e, decoder);
+                let mut properties = attributes(&e, decoder, pid_map);
                 properties.insert("#namespace".into(), namespace.into());
                 properties.insert("#text".into(), "".to_string().into());
                 children.push((local_name, properties));
@@ -63,14 +162,16 @@ where
             // Characters between start and end tags.
             Event::Text(e) => {
                 // Remove non-significant whitespace.
-                let bytes = &e.unescaped().unwrap();
-                unsafe {
-                    let string = std::str::from_utf8_unchecked(bytes).to_string();
-                    if !string.trim().is_empty() {
-                        // Only copy non whitespace text so that the document is formatted pretty.
-                        // We don't really handle mixed content at this point.
-                        text = ImmutableString::from(string);
-                    }
+                budget.text(&e)?;
+                // Decoded before unescaping, as the escaped characters are always UTF-8.
+                let escaped = decoder.decode(&e);
+                let escaped = BytesText::from_escaped(escaped.as_bytes());
+                let unescaped = escaped.unescaped()?;
+                let string = String::from_utf8_lossy(&unescaped);
+                if !string.trim().is_empty() {
+                    // Only copy non whitespace text so that the document is formatted pretty.
+                    // We don't really handle mixed content at this point.
+                    text = ImmutableString::from(pid_map.rewrite_reference(&string));
                 }
             }
             // End of file has been reached, this should only occur in the `to_map()` function.
@@ -82,7 +183,7 @@ where
         buffer.clear();
     }
     // Group children by name into vectors.
-    let (namespace, local_name) = name(&e);
+    let (namespace, local_name) = name(&e, decoder);
     let children: CustomMap = {
         let init: HashMap<ImmutableString, Array> = HashMap::new();
         children
@@ -102,23 +203,35 @@ where
     Ok((local_name, properties))
 }
 
-fn map<B>(mut reader: Reader<B>) -> Result<CustomMap, quick_xml::Error>
+fn map<B>(
+    mut reader: Reader<B>,
+    limits: XmlLimits,
+    decoder: &mut Decoder,
+    pid_map: &PidMap,
+) -> Result<CustomMap, XmlError>
 where
     B: BufRead,
 {
+    let mut budget = Budget {
+        limits,
+        elements: 0,
+    };
     let mut buffer = Vec::new();
     loop {
         match reader.read_event(&mut buffer)? {
             // Only concerned with the root tag, return a map of it's attributes and children.
             Event::Start(e) => {
-                let (_, properties) = element(&mut reader, &e)?;
+                budget.element(1)?;
+                let (_, properties) = element(&mut reader, &e, &mut budget, decoder, pid_map, 1)?;
                 return Ok(properties);
             }
+            // The charset used should the content not be valid UTF-8.
+            Event::Decl(e) => decoder.declare(&e),
             // End of file has been reached.
             Event::Eof => {
-                return Err(quick_xml::Error::UnexpectedEof(
-                    "Unexpected end of file.".to_string(),
-                ))
+                return Err(
+                    quick_xml::Error::UnexpectedEof("Unexpected end of file.".to_string()).into(),
+                )
             }
             // We ignore Comments, CData, XML Declaration, Processing Instructions, and DocType elements, etc.
             _ => (),
@@ -128,12 +241,21 @@ where
     }
 }
 
-pub fn parse(datastream: &DatastreamVersion) -> Option<Result<CustomMap, quick_xml::Error>> {
+// Text and attribute values which reference objects are rewritten by the given
+// PID map, like the PIDs of the objects themselves.
+pub fn parse(
+    datastream: &DatastreamVersion,
+    limits: XmlLimits,
+    pid_map: &PidMap,
+) -> Option<Result<CustomMap, XmlError>> {
     let valid_mime_types = vec!["application/rdf+xml", "application/xml", "text/xml"];
     if valid_mime_types.contains(&datastream.mime_type.as_str()) {
         let file = File::open(&datastream.path()).unwrap();
         let reader = Reader::from_reader(BufReader::new(&file));
-        Some(map(reader))
+        let mut decoder = Decoder::default();
+        let result = map(reader, limits, &mut decoder, pid_map);
+        decoder.record(datastream);
+        Some(result)
     } else {
         None
     }
@@ -185,11 +307,98 @@ mod tests {
             ImmutableString::from("#text") => Dynamic::from("")
         });
         let reader = Reader::from_str(&content);
-        let result = map(reader);
+        let result = map(
+            reader,
+            XmlLimits::default(),
+            &mut Decoder::default(),
+            &PidMap::default(),
+        );
         assert!(result.is_ok());
         valid_map_equals_expected(&result.unwrap(), &expected);
     }
 
+    #[test]
+    fn limits() {
+        let limits = XmlLimits {
+            max_depth: 3,
+            max_elements: 5,
+            max_text: 4,
+        };
+        let error = |content: &str| {
+            map(
+                Reader::from_str(content),
+                limits,
+                &mut Decoder::default(),
+                &PidMap::default(),
+            )
+            .err()
+            .map(|error| error.to_string())
+        };
+        assert_eq!(error("<a><b><c>text</c></b></a>"), None);
+        assert_eq!(
+            error("<a><b><c><d/></c></b></a>"),
+            Some("Exceeded the maximum depth of 3, see --xml-max-depth".to_string())
+        );
+        assert_eq!(
+            error("<a><b/><b/><b/><b/><b/></a>"),
+            Some(
+                "Exceeded the maximum number of elements of 5, see --xml-max-elements".to_string()
+            )
+        );
+        assert_eq!(
+            error("<a>texts</a>"),
+            Some("Exceeded the maximum text size (bytes) of 4, see --xml-max-text".to_string())
+        );
+    }
+
+    #[test]
+    fn latin1_content() {
+        let content = b"<?xml version=\"1.0\" encoding=\"ISO-8859-1\"?><a b=\"\xe9\">caf\xe9 &amp; cr\xe8me</a>";
+        let mut decoder = Decoder::default();
+        let result = map(
+            Reader::from_reader(&content[..]),
+            XmlLimits::default(),
+            &mut decoder,
+            &PidMap::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            result.get("@b").unwrap().clone().try_cast::<String>(),
+            Some("é".to_string())
+        );
+        assert_eq!(
+            result
+                .get("#text")
+                .unwrap()
+                .clone()
+                .try_cast::<ImmutableString>(),
+            Some(ImmutableString::from("café & crème"))
+        );
+    }
+
+    #[test]
+    fn pid_map_content() {
+        let content = r#"<a b="info:fedora/archden:1"><c>archden:2</c><c>archden: a history</c></a>"#;
+        let pid_map = PidMap::new(vec!["archden=denver".parse().unwrap()]);
+        let result = map(
+            Reader::from_str(content),
+            XmlLimits::default(),
+            &mut Decoder::default(),
+            &pid_map,
+        )
+        .unwrap();
+        assert_eq!(
+            result.get("@b").unwrap().clone().try_cast::<String>(),
+            Some("info:fedora/denver:1".to_string())
+        );
+        let text = result
+            .find(vec!["#text".into(), "c".into()])
+            .into_iter()
+            .map(|text| text.cast::<ImmutableString>().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(text, vec!["denver:2", "archden: a history"]);
+    }
+
     fn valid_map_equals_expected(result: &CustomMap, expected: &CustomMap) {
         // Check keys match.
         let result_keys = {
@@ -1,3 +1,4 @@
+use crate::mime_types::normalize_mime_type;
 use std::collections::HashMap;
 
 // Map specific fedora users to Drupal users for the migration.
@@ -237,6 +238,7 @@ lazy_static! {
         m.insert("audio/aac", "aac");
         m.insert("audio/aiff", "aiff");
         m.insert("audio/basic", "snd");
+        m.insert("audio/flac", "flac");
         m.insert("audio/it", "it");
         m.insert("audio/make", "funk");
         m.insert("audio/mid", "rmi");
@@ -412,9 +414,12 @@ lazy_static! {
     };
 }
 
+// The mime type is normalized first, so that the file name is the same whether
+// or not the caller has already done so.
 pub fn version_file_name(pid: &str, version: &str, label: &str, mime_type: &str) -> String {
+    let (mime_type, _) = normalize_mime_type(mime_type);
     let extension = EXTENSIONS
-        .get(&mime_type)
+        .get(mime_type.as_str())
         .unwrap_or_else(|| panic!("No extension known for mime type: {}", &mime_type));
     let is_filename = EXTENSIONS
         .values()
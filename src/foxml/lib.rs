@@ -3,15 +3,18 @@
 extern crate lazy_static;
 
 pub mod extensions;
+pub mod mime_types;
+pub mod pids;
+mod stream;
+mod write;
 
-use chrono::{DateTime, FixedOffset};
-use core::panic;
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
 use serde::Deserialize;
 use std::cmp::Ordering;
 use std::hash::{Hash, Hasher};
 use std::path::Path;
 use std::str::FromStr;
-use strum_macros::{EnumDiscriminants, EnumString};
+use strum_macros::{AsRefStr, EnumDiscriminants, EnumString};
 
 #[derive(Debug, EnumDiscriminants)]
 pub enum FoxmlError {
@@ -19,6 +22,14 @@ pub enum FoxmlError {
     IOError(std::io::Error),                                          // Could not read file.
     QuickXMLError(quick_xml::Error),                                  // Wrap QuickXML error.
     Utf8Error(std::str::Utf8Error), // Could not decode byte string into utf8.
+    EmptyFile,                      // File is empty or only whitespace.
+    NotXml(String), // File is not XML e.g. an HTML error page, describes what was found.
+    MissingProperty(String), // A required object property is missing.
+    InvalidProperty(String, String), // An object property could not be parsed, gives the name & value.
+    MissingElement(String),          // A required element is missing.
+    MissingAttribute(String, String), // A required attribute is missing, gives the element & name.
+    InvalidAttribute(String, String), // An attribute could not be parsed, gives the name & value.
+    UnretainedContent(String), // Inline XML that was not retained so can not be serialized, gives the datastream.
 }
 
 impl From<serde_path_to_error::Error<quick_xml::DeError>> for FoxmlError {
@@ -52,10 +63,76 @@ impl std::fmt::Display for FoxmlError {
             FoxmlError::IOError(err) => err.fmt(f),
             FoxmlError::QuickXMLError(err) => err.fmt(f),
             FoxmlError::Utf8Error(err) => err.fmt(f),
+            FoxmlError::EmptyFile => write!(f, "File is empty"),
+            FoxmlError::NotXml(found) => write!(f, "File is not XML, found {}", found),
+            FoxmlError::MissingProperty(name) => {
+                write!(f, "Failed to find required property: {}", name)
+            }
+            FoxmlError::InvalidProperty(name, value) => {
+                write!(f, "Failed to parse property {}: {}", name, value)
+            }
+            FoxmlError::MissingElement(name) => {
+                write!(f, "Failed to find required element: {}", name)
+            }
+            FoxmlError::MissingAttribute(element, name) => {
+                write!(
+                    f,
+                    "Failed to find required attribute {} of {}",
+                    name, element
+                )
+            }
+            FoxmlError::InvalidAttribute(name, value) => {
+                write!(f, "Failed to parse attribute {}: {}", name, value)
+            }
+            FoxmlError::UnretainedContent(datastream) => {
+                write!(
+                    f,
+                    "Failed to serialize the inline XML of {}, only the audit trail is retained",
+                    datastream
+                )
+            }
         }
     }
 }
 
+impl FoxmlError {
+    // Distinguishes corrupt files from those that do not match the schema.
+    pub fn category(&self) -> &'static str {
+        match self {
+            FoxmlError::EmptyFile => "empty",
+            FoxmlError::NotXml(_) => "not_xml",
+            FoxmlError::IOError(_) | FoxmlError::Utf8Error(_) => "unreadable",
+            FoxmlError::DeserializeError(_)
+            | FoxmlError::QuickXMLError(_)
+            | FoxmlError::MissingProperty(_)
+            | FoxmlError::InvalidProperty(_, _)
+            | FoxmlError::MissingElement(_)
+            | FoxmlError::MissingAttribute(_, _)
+            | FoxmlError::InvalidAttribute(_, _) => "invalid_foxml",
+            FoxmlError::UnretainedContent(_) => "unserializable",
+        }
+    }
+}
+
+// Checks the size and leading bytes of the file, as zero-byte files and HTML
+// error pages occasionally end up in the objectStore.
+fn sniff(content: &[u8]) -> Result<(), FoxmlError> {
+    let content = content.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(content);
+    let start = match content.iter().position(|b| !b.is_ascii_whitespace()) {
+        Some(start) => start,
+        None => return Err(FoxmlError::EmptyFile),
+    };
+    let head = String::from_utf8_lossy(&content[start..content.len().min(start + 32)]);
+    let lowercase = head.to_lowercase();
+    if lowercase.starts_with("<!doctype html") || lowercase.starts_with("<html") {
+        Err(FoxmlError::NotXml("an HTML page".to_string()))
+    } else if !head.starts_with('<') {
+        Err(FoxmlError::NotXml(format!("{:?}", head)))
+    } else {
+        Ok(())
+    }
+}
+
 // The object state can be Active (A), Inactive (I), or Deleted (D)
 #[derive(Debug, Deserialize, PartialEq, EnumString)]
 pub enum FoxmlObjectState {
@@ -64,9 +141,11 @@ pub enum FoxmlObjectState {
     Deleted,
 }
 
-// The object state can be Active (A), Inactive (I), or Deleted (D)
-#[derive(Debug, Deserialize, PartialEq, EnumString)]
+// The object state can be Active (A), Inactive (I), or Deleted (D),
+// and is optional in the schema, in which case Fedora treats it as active.
+#[derive(AsRefStr, Debug, Default, Deserialize, PartialEq, EnumString)]
 pub enum FoxmlDatastreamState {
+    #[default]
     A,
     I,
     D,
@@ -74,7 +153,7 @@ pub enum FoxmlDatastreamState {
 
 // Indicates the kind of datastream, either Externally Referenced Content (E),
 // Redirected Content (R), Managed Content (M) or Inline XML (X)
-#[derive(Debug, Deserialize, PartialEq, EnumString)]
+#[derive(AsRefStr, Debug, Deserialize, PartialEq, EnumString)]
 pub enum FoxmlControlGroup {
     E,
     R,
@@ -82,6 +161,19 @@ pub enum FoxmlControlGroup {
     X,
 }
 
+// Which versions of each datastream are migrated, sites that only want the
+// current content in Drupal can leave the history of their datastreams behind.
+#[derive(Clone, Copy, Debug, EnumString, PartialEq)]
+#[strum(serialize_all = "kebab-case")]
+pub enum VersionPolicy {
+    All,
+    LatestOnly,
+}
+
+fn default_versionable() -> bool {
+    true
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct FoxmlProperty {
     #[serde(rename = "NAME")]
@@ -94,44 +186,61 @@ pub struct FoxmlProperty {
 pub struct FoxmlObjectProperties {
     #[serde(rename = "property")]
     pub properties: Vec<FoxmlProperty>,
+    // Properties outside of the Fedora namespace, only present in FOXML 1.0.
+    #[serde(rename = "extproperty", default)]
+    pub ext_properties: Vec<FoxmlProperty>,
 }
 
 impl FoxmlObjectProperties {
-    fn property(&self, name: &str) -> String {
-        match self.properties.iter().find(|x| x.name == name) {
-            Some(property) => property.value.clone(),
-            // All public functions refer to required properties in the spec so
-            // panicking at runtime is acceptable. As we do not expect to have
-            // to deal with invalid FOXML.
-            None => panic!("Failed to find required property: {}", name),
-        }
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.properties
+            .iter()
+            .find(|x| x.name == name)
+            .map(|property| property.value.as_str())
+    }
+
+    pub fn ext_property(&self, name: &str) -> Option<&str> {
+        self.ext_properties
+            .iter()
+            .find(|x| x.name == name)
+            .map(|property| property.value.as_str())
     }
 
-    fn date_property(&self, name: &str) -> DateTime<FixedOffset> {
-        let date = self.property(&name);
-        // It should be acceptable to panic here as we do not expect the FOXML to
-        // be invalid.
-        DateTime::parse_from_rfc3339(&date).expect("Failed to parse date property of FOXML file.")
+    fn required(&self, name: &str) -> Result<&str, FoxmlError> {
+        self.get(name)
+            .ok_or_else(|| FoxmlError::MissingProperty(name.to_string()))
     }
 
-    pub fn state(&self) -> FoxmlObjectState {
-        let state = self.property("info:fedora/fedora-system:def/model#state");
-        FoxmlObjectState::from_str(&state).unwrap()
+    fn date_property(&self, name: &str) -> Result<DateTime<FixedOffset>, FoxmlError> {
+        let date = self.required(name)?;
+        DateTime::parse_from_rfc3339(date)
+            .map_err(|_| FoxmlError::InvalidProperty(name.to_string(), date.to_string()))
     }
 
-    pub fn label(&self) -> String {
-        self.property("info:fedora/fedora-system:def/model#label")
+    // Required properties in the spec, but malformed objects are found in the
+    // wild so they are reported per object rather than panicking.
+    pub fn state(&self) -> Result<FoxmlObjectState, FoxmlError> {
+        let name = "info:fedora/fedora-system:def/model#state";
+        let state = self.required(name)?;
+        FoxmlObjectState::from_str(state)
+            .map_err(|_| FoxmlError::InvalidProperty(name.to_string(), state.to_string()))
     }
 
-    pub fn owner_id(&self) -> String {
-        self.property("info:fedora/fedora-system:def/model#ownerId")
+    pub fn label(&self) -> Result<String, FoxmlError> {
+        self.required("info:fedora/fedora-system:def/model#label")
+            .map(str::to_string)
     }
 
-    pub fn created_date(&self) -> DateTime<FixedOffset> {
+    pub fn owner_id(&self) -> Result<String, FoxmlError> {
+        self.required("info:fedora/fedora-system:def/model#ownerId")
+            .map(str::to_string)
+    }
+
+    pub fn created_date(&self) -> Result<DateTime<FixedOffset>, FoxmlError> {
         self.date_property("info:fedora/fedora-system:def/model#createdDate")
     }
 
-    pub fn modified_date(&self) -> DateTime<FixedOffset> {
+    pub fn modified_date(&self) -> Result<DateTime<FixedOffset>, FoxmlError> {
         self.date_property("info:fedora/fedora-system:def/view#lastModifiedDate")
     }
 }
@@ -152,6 +261,42 @@ pub struct FoxmlDatastreamContentDigest {
     pub digest: String,
 }
 
+// @see https://wiki.lyrasis.org/display/FEDORA35/Audit+Trail
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct FoxmlAuditProcess {
+    #[serde(rename = "type")]
+    pub r#type: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct FoxmlAuditRecord {
+    #[serde(rename = "ID")]
+    pub id: String,
+    pub process: FoxmlAuditProcess,
+    pub action: String,
+    // Empty when the object rather than a datastream was modified.
+    #[serde(rename = "componentID", default)]
+    pub component_id: String,
+    pub responsibility: String,
+    pub date: DateTime<FixedOffset>,
+    #[serde(default)]
+    pub justification: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct FoxmlAuditTrail {
+    #[serde(rename = "record", default)]
+    pub records: Vec<FoxmlAuditRecord>,
+}
+
+// Inline XML is left unparsed, bar the audit trail which Fedora stores in the
+// AUDIT datastream.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+pub struct FoxmlXmlContent {
+    #[serde(rename = "auditTrail")]
+    pub audit_trail: Option<FoxmlAuditTrail>,
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
 pub enum FoxmlDatastreamContent {
     #[serde(rename = "foxml:contentLocation")]
@@ -159,14 +304,18 @@ pub enum FoxmlDatastreamContent {
     #[serde(rename = "foxml:contentDigest")]
     ContentDigest(FoxmlDatastreamContentDigest),
     #[serde(rename = "foxml:xmlContent")]
-    XmlContent,
+    XmlContent(FoxmlXmlContent),
+    // Managed datastreams are embedded as base64 in exports made in the
+    // "archive" context.
+    #[serde(rename = "foxml:binaryContent")]
+    BinaryContent(String),
 }
 
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct FoxmlDatastreamVersion {
     #[serde(rename = "ID")]
     pub id: String,
-    #[serde(rename = "LABEL")]
+    #[serde(rename = "LABEL", default)]
     pub label: String,
     #[serde(rename = "CREATED")]
     pub created: DateTime<FixedOffset>,
@@ -176,36 +325,165 @@ pub struct FoxmlDatastreamVersion {
     pub size: Option<i64>,
     #[serde(rename = "FORMAT_URI")]
     pub format: Option<String>,
-    #[serde(rename = "$value")]
+    // Space separated.
+    #[serde(rename = "ALT_IDS")]
+    pub alt_ids: Option<String>,
+    #[serde(rename = "$value", default)]
     pub content: Vec<FoxmlDatastreamContent>,
 }
 
+impl FoxmlDatastreamVersion {
+    pub fn alternate_ids(&self) -> Vec<&str> {
+        self.alt_ids
+            .as_deref()
+            .map_or_else(Vec::new, |ids| ids.split_whitespace().collect())
+    }
+
+    pub fn content_location(&self) -> Option<&FoxmlDatastreamContentLocation> {
+        self.content.iter().find_map(|content| match content {
+            FoxmlDatastreamContent::ContentLocation(location) => Some(location),
+            _ => None,
+        })
+    }
+
+    pub fn content_digest(&self) -> Option<&FoxmlDatastreamContentDigest> {
+        self.content.iter().find_map(|content| match content {
+            FoxmlDatastreamContent::ContentDigest(digest) => Some(digest),
+            _ => None,
+        })
+    }
+
+    pub fn xml_content(&self) -> Option<&FoxmlXmlContent> {
+        self.content.iter().find_map(|content| match content {
+            FoxmlDatastreamContent::XmlContent(xml) => Some(xml),
+            _ => None,
+        })
+    }
+
+    // Base64 encoded, possibly across multiple lines.
+    pub fn binary_content(&self) -> Option<&str> {
+        self.content.iter().find_map(|content| match content {
+            FoxmlDatastreamContent::BinaryContent(base64) => Some(base64.as_str()),
+            _ => None,
+        })
+    }
+}
+
 #[derive(Debug, Deserialize, PartialEq)]
 pub struct FoxmlDatastream {
     #[serde(rename = "ID")]
     pub id: String,
-    #[serde(rename = "STATE")]
+    #[serde(rename = "STATE", default)]
     pub state: FoxmlDatastreamState,
     #[serde(rename = "CONTROL_GROUP")]
     pub control_group: FoxmlControlGroup,
-    #[serde(rename = "VERSIONABLE")]
+    #[serde(rename = "VERSIONABLE", default = "default_versionable")]
     pub versionable: bool,
+    #[serde(rename = "FEDORA_URI")]
+    pub fedora_uri: Option<String>,
     #[serde(rename = "datastreamVersion")]
     pub versions: Vec<FoxmlDatastreamVersion>,
 }
 
+impl FoxmlDatastream {
+    // Fedora writes versions in the order they were created.
+    pub fn latest_version(&self) -> Option<&FoxmlDatastreamVersion> {
+        self.versions.last()
+    }
+
+    pub fn migrated_versions(&self, policy: VersionPolicy) -> &[FoxmlDatastreamVersion] {
+        match policy {
+            VersionPolicy::All => &self.versions,
+            VersionPolicy::LatestOnly => &self.versions[self.versions.len().saturating_sub(1)..],
+        }
+    }
+}
+
+// Disseminators were replaced by CMA in Fedora 3, but may still be found in
+// objects exported from earlier versions. Objects upgraded from Fedora 2 often
+// have incomplete disseminators, so every attribute is optional and they are
+// captured as is rather than failing to parse the whole object.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct FoxmlDatastreamBinding {
+    #[serde(rename = "KEY", default)]
+    pub key: String,
+    #[serde(rename = "DATASTREAM_ID", default)]
+    pub datastream_id: String,
+    #[serde(rename = "LABEL")]
+    pub label: Option<String>,
+    #[serde(rename = "ORDER")]
+    pub order: Option<String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct FoxmlServiceInputMap {
+    #[serde(rename = "datastreamBinding", default)]
+    pub bindings: Vec<FoxmlDatastreamBinding>,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct FoxmlDisseminatorVersion {
+    #[serde(rename = "ID", default)]
+    pub id: String,
+    #[serde(rename = "LABEL", default)]
+    pub label: String,
+    // Left unparsed as Fedora 2 did not always write RFC 3339 dates.
+    #[serde(rename = "CREATED")]
+    pub created: Option<String>,
+    #[serde(rename = "BMECH_CONTRACT_PID", default)]
+    pub bmech_contract_pid: String,
+    #[serde(rename = "serviceInputMap")]
+    pub service_input_map: Option<FoxmlServiceInputMap>,
+}
+
+impl FoxmlDisseminatorVersion {
+    // None if not given or not a valid date, dates without a timezone are
+    // assumed to be UTC.
+    pub fn created(&self) -> Option<DateTime<FixedOffset>> {
+        let created = self.created.as_deref()?.trim();
+        DateTime::parse_from_rfc3339(created).ok().or_else(|| {
+            ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+                .iter()
+                .find_map(|format| NaiveDateTime::parse_from_str(created, format).ok())
+                .map(|created| created.and_utc().fixed_offset())
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct FoxmlDisseminator {
+    #[serde(rename = "ID", default)]
+    pub id: String,
+    #[serde(rename = "BDEF_CONTRACT_PID", default)]
+    pub bdef_contract_pid: String,
+    #[serde(rename = "STATE", default)]
+    pub state: FoxmlDatastreamState,
+    #[serde(rename = "VERSIONABLE", default = "default_versionable")]
+    pub versionable: bool,
+    #[serde(rename = "disseminatorVersion", default)]
+    pub versions: Vec<FoxmlDisseminatorVersion>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Foxml {
     #[serde(rename = "PID", default)]
     pub pid: String,
+    // Of the FOXML schema e.g. 1.1.
+    #[serde(rename = "VERSION")]
+    pub version: Option<String>,
+    #[serde(rename = "FEDORA_URI")]
+    pub fedora_uri: Option<String>,
     #[serde(rename = "objectProperties")]
     pub properties: FoxmlObjectProperties,
-    #[serde(rename = "datastream")]
+    #[serde(rename = "datastream", default)]
     pub datastreams: Vec<FoxmlDatastream>,
+    #[serde(rename = "disseminator", default)]
+    pub disseminators: Vec<FoxmlDisseminator>,
 }
 
 impl Foxml {
     pub fn new(content: &str) -> Result<Foxml, FoxmlError> {
+        sniff(content.as_bytes())?;
         let deserializer = &mut quick_xml::de::Deserializer::from_reader(content.as_bytes());
         let result: Result<Foxml, _> = serde_path_to_error::deserialize(deserializer);
         match result {
@@ -215,8 +493,26 @@ impl Foxml {
     }
 
     pub fn from_path(path: &Path) -> Result<Foxml, FoxmlError> {
-        let content = std::fs::read_to_string(path)?;
-        Self::new(&content)
+        let content = std::fs::read(path)?;
+        // Before decoding, as binary files are unlikely to be valid utf8.
+        sniff(&content)?;
+        Self::new(std::str::from_utf8(&content)?)
+    }
+
+    pub fn datastream(&self, id: &str) -> Option<&FoxmlDatastream> {
+        self.datastreams
+            .iter()
+            .find(|datastream| datastream.id == id)
+    }
+
+    // Records of the latest version of the AUDIT datastream, in the order they
+    // were made.
+    pub fn audit_trail(&self) -> &[FoxmlAuditRecord] {
+        self.datastream("AUDIT")
+            .and_then(FoxmlDatastream::latest_version)
+            .and_then(FoxmlDatastreamVersion::xml_content)
+            .and_then(|xml| xml.audit_trail.as_ref())
+            .map_or(&[], |audit_trail| audit_trail.records.as_slice())
     }
 }
 
@@ -278,8 +574,22 @@ mod tests {
         path.push("invalid.foxml.xml");
         let result = Foxml::from_path(path.as_path());
         assert!(result.is_err());
+        // The fixture is plain text so it is caught before deserializing.
         let err: FoxmlErrorDiscriminants = result.unwrap_err().into();
-        assert_eq!(err, FoxmlErrorDiscriminants::DeserializeError);
+        assert_eq!(err, FoxmlErrorDiscriminants::NotXml);
+    }
+
+    #[test]
+    fn corrupt_content() {
+        let category = |content: &str| Foxml::new(content).unwrap_err().category();
+        assert_eq!(category(""), "empty");
+        assert_eq!(category(" \n"), "empty");
+        assert_eq!(
+            category("<!DOCTYPE html><html><body>502 Bad Gateway"),
+            "not_xml"
+        );
+        assert_eq!(category("Internal Server Error"), "not_xml");
+        assert_eq!(category("<foxml:digitalObject>"), "invalid_foxml");
     }
 
     #[test]
@@ -288,5 +598,118 @@ mod tests {
         path.push("valid.foxml.xml");
         let result = Foxml::from_path(path.as_path());
         assert!(result.is_ok());
+        let foxml = result.unwrap();
+        assert_eq!(foxml.version.as_deref(), Some("1.1"));
+        let audit_trail = foxml.audit_trail();
+        assert_eq!(audit_trail.len(), 24);
+        assert_eq!(audit_trail[6].component_id, "");
+        assert_eq!(audit_trail[7].responsibility, "archivist1");
+        let obj = foxml.datastream("OBJ").unwrap().latest_version().unwrap();
+        assert_eq!(obj.content_digest().unwrap().r#type, "MD5");
+        assert_eq!(
+            obj.content_location().unwrap().r#ref,
+            "archden:463+OBJ+OBJ.0"
+        );
+        assert_eq!(obj.binary_content(), None);
+        let mods = foxml.datastream("MODS").unwrap();
+        assert_eq!(mods.migrated_versions(VersionPolicy::All).len(), 4);
+        let latest = mods.migrated_versions(VersionPolicy::LatestOnly);
+        assert_eq!(latest, std::slice::from_ref(mods.latest_version().unwrap()));
+    }
+
+    #[test]
+    fn archive_content() {
+        let mut path = fixtures_directory();
+        path.push("archive.foxml.xml");
+        let foxml = Foxml::from_path(path.as_path()).unwrap();
+        assert_eq!(
+            foxml.properties.state().unwrap(),
+            FoxmlObjectState::Inactive
+        );
+        assert_eq!(
+            foxml
+                .properties
+                .ext_property("http://islandora.ca/ontology/example#batch"),
+            Some("7")
+        );
+        assert!(foxml.properties.get("missing").is_none());
+        let datastream = foxml.datastream("OBJ").unwrap();
+        // Defaults for the optional attributes.
+        assert_eq!(datastream.state, FoxmlDatastreamState::A);
+        assert!(datastream.versionable);
+        assert_eq!(
+            datastream.fedora_uri.as_deref(),
+            Some("info:fedora/archden:464/OBJ")
+        );
+        let version = datastream.latest_version().unwrap();
+        assert_eq!(
+            version.alternate_ids(),
+            vec!["urn:example:1", "urn:example:2"]
+        );
+        assert_eq!(version.content_digest().unwrap().r#type, "SHA-1");
+        assert_eq!(
+            version
+                .binary_content()
+                .unwrap()
+                .split_whitespace()
+                .collect::<String>(),
+            "aGVsbG8gd29ybGQK"
+        );
+        assert_eq!(foxml.audit_trail()[0].action, "addDatastream");
+        let disseminator = &foxml.disseminators[0];
+        assert_eq!(disseminator.bdef_contract_pid, "demo:1");
+        let bindings = &disseminator.versions[0]
+            .service_input_map
+            .as_ref()
+            .unwrap()
+            .bindings;
+        assert_eq!(bindings[0].datastream_id, "OBJ");
+        assert_eq!(
+            disseminator.versions[0].created().unwrap().to_rfc3339(),
+            "2004-12-07T16:12:56.490+00:00"
+        );
+    }
+
+    #[test]
+    fn legacy_disseminators() {
+        // As found in objects upgraded from Fedora 2.
+        let foxml = Foxml::new(
+            r#"<foxml:digitalObject PID="a:1"><foxml:objectProperties>
+            <foxml:property NAME="info:fedora/fedora-system:def/model#label" VALUE="Legacy"/>
+            </foxml:objectProperties>
+            <foxml:disseminator ID="DISS1" BDEF_CONTRACT_PID="demo:1">
+              <foxml:disseminatorVersion ID="DISS1.0" CREATED="2004-12-07 16:12:56">
+                <foxml:serviceInputMap><foxml:datastreamBinding DATASTREAM_ID="OBJ"/></foxml:serviceInputMap>
+              </foxml:disseminatorVersion>
+              <foxml:disseminatorVersion ID="DISS1.1" CREATED="yesterday"/>
+            </foxml:disseminator>
+            <foxml:disseminator ID="DISS2"/>
+            </foxml:digitalObject>"#,
+        )
+        .unwrap();
+        assert_eq!(foxml.disseminators.len(), 2);
+        let versions = &foxml.disseminators[0].versions;
+        assert_eq!(versions[0].bmech_contract_pid, "");
+        assert_eq!(
+            versions[0].created().unwrap().to_rfc3339(),
+            "2004-12-07T16:12:56+00:00"
+        );
+        assert_eq!(versions[1].created(), None);
+        assert!(foxml.disseminators[1].versions.is_empty());
+    }
+
+    #[test]
+    fn missing_properties() {
+        let foxml = Foxml::new(
+            r#"<foxml:digitalObject PID="a:1"><foxml:objectProperties>
+            <foxml:property NAME="info:fedora/fedora-system:def/model#createdDate" VALUE="yesterday"/>
+            </foxml:objectProperties></foxml:digitalObject>"#,
+        )
+        .unwrap();
+        assert!(foxml.datastreams.is_empty());
+        let err: FoxmlErrorDiscriminants = foxml.properties.label().unwrap_err().into();
+        assert_eq!(err, FoxmlErrorDiscriminants::MissingProperty);
+        let err: FoxmlErrorDiscriminants = foxml.properties.created_date().unwrap_err().into();
+        assert_eq!(err, FoxmlErrorDiscriminants::InvalidProperty);
     }
 }
@@ -0,0 +1,190 @@
+// Fedora stores the mime type of each datastream version as it was given when
+// ingested, so typos and aliases like "image/jpg" or parameters like
+// "text/xml; charset=utf-8" are common. These are normalized so that mapping
+// to media bundles, file extensions and Drupal's mime type validation work.
+use std::collections::{HashMap, HashSet};
+
+lazy_static! {
+    // Aliases of registered types, each must map to the same file extension
+    // as its alias (see `extensions`) so that file names do not change.
+    #[rustfmt::skip]
+    static ref ALIASES: HashMap<&'static str, &'static str> = {
+        let mut m = HashMap::new();
+        m.insert("application/acrobat", "application/pdf");
+        m.insert("application/csv", "text/csv");
+        m.insert("application/vnd.ms-word", "application/msword");
+        m.insert("application/x-flac", "audio/flac");
+        m.insert("application/x-pdf", "application/pdf");
+        m.insert("application/x-zip", "application/zip");
+        m.insert("application/x-zip-compressed", "application/zip");
+        m.insert("audio/mp3", "audio/mpeg");
+        m.insert("audio/mpeg3", "audio/mpeg");
+        m.insert("audio/vnd.wave", "audio/wav");
+        m.insert("audio/wave", "audio/wav");
+        m.insert("audio/x-flac", "audio/flac");
+        m.insert("audio/x-mp3", "audio/mpeg");
+        m.insert("audio/x-mpeg-3", "audio/mpeg");
+        m.insert("audio/x-wav", "audio/wav");
+        m.insert("audio/x-wave", "audio/wav");
+        m.insert("image/jpg", "image/jpeg");
+        m.insert("image/pjpeg", "image/jpeg");
+        m.insert("image/tif", "image/tiff");
+        m.insert("image/x-png", "image/png");
+        m.insert("image/x-tiff", "image/tiff");
+        m.insert("image/x-windows-bmp", "image/bmp");
+        m.insert("text/pdf", "application/pdf");
+        m.insert("text/x-csv", "text/csv");
+        m.insert("video/mpg", "video/mpeg");
+        m
+    };
+    // Types registered with IANA that are commonly found in Islandora
+    // repositories, along with "audio/wav" which is not registered but is the
+    // de facto standard. Others are reported as unregistered but left as is.
+    // @see https://www.iana.org/assignments/media-types/media-types.xhtml
+    #[rustfmt::skip]
+    static ref REGISTERED: HashSet<&'static str> = [
+        "application/epub+zip",
+        "application/gzip",
+        "application/json",
+        "application/marc",
+        "application/mods+xml",
+        "application/msword",
+        "application/octet-stream",
+        "application/ogg",
+        "application/pdf",
+        "application/postscript",
+        "application/rdf+xml",
+        "application/rtf",
+        "application/vnd.ms-excel",
+        "application/vnd.ms-powerpoint",
+        "application/vnd.oasis.opendocument.presentation",
+        "application/vnd.oasis.opendocument.spreadsheet",
+        "application/vnd.oasis.opendocument.text",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "application/warc",
+        "application/xhtml+xml",
+        "application/xml",
+        "application/zip",
+        "audio/aac",
+        "audio/flac",
+        "audio/mp4",
+        "audio/mpeg",
+        "audio/ogg",
+        "audio/wav",
+        "image/bmp",
+        "image/gif",
+        "image/jp2",
+        "image/jpeg",
+        "image/png",
+        "image/svg+xml",
+        "image/tiff",
+        "image/webp",
+        "text/css",
+        "text/csv",
+        "text/html",
+        "text/plain",
+        "text/rtf",
+        "text/tab-separated-values",
+        "text/xml",
+        "video/mp4",
+        "video/mpeg",
+        "video/ogg",
+        "video/quicktime",
+        "video/webm",
+    ]
+    .iter()
+    .cloned()
+    .collect();
+}
+
+// Given to mime types that are not even of the form type/subtype.
+pub static DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MimeTypeStatus {
+    Valid,
+    // Parameters or case were removed, or an alias replaced.
+    Normalized,
+    // Well formed but not known to be registered, left as is.
+    Unregistered,
+    // Malformed, replaced by `DEFAULT_MIME_TYPE`.
+    Invalid,
+}
+
+impl MimeTypeStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MimeTypeStatus::Valid => "valid",
+            MimeTypeStatus::Normalized => "normalized",
+            MimeTypeStatus::Unregistered => "unregistered",
+            MimeTypeStatus::Invalid => "invalid",
+        }
+    }
+}
+
+fn is_token(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+}
+
+pub fn normalize_mime_type(mime_type: &str) -> (String, MimeTypeStatus) {
+    let essence = mime_type
+        .split(';')
+        .next()
+        .unwrap()
+        .trim()
+        .to_ascii_lowercase();
+    let well_formed = match essence.split_once('/') {
+        Some((r#type, subtype)) => is_token(r#type) && is_token(subtype),
+        None => false,
+    };
+    if !well_formed {
+        return (DEFAULT_MIME_TYPE.to_string(), MimeTypeStatus::Invalid);
+    }
+    let normalized = ALIASES
+        .get(essence.as_str())
+        .map_or(essence, |registered| registered.to_string());
+    let status = if normalized != mime_type {
+        MimeTypeStatus::Normalized
+    } else if REGISTERED.contains(normalized.as_str()) {
+        MimeTypeStatus::Valid
+    } else {
+        MimeTypeStatus::Unregistered
+    };
+    (normalized, status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize() {
+        let normalize = |mime_type| {
+            let (normalized, status) = normalize_mime_type(mime_type);
+            (normalized, status.as_str())
+        };
+        assert_eq!(normalize("image/jpeg"), ("image/jpeg".to_string(), "valid"));
+        assert_eq!(
+            normalize("image/jpg"),
+            ("image/jpeg".to_string(), "normalized")
+        );
+        assert_eq!(
+            normalize("text/xml; charset=utf-8"),
+            ("text/xml".to_string(), "normalized")
+        );
+        assert_eq!(
+            normalize(" Image/TIFF "),
+            ("image/tiff".to_string(), "normalized")
+        );
+        assert_eq!(
+            normalize("application/x-foo"),
+            ("application/x-foo".to_string(), "unregistered")
+        );
+        assert_eq!(normalize("jpg"), (DEFAULT_MIME_TYPE.to_string(), "invalid"));
+        assert_eq!(normalize(""), (DEFAULT_MIME_TYPE.to_string(), "invalid"));
+    }
+}
@@ -0,0 +1,206 @@
+// Rewrites PIDs, for institutions consolidating namespaces during the
+// migration. The same map must be given to every command so that object files,
+// datastream paths and the generated CSV files agree. Objects are parsed as is,
+// the map is applied by the commands as they identify objects.
+use regex::Regex;
+use std::str::FromStr;
+use std::sync::RwLock;
+
+lazy_static! {
+    static ref SHARD: RwLock<Option<Shard>> = RwLock::new(None);
+    // Namespaces are restricted to these characters by Fedora.
+    static ref NAMESPACE_REGEX: Regex = Regex::new(r"^[A-Za-z0-9.\-]+$").unwrap();
+    // As allowed by Fedora, the local id may be percent encoded.
+    static ref PID_REGEX: Regex =
+        Regex::new(r"^[A-Za-z0-9.\-]+:(?:[A-Za-z0-9.\-~_]|%[0-9A-Fa-f]{2})+$").unwrap();
+}
+
+// A single rewrite rule, either a namespace `old=new` or a regular expression
+// `/pattern/replacement/`.
+#[derive(Clone, Debug)]
+pub struct PidRule(Regex, String);
+
+impl FromStr for PidRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() > 2 && s.starts_with('/') && s.ends_with('/') {
+            let mut parts = s[1..s.len() - 1].splitn(2, '/');
+            let pattern = parts.next().unwrap();
+            let replacement = parts
+                .next()
+                .ok_or_else(|| format!("The PID map '{}' is missing a replacement", s))?;
+            let regex = Regex::new(pattern)
+                .map_err(|error| format!("The PID map '{}' is invalid: {}", s, error))?;
+            return Ok(PidRule(regex, replacement.to_string()));
+        }
+        let mut parts = s.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(old), Some(new))
+                if NAMESPACE_REGEX.is_match(old) && NAMESPACE_REGEX.is_match(new) =>
+            {
+                let regex = Regex::new(&format!("^{}:", regex::escape(old))).unwrap();
+                Ok(PidRule(regex, format!("{}:", new)))
+            }
+            _ => Err(format!(
+                "The PID map '{}' is not of the form old-namespace=new-namespace or /pattern/replacement/",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PidMap(Vec<PidRule>);
+
+impl PidMap {
+    pub fn new(rules: Vec<PidRule>) -> Self {
+        PidMap(rules)
+    }
+
+    // Only the first matching rule is applied.
+    pub fn rewrite(&self, pid: &str) -> String {
+        self.0
+            .iter()
+            .find(|PidRule(regex, _)| regex.is_match(pid))
+            .map_or_else(
+                || pid.to_string(),
+                |PidRule(regex, replacement)| regex.replace(pid, replacement.as_str()).to_string(),
+            )
+    }
+
+    // Rewrites values found in DC, MODS, RELS-EXT, etc. which are a PID or an
+    // `info:fedora/` URI of an object or datastream, other text is left as is.
+    // Surrounding whitespace is ignored.
+    pub fn rewrite_reference(&self, value: &str) -> String {
+        let trimmed = value.trim();
+        if self.0.is_empty() || trimmed.is_empty() {
+            return value.to_string();
+        }
+        let (prefix, reference) = match trimmed.strip_prefix("info:fedora/") {
+            Some(reference) => ("info:fedora/", reference),
+            None => ("", trimmed),
+        };
+        let (pid, rest) = match reference.find('/') {
+            Some(index) if !prefix.is_empty() => reference.split_at(index),
+            _ => (reference, ""),
+        };
+        if PID_REGEX.is_match(pid) {
+            let rewritten = format!("{}{}{}", prefix, self.rewrite(pid), rest);
+            value.replacen(trimmed, &rewritten, 1)
+        } else {
+            value.to_string()
+        }
+    }
+}
+
+// One of `count` disjoint partitions of the objects, so that commands can be
+// run concurrently on multiple machines over a shared filesystem. Given as
+// `index/count` where the index starts at 1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Shard {
+    pub index: u64,
+    pub count: u64,
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '/');
+        match (
+            parts.next().and_then(|index| index.parse::<u64>().ok()),
+            parts.next().and_then(|count| count.parse::<u64>().ok()),
+        ) {
+            (Some(index), Some(count)) if index >= 1 && index <= count => {
+                Ok(Shard { index, count })
+            }
+            _ => Err(format!(
+                "The shard '{}' is not of the form i/n where 1 <= i <= n",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Shard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "shard-{}-of-{}", self.index, self.count)
+    }
+}
+
+impl Shard {
+    // FNV-1a, as unlike the standard library's hasher it is guaranteed to be
+    // stable across platforms and releases.
+    fn hash(pid: &str) -> u64 {
+        pid.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+
+    pub fn contains(&self, pid: &str) -> bool {
+        Self::hash(pid) % self.count == self.index - 1
+    }
+}
+
+// Sets the shard used by `in_shard()` for the remainder of the program.
+pub fn set_shard(shard: Option<Shard>) {
+    *SHARD.write().unwrap() = shard;
+}
+
+pub fn shard() -> Option<Shard> {
+    *SHARD.read().unwrap()
+}
+
+// True if no shard was given, expects the PID to have been mapped.
+pub fn in_shard(pid: &str) -> bool {
+    shard().is_none_or(|shard| shard.contains(pid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrite() {
+        let map = PidMap::new(vec![
+            "old=new".parse().unwrap(),
+            "/^archive:(\\d+)$/archden:a$1/".parse().unwrap(),
+        ]);
+        assert_eq!(map.rewrite("old:123"), "new:123");
+        assert_eq!(map.rewrite("older:123"), "older:123");
+        assert_eq!(map.rewrite("archive:7"), "archden:a7");
+        assert!("old:=new".parse::<PidRule>().is_err());
+        // Only whole values are references.
+        assert_eq!(map.rewrite_reference("old:123"), "new:123");
+        assert_eq!(
+            map.rewrite_reference("info:fedora/old:123/OBJ"),
+            "info:fedora/new:123/OBJ"
+        );
+        assert_eq!(map.rewrite_reference("old: a history"), "old: a history");
+        assert_eq!(map.rewrite_reference("see old:123"), "see old:123");
+        assert_eq!(map.rewrite_reference(" old:123\n"), " new:123\n");
+    }
+
+    #[test]
+    fn shards() {
+        assert_eq!("2/4".parse(), Ok(Shard { index: 2, count: 4 }));
+        assert!("0/4".parse::<Shard>().is_err());
+        assert!("5/4".parse::<Shard>().is_err());
+        assert_eq!(Shard { index: 2, count: 4 }.to_string(), "shard-2-of-4");
+        // Every PID belongs to exactly one shard, and always the same one.
+        assert_eq!(Shard::hash("archden:1"), 0x0f32_2b1e_6e72_ef25);
+        for pid in &["archden:1", "archden:2", "archden:463", "islandora:root"] {
+            let shards = (1..=4)
+                .filter(|index| {
+                    Shard {
+                        index: *index,
+                        count: 4,
+                    }
+                    .contains(pid)
+                })
+                .count();
+            assert_eq!(shards, 1);
+        }
+    }
+}
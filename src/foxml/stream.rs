@@ -0,0 +1,343 @@
+// `Foxml::from_path()` reads the whole file into memory before deserializing
+// it, which for objects with very large inline or base64 encoded datastreams
+// can exhaust the memory available, especially when many objects are parsed in
+// parallel. When only the object properties and the metadata of datastreams are
+// needed the file can instead be read as a stream of events, skipping the
+// content of `xmlContent` and `binaryContent` elements as it is read.
+use super::*;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+// The unescaped attributes of an element, removed as they are read.
+struct Attributes {
+    element: String,
+    values: HashMap<String, String>,
+}
+
+impl Attributes {
+    fn new(element: &BytesStart) -> Result<Self, FoxmlError> {
+        let mut values = HashMap::new();
+        for attribute in element.attributes() {
+            let attribute = attribute?;
+            let key = std::str::from_utf8(attribute.key)?.to_string();
+            let value = attribute.unescaped_value()?;
+            values.insert(key, std::str::from_utf8(&value)?.to_string());
+        }
+        Ok(Attributes {
+            element: String::from_utf8_lossy(element.local_name()).into_owned(),
+            values,
+        })
+    }
+
+    fn get(&mut self, name: &str) -> Option<String> {
+        self.values.remove(name)
+    }
+
+    fn required(&mut self, name: &str) -> Result<String, FoxmlError> {
+        self.get(name)
+            .ok_or_else(|| FoxmlError::MissingAttribute(self.element.clone(), name.to_string()))
+    }
+
+    fn parse<T: FromStr>(&mut self, name: &str) -> Result<Option<T>, FoxmlError> {
+        match self.get(name) {
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| FoxmlError::InvalidAttribute(name.to_string(), value)),
+            None => Ok(None),
+        }
+    }
+
+    fn parse_required<T: FromStr>(&mut self, name: &str) -> Result<T, FoxmlError> {
+        let value = self.required(name)?;
+        value
+            .parse()
+            .map_err(|_| FoxmlError::InvalidAttribute(name.to_string(), value))
+    }
+}
+
+fn property(mut attributes: Attributes) -> Result<FoxmlProperty, FoxmlError> {
+    Ok(FoxmlProperty {
+        name: attributes.required("NAME")?,
+        value: attributes.required("VALUE")?,
+    })
+}
+
+fn datastream(mut attributes: Attributes) -> Result<FoxmlDatastream, FoxmlError> {
+    Ok(FoxmlDatastream {
+        id: attributes.required("ID")?,
+        state: attributes.parse("STATE")?.unwrap_or_default(),
+        control_group: attributes.parse_required("CONTROL_GROUP")?,
+        versionable: attributes
+            .parse("VERSIONABLE")?
+            .unwrap_or_else(default_versionable),
+        fedora_uri: attributes.get("FEDORA_URI"),
+        versions: vec![],
+    })
+}
+
+fn datastream_version(mut attributes: Attributes) -> Result<FoxmlDatastreamVersion, FoxmlError> {
+    Ok(FoxmlDatastreamVersion {
+        id: attributes.required("ID")?,
+        label: attributes.get("LABEL").unwrap_or_default(),
+        created: attributes.parse_required("CREATED")?,
+        mime_type: attributes.required("MIMETYPE")?,
+        size: attributes.parse("SIZE")?,
+        format: attributes.get("FORMAT_URI"),
+        alt_ids: attributes.get("ALT_IDS"),
+        content: vec![],
+    })
+}
+
+fn disseminator(mut attributes: Attributes) -> Result<FoxmlDisseminator, FoxmlError> {
+    Ok(FoxmlDisseminator {
+        id: attributes.get("ID").unwrap_or_default(),
+        bdef_contract_pid: attributes.get("BDEF_CONTRACT_PID").unwrap_or_default(),
+        state: attributes.parse("STATE")?.unwrap_or_default(),
+        versionable: attributes
+            .parse("VERSIONABLE")?
+            .unwrap_or_else(default_versionable),
+        versions: vec![],
+    })
+}
+
+fn disseminator_version(mut attributes: Attributes) -> FoxmlDisseminatorVersion {
+    FoxmlDisseminatorVersion {
+        id: attributes.get("ID").unwrap_or_default(),
+        label: attributes.get("LABEL").unwrap_or_default(),
+        created: attributes.get("CREATED"),
+        bmech_contract_pid: attributes.get("BMECH_CONTRACT_PID").unwrap_or_default(),
+        service_input_map: None,
+    }
+}
+
+fn datastream_binding(mut attributes: Attributes) -> FoxmlDatastreamBinding {
+    FoxmlDatastreamBinding {
+        key: attributes.get("KEY").unwrap_or_default(),
+        datastream_id: attributes.get("DATASTREAM_ID").unwrap_or_default(),
+        label: attributes.get("LABEL"),
+        order: attributes.get("ORDER"),
+    }
+}
+
+// The object being read, elements are appended to the last datastream or
+// disseminator read as they can not be nested.
+#[derive(Default)]
+struct Builder {
+    pid: String,
+    version: Option<String>,
+    fedora_uri: Option<String>,
+    properties: Option<FoxmlObjectProperties>,
+    datastreams: Vec<FoxmlDatastream>,
+    disseminators: Vec<FoxmlDisseminator>,
+}
+
+impl Builder {
+    fn last_version(&mut self) -> Option<&mut FoxmlDatastreamVersion> {
+        self.datastreams
+            .last_mut()
+            .and_then(|datastream| datastream.versions.last_mut())
+    }
+
+    fn last_disseminator_version(&mut self) -> Option<&mut FoxmlDisseminatorVersion> {
+        self.disseminators
+            .last_mut()
+            .and_then(|disseminator| disseminator.versions.last_mut())
+    }
+
+    fn element(&mut self, element: &BytesStart) -> Result<(), FoxmlError> {
+        let attributes = Attributes::new(element)?;
+        match element.local_name() {
+            b"digitalObject" => {
+                let mut attributes = attributes;
+                self.pid = attributes.get("PID").unwrap_or_default();
+                self.version = attributes.get("VERSION");
+                self.fedora_uri = attributes.get("FEDORA_URI");
+            }
+            b"objectProperties" => {
+                self.properties.get_or_insert(FoxmlObjectProperties {
+                    properties: vec![],
+                    ext_properties: vec![],
+                });
+            }
+            b"property" => {
+                if let Some(properties) = self.properties.as_mut() {
+                    properties.properties.push(property(attributes)?);
+                }
+            }
+            b"extproperty" => {
+                if let Some(properties) = self.properties.as_mut() {
+                    properties.ext_properties.push(property(attributes)?);
+                }
+            }
+            b"datastream" => self.datastreams.push(datastream(attributes)?),
+            b"datastreamVersion" => {
+                let version = datastream_version(attributes)?;
+                if let Some(datastream) = self.datastreams.last_mut() {
+                    datastream.versions.push(version);
+                }
+            }
+            b"contentLocation" => {
+                let mut attributes = attributes;
+                let location = FoxmlDatastreamContentLocation {
+                    r#type: attributes.required("TYPE")?,
+                    r#ref: attributes.required("REF")?,
+                };
+                if let Some(version) = self.last_version() {
+                    version
+                        .content
+                        .push(FoxmlDatastreamContent::ContentLocation(location));
+                }
+            }
+            b"contentDigest" => {
+                let mut attributes = attributes;
+                let digest = FoxmlDatastreamContentDigest {
+                    r#type: attributes.required("TYPE")?,
+                    digest: attributes.required("DIGEST")?,
+                };
+                if let Some(version) = self.last_version() {
+                    version
+                        .content
+                        .push(FoxmlDatastreamContent::ContentDigest(digest));
+                }
+            }
+            b"disseminator" => self.disseminators.push(disseminator(attributes)?),
+            b"disseminatorVersion" => {
+                let version = disseminator_version(attributes);
+                if let Some(disseminator) = self.disseminators.last_mut() {
+                    disseminator.versions.push(version);
+                }
+            }
+            b"serviceInputMap" => {
+                if let Some(version) = self.last_disseminator_version() {
+                    version
+                        .service_input_map
+                        .get_or_insert(FoxmlServiceInputMap { bindings: vec![] });
+                }
+            }
+            b"datastreamBinding" => {
+                let binding = datastream_binding(attributes);
+                if let Some(map) = self
+                    .last_disseminator_version()
+                    .and_then(|version| version.service_input_map.as_mut())
+                {
+                    map.bindings.push(binding);
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn build(self) -> Result<Foxml, FoxmlError> {
+        Ok(Foxml {
+            pid: self.pid,
+            version: self.version,
+            fedora_uri: self.fedora_uri,
+            properties: self
+                .properties
+                .ok_or_else(|| FoxmlError::MissingElement("objectProperties".to_string()))?,
+            datastreams: self.datastreams,
+            disseminators: self.disseminators,
+        })
+    }
+}
+
+impl Foxml {
+    // Reads the object properties and the metadata of datastreams and
+    // disseminators, the content of inline and embedded datastreams is skipped
+    // so `xml_content()`, `binary_content()` and `audit_trail()` find nothing.
+    // Memory use is bounded by the largest element rather than the file.
+    pub fn metadata_from_reader<R: BufRead>(mut input: R) -> Result<Foxml, FoxmlError> {
+        sniff(input.fill_buf()?)?;
+        let mut reader = Reader::from_reader(input);
+        let mut builder = Builder::default();
+        let mut buf = Vec::new();
+        loop {
+            match reader.read_event(&mut buf)? {
+                Event::Start(ref e)
+                    if e.local_name() == b"xmlContent" || e.local_name() == b"binaryContent" =>
+                {
+                    let name = e.name().to_vec();
+                    buf.clear();
+                    reader.read_to_end(name, &mut buf)?;
+                }
+                Event::Start(ref e) | Event::Empty(ref e) => builder.element(e)?,
+                Event::Eof => break,
+                _ => (),
+            }
+            buf.clear();
+        }
+        builder.build()
+    }
+
+    pub fn metadata_from_path(path: &Path) -> Result<Foxml, FoxmlError> {
+        Self::metadata_from_reader(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../assets/fixtures")
+            .join(name)
+    }
+
+    // Everything but the content of inline and embedded datastreams matches
+    // the deserialized object.
+    #[test]
+    fn matches_deserialized() {
+        for name in &["valid.foxml.xml", "archive.foxml.xml"] {
+            let path = fixture(name);
+            let mut expected = Foxml::from_path(&path).unwrap();
+            for version in expected
+                .datastreams
+                .iter_mut()
+                .flat_map(|datastream| datastream.versions.iter_mut())
+            {
+                version.content.retain(|content| {
+                    !matches!(
+                        content,
+                        FoxmlDatastreamContent::XmlContent(_)
+                            | FoxmlDatastreamContent::BinaryContent(_)
+                    )
+                });
+            }
+            let foxml = Foxml::metadata_from_path(&path).unwrap();
+            assert_eq!(foxml.pid, expected.pid);
+            assert_eq!(foxml.version, expected.version);
+            assert_eq!(foxml.properties, expected.properties);
+            assert_eq!(foxml.datastreams, expected.datastreams);
+            assert_eq!(foxml.disseminators, expected.disseminators);
+            assert!(foxml.audit_trail().is_empty());
+        }
+    }
+
+    #[test]
+    fn invalid_metadata() {
+        let category = |content: &str| {
+            Foxml::metadata_from_reader(content.as_bytes())
+                .unwrap_err()
+                .category()
+        };
+        assert_eq!(category(""), "empty");
+        assert_eq!(category("<html><body>502 Bad Gateway"), "not_xml");
+        assert_eq!(
+            category("<foxml:digitalObject PID=\"a:1\"/>"),
+            "invalid_foxml"
+        );
+        let content = r#"<foxml:digitalObject PID="a:1"><foxml:objectProperties/>
+            <foxml:datastream ID="OBJ" CONTROL_GROUP="Z"/></foxml:digitalObject>"#;
+        let err: FoxmlErrorDiscriminants = Foxml::metadata_from_reader(content.as_bytes())
+            .unwrap_err()
+            .into();
+        assert_eq!(err, FoxmlErrorDiscriminants::InvalidAttribute);
+    }
+}
@@ -0,0 +1,269 @@
+// The throughput of each phase of previous runs, persisted in the output
+// directory as `run_history.json` so that the duration of each phase, and of
+// the whole command, can be estimated at startup. Estimates assume the next run
+// processes as many items (files, objects, rows) and bytes as the last, and are
+// based on the average throughput of previous runs, weighted towards the most
+// recent.
+use log::{info, warn};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static HISTORY_FILE: &str = "run_history.json";
+
+// Weight given to the latest run when averaging throughput.
+const WEIGHT: f64 = 0.5;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Phase {
+    name: String,
+    // Processed by the last run.
+    items: u64,
+    bytes: u64,
+    seconds: f64,
+    // Averaged over previous runs, zero if unknown.
+    items_per_second: f64,
+    bytes_per_second: f64,
+}
+
+impl Phase {
+    fn from_json(value: &Value) -> Option<Self> {
+        let number = |key| value.get(key).and_then(Value::as_f64).unwrap_or_default();
+        Some(Phase {
+            name: value.get("phase")?.as_str()?.to_string(),
+            items: number("items") as u64,
+            bytes: number("bytes") as u64,
+            seconds: number("seconds"),
+            items_per_second: number("items_per_second"),
+            bytes_per_second: number("bytes_per_second"),
+        })
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "phase": self.name,
+            "items": self.items,
+            "bytes": self.bytes,
+            "seconds": self.seconds,
+            "items_per_second": self.items_per_second,
+            "bytes_per_second": self.bytes_per_second,
+        })
+    }
+
+    // Bytes are the better predictor when copying files, otherwise items.
+    fn estimate(&self) -> Duration {
+        let seconds = if self.bytes > 0 && self.bytes_per_second > 0.0 {
+            self.bytes as f64 / self.bytes_per_second
+        } else if self.items > 0 && self.items_per_second > 0.0 {
+            self.items as f64 / self.items_per_second
+        } else {
+            self.seconds
+        };
+        Duration::from_secs_f64(seconds)
+    }
+
+    fn record(&mut self, items: u64, bytes: u64, seconds: f64) {
+        let average = |previous: f64, amount: u64| {
+            if amount == 0 || seconds <= 0.0 {
+                return previous;
+            }
+            let rate = amount as f64 / seconds;
+            if previous > 0.0 {
+                WEIGHT * rate + (1.0 - WEIGHT) * previous
+            } else {
+                rate
+            }
+        };
+        self.items_per_second = average(self.items_per_second, items);
+        self.bytes_per_second = average(self.bytes_per_second, bytes);
+        self.items = items;
+        self.bytes = bytes;
+        self.seconds = seconds;
+    }
+}
+
+struct History {
+    path: PathBuf,
+    command: String,
+    // Phases of every command, as the file is shared between them.
+    commands: Map<String, Value>,
+    // Phases of this command in the order they last ran.
+    phases: Vec<Phase>,
+    started: HashMap<String, Instant>,
+}
+
+lazy_static! {
+    static ref HISTORY: Mutex<Option<History>> = Mutex::new(None);
+}
+
+// Human readable, to the nearest second.
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs_f64().round() as u64;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+fn format_rate(phase: &Phase) -> String {
+    let mut rates = Vec::new();
+    if phase.items > 0 && phase.items_per_second > 0.0 {
+        rates.push(format!(
+            "{} items at {:.1}/s",
+            phase.items, phase.items_per_second
+        ));
+    }
+    if phase.bytes > 0 && phase.bytes_per_second > 0.0 {
+        rates.push(format!(
+            "{:.1} MB at {:.1} MB/s",
+            phase.bytes as f64 / 1_000_000.0,
+            phase.bytes_per_second / 1_000_000.0
+        ));
+    }
+    rates.join(", ")
+}
+
+fn read(path: &Path) -> Result<Map<String, Value>, String> {
+    let content = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+    match serde_json::from_str(&content).map_err(|error| error.to_string())? {
+        Value::Object(commands) => Ok(commands),
+        _ => Err("Expected an object".to_string()),
+    }
+}
+
+// Loads the history of the given command from the output directory and logs
+// the estimated duration of each phase, if the command has been run before.
+pub fn load(output: &Path, command: &str) {
+    let path = output.join(HISTORY_FILE);
+    let commands = if path.exists() {
+        read(&path).unwrap_or_else(|error| {
+            warn!("Ignoring {}: {}", path.display(), error);
+            Map::new()
+        })
+    } else {
+        Map::new()
+    };
+    let phases = commands
+        .get(command)
+        .and_then(Value::as_array)
+        .map(|phases| phases.iter().filter_map(Phase::from_json).collect())
+        .unwrap_or_else(Vec::new);
+    if !phases.is_empty() {
+        let total = phases.iter().map(Phase::estimate).sum::<Duration>();
+        info!(
+            "Estimated to take {} based on previous runs:",
+            format_duration(total)
+        );
+        for phase in &phases {
+            info!(
+                "  {}: {} ({})",
+                phase.name,
+                format_duration(phase.estimate()),
+                format_rate(phase)
+            );
+        }
+    }
+    *HISTORY.lock().unwrap() = Some(History {
+        path,
+        command: command.to_string(),
+        commands,
+        phases,
+        started: HashMap::new(),
+    });
+}
+
+pub(crate) fn phase_started(phase: &str) {
+    let mut lock = HISTORY.lock().unwrap();
+    let history = match lock.as_mut() {
+        Some(history) => history,
+        None => return,
+    };
+    if let Some(previous) = history.phases.iter().find(|p| p.name == phase) {
+        info!(
+            "Started {}, estimated to take {}",
+            phase,
+            format_duration(previous.estimate())
+        );
+    }
+    history.started.insert(phase.to_string(), Instant::now());
+}
+
+// Items are the "total" count if given, otherwise the sum of the counts, bar
+// "bytes" which is recorded separately.
+fn amounts(counts: &[(&str, usize)]) -> (u64, u64) {
+    let count = |name| counts.iter().find(|(n, _)| *n == name).map(|(_, c)| *c);
+    let bytes = count("bytes").unwrap_or_default() as u64;
+    let items = count("total").unwrap_or_else(|| {
+        counts
+            .iter()
+            .filter(|(name, _)| *name != "bytes")
+            .map(|(_, count)| count)
+            .sum()
+    });
+    (items as u64, bytes)
+}
+
+// Saved after every phase so that interrupted runs still contribute.
+pub(crate) fn phase_finished(phase: &str, counts: &[(&str, usize)]) {
+    let mut lock = HISTORY.lock().unwrap();
+    let history = match lock.as_mut() {
+        Some(history) => history,
+        None => return,
+    };
+    let seconds = match history.started.remove(phase) {
+        Some(started) => started.elapsed().as_secs_f64(),
+        None => return,
+    };
+    let (items, bytes) = amounts(counts);
+    match history.phases.iter_mut().find(|p| p.name == phase) {
+        Some(previous) => previous.record(items, bytes, seconds),
+        None => {
+            let mut new = Phase {
+                name: phase.to_string(),
+                ..Default::default()
+            };
+            new.record(items, bytes, seconds);
+            history.phases.push(new);
+        }
+    }
+    let phases = history.phases.iter().map(Phase::to_json).collect();
+    history
+        .commands
+        .insert(history.command.clone(), Value::Array(phases));
+    let content = serde_json::to_string_pretty(&history.commands).unwrap();
+    if let Err(error) = std::fs::write(&history.path, content) {
+        warn!("Failed to write {}: {}", history.path.display(), error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimates() {
+        let mut phase = Phase {
+            name: "managed datastreams".to_string(),
+            ..Default::default()
+        };
+        phase.record(100, 1_000, 10.0);
+        phase.record(100, 1_000, 5.0);
+        // Averaged 100 and 200 bytes per second.
+        assert_eq!(phase.bytes_per_second, 150.0);
+        assert_eq!(phase.estimate(), Duration::from_secs_f64(1_000.0 / 150.0));
+        assert_eq!(Phase::from_json(&phase.to_json()), Some(phase));
+        assert_eq!(
+            amounts(&[("total", 3), ("skipped", 2), ("bytes", 10)]),
+            (3, 10)
+        );
+        assert_eq!(amounts(&[("a.csv", 3), ("b.csv", 2)]), (5, 0));
+        assert_eq!(format_duration(Duration::from_secs(3725)), "1h 2m");
+        assert_eq!(format_duration(Duration::from_secs(125)), "2m 5s");
+    }
+}
@@ -1,10 +1,78 @@
+#[macro_use]
+extern crate lazy_static;
+
+#[macro_use]
+extern crate strum_macros;
+
+pub mod history;
+pub mod state;
+pub mod summary;
+pub mod webhook;
+
 use chrono::offset::Local;
 use colored::*;
 use core::fmt::Arguments;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use log::{Level, Metadata, Record};
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::hash::Hash;
+use std::io::IsTerminal;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+#[derive(Clone, Copy, Debug, EnumString, PartialEq)]
+#[strum(serialize_all = "lowercase")]
+pub enum ColorChoice {
+    Auto,   // Only when writing to a terminal.
+    Always, // Even when redirected to a file.
+    Never,
+}
+
+static PROGRESS: AtomicBool = AtomicBool::new(true);
+
+// Maximum number of times a second progress bars are drawn.
+const DEFAULT_PROGRESS_RATE: u64 = 15;
+static PROGRESS_RATE: AtomicU64 = AtomicU64::new(DEFAULT_PROGRESS_RATE);
+
+// Bars are only redrawn once they advance by this fraction of their length, as
+// otherwise every increment of a bar within a `MultiProgress` is sent to be
+// drawn, regardless of the rate.
+const DRAW_STEPS: u64 = 1000;
+
+// By default colors and progress bars are only shown when writing to a
+// terminal, so that logs redirected to a file (e.g. by cron) are not littered
+// with escape codes.
+pub fn configure(color: ColorChoice, progress: bool) {
+    let color = match color {
+        ColorChoice::Auto => std::io::stdout().is_terminal(),
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    };
+    colored::control::set_override(color);
+    PROGRESS.store(
+        progress && std::io::stderr().is_terminal(),
+        Ordering::Relaxed,
+    );
+}
+
+fn show_progress() -> bool {
+    PROGRESS.load(Ordering::Relaxed)
+}
+
+// Limits how often progress bars are drawn, e.g. to reduce the overhead of
+// huge runs or the clutter of CI logs.
+pub fn set_progress_rate(rate: u64) {
+    PROGRESS_RATE.store(rate.max(1), Ordering::Relaxed);
+}
+
+fn draw_target() -> ProgressDrawTarget {
+    if show_progress() {
+        ProgressDrawTarget::stderr_with_hz(PROGRESS_RATE.load(Ordering::Relaxed))
+    } else {
+        ProgressDrawTarget::hidden()
+    }
+}
 
 pub struct Logger;
 
@@ -40,6 +108,7 @@ impl log::Log for Logger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
+            summary::record(record.level(), record.target());
             Logger::println(record.level(), record.args());
         }
     }
@@ -47,23 +116,62 @@ impl log::Log for Logger {
     fn flush(&self) {}
 }
 
+thread_local! {
+    // Whether panics on this thread are caught by the task it runs.
+    static CATCHING_PANICS: Cell<bool> = const { Cell::new(false) };
+}
+
+// Marks the current thread as running tasks within `catch_panic()`, the panic
+// hook should not exit the program for panics on it. Threads a task waits on
+// (e.g. those of the rayon pool it is installed in) must be marked as well, as
+// their panics are propagated to the task.
+pub fn set_catching_panics(catching: bool) {
+    CATCHING_PANICS.with(|cell| cell.set(catching));
+}
+
+pub fn is_catching_panics() -> bool {
+    CATCHING_PANICS.with(Cell::get)
+}
+
+// Runs the given task returning any panic as an error.
+pub fn catch_panic<F, T>(task: F) -> Result<T, String>
+where
+    F: FnOnce() -> T,
+{
+    std::panic::catch_unwind(AssertUnwindSafe(task)).map_err(|payload| {
+        payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_else(|| "Unknown error".to_string())
+    })
+}
+
 pub fn multi_progress() -> MultiProgress {
-    MultiProgress::new()
+    MultiProgress::with_draw_target(draw_target())
 }
 
 pub fn progress_bar(total: u64) -> ProgressBar {
-    let progress_bar = ProgressBar::new(total);
+    let progress_bar = ProgressBar::with_draw_target(total, draw_target());
     let style = ProgressStyle::default_bar()
         .template(
             "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos:>7}/{len:7} ({eta})",
         )
         .progress_chars("#>-");
     progress_bar.set_style(style);
+    progress_bar.set_draw_delta(total / DRAW_STEPS);
     progress_bar
 }
 
+// Bars created before their length is known must be given it with this rather
+// than `ProgressBar::set_length()`, so that they are redrawn in proportion.
+pub fn set_length(progress_bar: &ProgressBar, total: u64) {
+    progress_bar.set_length(total);
+    progress_bar.set_draw_delta(total / DRAW_STEPS);
+}
+
 pub fn spinner() -> ProgressBar {
-    let spinner = ProgressBar::new(1);
+    let spinner = ProgressBar::with_draw_target(1, draw_target());
     let style = ProgressStyle::default_spinner()
         .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ")
         .template("{prefix:.bold.dim} {spinner} {wide_msg}");
@@ -76,7 +184,7 @@ where
     T: Eq + Hash,
     I: IntoIterator<Item = T>,
 {
-    let multi = MultiProgress::new();
+    let multi = multi_progress();
     let bars = keys
         .into_iter()
         .map(|key| {
@@ -86,3 +194,21 @@ where
         .collect();
     (multi, bars)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn catching_panics_is_per_thread() {
+        set_catching_panics(true);
+        assert!(is_catching_panics());
+        assert!(!std::thread::spawn(is_catching_panics).join().unwrap());
+        set_catching_panics(false);
+        assert_eq!(catch_panic(|| 1), Ok(1));
+        assert_eq!(
+            catch_panic(|| panic!("Failed to parse {}", "archden:1")),
+            Err::<(), _>("Failed to parse archden:1".to_string())
+        );
+    }
+}
@@ -0,0 +1,233 @@
+// Opt-in record of how far a command got, written to the file given by
+// `--state-file` at regular intervals, so that when a run is killed without
+// warning (e.g. by the OOM killer) operators know where it stopped and which
+// objects were being processed at the time. For example:
+// {"command":"csv","status":"running","updated":"2020-10-16T12:22:03+00:00",
+//  "phase":"objects","phases":[{"phase":"objects","finished":false,
+//  "processed":1204,"last":"archden:463","in_progress":["archden:464"]}]}
+use chrono::offset::Local;
+use log::warn;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// How often the file is rewritten while items are being processed.
+const INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Default)]
+struct Phase {
+    name: String,
+    finished: bool,
+    processed: usize,
+    // Last item (PID or file) to be successfully processed.
+    last: Option<String>,
+    // Items currently being processed, counted as the same object may be
+    // processed by several threads at once e.g. by different scripts.
+    in_progress: BTreeMap<String, usize>,
+}
+
+impl Phase {
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "phase": self.name,
+            "finished": self.finished,
+            "processed": self.processed,
+            "last": self.last,
+            "in_progress": self.in_progress.keys().collect::<Vec<_>>(),
+        })
+    }
+}
+
+struct State {
+    path: PathBuf,
+    command: String,
+    // Items processed outside of a phase are attributed to the last started.
+    phases: Vec<Phase>,
+    written: Instant,
+}
+
+impl State {
+    fn phase(&mut self) -> &mut Phase {
+        if self.phases.is_empty() {
+            self.phases.push(Phase {
+                name: self.command.clone(),
+                ..Default::default()
+            });
+        }
+        self.phases.last_mut().unwrap()
+    }
+
+    fn content(&self, status: &str) -> String {
+        let phase = self.phases.last().map(|phase| phase.name.as_str());
+        let content = json!({
+            "command": self.command,
+            "status": status,
+            "updated": Local::now().to_rfc3339(),
+            "phase": phase,
+            "phases": self.phases.iter().map(Phase::to_json).collect::<Vec<_>>(),
+        });
+        serde_json::to_string_pretty(&content).unwrap()
+    }
+
+    // Replaces the file atomically, so it is never left half written if the
+    // process is killed.
+    fn write(&mut self, status: &str) {
+        self.written = Instant::now();
+        let partial = PathBuf::from(format!("{}.partial", self.path.display()));
+        let result = std::fs::write(&partial, self.content(status))
+            .and_then(|_| std::fs::rename(&partial, &self.path));
+        if let Err(error) = result {
+            warn!("Failed to write {}: {}", self.path.display(), error);
+        }
+    }
+}
+
+lazy_static! {
+    static ref STATE: Mutex<Option<State>> = Mutex::new(None);
+}
+
+// Avoids locking for every item when not enabled.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+// Enables recording the state of the given command, does nothing if no file
+// is given.
+pub fn set_state_file(path: Option<&Path>, command: &str) {
+    let mut state = path.map(|path| State {
+        path: path.to_path_buf(),
+        command: command.to_string(),
+        phases: vec![],
+        written: Instant::now(),
+    });
+    if let Some(state) = state.as_mut() {
+        state.write("running");
+    }
+    ENABLED.store(state.is_some(), Ordering::Relaxed);
+    *STATE.lock().unwrap() = state;
+}
+
+fn update<F>(f: F)
+where
+    F: FnOnce(&mut State),
+{
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Some(state) = STATE.lock().unwrap().as_mut() {
+        f(state)
+    }
+}
+
+pub(crate) fn phase_started(phase: &str) {
+    update(|state| {
+        state.phases.push(Phase {
+            name: phase.to_string(),
+            ..Default::default()
+        });
+        state.write("running");
+    });
+}
+
+pub(crate) fn phase_finished(phase: &str) {
+    update(|state| {
+        if let Some(phase) = state.phases.iter_mut().rev().find(|p| p.name == phase) {
+            phase.finished = true;
+        }
+        state.write("running");
+    });
+}
+
+pub fn completed() {
+    update(|state| state.write("completed"));
+}
+
+// Called from the panic hook, which may be invoked while the state is being
+// updated, in which case the file is left as is rather than deadlocking.
+pub fn failed() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Ok(mut state) = STATE.try_lock() {
+        if let Some(state) = state.as_mut() {
+            state.write("failed");
+        }
+    }
+}
+
+// Marks the given item (PID or file) as being processed by the current phase
+// until the returned guard is dropped.
+pub fn processing(item: &str) -> Processing {
+    let mut phase = None;
+    update(|state| {
+        *state
+            .phase()
+            .in_progress
+            .entry(item.to_string())
+            .or_insert(0) += 1;
+        phase = Some(state.phases.len() - 1);
+    });
+    Processing(phase.map(|phase| (phase, item.to_string())))
+}
+
+// The phase (by index) and the item being processed.
+pub struct Processing(Option<(usize, String)>);
+
+impl Drop for Processing {
+    fn drop(&mut self) {
+        let (phase, item) = match self.0.take() {
+            Some(processing) => processing,
+            None => return,
+        };
+        // Items that panicked were not successfully processed.
+        let succeeded = !std::thread::panicking();
+        update(|state| {
+            let phase = &mut state.phases[phase];
+            if let Some(count) = phase.in_progress.get_mut(&item) {
+                *count -= 1;
+                if *count == 0 {
+                    phase.in_progress.remove(&item);
+                }
+            }
+            if succeeded {
+                phase.processed += 1;
+                phase.last = Some(item);
+            }
+            if state.written.elapsed() >= INTERVAL {
+                state.write("running");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_content() {
+        let mut state = State {
+            path: PathBuf::from("state.json"),
+            command: "csv".to_string(),
+            phases: vec![],
+            written: Instant::now(),
+        };
+        let phase = state.phase();
+        phase.processed = 2;
+        phase.last = Some("archden:463".to_string());
+        phase.in_progress.insert("archden:464".to_string(), 1);
+        let content: serde_json::Value = serde_json::from_str(&state.content("running")).unwrap();
+        assert_eq!(content["phase"], "csv");
+        assert_eq!(
+            content["phases"][0],
+            json!({
+                "phase": "csv",
+                "finished": false,
+                "processed": 2,
+                "last": "archden:463",
+                "in_progress": ["archden:464"],
+            })
+        );
+    }
+}
@@ -0,0 +1,218 @@
+// A single JSON line written to stderr once a command completes or fails, so
+// that wrapper scripts can parse it rather than scrape the log output. Errors
+// and warnings are counted by the module that logged them, e.g.
+// {"command":"csv","status":"completed","elapsed_seconds":12,"errors":{},
+//  "warnings":{"csv::rows":2},"reports":["/output/dangling_parents.csv"]}
+// Problems are also counted by category along with a few examples, e.g.
+// "categories":{"dangling_parents":{"count":2,"examples":["archden:1 ..."]}}
+// which is omitted if there are none.
+use log::Level;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+// Number of examples kept per category.
+pub const EXAMPLES: usize = 3;
+
+#[derive(Clone, Default)]
+struct Category {
+    count: usize,
+    examples: Vec<String>,
+}
+
+#[derive(Default)]
+struct Summary {
+    command: String,
+    started: Option<Instant>,
+    errors: BTreeMap<String, usize>,
+    warnings: BTreeMap<String, usize>,
+    // Reports listing problems, e.g. parse_errors.csv.
+    reports: BTreeSet<String>,
+    // Problems by category e.g. 'missing_files' or 'unparsable_dc'.
+    categories: BTreeMap<String, Category>,
+}
+
+impl Category {
+    fn add(&mut self, count: usize, examples: &[String]) {
+        self.count += count;
+        let remaining = EXAMPLES.saturating_sub(self.examples.len());
+        self.examples
+            .extend(examples.iter().take(remaining).cloned());
+    }
+}
+
+lazy_static! {
+    static ref SUMMARY: Mutex<Summary> = Mutex::new(Summary::default());
+}
+
+pub fn set_command(command: &str) {
+    let mut summary = SUMMARY.lock().unwrap();
+    summary.command = command.to_string();
+    summary.started = Some(Instant::now());
+}
+
+pub(crate) fn record(level: Level, category: &str) {
+    let mut summary = SUMMARY.lock().unwrap();
+    let counts = match level {
+        Level::Error => &mut summary.errors,
+        Level::Warn => &mut summary.warnings,
+        _ => return,
+    };
+    *counts.entry(category.to_string()).or_insert(0) += 1;
+}
+
+pub fn add_report(path: &Path) {
+    SUMMARY
+        .lock()
+        .unwrap()
+        .reports
+        .insert(path.to_string_lossy().to_string());
+}
+
+// Counts a problem of the given category, e.g. the PID it was found in.
+pub fn warning(category: &str, example: &str) {
+    warnings(category, 1, &[example.to_string()]);
+}
+
+// Counts the given number of problems of the given category, of which only
+// the first few examples are kept.
+pub fn warnings(category: &str, count: usize, examples: &[String]) {
+    if count == 0 {
+        return;
+    }
+    SUMMARY
+        .lock()
+        .unwrap()
+        .categories
+        .entry(category.to_string())
+        .or_default()
+        .add(count, examples);
+}
+
+// The number of problems and examples of each category counted so far.
+pub fn categories() -> Vec<(String, usize, Vec<String>)> {
+    SUMMARY
+        .lock()
+        .unwrap()
+        .categories
+        .iter()
+        .map(|(name, category)| (name.clone(), category.count, category.examples.clone()))
+        .collect()
+}
+
+fn line(summary: &Summary, status: &str, error: Option<&str>) -> String {
+    let mut line = json!({
+        "command": summary.command,
+        "status": status,
+        "elapsed_seconds": summary.started.map_or(0, |started| started.elapsed().as_secs()),
+        "errors": summary.errors,
+        "warnings": summary.warnings,
+        "reports": summary.reports,
+    });
+    if !summary.categories.is_empty() {
+        line["categories"] = summary
+            .categories
+            .iter()
+            .map(|(name, category)| {
+                let category = json!({
+                    "count": category.count,
+                    "examples": category.examples,
+                });
+                (name.clone(), category)
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into();
+    }
+    if let Some(error) = error {
+        line["error"] = error.into();
+    }
+    line.to_string()
+}
+
+pub fn completed() {
+    eprintln!("{}", line(&SUMMARY.lock().unwrap(), "completed", None));
+}
+
+// Writes the summary as it stands to the given file, for commands which keep
+// a record of it alongside their output.
+pub fn write(path: &Path) -> std::io::Result<()> {
+    let line = line(&SUMMARY.lock().unwrap(), "completed", None);
+    std::fs::write(path, format!("{}\n", line))
+}
+
+// Called from the panic hook, which may be invoked while the summary is being
+// updated, in which case the counts are omitted rather than deadlocking.
+pub fn failed(error: &str) {
+    let line = match SUMMARY.try_lock() {
+        Ok(summary) => line(&summary, "failed", Some(error)),
+        Err(_) => line(&Summary::default(), "failed", Some(error)),
+    };
+    eprintln!("{}", line);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_line() {
+        let mut summary = Summary {
+            command: "csv".to_string(),
+            ..Default::default()
+        };
+        summary.warnings.insert("csv::rows".to_string(), 2);
+        summary
+            .reports
+            .insert("/output/parse_errors.csv".to_string());
+        // Compared as values, as the order of the keys depends on whether
+        // serde_json preserves it, which other crates may enable.
+        let line: serde_json::Value =
+            serde_json::from_str(&line(&summary, "failed", Some("Oops"))).unwrap();
+        assert_eq!(
+            line,
+            json!({
+                "command": "csv",
+                "status": "failed",
+                "elapsed_seconds": 0,
+                "errors": {},
+                "warnings": {"csv::rows": 2},
+                "reports": ["/output/parse_errors.csv"],
+                "error": "Oops"
+            })
+        );
+    }
+
+    #[test]
+    fn categories() {
+        let mut summary = Summary::default();
+        let examples =
+            |examples: &[&str]| examples.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        let category = summary
+            .categories
+            .entry("missing_files".to_string())
+            .or_default();
+        category.add(2, &examples(&["archden:1", "archden:2"]));
+        category.add(3, &examples(&["archden:3", "archden:4", "archden:5"]));
+        let line: serde_json::Value =
+            serde_json::from_str(&line(&summary, "completed", None)).unwrap();
+        assert_eq!(
+            line,
+            json!({
+                "command": "",
+                "status": "completed",
+                "elapsed_seconds": 0,
+                "errors": {},
+                "warnings": {},
+                "reports": [],
+                "categories": {
+                    "missing_files": {
+                        "count": 5,
+                        "examples": ["archden:1", "archden:2", "archden:3"]
+                    }
+                }
+            })
+        );
+    }
+}
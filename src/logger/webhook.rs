@@ -0,0 +1,135 @@
+// Notifies a webhook as each phase of a command starts and finishes, and once
+// the command completes or fails, so that long running migrations can alert a
+// chat channel. The payload includes a `text` field so it can be posted
+// directly to Slack or Teams incoming webhooks.
+use chrono::offset::Local;
+use log::warn;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// Notifications should never hold up the migration for long.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Webhook {
+    url: String,
+    command: String,
+    started: Instant,
+    // Counts reported by each finished phase, included on completion.
+    phases: BTreeMap<String, BTreeMap<String, usize>>,
+}
+
+lazy_static! {
+    static ref WEBHOOK: Mutex<Option<Webhook>> = Mutex::new(None);
+}
+
+// Enables notifications for the given command, does nothing if no URL is given.
+pub fn set_webhook(url: Option<&str>, command: &str) {
+    *WEBHOOK.lock().unwrap() = url.map(|url| Webhook {
+        url: url.to_string(),
+        command: command.to_string(),
+        started: Instant::now(),
+        phases: BTreeMap::new(),
+    });
+}
+
+fn format_counts(counts: &BTreeMap<String, usize>) -> String {
+    counts
+        .iter()
+        .map(|(name, count)| format!("{}: {}", name, count))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn notify<F>(event: &str, build: F)
+where
+    F: FnOnce(&mut Webhook) -> (String, Map<String, Value>),
+{
+    notify_with(WEBHOOK.lock().unwrap(), event, build)
+}
+
+fn notify_with<F>(mut lock: MutexGuard<Option<Webhook>>, event: &str, build: F)
+where
+    F: FnOnce(&mut Webhook) -> (String, Map<String, Value>),
+{
+    let webhook = match lock.as_mut() {
+        Some(webhook) => webhook,
+        None => return,
+    };
+    let (text, fields) = build(webhook);
+    let mut payload = json!({
+        "text": format!("migration {}: {}", webhook.command, text),
+        "command": webhook.command,
+        "event": event,
+        "timestamp": Local::now().to_rfc3339(),
+        "elapsed_seconds": webhook.started.elapsed().as_secs(),
+    });
+    payload.as_object_mut().unwrap().extend(fields);
+    let result = ureq::AgentBuilder::new()
+        .timeout(TIMEOUT)
+        .build()
+        .post(&webhook.url)
+        .set("Content-Type", "application/json")
+        .send_string(&payload.to_string());
+    if let Err(error) = result {
+        warn!("Failed to notify webhook {}: {}", webhook.url, error);
+    }
+}
+
+pub fn phase_started(phase: &str) {
+    super::history::phase_started(phase);
+    super::state::phase_started(phase);
+    notify("phase_started", |_| {
+        (
+            format!("started {}", phase),
+            json!({ "phase": phase }).as_object().unwrap().clone(),
+        )
+    });
+}
+
+pub fn phase_finished(phase: &str, counts: &[(&str, usize)]) {
+    super::history::phase_finished(phase, counts);
+    super::state::phase_finished(phase);
+    notify("phase_finished", |webhook| {
+        let counts = counts
+            .iter()
+            .map(|(name, count)| (name.to_string(), *count))
+            .collect::<BTreeMap<_, _>>();
+        let text = format!("finished {} ({})", phase, format_counts(&counts));
+        let fields = json!({ "phase": phase, "counts": counts });
+        webhook.phases.insert(phase.to_string(), counts);
+        (text, fields.as_object().unwrap().clone())
+    });
+}
+
+pub fn completed() {
+    notify("completed", |webhook| {
+        let text = format!(
+            "completed in {}s ({})",
+            webhook.started.elapsed().as_secs(),
+            webhook
+                .phases
+                .iter()
+                .map(|(phase, counts)| format!("{}: {}", phase, format_counts(counts)))
+                .collect::<Vec<_>>()
+                .join("; ")
+        );
+        let fields = json!({ "phases": webhook.phases });
+        (text, fields.as_object().unwrap().clone())
+    });
+}
+
+// Called from the panic hook, which may be invoked while a notification is
+// being sent, in which case it is skipped rather than deadlocking.
+pub fn failed(error: &str) {
+    let lock = match WEBHOOK.try_lock() {
+        Ok(lock) => lock,
+        Err(_) => return,
+    };
+    notify_with(lock, "failed", |webhook| {
+        let text = format!("failed: {}", error);
+        let fields = json!({ "error": error, "phases": webhook.phases });
+        (text, fields.as_object().unwrap().clone())
+    });
+}
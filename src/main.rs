@@ -10,6 +10,7 @@ mod args;
 use args::*;
 use log::*;
 use logger::Logger;
+use std::path::Path;
 
 static LOGGER: Logger = Logger;
 
@@ -17,8 +18,15 @@ fn main() {
     // Force exit if panics on thread.
     let original_panic_hook = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |panic_info| {
+        // Panics within tasks that report their own failure are only logged.
+        let caught = logger::is_catching_panics();
         // Use custom logger.
         if let Some(error) = panic_info.payload().downcast_ref::<String>() {
+            if !caught {
+                logger::webhook::failed(error);
+                logger::summary::failed(error);
+                logger::state::failed();
+            }
             if let Some(location) = panic_info.location() {
                 Logger::error(&format!(
                     "Panic (File: {}, Line: {}, Column: {}): {}",
@@ -31,10 +39,19 @@ fn main() {
                 Logger::error(&format!("Panic: {}", error));
             }
         } else {
+            if let Some(error) = panic_info.payload().downcast_ref::<&str>() {
+                if !caught {
+                    logger::webhook::failed(error);
+                    logger::summary::failed(error);
+                    logger::state::failed();
+                }
+            }
             // Invoke the default handler as a fallback.
             original_panic_hook(panic_info);
         }
-        std::process::exit(1);
+        if !caught {
+            std::process::exit(1);
+        }
     }));
 
     // Configure logger.
@@ -44,33 +61,137 @@ fn main() {
 
     // Process arguments and execute the given command.
     let mut args = args();
-    match args.clone().get_matches().subcommand() {
+    let matches = args.clone().get_matches();
+    logger::configure(get_color(&matches), !matches.is_present("no-progress"));
+    logger::set_progress_rate(get_progress_rate(&matches));
+    if let Some(command) = matches.subcommand_name() {
+        logger::summary::set_command(command);
+    }
+    match matches.subcommand() {
         ("migrate", Some(matches)) => {
-            let (fedora_directory, output_directory, copy, checksum) =
-                get_migrate_subcommand_args(matches);
-            migrate::migrate_data_from_fedora(fedora_directory, output_directory, copy, checksum);
+            logger::webhook::set_webhook(matches.value_of("webhook-url"), "migrate");
+            logger::state::set_state_file(matches.value_of("state-file").map(Path::new), "migrate");
+            foxml::pids::set_shard(get_shard(matches));
+            let options = get_migrate_options(matches);
+            migrate::pause_on_signal();
+            let (
+                fedora_directory,
+                output_directory,
+                method,
+                spread_across,
+                datastream_store,
+                split_pages,
+                checksum_manifest,
+            ) = get_migrate_subcommand_args(matches);
+            let limit_to_pids = get_limit_to_pids(matches);
+            migrate::create_output_directory(output_directory, !matches.is_present("no-create"));
+            // Shards share the output directory, so only unsharded runs keep a
+            // history.
+            if foxml::pids::shard().is_none() {
+                logger::history::load(output_directory, "migrate");
+            }
+            migrate::migrate_data_from_fedora(
+                fedora_directory,
+                output_directory,
+                method,
+                spread_across.clone(),
+                datastream_store,
+                limit_to_pids,
+                &options,
+            );
+            // The index covers every object file, so is only built by unsharded
+            // runs.
+            if foxml::pids::shard().is_none() {
+                csv::index_objects(output_directory, &options.pid_map);
+            }
+            if let Some((dsid, delimiter)) = split_pages {
+                csv::split_pages(output_directory, dsid, &delimiter, &options.pid_map);
+            }
+            // Includes any split pages.
+            if let Some(scope) = checksum_manifest {
+                migrate::write_checksum_manifests(output_directory, spread_across, scope);
+            }
+            migrate::write_fixity_digest(output_directory, &options);
+            logger::webhook::completed();
+            logger::state::completed();
+            logger::summary::completed();
+        }
+        ("verify", Some(matches)) => {
+            let options = get_migrate_options(matches);
+            let (fedora_directory, output_directory, spread_across, datastream_store) =
+                get_verify_subcommand_args(matches);
+            let failed = migrate::verify_migration(
+                fedora_directory,
+                output_directory,
+                spread_across,
+                datastream_store,
+                get_limit_to_pids(matches),
+                &options,
+            );
+            if failed > 0 {
+                panic!("Verification failed, {} checks did not pass", failed);
+            }
+            logger::summary::completed();
         }
         ("csv", Some(matches)) => {
+            logger::webhook::set_webhook(matches.value_of("webhook-url"), "csv");
+            logger::state::set_state_file(matches.value_of("state-file").map(Path::new), "csv");
+            foxml::pids::set_shard(get_shard(matches));
+            csv::set_slowest_objects(get_slowest_objects(matches));
+            csv::set_fail_fast(matches.is_present("fail-fast"));
+            csv::set_detect_charset(matches.is_present("detect-charset"));
             // Source directory should be the output directory of the "fedora" sub command.
-            let (source_directory, output_directory, pids) = get_csv_subcommand_args(matches);
-            csv::generate_csvs(source_directory, output_directory, pids);
+            let (source_directory, output_directory, pids, triples, options) =
+                get_csv_subcommand_args(matches);
+            let output_directory = &csv::shard_directory(output_directory);
+            csv::create_output_directory(output_directory, !matches.is_present("no-create"));
+            logger::history::load(output_directory, "csv");
+            csv::generate_csvs(source_directory, output_directory, pids, triples, options);
+            logger::webhook::completed();
+            logger::state::completed();
+            logger::summary::completed();
         }
         ("scripts", Some(matches)) => {
+            logger::webhook::set_webhook(matches.value_of("webhook-url"), "scripts");
+            logger::state::set_state_file(matches.value_of("state-file").map(Path::new), "scripts");
+            foxml::pids::set_shard(get_shard(matches));
+            csv::set_slowest_objects(get_slowest_objects(matches));
+            csv::set_fail_fast(matches.is_present("fail-fast"));
+            csv::set_detect_charset(matches.is_present("detect-charset"));
             // Source directory should be the output directory of the "fedora" sub command.
-            let (source_directory, output_directory, script_directories, module_directories, pids) =
+            let (source_directory, output_directory, pids, triples, options) =
                 get_scripts_subcommand_args(matches);
-            csv::execute_scripts(
-                source_directory,
-                output_directory,
-                script_directories,
-                module_directories,
-                pids,
-            );
+            let output_directory = &csv::shard_directory(output_directory);
+            csv::create_output_directory(output_directory, !matches.is_present("no-create"));
+            logger::history::load(output_directory, "scripts");
+            csv::execute_scripts(source_directory, output_directory, pids, triples, options);
+            logger::webhook::completed();
+            logger::state::completed();
+            logger::summary::completed();
+        }
+        ("inspect", Some(matches)) => {
+            let (source_directory, pid, triples, options) = get_inspect_subcommand_args(matches);
+            csv::inspect(source_directory, pid, triples, options);
+            logger::summary::completed();
+        }
+        ("merge", Some(matches)) => {
+            let (input_directory, output_directory) = get_merge_subcommand_args(matches);
+            csv::create_output_directory(output_directory, !matches.is_present("no-create"));
+            csv::merge_shards(input_directory, output_directory);
+            logger::summary::completed();
+        }
+        ("push", Some(matches)) => {
+            // Source directory should be the output directory of the "csv" sub command.
+            let (source_directory, options) = get_push_subcommand_args(matches);
+            push::push(source_directory, options);
+            logger::summary::completed();
         }
         ("sql", Some(matches)) => {
             // Source directory should be the output directory of the "fedora" sub command.
             let (source_directory, output_directory) = get_sql_subcommand_args(matches);
+            sql::create_output_directory(output_directory, !matches.is_present("no-create"));
             sql::generate_sql(source_directory, output_directory);
+            logger::summary::completed();
         }
         _ => {
             args.print_long_help().unwrap();
@@ -0,0 +1,138 @@
+// Manifests of the SHA-256 checksum of every migrated datastream, in the format
+// output by `sha256sum` so that the transfer can be verified with off-the-shelf
+// tools, i.e. `cd <output> && sha256sum -c manifest-sha256.txt`. Paths are
+// relative to the output directory, unless the datastreams were spread across
+// volumes elsewhere in which case they are absolute.
+use super::identifiers;
+use super::volumes::Volumes;
+use log::info;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ManifestScope {
+    // A single manifest-sha256.txt.
+    Single,
+    // A manifest-sha256-<namespace>.txt per namespace.
+    Namespace,
+}
+
+// Left behind by interrupted copies, see `resume`.
+fn is_partial(path: &Path) -> bool {
+    let name = path.file_name().unwrap().to_string_lossy();
+    name.ends_with(".partial") || name.ends_with(".partial.checkpoint")
+}
+
+fn sha256(path: &Path) -> String {
+    let mut file = File::open(path)
+        .unwrap_or_else(|error| panic!("Failed to open {}: {}", path.display(), error));
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .unwrap_or_else(|error| panic!("Failed to read {}: {}", path.display(), error));
+    format!("{:x}", hasher.finalize())
+}
+
+// Datastreams are stored in `datastreams/<pid>/...`.
+fn namespace(directory: &Path, path: &Path) -> String {
+    let pid = path
+        .strip_prefix(directory)
+        .ok()
+        .and_then(|relative| relative.components().next())
+        .map(|component| component.as_os_str().to_string_lossy().to_string())
+        .unwrap_or_default();
+    pid.split(':').next().unwrap().to_string()
+}
+
+fn manifest_name(scope: ManifestScope, namespace: &str) -> String {
+    match scope {
+        ManifestScope::Single => "manifest-sha256.txt".to_string(),
+        ManifestScope::Namespace => format!("manifest-sha256-{}.txt", namespace),
+    }
+}
+
+// Returns the number of datastreams and their total size.
+pub fn write_checksum_manifests(
+    output: &Path,
+    volumes: &Volumes,
+    scope: ManifestScope,
+) -> (usize, u64) {
+    let output = output
+        .canonicalize()
+        .unwrap_or_else(|error| panic!("Failed to find {}: {}", output.display(), error));
+    let files = volumes
+        .datastreams_directories()
+        .into_iter()
+        .filter(|directory| directory.exists())
+        .flat_map(|directory| {
+            let directory = directory.canonicalize().unwrap();
+            identifiers::files(&directory, vec![])
+                .into_iter()
+                .filter(|path| !is_partial(path))
+                .map(|path| (namespace(&directory, &path), path))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    info!(
+        "Generating SHA-256 checksums of {} datastreams.",
+        files.len()
+    );
+    let progress_bar = logger::progress_bar(files.len() as u64);
+    let mut checksums = files
+        .par_iter()
+        .map(|(namespace, path)| {
+            let checksum = sha256(path);
+            progress_bar.inc(1);
+            let relative = path.strip_prefix(&output).unwrap_or(path);
+            let size = path.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            (namespace, relative.to_path_buf(), checksum, size)
+        })
+        .collect::<Vec<_>>();
+    progress_bar.finish_and_clear();
+    let bytes = checksums.iter().map(|(_, _, _, size)| size).sum();
+    checksums.sort();
+    let mut manifests = BTreeMap::new();
+    for (namespace, path, checksum, _) in checksums.iter() {
+        manifests
+            .entry(manifest_name(scope, namespace))
+            .or_insert_with(Vec::new)
+            .push(format!("{}  {}", checksum, path.display()));
+    }
+    for (name, lines) in manifests {
+        let path = output.join(&name);
+        let write = || -> std::io::Result<()> {
+            let mut writer = BufWriter::new(File::create(&path)?);
+            for line in &lines {
+                writeln!(writer, "{}", line)?;
+            }
+            writer.flush()
+        };
+        write().unwrap_or_else(|error| panic!("Failed to write {}: {}", path.display(), error));
+        info!("Wrote {} checksums to {}", lines.len(), path.display());
+    }
+    (checksums.len(), bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifest_names() {
+        let directory = Path::new("/output/datastreams");
+        let path = directory.join("archden:463/OBJ/OBJ.0/OBJ.0.archden:463.pdf");
+        let namespace = namespace(directory, &path);
+        assert_eq!(namespace, "archden");
+        assert_eq!(
+            manifest_name(ManifestScope::Namespace, &namespace),
+            "manifest-sha256-archden.txt"
+        );
+        assert_eq!(
+            manifest_name(ManifestScope::Single, &namespace),
+            "manifest-sha256.txt"
+        );
+    }
+}
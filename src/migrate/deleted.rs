@@ -0,0 +1,186 @@
+// Given `--exclude-deleted`, objects and datastreams in the Deleted state are
+// not migrated. They are listed in deleted.csv (`pid`, `dsid`,
+// `modified_date`), so that records management can sign off that they were
+// intentionally not carried into Drupal.
+use super::identifiers::{object_metadata, ObjectPathMap};
+use foxml::pids::PidMap;
+use foxml::{Foxml, FoxmlDatastreamState, FoxmlObjectState};
+use log::info;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::path::Path;
+
+static REPORT_FILE: &str = "deleted";
+
+// The dsid is empty for objects.
+struct DeletedRow {
+    pid: String,
+    dsid: String,
+    modified_date: String,
+}
+
+// The deleted objects found amongst the object files to migrate, along with
+// every deleted datastream.
+#[derive(Default)]
+pub struct Deleted {
+    objects: HashSet<String>,
+    rows: Vec<DeletedRow>,
+}
+
+impl Deleted {
+    pub fn contains(&self, pid: &str) -> bool {
+        self.objects.contains(pid)
+    }
+
+    // Sorted by pid and dsid, so that runs can be compared.
+    pub fn write_report(&mut self, output_directory: &Path) {
+        self.rows
+            .sort_by(|a, b| a.pid.cmp(&b.pid).then_with(|| a.dsid.cmp(&b.dsid)));
+        // Shards share the output directory.
+        let report = match foxml::pids::shard() {
+            Some(shard) => output_directory.join(format!("{}-{}.csv", REPORT_FILE, shard)),
+            None => output_directory.join(format!("{}.csv", REPORT_FILE)),
+        };
+        let write = || -> Result<(), csv::Error> {
+            let mut writer = csv::Writer::from_path(&report)?;
+            writer.write_record(["pid", "dsid", "modified_date"])?;
+            for row in &self.rows {
+                writer.write_record([&row.pid, &row.dsid, &row.modified_date])?;
+            }
+            writer.flush()?;
+            Ok(())
+        };
+        write().unwrap_or_else(|error| panic!("Failed to write {}: {}", report.display(), error));
+        logger::summary::add_report(&report);
+    }
+}
+
+fn is_deleted(foxml: &Foxml) -> bool {
+    foxml
+        .properties
+        .state()
+        .is_ok_and(|state| state == FoxmlObjectState::Deleted)
+}
+
+fn deleted_rows(foxml: &Foxml) -> Vec<DeletedRow> {
+    let mut rows = Vec::new();
+    if is_deleted(foxml) {
+        rows.push(DeletedRow {
+            pid: foxml.pid.clone(),
+            dsid: String::new(),
+            modified_date: foxml
+                .properties
+                .modified_date()
+                .map_or_else(|_| String::new(), |date| date.to_rfc3339()),
+        });
+    }
+    rows.extend(
+        foxml
+            .datastreams
+            .iter()
+            .filter(|datastream| datastream.state == FoxmlDatastreamState::D)
+            .map(|datastream| DeletedRow {
+                pid: foxml.pid.clone(),
+                dsid: datastream.id.clone(),
+                modified_date: datastream
+                    .latest_version()
+                    .map_or_else(String::new, |version| version.created.to_rfc3339()),
+            }),
+    );
+    rows
+}
+
+// Removes the object files of deleted objects. Those that can not be read are
+// kept, as they are reported when their datastreams are migrated.
+pub fn remove_deleted(object_files: &mut ObjectPathMap, pid_map: &PidMap) -> Deleted {
+    let rows = object_files
+        .par_iter()
+        .filter_map(|(_, path)| object_metadata(path, pid_map).ok())
+        .flat_map_iter(|foxml| deleted_rows(&foxml))
+        .collect::<Vec<_>>();
+    let objects = rows
+        .iter()
+        .filter(|row| row.dsid.is_empty())
+        .map(|row| row.pid.clone())
+        .collect::<HashSet<_>>();
+    object_files.retain(|identifier, _| !objects.contains(&identifier.pid));
+    info!(
+        "Excluding {} deleted objects and {} deleted datastreams",
+        objects.len(),
+        rows.len() - objects.len()
+    );
+    Deleted { objects, rows }
+}
+
+// The pid and dsid of the deleted datastreams of the given object files, whose
+// files in the datastreamStore are left behind rather than orphaned.
+pub fn deleted_datastreams(objects: &[Box<Path>], pid_map: &PidMap) -> HashSet<(String, String)> {
+    objects
+        .par_iter()
+        .filter_map(|path| object_metadata(path, pid_map).ok())
+        .flat_map_iter(|foxml| {
+            let pid = foxml.pid;
+            foxml
+                .datastreams
+                .into_iter()
+                .filter(|datastream| datastream.state == FoxmlDatastreamState::D)
+                .map(move |datastream| (pid.clone(), datastream.id))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::identifiers::ObjectIdentifier;
+    use super::*;
+
+    #[test]
+    fn remove_deleted_objects() {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../assets/fixtures");
+        let directory =
+            std::env::temp_dir().join(format!("migrate-deleted-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let valid = std::fs::read_to_string(fixture.join("valid.foxml.xml")).unwrap();
+        let deleted = directory.join("deleted.xml");
+        std::fs::write(
+            &deleted,
+            valid.replace(
+                "model#state\" VALUE=\"Active\"",
+                "model#state\" VALUE=\"Deleted\"",
+            ),
+        )
+        .unwrap();
+        let purged = directory.join("purged.xml");
+        std::fs::write(
+            &purged,
+            valid.replace("ID=\"TN\" STATE=\"A\"", "ID=\"TN\" STATE=\"D\""),
+        )
+        .unwrap();
+        let object = |pid: &str, path: &Path| {
+            (
+                ObjectIdentifier {
+                    pid: pid.to_string(),
+                },
+                path.to_path_buf().into_boxed_path(),
+            )
+        };
+        let mut object_files = vec![object("demo:1", &purged), object("archden:463", &deleted)]
+            .into_iter()
+            .collect::<ObjectPathMap>();
+        let deleted = remove_deleted(&mut object_files, &PidMap::default());
+        assert!(deleted.contains("archden:463"));
+        assert_eq!(
+            object_files
+                .keys()
+                .map(|identifier| identifier.pid.as_str())
+                .collect::<Vec<_>>(),
+            vec!["demo:1"]
+        );
+        // The deleted TN of the remaining object is reported but not removed.
+        assert!(deleted.rows.iter().any(|row| row.dsid == "TN"));
+        let datastreams = deleted_datastreams(&[purged.into_boxed_path()], &PidMap::default());
+        assert_eq!(datastreams.len(), 1);
+        assert!(datastreams.iter().all(|(_, dsid)| dsid == "TN"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+// Fedora records the digest of managed datastreams as they are ingested, as the
+// contentDigest of each version. With `--verify-digests` the migrated files are
+// hashed and compared against it, so that content corrupted in Fedora or during
+// the transfer is found before it is imported into Drupal. Mismatches are
+// reported in the summary written once the command completes.
+use super::identifiers::{datastream_path, object_metadata};
+use super::volumes::Volumes;
+use super::MigrateOptions;
+use foxml::FoxmlControlGroup;
+use log::{error, info, warn};
+use md5::Md5;
+use rayon::prelude::*;
+use sha1::Sha1;
+use sha2::digest::DynDigest;
+use sha2::{Sha256, Sha384, Sha512};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+// A recorded digest of a migrated datastream version.
+pub struct Digest {
+    // i.e. 'pid/dsid/version'.
+    pub id: String,
+    pub algorithm: String,
+    pub expected: String,
+    pub path: PathBuf,
+}
+
+pub enum Outcome {
+    Verified,
+    // The digest of the migrated file.
+    Mismatched(String),
+    Missing,
+    // Algorithms other than MD5 and SHA-1/256/384/512, e.g. TIGER.
+    Unsupported,
+}
+
+#[derive(Default)]
+pub struct DigestResults {
+    verified: usize,
+    mismatched: usize,
+    missing: usize,
+    unsupported: usize,
+}
+
+impl DigestResults {
+    pub fn counts(&self) -> Vec<(&'static str, usize)> {
+        vec![
+            ("verified", self.verified),
+            ("mismatched", self.mismatched),
+            ("missing", self.missing),
+            ("unsupported", self.unsupported),
+        ]
+    }
+}
+
+impl fmt::Display for DigestResults {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Verified: {}, Mismatched: {}, Missing: {}, Unsupported: {}",
+            self.verified, self.mismatched, self.missing, self.unsupported
+        )
+    }
+}
+
+fn hasher(algorithm: &str) -> Option<Box<dyn DynDigest>> {
+    match algorithm.to_uppercase().as_str() {
+        "MD5" => Some(Box::new(Md5::default())),
+        "SHA-1" => Some(Box::new(Sha1::default())),
+        "SHA-256" => Some(Box::new(Sha256::default())),
+        "SHA-384" => Some(Box::new(Sha384::default())),
+        "SHA-512" => Some(Box::new(Sha512::default())),
+        _ => None,
+    }
+}
+
+fn hash<R: Read>(mut hasher: Box<dyn DynDigest>, mut reader: R) -> io::Result<String> {
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match reader.read(&mut buffer)? {
+            0 => break,
+            length => hasher.update(&buffer[..length]),
+        }
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+// Digests of the managed datastreams of the given object file, Fedora records
+// 'none' when digests are disabled.
+pub fn digests(path: &Path, volumes: &Volumes, options: &MigrateOptions) -> Vec<Digest> {
+    let foxml = match object_metadata(path, &options.pid_map) {
+        Ok(foxml) => foxml,
+        Err(err) => {
+            error!(
+                "Failed to parse file: {}, with error: {}",
+                &path.to_string_lossy(),
+                err
+            );
+            return vec![];
+        }
+    };
+    let pid = &foxml.pid;
+    foxml
+        .datastreams
+        .iter()
+        .filter(|datastream| datastream.control_group == FoxmlControlGroup::M)
+        .flat_map(|datastream| {
+            options
+                .migrated_versions(datastream)
+                .iter()
+                .filter_map(move |version| {
+                    let digest = version.content_digest()?;
+                    if digest.r#type == "DISABLED" || digest.digest == "none" {
+                        return None;
+                    }
+                    Some(Digest {
+                        id: format!("{}/{}/{}", pid, datastream.id, version.id),
+                        algorithm: digest.r#type.clone(),
+                        expected: digest.digest.to_lowercase(),
+                        path: datastream_path(volumes, pid, &datastream.id, version),
+                    })
+                })
+        })
+        .collect()
+}
+
+pub fn verify(digest: &Digest) -> Outcome {
+    let hasher = match hasher(&digest.algorithm) {
+        Some(hasher) => hasher,
+        None => return Outcome::Unsupported,
+    };
+    let actual = match File::open(&digest.path).and_then(|file| hash(hasher, file)) {
+        Ok(actual) => actual,
+        Err(_) => return Outcome::Missing,
+    };
+    if actual == digest.expected {
+        Outcome::Verified
+    } else {
+        logger::summary::warning(
+            "digest_mismatches",
+            &format!(
+                "{} ({} {} != {})",
+                digest.id, digest.algorithm, actual, digest.expected
+            ),
+        );
+        Outcome::Mismatched(actual)
+    }
+}
+
+pub fn verify_digests(
+    objects: &[Box<Path>],
+    volumes: &Volumes,
+    options: &MigrateOptions,
+) -> DigestResults {
+    info!("Reading the digests recorded for managed datastreams.");
+    let digests = objects
+        .par_iter()
+        .flat_map_iter(|path| digests(path, volumes, options))
+        .collect::<Vec<_>>();
+    info!("Verifying {} digests.", digests.len());
+    let progress_bar = logger::progress_bar(digests.len() as u64);
+    // Each file is its own task, as their sizes vary greatly.
+    let outcomes = digests
+        .par_iter()
+        .with_max_len(1)
+        .map(|digest| {
+            let outcome = verify(digest);
+            progress_bar.inc(1);
+            outcome
+        })
+        .collect::<Vec<_>>();
+    progress_bar.finish_and_clear();
+    let mut results = DigestResults::default();
+    for outcome in outcomes {
+        match outcome {
+            Outcome::Verified => results.verified += 1,
+            Outcome::Mismatched(_) => results.mismatched += 1,
+            Outcome::Missing => results.missing += 1,
+            Outcome::Unsupported => results.unsupported += 1,
+        }
+    }
+    if results.mismatched > 0 {
+        warn!(
+            "The content of {} managed datastreams does not match the digest recorded by Fedora",
+            results.mismatched
+        );
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes() {
+        let digest = |algorithm: &str| hash(hasher(algorithm).unwrap(), &b"abc"[..]).unwrap();
+        assert_eq!(digest("MD5"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(digest("SHA-1"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(
+            digest("sha-256"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert!(hasher("TIGER").is_none());
+    }
+}
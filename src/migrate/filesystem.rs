@@ -0,0 +1,195 @@
+// Case-insensitive (NTFS, APFS and HFS+ by default) and normalization-insensitive
+// (APFS and HFS+) filesystems treat names that only differ by case or Unicode
+// normalization as the same file, so PIDs such as 'demo:ABC' and 'demo:abc'
+// would silently overwrite each other. The destination is probed before
+// migrating, and if need be all but the first of the colliding objects and
+// datastreams are skipped and reported.
+use log::{info, warn};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::RwLock;
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Filesystem {
+    pub case_insensitive: bool,
+    pub normalization_insensitive: bool,
+}
+
+lazy_static! {
+    // Set by `probe_destinations()`, case and normalization sensitive until then.
+    static ref FILESYSTEM: RwLock<Filesystem> = RwLock::new(Filesystem::default());
+}
+
+// Creates a file with the given name and checks if it can be found by the other.
+fn same_file(directory: &Path, name: &str, other: &str) -> io::Result<bool> {
+    let path = directory.join(name);
+    fs::File::create(&path)?;
+    let same = directory.join(other).exists();
+    fs::remove_file(&path)?;
+    Ok(same)
+}
+
+impl Filesystem {
+    pub fn probe(directory: &Path) -> io::Result<Self> {
+        Ok(Filesystem {
+            case_insensitive: same_file(directory, ".migration-probe-a", ".migration-probe-A")?,
+            // Precomposed 'é' versus 'e' followed by a combining acute accent.
+            normalization_insensitive: same_file(
+                directory,
+                ".migration-probe-\u{e9}",
+                ".migration-probe-e\u{301}",
+            )?,
+        })
+    }
+
+    pub fn is_insensitive(&self) -> bool {
+        self.case_insensitive || self.normalization_insensitive
+    }
+
+    // Names which are the same file on this filesystem fold to the same string.
+    pub fn fold(&self, name: &str) -> String {
+        let name = if self.normalization_insensitive {
+            name.nfc().collect()
+        } else {
+            name.to_string()
+        };
+        if self.case_insensitive {
+            name.to_lowercase()
+        } else {
+            name
+        }
+    }
+
+    // The most restrictive of the two, for objects and datastreams may be
+    // written to different volumes.
+    fn union(self, other: Self) -> Self {
+        Filesystem {
+            case_insensitive: self.case_insensitive || other.case_insensitive,
+            normalization_insensitive: self.normalization_insensitive
+                || other.normalization_insensitive,
+        }
+    }
+}
+
+// Probes every directory files will be written to, which must exist.
+pub fn probe_destinations(directories: &[&Path]) {
+    let filesystem = directories
+        .iter()
+        .map(|directory| {
+            Filesystem::probe(directory).unwrap_or_else(|error| {
+                panic!(
+                    "Failed to probe the filesystem of {}: {}",
+                    directory.display(),
+                    error
+                )
+            })
+        })
+        .fold(Filesystem::default(), Filesystem::union);
+    if filesystem.case_insensitive {
+        info!("The destination is case-insensitive, PIDs and datastream IDs that only differ by case will be skipped");
+    }
+    if filesystem.normalization_insensitive {
+        info!("The destination is normalization-insensitive, PIDs and datastream IDs that only differ by Unicode normalization will be skipped");
+    }
+    *FILESYSTEM.write().unwrap() = filesystem;
+}
+
+pub fn filesystem() -> Filesystem {
+    *FILESYSTEM.read().unwrap()
+}
+
+// Removes all but the first of the entries whose names are the same file on
+// the destination, reporting them under the given category. Returns the keys
+// of the removed entries.
+pub fn remove_collisions<K, V, F>(map: &mut BTreeMap<K, V>, name: F, category: &str) -> Vec<String>
+where
+    K: fmt::Display + Ord,
+    F: Fn(&K, &V) -> String,
+{
+    let filesystem = filesystem();
+    if !filesystem.is_insensitive() {
+        return vec![];
+    }
+    let mut kept = HashMap::new();
+    let mut removed = Vec::new();
+    let mut collisions = Vec::new();
+    map.retain(
+        |key, value| match kept.entry(filesystem.fold(&name(key, value))) {
+            Entry::Vacant(entry) => {
+                entry.insert(key.to_string());
+                true
+            }
+            Entry::Occupied(entry) => {
+                collisions.push(format!("{} collides with {}", key, entry.get()));
+                removed.push(key.to_string());
+                false
+            }
+        },
+    );
+    if !collisions.is_empty() {
+        for collision in &collisions {
+            logger::summary::warning(category, collision);
+        }
+        warn!(
+            "The following would overwrite each other on the destination filesystem and have been skipped:\n\t{}",
+            collisions.join("\n\t")
+        );
+    }
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fold() {
+        let sensitive = Filesystem::default();
+        assert_eq!(sensitive.fold("demo:ABC"), "demo:ABC");
+        let insensitive = Filesystem {
+            case_insensitive: true,
+            normalization_insensitive: true,
+        };
+        assert_eq!(insensitive.fold("demo:ABC"), insensitive.fold("demo:abc"));
+        assert_eq!(
+            insensitive.fold("demo:caf\u{e9}"),
+            insensitive.fold("demo:cafe\u{301}")
+        );
+        assert_ne!(insensitive.fold("demo:1"), insensitive.fold("demo:2"));
+    }
+
+    #[test]
+    fn collisions() {
+        *FILESYSTEM.write().unwrap() = Filesystem {
+            case_insensitive: true,
+            normalization_insensitive: false,
+        };
+        let mut map = ["demo:1", "demo:ABC", "demo:Abc", "demo:abc"]
+            .iter()
+            .map(|pid| (pid.to_string(), ()))
+            .collect::<BTreeMap<_, _>>();
+        let removed = remove_collisions(&mut map, |pid, _| pid.clone(), "colliding_objects");
+        assert_eq!(removed, vec!["demo:Abc", "demo:abc"]);
+        assert_eq!(map.keys().collect::<Vec<_>>(), vec!["demo:1", "demo:ABC"]);
+        *FILESYSTEM.write().unwrap() = Filesystem::default();
+    }
+
+    #[test]
+    fn probe() {
+        let directory = std::env::temp_dir().join("migrate_filesystem_test");
+        fs::create_dir_all(&directory).unwrap();
+        let filesystem = Filesystem::probe(&directory).unwrap();
+        // The probe leaves nothing behind.
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+        // Linux filesystems used for testing are case-sensitive.
+        if cfg!(target_os = "linux") {
+            assert!(!filesystem.case_insensitive);
+        }
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+// For chain of custody, given `--fixity-digest` the reports written by a run
+// (the migration manifest, the checksum manifests and the summary) are listed
+// with their SHA-256 checksum in fixity-sha256.txt once it completes, in the
+// format output by `sha256sum`. The checksum of that file is logged as the
+// digest of the whole run, and given `--signing-key` it is also signed with an
+// Ed25519 key, so auditors can prove the reports were not modified afterwards.
+use log::info;
+use ring::signature::Ed25519KeyPair;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+static FIXITY_FILE: &str = "fixity-sha256";
+static SUMMARY_FILE: &str = "migrate_summary";
+
+// An Ed25519 private key in PKCS#8 PEM format, as generated by
+// `openssl genpkey -algorithm ed25519`. Read up front so that an unusable key
+// is reported before anything is migrated.
+pub fn read_signing_key(path: &Path) -> Ed25519KeyPair {
+    parse_signing_key(path).unwrap_or_else(|error| {
+        panic!(
+            "Failed to read the signing key {}: {}",
+            path.display(),
+            error
+        )
+    })
+}
+
+fn parse_signing_key(path: &Path) -> Result<Ed25519KeyPair, String> {
+    let content = fs::read(path).map_err(|error| error.to_string())?;
+    let pem = pem::parse(content).map_err(|error| error.to_string())?;
+    if pem.tag() != "PRIVATE KEY" {
+        return Err(format!("Expected a PRIVATE KEY but found a {}", pem.tag()));
+    }
+    Ed25519KeyPair::from_pkcs8_maybe_unchecked(pem.contents())
+        .map_err(|error| format!("Not an Ed25519 key: {}", error))
+}
+
+// Shards share the output directory.
+fn file_name(name: &str, extension: &str) -> String {
+    match foxml::pids::shard() {
+        Some(shard) => format!("{}-{}.{}", name, shard, extension),
+        None => format!("{}.{}", name, extension),
+    }
+}
+
+fn sha256(content: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(content))
+}
+
+// The reports written by this run, relative to the output directory.
+fn reports(output_directory: &Path) -> Vec<String> {
+    let mut reports = fs::read_dir(output_directory)
+        .unwrap_or_else(|error| panic!("Failed to read {}: {}", output_directory.display(), error))
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with("manifest-sha256") && name.ends_with(".txt"))
+        .collect::<Vec<_>>();
+    let manifest = file_name("migration_manifest", "csv");
+    if output_directory.join(&manifest).exists() {
+        reports.push(manifest);
+    }
+    reports.push(file_name(SUMMARY_FILE, "json"));
+    reports.sort();
+    reports
+}
+
+// Should be called once every other report has been written. Returns the path
+// of fixity-sha256.txt.
+pub fn write_fixity_digest(output_directory: &Path, key: Option<&Ed25519KeyPair>) -> PathBuf {
+    let summary = output_directory.join(file_name(SUMMARY_FILE, "json"));
+    logger::summary::write(&summary)
+        .unwrap_or_else(|error| panic!("Failed to write {}: {}", summary.display(), error));
+    let lines = reports(output_directory)
+        .into_iter()
+        .map(|name| {
+            let path = output_directory.join(&name);
+            let content = fs::read(&path)
+                .unwrap_or_else(|error| panic!("Failed to read {}: {}", path.display(), error));
+            format!("{}  {}\n", sha256(&content), name)
+        })
+        .collect::<String>();
+    let fixity = output_directory.join(file_name(FIXITY_FILE, "txt"));
+    fs::write(&fixity, &lines)
+        .unwrap_or_else(|error| panic!("Failed to write {}: {}", fixity.display(), error));
+    info!(
+        "The digest of this run is {}, the SHA-256 checksum of {}",
+        sha256(lines.as_bytes()),
+        fixity.display()
+    );
+    if let Some(key) = key {
+        let mut signature = fixity.clone().into_os_string();
+        signature.push(".sig");
+        let signature = PathBuf::from(signature);
+        fs::write(&signature, key.sign(lines.as_bytes()).as_ref())
+            .unwrap_or_else(|error| panic!("Failed to write {}: {}", signature.display(), error));
+        info!("Signed {} in {}", fixity.display(), signature.display());
+    }
+    fixity
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{KeyPair, UnparsedPublicKey, ED25519};
+
+    #[test]
+    fn fixity_digest() {
+        let directory = std::env::temp_dir().join("migrate_fixity_test");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("manifest-sha256.txt"), "abc").unwrap();
+        fs::write(directory.join("migration_manifest.csv"), "abc").unwrap();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key = directory.join("key.pem");
+        fs::write(
+            &key,
+            pem::encode(&pem::Pem::new("PRIVATE KEY", pkcs8.as_ref())),
+        )
+        .unwrap();
+        let key = read_signing_key(&key);
+        let fixity = write_fixity_digest(&directory, Some(&key));
+        let content = fs::read_to_string(&fixity).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        // The SHA-256 checksum of 'abc'.
+        let abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], format!("{}  manifest-sha256.txt", abc));
+        assert_eq!(
+            lines[1],
+            format!(
+                "{}  migrate_summary.json",
+                sha256(&fs::read(directory.join("migrate_summary.json")).unwrap())
+            )
+        );
+        assert_eq!(lines[2], format!("{}  migration_manifest.csv", abc));
+        // The signature can be verified with the public key.
+        let signature = fs::read(directory.join("fixity-sha256.txt.sig")).unwrap();
+        let public_key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+            .unwrap()
+            .public_key()
+            .as_ref()
+            .to_vec();
+        let public_key = UnparsedPublicKey::new(&ED25519, public_key);
+        assert!(public_key.verify(content.as_bytes(), &signature).is_ok());
+        assert!(public_key.verify(b"modified", &signature).is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,7 +1,14 @@
 // Represents identifiers extracted from Fedora datastreamStore and objectStore folders.
 // @see https://wiki.lyrasis.org/display/FEDORA35/Fedora+Identifiers
-use log::{error, warn};
+use super::filesystem;
+use super::volumes::Volumes;
+use super::MigrateOptions;
+use foxml::pids::PidMap;
+use foxml::{Foxml, FoxmlError};
+use jwalk::{Parallelism, WalkDir};
+use log::{error, info, warn};
 use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use regex::Regex;
 use std::borrow::Cow;
 use std::cmp::Ordering;
@@ -9,21 +16,22 @@ use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
-use std::sync::{atomic, Mutex};
-use walkdir::WalkDir;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
 
 pub type Paths = Vec<Box<Path>>;
 pub type PathMap = HashMap<Box<Path>, Box<Path>>;
 pub type IdentifierPathMap<T> = BTreeMap<T, Box<Path>>;
 pub type ObjectPathMap = BTreeMap<ObjectIdentifier, Box<Path>>;
 pub type DatastreamPathMap = BTreeMap<DatastreamIdentifier, Box<Path>>;
-pub type DatastreamContentMap = BTreeMap<DatastreamIdentifier, String>;
+pub type DatastreamContentMap = BTreeMap<DatastreamIdentifier, Vec<u8>>;
 
 lazy_static! {
     // e.g info%3Afedora%2Farchden%3A13
     static ref OBJECT_FILE_REGEX: Regex = Regex::new(r"info%3Afedora%2F(.*)%3A(.*)").unwrap();
-    // e.g info%3Afedora%2Farchden%3A13%2FTECHMD%2FTECHMD.0
-    static ref DATASTREAM_FILE_REGEX: Regex = Regex::new(r"info%3Afedora%2F(.*)%3A(.*)%2F(.*)%2F(.*)").unwrap();
+    // e.g info%3Afedora%2Farchden%3A13%2FTECHMD%2FTECHMD.0, very old content may
+    // use other version ids e.g. DS1 or omit the version entirely.
+    static ref DATASTREAM_FILE_REGEX: Regex = Regex::new(r"^info%3Afedora%2F(.+?)%3A(.+?)%2F(.+?)(?:%2F(.+))?$").unwrap();
     // Map URL encoded strings that can be used in identifiers to their decoded values.
     static ref ENCODING: HashMap<&'static str, &'static str> = {
         let mut m = HashMap::new();
@@ -32,59 +40,120 @@ lazy_static! {
     };
 }
 
+// The namespace of the PID (after --pid-map) is one of those given to
+// `--namespace`, every namespace is migrated if none were given.
+pub fn in_namespaces(namespaces: &[String], pid: &str) -> bool {
+    namespaces.is_empty()
+        || pid
+            .split_once(':')
+            .is_some_and(|(namespace, _)| namespaces.iter().any(|other| other == namespace))
+}
+
+// The PID of identifiers is rewritten by the PID map.
 pub trait Identifier {
     type Item;
-    fn from_path(path: &Path) -> Option<Self::Item>;
+    fn from_path(path: &Path, pid_map: &PidMap) -> Option<Self::Item>;
+    fn pid(&self) -> &str;
 }
 
-// Find all files recursively in the given folder.
+// Number of files found between updates of the spinner.
+const PROGRESS_INTERVAL: usize = 10_000;
+
+lazy_static! {
+    // Directories are read on a pool of their own, as files are often found
+    // from within tasks running on the global pool, which may be busy. The
+    // walk stalls with a single thread, so at least two are used.
+    static ref WALK_POOL: Arc<ThreadPool> = Arc::new(
+        ThreadPoolBuilder::new()
+            .num_threads(rayon::current_num_threads().max(2))
+            .thread_name(|index| format!("walk-{}", index))
+            .build()
+            .expect("Failed to create thread pool")
+    );
+}
+
+// Find all files recursively in the given folder, sorted by path so the order
+// is the same on every run. Directories are read in parallel and entries are
+// yielded in order as they are read. The excluded folders are not descended
+// into.
 pub fn files(path: &Path, exclude: Vec<&Path>) -> Paths {
     let spinner = logger::spinner();
-    let count = atomic::AtomicUsize::new(0);
-    WalkDir::new(&path)
-        .follow_links(false)
+    let error = |error: &dyn fmt::Display| -> ! {
+        panic!(
+            "Failed to find files in path: {}. Error: {}",
+            &path.to_string_lossy(),
+            error
+        )
+    };
+    // Symbolic links are not followed, so every path found under the
+    // canonical root is also canonical.
+    let root = path.canonicalize().unwrap_or_else(|e| error(&e));
+    let exclude = exclude
         .into_iter()
-        .par_bridge()
-        .filter(|entry| {
-          entry
-              .as_ref()
-              .map_or(false, |e| !exclude.contains(&e.path()))
-        })
-        .filter(|entry| {
-            entry
-                .as_ref()
-                .map_or(false, |e| e.metadata().map_or(false, |m| m.is_file()))
-        })
-        .map(|entry| {
-            count.fetch_add(1, atomic::Ordering::Relaxed);
-            spinner.set_message(&format!("Found: {}", count.load(atomic::Ordering::Relaxed)));
-            Ok(entry?.path().canonicalize()?.into_boxed_path())
-        })
-        .collect::<Result<Vec<_>, std::io::Error>>()
-        .unwrap_or_else(|error| {
-            panic!(
-                "Failed to find files in path: {}. Error: {}",
-                &path.to_string_lossy(),
-                error
-            )
+        .filter_map(|path| path.canonicalize().ok())
+        .collect::<Vec<_>>();
+    let walk = WalkDir::new(&root)
+        .follow_links(false)
+        .skip_hidden(false)
+        .sort(true)
+        .parallelism(Parallelism::RayonExistingPool {
+            pool: WALK_POOL.clone(),
+            busy_timeout: None,
         })
+        .process_read_dir(move |_, _, _, children| {
+            children.retain(|child| {
+                child
+                    .as_ref()
+                    .map_or(true, |child| !exclude.contains(&child.path()))
+            });
+        });
+    let mut files = Vec::new();
+    for entry in walk {
+        let entry = entry.unwrap_or_else(|e| error(&e));
+        if entry.file_type().is_file() {
+            files.push(entry.path().into_boxed_path());
+            if files.len() % PROGRESS_INTERVAL == 0 {
+                spinner.set_message(&format!("Found: {}", files.len()));
+            }
+        }
+    }
+    spinner.finish_and_clear();
+    files
 }
 
-// Returns a tuple consisting of a map of identifiers to paths.
-pub fn identify_files<T>(src: &Path, dest: &Path) -> IdentifierPathMap<T>
+// Returns a tuple consisting of a map of identifiers to paths, leaving out
+// those outside of the given namespaces.
+pub fn identify_files<T>(
+    src: &Path,
+    dest: &Path,
+    namespaces: &[String],
+    pid_map: &PidMap,
+) -> IdentifierPathMap<T>
 where
     T: Identifier<Item = T> + Ord + Sync + Send,
 {
     let map = Mutex::new(BTreeMap::new());
     let failed = Mutex::new(Paths::new());
+    let excluded = AtomicUsize::new(0);
     files(&src, vec![dest])
         .into_par_iter()
-        .for_each(|path| match T::from_path(&path) {
+        .for_each(|path| match T::from_path(&path, pid_map) {
+            Some(identifier) if !in_namespaces(namespaces, identifier.pid()) => {
+                excluded.fetch_add(1, AtomicOrdering::Relaxed);
+            }
             Some(identifier) => {
                 map.lock().unwrap().insert(identifier, path);
             }
             None => failed.lock().unwrap().push(path),
         });
+    let excluded = excluded.into_inner();
+    if excluded > 0 {
+        info!(
+            "Skipping {} files in {} outside of the given namespaces",
+            excluded,
+            src.display()
+        );
+    }
     let unknown_files = failed.into_inner().unwrap();
     if !unknown_files.is_empty() {
         warn!(
@@ -99,43 +168,68 @@ where
     map.into_inner().unwrap()
 }
 
+// Where the given datastream version is migrated to, always laid out by
+// version id as expected by the csv command.
+pub fn datastream_path(
+    volumes: &Volumes,
+    pid: &str,
+    dsid: &str,
+    version: &foxml::FoxmlDatastreamVersion,
+) -> PathBuf {
+    // Some datastreams have an appropriate label like '01-01-1942_web.pdf', but
+    // others are things like 'MODS'. So we do a basic check to see if the version
+    // label appears to be a valid name with an known extension if so we use the label
+    // otherwise we generate one based on the the datastream.
+    let file_name =
+        foxml::extensions::version_file_name(pid, &version.id, &version.label, &version.mime_type);
+    let mut dest = volumes.datastreams_directory(pid);
+    dest.push(pid);
+    dest.push(dsid);
+    dest.push(&version.id);
+    dest.push(file_name);
+    dest
+}
+
+// The metadata of the given object file, with its PID rewritten by the PID map
+// like those of identifiers.
+pub fn object_metadata(path: &Path, pid_map: &PidMap) -> Result<Foxml, FoxmlError> {
+    let mut foxml = Foxml::metadata_from_path(path)?;
+    foxml.pid = pid_map.rewrite(&foxml.pid);
+    Ok(foxml)
+}
+
 pub fn datastreams(
     objects: &Vec<Box<Path>>,
     group: foxml::FoxmlControlGroup,
-    dest: &Path,
+    volumes: &Volumes,
+    options: &MigrateOptions,
 ) -> DatastreamPathMap {
-    objects
+    let mut datastreams = objects
         .par_iter()
         .flat_map(|path| {
-            match foxml::Foxml::from_path(&path) {
+            match object_metadata(&path, &options.pid_map) {
                 Ok(object) => {
                   object
                   .datastreams
                   .par_iter()
                   .filter(|datastream| datastream.control_group == group)
                   .flat_map(|datastream| {
-                      datastream
-                          .versions
+                      options
+                          .migrated_versions(datastream)
                           .par_iter()
                           .map(|version| {
-                              let identifier = DatastreamIdentifier {
-                                  pid: object.pid.clone(),
-                                  dsid: datastream.id.clone(),
-                                  version: version.id.clone(),
-                              };
-                              // Some datastreams have an appropriate label like '01-01-1942_web.pdf', but
-                              // others are things like 'MODS'. So we do a basic check to see if the version
-                              // label appears to be a valid name with an known extension if so we use the label
-                              // otherwise we generate one based on the the datastream.
-                              let file_name = foxml::extensions::version_file_name(
-                                  &object.pid,
-                                  &version.id,
-                                  &version.label,
-                                  &version.mime_type,
-                              );
-                              let mut dest = PathBuf::from(dest);
-                              dest.push(identifier.as_path());
-                              dest.push(file_name);
+                              // Files in the datastreamStore are named after the
+                              // reference, which for very old content may not
+                              // match the version id.
+                              let identifier =
+                                  DatastreamIdentifier::from_version(version, &options.pid_map)
+                                  .unwrap_or_else(|| DatastreamIdentifier {
+                                      pid: object.pid.clone(),
+                                      dsid: datastream.id.clone(),
+                                      version: version.id.clone(),
+                                  });
+                              let dest =
+                                  datastream_path(volumes, &object.pid, &datastream.id, version);
                               (identifier, dest.into_boxed_path())
                           })
                           .collect::<Vec<_>>()
@@ -152,7 +246,13 @@ pub fn datastreams(
                 }
             }
         })
-        .collect::<DatastreamPathMap>()
+        .collect::<DatastreamPathMap>();
+    filesystem::remove_collisions(
+        &mut datastreams,
+        |_, dest| dest.to_string_lossy().to_string(),
+        "colliding_datastreams",
+    );
+    datastreams
 }
 
 fn decode(s: &str) -> Cow<str> {
@@ -169,7 +269,7 @@ pub struct ObjectIdentifier {
 impl Identifier for ObjectIdentifier {
     type Item = ObjectIdentifier;
 
-    fn from_path(path: &Path) -> Option<Self> {
+    fn from_path(path: &Path, pid_map: &PidMap) -> Option<Self> {
         let file_name = path.file_name()?.to_str()?;
         let capture = OBJECT_FILE_REGEX.captures(file_name)?;
         let pid = format!(
@@ -177,8 +277,13 @@ impl Identifier for ObjectIdentifier {
             decode(capture.get(1)?.as_str()),
             decode(capture.get(2)?.as_str())
         );
+        let pid = pid_map.rewrite(&pid);
         Some(Self { pid })
     }
+
+    fn pid(&self) -> &str {
+        &self.pid
+    }
 }
 
 impl Hash for ObjectIdentifier {
@@ -219,19 +324,28 @@ pub struct DatastreamIdentifier {
 }
 
 impl DatastreamIdentifier {
-    fn as_path(&self) -> PathBuf {
-        let mut path = PathBuf::new();
-        path.push(&self.pid);
-        path.push(&self.dsid);
-        path.push(&self.version);
-        path
+    fn from_version(version: &foxml::FoxmlDatastreamVersion, pid_map: &PidMap) -> Option<Self> {
+        version
+            .content_location()
+            .filter(|location| location.r#type == "INTERNAL_ID")
+            .and_then(|location| Self::from_reference(&location.r#ref, pid_map))
+    }
+
+    // Internal references take the form 'pid+DSID+VERSION', where the version
+    // is absent for very old content.
+    fn from_reference(reference: &str, pid_map: &PidMap) -> Option<Self> {
+        let mut parts = reference.splitn(3, '+');
+        let pid = pid_map.rewrite(parts.next()?);
+        let dsid = parts.next()?.to_string();
+        let version = parts.next().unwrap_or_default().to_string();
+        Some(Self { pid, dsid, version })
     }
 }
 
 impl Identifier for DatastreamIdentifier {
     type Item = DatastreamIdentifier;
 
-    fn from_path(path: &Path) -> Option<Self> {
+    fn from_path(path: &Path, pid_map: &PidMap) -> Option<Self> {
         let file_name = path.file_name()?.to_str()?;
         let capture = DATASTREAM_FILE_REGEX.captures(file_name)?;
         let pid = format!(
@@ -239,10 +353,18 @@ impl Identifier for DatastreamIdentifier {
             decode(capture.get(1)?.as_str()),
             decode(capture.get(2)?.as_str())
         );
+        let pid = pid_map.rewrite(&pid);
         let dsid = decode(capture.get(3)?.as_str()).into();
-        let version = decode(capture.get(4)?.as_str()).into();
+        let version = capture
+            .get(4)
+            .map(|version| decode(version.as_str()).into())
+            .unwrap_or_default();
         Some(Self { pid, dsid, version })
     }
+
+    fn pid(&self) -> &str {
+        &self.pid
+    }
 }
 
 impl Hash for DatastreamIdentifier {
@@ -286,3 +408,94 @@ impl PartialEq for DatastreamIdentifier {
         self.pid == other.pid && self.dsid == other.dsid && self.version == other.version
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_datastream_identifiers() {
+        let identify = |name: &str| {
+            DatastreamIdentifier::from_path(Path::new(name), &PidMap::default())
+                .map(|identifier| identifier.to_string())
+        };
+        assert_eq!(
+            identify("info%3Afedora%2Farchden%3A13%2FTECHMD%2FTECHMD.0").as_deref(),
+            Some("archden:13 TECHMD TECHMD.0")
+        );
+        assert_eq!(
+            identify("info%3Afedora%2Fold%3A1%2FDS1%2FDS1").as_deref(),
+            Some("old:1 DS1 DS1")
+        );
+        assert_eq!(
+            identify("info%3Afedora%2Fold%3A1%2FDS1").as_deref(),
+            Some("old:1 DS1 ")
+        );
+        assert_eq!(identify("info%3Afedora%2Fold%3A1"), None);
+        // Matches the file named after the same reference.
+        let reference = |reference: &str| {
+            DatastreamIdentifier::from_reference(reference, &PidMap::default())
+                .map(|identifier| identifier.to_string())
+        };
+        assert_eq!(
+            reference("old:1+DS1+DS1"),
+            identify("info%3Afedora%2Fold%3A1%2FDS1%2FDS1")
+        );
+        assert_eq!(
+            reference("old:1+DS1"),
+            identify("info%3Afedora%2Fold%3A1%2FDS1")
+        );
+    }
+
+    #[test]
+    fn sorted_files() {
+        let directory =
+            std::env::temp_dir().join(format!("identifiers-files-{}", std::process::id()));
+        for path in ["b/2", "b/1", "a", "c/d/3", "excluded/4", ".hidden"] {
+            let path = directory.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, "").unwrap();
+        }
+        let root = directory.canonicalize().unwrap();
+        let found = files(&directory, vec![&directory.join("excluded")])
+            .into_iter()
+            .map(|path| {
+                path.strip_prefix(&root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(found, vec![".hidden", "a", "b/1", "b/2", "c/d/3"]);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn namespaces() {
+        let directory =
+            std::env::temp_dir().join(format!("identifiers-namespaces-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        for name in [
+            "info%3Afedora%2Farchden%3A1",
+            "info%3Afedora%2Farchden%3A2",
+            "info%3Afedora%2Fother%3A1",
+            "info%3Afedora%2Farchdenver%3A1",
+        ] {
+            std::fs::write(directory.join(name), "").unwrap();
+        }
+        let namespaces = vec!["archden".to_string()];
+        let objects: ObjectPathMap = identify_files(
+            &directory,
+            &directory.join("dest"),
+            &namespaces,
+            &PidMap::default(),
+        );
+        let pids = objects
+            .keys()
+            .map(|identifier| identifier.pid.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(pids, vec!["archden:1", "archden:2"]);
+        assert!(in_namespaces(&[], "other:1"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -2,8 +2,14 @@ extern crate quick_xml;
 
 use super::identifiers::*;
 use super::migrate::migrate_inline_content;
+use super::volumes::Volumes;
+use super::MigrateOptions;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use foxml::pids::PidMap;
 use foxml::FoxmlControlGroup;
 use log::info;
+use logger::webhook;
 use quick_xml::events::attributes::Attribute;
 use quick_xml::events::{BytesDecl, BytesStart, Event};
 use quick_xml::{Reader, Writer};
@@ -61,15 +67,15 @@ fn is_inline_datastream(event: &Event) -> bool {
     }
 }
 
-// Extracts the PID from the foxml.
-fn get_pid(reader: &mut Reader<&[u8]>) -> String {
+// Extracts the PID from the foxml, rewritten by the PID map if applicable.
+fn get_pid(reader: &mut Reader<&[u8]>, pid_map: &PidMap) -> String {
     let mut buf = Vec::new();
     loop {
         // Panic if fails to read.
         match reader.read_event(&mut buf).unwrap() {
             ref event @ Event::Start(_) if is_element(event, b"foxml:digitalObject") => {
                 if let Event::Start(ref e) = event {
-                    return get_attribute_value(e, b"PID");
+                    return pid_map.rewrite(&get_attribute_value(e, b"PID"));
                 }
             }
             Event::Eof => break, // If we reach the end of the file something has gone horribly wrong.
@@ -152,11 +158,11 @@ fn extract_inline_datastream_version(reader: &mut Reader<&[u8]>) -> Writer<Curso
 }
 
 // Extracts all the inline datastreams in the given FOXML document.
-fn extract_inline_datastreams(path: &Path) -> DatastreamContentMap {
+fn extract_inline_datastreams(path: &Path, pid_map: &PidMap) -> DatastreamContentMap {
     let foxml = std::fs::read_to_string(&path)
         .unwrap_or_else(|_| panic!("Failed to read file {}", &path.to_string_lossy()));
     let mut reader = Reader::from_str(&foxml);
-    let pid = get_pid(&mut reader);
+    let pid = get_pid(&mut reader, pid_map);
     let mut results = DatastreamContentMap::new();
     while let Some(dsid) = next_inline_datastream(&mut reader) {
         while let Some(version) = next_datastream_version(&mut reader) {
@@ -168,19 +174,75 @@ fn extract_inline_datastreams(path: &Path) -> DatastreamContentMap {
                     dsid: dsid.clone(),
                     version: version.clone(),
                 },
-                String::from_utf8(writer.into_inner().into_inner()).unwrap(),
+                writer.into_inner().into_inner(),
             );
         }
     }
     results
 }
 
+// Extracts all the base64 encoded managed datastreams in the given FOXML
+// document, as found in exports made in the "archive" context.
+pub fn extract_binary_datastreams(path: &Path, pid_map: &PidMap) -> DatastreamContentMap {
+    let foxml = std::fs::read_to_string(path)
+        .unwrap_or_else(|_| panic!("Failed to read file {}", &path.to_string_lossy()));
+    let mut reader = Reader::from_str(&foxml);
+    let pid = get_pid(&mut reader, pid_map);
+    let mut results = DatastreamContentMap::new();
+    let mut buf = Vec::new();
+    let mut dsid = String::new();
+    let mut version = String::new();
+    loop {
+        match reader.read_event(&mut buf).unwrap() {
+            Event::Start(ref e) if e.name() == b"foxml:datastream" => {
+                dsid = get_attribute_value(e, b"ID");
+            }
+            Event::Start(ref e) if e.name() == b"foxml:datastreamVersion" => {
+                version = get_attribute_value(e, b"ID");
+            }
+            Event::Start(ref e) if e.name() == b"foxml:binaryContent" => {
+                let text = reader
+                    .read_text(b"foxml:binaryContent", &mut Vec::new())
+                    .unwrap();
+                // The encoded content is wrapped across multiple lines.
+                let text = text.split_whitespace().collect::<String>();
+                let content = STANDARD.decode(text).unwrap_or_else(|error| {
+                    panic!(
+                        "Failed to decode {}/{} in {}: {}",
+                        dsid,
+                        version,
+                        path.to_string_lossy(),
+                        error
+                    )
+                });
+                results.insert(
+                    DatastreamIdentifier {
+                        pid: pid.clone(),
+                        dsid: dsid.clone(),
+                        version: version.clone(),
+                    },
+                    content,
+                );
+            }
+            Event::Eof => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    results
+}
+
 // Extracts all the inline datastreams to the given destination.
-pub fn migrate_inline_datastreams(objects: &Vec<Box<Path>>, dest: &Path, checksum: bool) {
+pub fn migrate_inline_datastreams(
+    objects: &Vec<Box<Path>>,
+    volumes: &Volumes,
+    options: &MigrateOptions,
+) {
+    webhook::phase_started("inline datastreams");
     info!("Migrating inline datastreams in {} object files.",
       objects.len()
     );
-    let inline_datastreams = datastreams(&objects, FoxmlControlGroup::X, &dest);
+    let inline_datastreams = datastreams(&objects, FoxmlControlGroup::X, volumes, options);
     info!(
         "Found {} inline datastreams in {} object files.",
         inline_datastreams.len(),
@@ -188,10 +250,12 @@ pub fn migrate_inline_datastreams(objects: &Vec<Box<Path>>, dest: &Path, checksu
     );
 
     let results = migrate_inline_content(
+        "inline datastreams",
         &objects,
         &inline_datastreams,
         extract_inline_datastreams,
-        checksum,
+        options,
     );
     info!("Finished migrating inline datastreams: {}", results);
+    webhook::phase_finished("inline datastreams", &results.counts());
 }
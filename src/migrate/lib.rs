@@ -3,23 +3,126 @@
 #[macro_use]
 extern crate lazy_static;
 
+mod checksums;
+mod deleted;
+mod digests;
+mod filesystem;
+mod fixity;
 mod identifiers;
 mod inline;
+mod manifest;
 mod migrate;
+mod modified;
+mod orphans;
+mod pause;
+mod reflink;
+mod resume;
+mod state;
+mod verify;
+mod volumes;
+
+pub use checksums::ManifestScope;
+pub use fixity::read_signing_key;
+pub use migrate::CopyMethod;
+pub use pause::pause_on_signal;
+pub use verify::verify_migration;
 
 use crate::migrate::*;
-use foxml::FoxmlControlGroup;
+use chrono::{DateTime, FixedOffset};
+use foxml::pids::PidMap;
+use foxml::{
+    FoxmlControlGroup, FoxmlDatastream, FoxmlDatastreamState, FoxmlDatastreamVersion, VersionPolicy,
+};
 use identifiers::*;
 use log::*;
+use logger::webhook;
 use rayon::prelude::*;
-use std::collections::HashSet;
-use std::path::Path;
+use ring::signature::Ed25519KeyPair;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use volumes::Volumes;
 
 static OBJECT_STORE: &str = "data/objectStore";
 static DATASTREAM_STORE: &str = "data/datastreamStore";
 static POLICY_STORE: &str = "data/fedora-xacml-policies/repository-policies";
 
-fn migrate_policy_files(src: &Path, dest: &Path, copy: bool, checksum: bool) {
+// Options which control how the data is migrated and verified.
+pub struct MigrateOptions {
+    // Compare the content of files rather than their size & modified time.
+    pub checksum: bool,
+    // Which datastream versions are migrated.
+    pub version_policy: VersionPolicy,
+    // Only migrate objects in these namespaces, all of them if empty.
+    pub namespaces: Vec<String>,
+    // Only migrate objects modified since this date.
+    pub modified_since: Option<DateTime<FixedOffset>>,
+    // Leave behind objects and datastreams in the Deleted state, see
+    // deleted.csv.
+    pub exclude_deleted: bool,
+    // Where to quarantine orphaned datastreams, they are left behind if absent.
+    pub orphans_directory: Option<PathBuf>,
+    // Migration pauses between files while this file exists.
+    pub pause_file: Option<PathBuf>,
+    // Rewrites the PIDs of object files and datastreams, given `--pid-map`.
+    pub pid_map: PidMap,
+    // Skip the files recorded as migrated by a previous run.
+    pub resume: bool,
+    // Write migration_manifest.csv listing every file migrated.
+    pub migration_manifest: bool,
+    // Verify managed datastreams against the digests recorded in Fedora.
+    pub verify_digests: bool,
+    // Write a digest of the reports written by this run.
+    pub fixity_digest: bool,
+    // Signs the fixity digest, if given.
+    pub signing_key: Option<Ed25519KeyPair>,
+}
+
+impl MigrateOptions {
+    // None if the datastream is deleted and `--exclude-deleted` was given.
+    fn migrated_versions<'a>(
+        &self,
+        datastream: &'a FoxmlDatastream,
+    ) -> &'a [FoxmlDatastreamVersion] {
+        if self.exclude_deleted && datastream.state == FoxmlDatastreamState::D {
+            &[]
+        } else {
+            datastream.migrated_versions(self.version_policy)
+        }
+    }
+}
+
+// Objects are limited to the given PIDs, if any, to the shard, to those
+// modified since `--modified-since`, and to those not deleted given
+// `--exclude-deleted`.
+struct Limits {
+    pids: HashSet<String>,
+    // Absent unless `--modified-since` was given.
+    modified: Option<HashSet<String>>,
+    deleted: deleted::Deleted,
+}
+
+impl Limits {
+    fn new(pids: Vec<String>) -> Self {
+        Limits {
+            pids: pids.into_iter().collect(),
+            modified: None,
+            deleted: deleted::Deleted::default(),
+        }
+    }
+
+    fn contains(&self, pid: &str) -> bool {
+        (self.pids.is_empty() || self.pids.contains(pid))
+            && foxml::pids::in_shard(pid)
+            && self
+                .modified
+                .as_ref()
+                .is_none_or(|modified| modified.contains(pid))
+            && !self.deleted.contains(pid)
+    }
+}
+
+fn migrate_policy_files(src: &Path, dest: &Path, method: CopyMethod, options: &MigrateOptions) {
+    webhook::phase_started("policy files");
     info!("Searching Fedora for policy files");
 
     let policy_files = identifiers::files(&src, vec![dest]);
@@ -34,18 +137,73 @@ fn migrate_policy_files(src: &Path, dest: &Path, copy: bool, checksum: bool) {
         })
         .collect::<identifiers::PathMap>();
 
-    let results = migrate_files(&identified_files, copy, checksum);
+    let results = migrate_files("policy files", &identified_files, method, options);
     info!("Finished migrating policy files: {}", results);
+    webhook::phase_finished("policy files", &results.counts());
+}
+
+// Creates the output directory along with its objects and datastreams
+// directories, unless told not to in which case it must already exist.
+pub fn create_output_directory(output_directory: &Path, create: bool) {
+    if !create && !output_directory.is_dir() {
+        panic!(
+            "The directory '{}' does not exist, and --no-create was given",
+            output_directory.display()
+        );
+    }
+    for directory in &["objects", "datastreams"] {
+        let directory = output_directory.join(directory);
+        std::fs::create_dir_all(&directory)
+            .unwrap_or_else(|error| panic!("Failed to create {}: {}", directory.display(), error));
+    }
 }
 
 fn migrate_object_files(
     src: &Path,
     dest: &Path,
-    copy: bool,
-    checksum: bool,
-) -> Vec<Box<Path>> {
+    method: CopyMethod,
+    limits: &mut Limits,
+    options: &MigrateOptions,
+) -> (Vec<Box<Path>>, HashSet<String>) {
+    webhook::phase_started("object files");
     info!("Searching Fedora for object files");
-    let object_files: ObjectPathMap = identify_files(&src, &dest);
+    let mut object_files: ObjectPathMap =
+        identify_files(&src, &dest, &options.namespaces, &options.pid_map);
+    object_files.retain(|identifier, _| limits.contains(&identifier.pid));
+    let colliding = filesystem::remove_collisions(
+        &mut object_files,
+        |identifier, _| identifier.pid.clone(),
+        "colliding_objects",
+    )
+    .into_iter()
+    .collect::<HashSet<_>>();
+
+    let found = object_files
+        .keys()
+        .map(|identifier| identifier.pid.as_str())
+        .collect::<HashSet<_>>();
+    let mut not_found = limits
+        .pids
+        .iter()
+        .map(String::as_str)
+        .filter(|pid| !found.contains(pid) && foxml::pids::in_shard(pid))
+        .collect::<Vec<_>>();
+    if !not_found.is_empty() {
+        not_found.sort_unstable();
+        for pid in &not_found {
+            logger::summary::warning("missing_objects", pid);
+        }
+        warn!(
+            "The following PIDs have no object file in Fedora:\n\t{}",
+            not_found.join("\n\t")
+        );
+    }
+    limits.modified = options
+        .modified_since
+        .map(|since| modified::retain_modified(&mut object_files, since));
+    if options.exclude_deleted {
+        limits.deleted = deleted::remove_deleted(&mut object_files, &options.pid_map);
+    }
 
     // Map source files to destination files.
     let identified_files = object_files
@@ -57,26 +215,64 @@ fn migrate_object_files(
         })
         .collect::<identifiers::PathMap>();
 
-    let results = migrate_files(&identified_files, copy, checksum);
+    let results = migrate_files("object files", &identified_files, method, options);
     info!("Finished migrating object files: {}", results);
+    webhook::phase_finished("object files", &results.counts());
 
     info!("Building list of migrated object files.");
-    files(&dest, vec![])
+    let objects = files(&dest, vec![])
+        .into_iter()
+        .filter(|path| {
+            path.file_stem()
+                .is_some_and(|pid| limits.contains(&pid.to_string_lossy()))
+        })
+        .collect();
+    (objects, colliding)
 }
 
 fn migrate_managed_datastreams(
     objects: &Vec<Box<Path>>,
     src: &Path,
-    dest: &Path,
-    copy: bool,
-    checksum: bool,
+    volumes: &mut Volumes,
+    method: CopyMethod,
+    limits: &Limits,
+    colliding: &HashSet<String>,
+    options: &MigrateOptions,
 ) {
+    webhook::phase_started("managed datastreams");
     info!("Searching Fedora datastream store for files.");
-    let files: DatastreamPathMap = identify_files(&src, &dest);
+    let mut files: DatastreamPathMap = identify_files(
+        &src,
+        &volumes.default_directory(),
+        &options.namespaces,
+        &options.pid_map,
+    );
+    // Otherwise the datastreams of every other object would be reported as
+    // orphaned.
+    // Likewise the datastreams of objects skipped as they collide with another.
+    files.retain(|identifier, _| {
+        limits.contains(&identifier.pid) && !colliding.contains(&identifier.pid)
+    });
+
+    // Objects are assigned to volumes by the size of their managed datastreams.
+    let mut sizes = objects
+        .iter()
+        .filter_map(|path| path.file_stem())
+        .map(|pid| (pid.to_string_lossy().to_string(), 0))
+        .collect::<BTreeMap<_, _>>();
+    for (identifier, path) in &files {
+        if let Some(size) = sizes.get_mut(&identifier.pid) {
+            *size += path.metadata().map_or(0, |metadata| metadata.len());
+        }
+    }
+    volumes.assign(sizes);
+    // Record the assignments before migrating, so they are not lost should the
+    // migration be interrupted.
+    volumes.write_manifest();
 
     // All managed datastreams referenced in object files.
     // May be more/less than files in the datastreamStore folder.
-    let managed_datastreams = datastreams(&objects, FoxmlControlGroup::M, &dest);
+    let managed_datastreams = datastreams(&objects, FoxmlControlGroup::M, volumes, options);
 
     info!(
         "Found {} managed datastreams in Fedora, with {} referenced by object files.",
@@ -88,19 +284,46 @@ fn migrate_managed_datastreams(
     let unreferenced = {
         let src: HashSet<_> = files.keys().collect();
         let dest: HashSet<_> = managed_datastreams.keys().collect();
-        // Source files which a object reference exists.
-        src.difference(&dest).cloned().collect::<Vec<_>>()
+        // Previous versions are left behind rather than orphaned, when only the
+        // latest version of each datastream is migrated.
+        let migrated: HashSet<_> = managed_datastreams
+            .keys()
+            .map(|identifier| (&identifier.pid, &identifier.dsid))
+            .collect();
+        // Likewise deleted datastreams, when they are excluded.
+        let deleted = if options.exclude_deleted {
+            deleted::deleted_datastreams(objects, &options.pid_map)
+        } else {
+            HashSet::new()
+        };
+        let (previous, unreferenced): (Vec<_>, Vec<_>) =
+            src.difference(&dest).cloned().partition(|identifier| {
+                (options.version_policy == VersionPolicy::LatestOnly
+                    && migrated.contains(&(&identifier.pid, &identifier.dsid)))
+                    || deleted.contains(&(identifier.pid.clone(), identifier.dsid.clone()))
+            });
+        if !previous.is_empty() {
+            info!(
+                "Skipping {} previous versions or deleted managed datastreams.",
+                previous.len()
+            );
+        }
+        unreferenced
     };
 
     if !unreferenced.is_empty() {
+        for identifier in &unreferenced {
+            logger::summary::warning("orphaned_datastreams", &identifier.to_string());
+        }
         warn!(
             "The following managed datastreams have been orphaned:\n\t{}",
             unreferenced
-                .into_iter()
+                .iter()
                 .map(|identifier| identifier.to_string())
                 .collect::<Vec<_>>()
                 .join("\n\t")
-        )
+        );
+        orphans::quarantine(&unreferenced, &files, method, options);
     }
 
     // Files to migrate.
@@ -115,54 +338,176 @@ fn migrate_managed_datastreams(
     };
 
     info!("Migrating {} managed datastreams.", files.len());
-    let results = migrate_files(&files, copy, checksum);
+    let results = migrate_files("managed datastreams", &files, method, options);
     info!("Finished migrating managed datastreams: {}", results);
+    webhook::phase_finished("managed datastreams", &results.counts());
+}
+
+// Exports made in the "archive" context have no datastreamStore, instead the
+// content of managed datastreams is embedded in the object files as base64.
+fn migrate_embedded_datastreams(
+    objects: &Vec<Box<Path>>,
+    volumes: &mut Volumes,
+    options: &MigrateOptions,
+) {
+    webhook::phase_started("managed datastreams");
+    // The content is embedded so the size of the object file is a fair estimate.
+    let sizes = objects
+        .iter()
+        .filter_map(|path| {
+            let pid = path.file_stem()?.to_string_lossy().to_string();
+            Some((pid, path.metadata().map_or(0, |metadata| metadata.len())))
+        })
+        .collect::<BTreeMap<_, _>>();
+    volumes.assign(sizes);
+    volumes.write_manifest();
+
+    let managed_datastreams = datastreams(objects, FoxmlControlGroup::M, volumes, options);
+    info!(
+        "Found {} managed datastreams referenced by {} object files.",
+        managed_datastreams.len(),
+        objects.len()
+    );
+
+    let results = migrate_inline_content(
+        "managed datastreams",
+        objects,
+        &managed_datastreams,
+        inline::extract_binary_datastreams,
+        options,
+    );
+    info!(
+        "Finished migrating embedded managed datastreams: {}",
+        results
+    );
+    webhook::phase_finished("managed datastreams", &results.counts());
 }
 
 pub fn migrate_data_from_fedora(
     fedora_directory: &Path,
     output_directory: &Path,
-    copy: bool,
-    checksum: bool,
+    method: CopyMethod,
+    spread_across: Vec<&Path>,
+    datastream_store: bool,
+    limit_to_pids: Vec<String>,
+    options: &MigrateOptions,
 ) {
+    if datastream_store {
+        valid_datastream_store(fedora_directory).unwrap_or_else(|error| {
+            panic!(
+                "{}, use --no-datastream-store if the object files were exported in the 'archive' context",
+                error
+            )
+        });
+    }
+
     info!(
         "Migrating Fedora data from {} to {}.",
         &fedora_directory.to_string_lossy(),
         &output_directory.to_string_lossy()
     );
 
-    migrate_policy_files(
-        &fedora_directory.join(POLICY_STORE),
-        &output_directory.join("policies"),
-        copy,
-        checksum,
-    );
+    if options.resume {
+        state::load(output_directory);
+    }
+
+    // Re-migrating a few objects should leave the rest of the output as is,
+    // including the policies which apply to the whole repository.
+    let mut limits = Limits::new(limit_to_pids);
+    // Only the first shard migrates the policies.
+    let shard = foxml::pids::shard();
+    if limits.pids.is_empty() && shard.is_none_or(|shard| shard.index == 1) {
+        migrate_policy_files(
+            &fedora_directory.join(POLICY_STORE),
+            &output_directory.join("policies"),
+            method,
+            options,
+        );
+    } else if let Some(shard) = shard.filter(|_| limits.pids.is_empty()) {
+        info!("Migrating {}, skipping policy files", shard);
+    } else {
+        info!(
+            "Limiting to {} PIDs, skipping policy files",
+            limits.pids.len()
+        );
+    }
+
+    let mut destinations = vec![output_directory];
+    destinations.extend(&spread_across);
+    filesystem::probe_destinations(&destinations);
 
-    let objects = migrate_object_files(
+    let (objects, colliding) = migrate_object_files(
         &fedora_directory.join(OBJECT_STORE),
         &output_directory.join("objects"),
-        copy,
-        checksum,
+        method,
+        &mut limits,
+        options,
     );
 
-    let datastreams_directory = output_directory.join("datastreams");
-    migrate_managed_datastreams(
-        &objects,
-        &fedora_directory.join(DATASTREAM_STORE),
-        &datastreams_directory,
-        copy,
-        checksum,
-    );
-    inline::migrate_inline_datastreams(&objects, &datastreams_directory, checksum);
+    if options.exclude_deleted {
+        limits.deleted.write_report(output_directory);
+    }
+
+    let mut volumes = Volumes::new(output_directory, spread_across);
+    if datastream_store {
+        migrate_managed_datastreams(
+            &objects,
+            &fedora_directory.join(DATASTREAM_STORE),
+            &mut volumes,
+            method,
+            &limits,
+            &colliding,
+            options,
+        );
+    } else {
+        migrate_embedded_datastreams(&objects, &mut volumes, options);
+    }
+    inline::migrate_inline_datastreams(&objects, &volumes, options);
+
+    if options.verify_digests {
+        webhook::phase_started("digests");
+        let results = digests::verify_digests(&objects, &volumes, options);
+        info!("Finished verifying digests: {}", results);
+        webhook::phase_finished("digests", &results.counts());
+    }
+
+    if options.migration_manifest {
+        manifest::write_migration_manifest(output_directory);
+    }
 
     info!("Enumerating all migrated datastreams.");
     info!(
         "In total {} objects, and {} datastreams have been migrated",
         objects.len(),
-        identifiers::files(&datastreams_directory, vec![]).len()
+        volumes
+            .datastreams_directories()
+            .iter()
+            .map(|directory| identifiers::files(directory, vec![]).len())
+            .sum::<usize>()
     );
 }
 
+// Writes sha256sum compatible manifests of every migrated datastream, should
+// be called once all datastreams have been written.
+pub fn write_checksum_manifests(
+    output_directory: &Path,
+    spread_across: Vec<&Path>,
+    scope: ManifestScope,
+) {
+    webhook::phase_started("checksums");
+    let volumes = Volumes::new(output_directory, spread_across);
+    let (files, bytes) = checksums::write_checksum_manifests(output_directory, &volumes, scope);
+    webhook::phase_finished("checksums", &[("files", files), ("bytes", bytes as usize)]);
+}
+
+// Digests the reports written by the run given `--fixity-digest`, should be
+// called once all of them have been written.
+pub fn write_fixity_digest(output_directory: &Path, options: &MigrateOptions) {
+    if options.fixity_digest {
+        fixity::write_fixity_digest(output_directory, options.signing_key.as_ref());
+    }
+}
+
 pub fn valid_fedora_directory(path: &Path) -> Result<(), String> {
     fn valid_directory(path: &Path) -> Result<(), String> {
         if path.is_dir() {
@@ -173,6 +518,15 @@ pub fn valid_fedora_directory(path: &Path) -> Result<(), String> {
     }
     valid_directory(&path)?;
     valid_directory(&path.join(OBJECT_STORE))?;
-    valid_directory(&path.join(DATASTREAM_STORE))?;
     Ok(())
 }
+
+// Not present in exports made in the "archive" context.
+pub fn valid_datastream_store(path: &Path) -> Result<(), String> {
+    let path = path.join(DATASTREAM_STORE);
+    if path.is_dir() {
+        Ok(())
+    } else {
+        Err(format!("The directory '{}' does not exist", path.display()))
+    }
+}
@@ -0,0 +1,146 @@
+// Every file written or checked by a run, along with its size and status, so
+// that storage teams can audit what was copied and reconcile it against their
+// backups without walking the output again.
+use chrono::{DateTime, SecondsFormat, Utc};
+use crc32fast::Hasher;
+use log::info;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::sync::Mutex;
+
+static MANIFEST_FILE: &str = "migration_manifest";
+
+struct ManifestRow {
+    phase: &'static str,
+    source: String,
+    destination: String,
+    size: u64,
+    // Only when given `--checksum`, as otherwise files are never hashed.
+    crc32: Option<u32>,
+    status: &'static str,
+    migrated: DateTime<Utc>,
+    modified: Option<DateTime<Utc>>,
+}
+
+lazy_static! {
+    static ref ROWS: Mutex<Vec<ManifestRow>> = Mutex::new(Vec::new());
+}
+
+// Read in chunks, as datastreams can be larger than memory.
+pub fn crc32(path: &Path) -> Option<u32> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let mut hasher = Hasher::new();
+    loop {
+        let buffer = reader.fill_buf().ok()?;
+        if buffer.is_empty() {
+            break;
+        }
+        hasher.update(buffer);
+        let length = buffer.len();
+        reader.consume(length);
+    }
+    Some(hasher.finalize())
+}
+
+// Records the outcome of migrating the given source, inline datastreams give
+// the object file they were extracted from. The CRC32 of the destination is
+// only given with `--checksum`.
+pub fn record(
+    phase: &'static str,
+    source: &Path,
+    dest: &Path,
+    status: &'static str,
+    crc32: Option<u32>,
+) {
+    let metadata = dest.metadata().ok();
+    let row = ManifestRow {
+        phase,
+        source: source.to_string_lossy().to_string(),
+        destination: dest.to_string_lossy().to_string(),
+        size: metadata.as_ref().map_or(0, |metadata| metadata.len()),
+        crc32,
+        status,
+        migrated: Utc::now(),
+        modified: metadata
+            .and_then(|metadata| metadata.modified().ok())
+            .map(DateTime::<Utc>::from),
+    };
+    ROWS.lock().unwrap().push(row);
+}
+
+// Sorted by phase and destination, so that runs can be compared.
+pub fn write_migration_manifest(output_directory: &Path) {
+    let mut rows = std::mem::take(&mut *ROWS.lock().unwrap());
+    rows.sort_by(|a, b| {
+        a.phase
+            .cmp(b.phase)
+            .then_with(|| a.destination.cmp(&b.destination))
+    });
+    // Shards share the output directory.
+    let manifest = match foxml::pids::shard() {
+        Some(shard) => output_directory.join(format!("{}-{}.csv", MANIFEST_FILE, shard)),
+        None => output_directory.join(format!("{}.csv", MANIFEST_FILE)),
+    };
+    let date = |date: &DateTime<Utc>| date.to_rfc3339_opts(SecondsFormat::Millis, true);
+    let write = || -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_path(&manifest)?;
+        writer.write_record([
+            "phase",
+            "source",
+            "destination",
+            "size",
+            "crc32",
+            "status",
+            "migrated",
+            "modified",
+        ])?;
+        for row in &rows {
+            writer.write_record([
+                row.phase,
+                &row.source,
+                &row.destination,
+                &row.size.to_string(),
+                &row.crc32
+                    .map_or_else(String::new, |crc32| format!("{:08x}", crc32)),
+                row.status,
+                &date(&row.migrated),
+                &row.modified.as_ref().map_or_else(String::new, date),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    };
+    write().unwrap_or_else(|error| panic!("Failed to write {}: {}", manifest.display(), error));
+    info!("Listed {} files in {}", rows.len(), manifest.display());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn write_manifest() {
+        let directory = std::env::temp_dir().join("migrate_manifest_test");
+        fs::create_dir_all(&directory).unwrap();
+        let (source, dest) = (directory.join("source.xml"), directory.join("dest.xml"));
+        fs::write(&source, "abc").unwrap();
+        fs::write(&dest, "abc").unwrap();
+        record("object files", &source, &dest, "migrated", crc32(&dest));
+        write_migration_manifest(&directory);
+        let content = fs::read_to_string(directory.join("migration_manifest.csv")).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[0],
+            "phase,source,destination,size,crc32,status,migrated,modified"
+        );
+        // The CRC32 of 'abc'.
+        assert!(lines[1].starts_with(&format!(
+            "object files,{},{},3,352441c2,migrated,",
+            source.display(),
+            dest.display()
+        )));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
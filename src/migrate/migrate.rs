@@ -1,13 +1,38 @@
 use super::identifiers::*;
-use crc32fast::Hasher;
-use log::info;
+use super::manifest;
+use super::pause;
+use super::reflink;
+use super::resume;
+use super::state;
+use super::MigrateOptions;
+use foxml::pids::PidMap;
+use log::{info, warn};
 use rayon::prelude::*;
 use std::fmt;
 use std::fs;
+use std::io;
 use std::io::prelude::*;
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use MigrationResult::*;
 
+// Files copied as they could not be linked, e.g. as the destination is on
+// another filesystem.
+static UNLINKED: AtomicUsize = AtomicUsize::new(0);
+
+// Files copied as they could not be cloned, e.g. as the filesystem does not
+// support it.
+static UNCLONED: AtomicUsize = AtomicUsize::new(0);
+
+// How files are migrated, given by `--move`, `--hardlink` or `--reflink`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CopyMethod {
+    Copy,
+    Move,
+    Hardlink,
+    Reflink,
+}
+
 #[derive(Eq, PartialEq)]
 enum MigrationResult {
     Migrated,
@@ -15,21 +40,36 @@ enum MigrationResult {
     Skipped,
 }
 
+impl MigrationResult {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Migrated => "migrated",
+            Updated => "updated",
+            Skipped => "skipped",
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct MigrationResults {
     total: usize,
     migrated: usize,
     updated: usize,
     skipped: usize,
+    // Written, i.e. excluding skipped files.
+    bytes: u64,
 }
 
 impl MigrationResults {
-    fn new(results: &[MigrationResult]) -> Self {
+    fn new(results: &[(MigrationResult, u64)]) -> Self {
         let mut summary = MigrationResults {
             total: results.len(),
             ..Default::default()
         };
-        for result in results {
+        for (result, bytes) in results {
+            if *result != Skipped {
+                summary.bytes += bytes;
+            }
             match result {
                 Migrated => summary.migrated += 1,
                 Updated => summary.updated += 1,
@@ -38,6 +78,17 @@ impl MigrationResults {
         }
         summary
     }
+
+    // Reported to the webhook, if any.
+    pub fn counts(&self) -> Vec<(&'static str, usize)> {
+        vec![
+            ("total", self.total),
+            ("migrated", self.migrated),
+            ("updated", self.updated),
+            ("skipped", self.skipped),
+            ("bytes", self.bytes as usize),
+        ]
+    }
 }
 
 impl fmt::Display for MigrationResults {
@@ -50,21 +101,15 @@ impl fmt::Display for MigrationResults {
     }
 }
 
+fn crc32(path: &Path) -> u32 {
+    manifest::crc32(path).unwrap_or_else(|| panic!("Failed to read {}", path.display()))
+}
+
 // Checks if the destination does not exist or if the file sizes differ.
 fn should_migrate_file(path: &Path, dest: &Path, checksum: bool) -> bool {
     !dest.exists()
         || if checksum {
-            let src = {
-                let mut hasher = Hasher::new();
-                hasher.update(&fs::read(&path).unwrap());
-                hasher.finalize()
-            };
-            let dest = {
-                let mut hasher = Hasher::new();
-                hasher.update(&fs::read(&dest).unwrap());
-                hasher.finalize()
-            };
-            src != dest
+            crc32(path) != crc32(dest)
         } else {
             // Check size and modified times.
             let path_metadata = path.metadata().unwrap();
@@ -87,20 +132,10 @@ fn create_parent_directories(dest: &Path) {
 }
 
 // Checks if the destination does not exist or if the file sizes differ.
-fn should_migrate_content(content: &str, dest: &Path, checksum: bool) -> bool {
+fn should_migrate_content(content: &[u8], dest: &Path, checksum: bool) -> bool {
     !dest.exists() || {
         if checksum {
-            let src = {
-                let mut hasher = Hasher::new();
-                hasher.update(&content.as_bytes());
-                hasher.finalize()
-            };
-            let dest = {
-                let mut hasher = Hasher::new();
-                hasher.update(&fs::read(&dest).unwrap());
-                hasher.finalize()
-            };
-            src != dest
+            crc32fast::hash(content) != crc32(dest)
         } else {
             // Check size, no modified time can be used.
             (content.len() as u64) != dest.metadata().unwrap().len()
@@ -108,13 +143,48 @@ fn should_migrate_content(content: &str, dest: &Path, checksum: bool) -> bool {
     }
 }
 
+// Links rather than copies the file, falling back to copying if it can not be
+// linked. An existing destination is removed first, so that it is never written
+// through a link to a previous source.
+fn link(path: &Path, dest: &Path) -> io::Result<()> {
+    if dest.exists() {
+        fs::remove_file(dest)?;
+    }
+    fs::hard_link(path, dest).or_else(|_| {
+        UNLINKED.fetch_add(1, Ordering::Relaxed);
+        resume::copy(path, dest)
+    })
+}
+
+// Clones rather than copies the file, falling back to copying if it can not be
+// cloned. Unlike `link()` the destination is independent of the source.
+fn reflink(path: &Path, dest: &Path) -> io::Result<()> {
+    if dest.exists() {
+        fs::remove_file(dest)?;
+    }
+    reflink::clone_file(path, dest).or_else(|_| {
+        UNCLONED.fetch_add(1, Ordering::Relaxed);
+        resume::copy(path, dest)
+    })
+}
+
 // No-op if already exists or not the same size.
 // Returns true/false if the file was copied or not.
-fn migrate_by_copy(path: &Path, dest: &Path, checksum: bool) -> MigrationResult {
+fn migrate_by_copy(
+    path: &Path,
+    dest: &Path,
+    checksum: bool,
+    method: CopyMethod,
+) -> MigrationResult {
     let existed = dest.exists();
     if should_migrate_file(&path, &dest, checksum) {
         create_parent_directories(&dest);
-        fs::copy(&path, &dest).unwrap_or_else(|error| {
+        let copied = match method {
+            CopyMethod::Hardlink => link(path, dest),
+            CopyMethod::Reflink => reflink(path, dest),
+            CopyMethod::Copy | CopyMethod::Move => resume::copy(path, dest),
+        };
+        copied.unwrap_or_else(|error| {
             panic!(
                 "Failed to copy file {} to {}, with error: {}",
                 &path.to_string_lossy(),
@@ -140,7 +210,7 @@ fn migrate_by_move(path: &Path, dest: &Path, checksum: bool) -> MigrationResult
         fs::rename(&path, &dest).unwrap_or_else(|_| {
             // If from and to are on a separate filesystem rename cannot be used
             // so fall back to copying.
-            fs::copy(&path, &dest).unwrap_or_else(|error| {
+            resume::copy(path, dest).unwrap_or_else(|error| {
               panic!(
                   "Failed to move/copy file {} to {}, with error: {}",
                   &path.to_string_lossy(),
@@ -154,60 +224,199 @@ fn migrate_by_move(path: &Path, dest: &Path, checksum: bool) -> MigrationResult
     Skipped
 }
 
-fn migrate_content(content: &str, dest: &Path, checksum: bool) -> MigrationResult {
+fn migrate_content(content: &[u8], dest: &Path, checksum: bool) -> MigrationResult {
     let existed = dest.exists();
     if should_migrate_content(&content, &dest, checksum) {
         create_parent_directories(&dest);
         let mut file = fs::File::create(&dest).unwrap();
-        file.write_all(&content.as_bytes())
+        file.write_all(content)
             .unwrap_or_else(|_| panic!("Failed to write to file {}", &dest.to_string_lossy()));
         return if existed { Updated } else { Migrated };
     }
     Skipped
 }
 
-// Migrates the given files, by either copying or moving.
-pub fn migrate_files(files: &PathMap, copy: bool, checksum: bool) -> MigrationResults {
-    // Move branch out of loop.
-    let action = if copy {
-        migrate_by_copy
-    } else {
-        migrate_by_move
+// Migrates the given files by the given method. The phase is recorded in the
+// migration manifest, if any.
+pub fn migrate_files(
+    phase: &'static str,
+    files: &PathMap,
+    method: CopyMethod,
+    options: &MigrateOptions,
+) -> MigrationResults {
+    let checksum = options.checksum;
+    let action = |path: &Path, dest: &Path| match method {
+        CopyMethod::Move => migrate_by_move(path, dest, checksum),
+        _ => migrate_by_copy(path, dest, checksum, method),
     };
     info!("Migrating {} files.", files.len());
     let progress_bar = logger::progress_bar(files.len() as u64);
-    let results: Vec<_> = files
-        .par_iter()
-        .map(|(src, dest)| {
-            progress_bar.inc(1);
-            action(&src, &dest, checksum)
-        })
-        .collect();
+    let files = files.iter().collect::<Vec<_>>();
+    let mut results = Vec::with_capacity(files.len());
+    for batch in files.chunks(state::BATCH_SIZE) {
+        let batch_results: Vec<_> = batch
+            .par_iter()
+            .map(|(src, dest)| {
+                pause::wait(options.pause_file.as_deref());
+                progress_bar.inc(1);
+                let _processing = logger::state::processing(&src.to_string_lossy());
+                // Completed by a previous run.
+                let resumed = options.resume && state::is_completed(src, dest);
+                let result = if resumed { Skipped } else { action(src, dest) };
+                // Read once for both the manifest and the state file.
+                let recorded = options.migration_manifest || options.resume;
+                let crc32 = if checksum && recorded {
+                    manifest::crc32(dest)
+                } else {
+                    None
+                };
+                if options.migration_manifest {
+                    manifest::record(phase, src, dest, result.as_str(), crc32);
+                }
+                let bytes = if result == Skipped {
+                    0
+                } else {
+                    dest.metadata().map(|metadata| metadata.len()).unwrap_or(0)
+                };
+                (result, bytes, resumed, crc32)
+            })
+            .collect();
+        if options.resume {
+            let completed = batch
+                .iter()
+                .zip(&batch_results)
+                .filter(|(_, (_, _, resumed, _))| !resumed)
+                .map(|((src, dest), (_, _, _, crc32))| (src.as_ref(), dest.as_ref(), *crc32));
+            state::complete(completed);
+        }
+        results.extend(
+            batch_results
+                .into_iter()
+                .map(|(result, bytes, _, _)| (result, bytes)),
+        );
+    }
+    let unlinked = UNLINKED.swap(0, Ordering::Relaxed);
+    if unlinked > 0 {
+        warn!(
+            "Failed to link {} {}, they were copied instead",
+            unlinked, phase
+        );
+    }
+    let uncloned = UNCLONED.swap(0, Ordering::Relaxed);
+    if uncloned > 0 {
+        warn!(
+            "Failed to clone {} {}, they were copied instead",
+            uncloned, phase
+        );
+    }
     MigrationResults::new(&results)
 }
 
 pub fn migrate_inline_content<F>(
-    objects: &Vec<Box<Path>>,
+    phase: &'static str,
+    objects: &[Box<Path>],
     dest: &DatastreamPathMap,
     extract: F,
-    checksum: bool,
+    options: &MigrateOptions,
 ) -> MigrationResults
 where
-    F: Fn(&Path) -> DatastreamContentMap + Sync + Send,
+    F: Fn(&Path, &PidMap) -> DatastreamContentMap + Sync + Send,
 {
+    let checksum = options.checksum;
     let progress_bar = logger::progress_bar(dest.len() as u64);
-    let results = objects
-        .par_iter()
-        .flat_map(|path| {
-            let datastreams = extract(&path);
-            datastreams
+    let mut results = Vec::new();
+    for batch in objects.chunks(state::BATCH_SIZE) {
+        let batch_results = batch
+            .par_iter()
+            .flat_map(|path| {
+                pause::wait(options.pause_file.as_deref());
+                let _processing = logger::state::processing(&path.to_string_lossy());
+                let datastreams = extract(&path, &options.pid_map);
+                datastreams
+                    .iter()
+                    // Previous versions are not migrated given `--version-policy latest-only`.
+                    .filter_map(|(id, content)| Some((dest.get(id)?, content)))
+                    .map(|(dest, content)| {
+                        progress_bar.inc(1);
+                        // Completed by a previous run.
+                        let resumed = options.resume && state::is_completed(path, dest);
+                        let result = if resumed {
+                            Skipped
+                        } else {
+                            migrate_content(content, dest, checksum)
+                        };
+                        // The destination holds the content once migrated.
+                        let crc32 = if checksum {
+                            Some(crc32fast::hash(content))
+                        } else {
+                            None
+                        };
+                        if options.migration_manifest {
+                            manifest::record(phase, path, dest, result.as_str(), crc32);
+                        }
+                        (path, dest, result, content.len() as u64, resumed, crc32)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        if options.resume {
+            let completed = batch_results
                 .iter()
-                .map(|(id, content)| {
-                    progress_bar.inc(1);
-                    migrate_content(content, &dest[id], checksum)
-                })
-                .collect::<Vec<_>>()
-        })
-        .collect::<Vec<_>>();
+                .filter(|(_, _, _, _, resumed, _)| !resumed)
+                .map(|(path, dest, _, _, _, crc32)| (path.as_ref(), dest.as_ref(), *crc32));
+            state::complete(completed);
+        }
+        results.extend(
+            batch_results
+                .into_iter()
+                .map(|(_, _, result, bytes, _, _)| (result, bytes)),
+        );
+    }
     MigrationResults::new(&results)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hardlink() {
+        let directory = std::env::temp_dir().join("migrate_hardlink_test");
+        fs::create_dir_all(&directory).unwrap();
+        let (path, dest) = (directory.join("source"), directory.join("dest/file"));
+        fs::write(&path, "abc").unwrap();
+        let method = CopyMethod::Hardlink;
+        assert!(migrate_by_copy(&path, &dest, false, method) == Migrated);
+        assert!(migrate_by_copy(&path, &dest, false, method) == Skipped);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert_eq!(path.metadata().unwrap().ino(), dest.metadata().unwrap().ino());
+        }
+        // A replaced source is linked again, leaving the previous one as is.
+        let previous = directory.join("previous");
+        fs::rename(&path, &previous).unwrap();
+        fs::write(&path, "abcd").unwrap();
+        assert!(migrate_by_copy(&path, &dest, false, method) == Updated);
+        assert_eq!(fs::read(&dest).unwrap(), b"abcd");
+        assert_eq!(fs::read(&previous).unwrap(), b"abc");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn reflink() {
+        let directory = std::env::temp_dir().join("migrate_reflink_test");
+        fs::create_dir_all(&directory).unwrap();
+        let (path, dest) = (directory.join("source"), directory.join("dest/file"));
+        fs::write(&path, "abc").unwrap();
+        // Copied instead where the filesystem does not support cloning.
+        let method = CopyMethod::Reflink;
+        assert!(migrate_by_copy(&path, &dest, false, method) == Migrated);
+        assert!(migrate_by_copy(&path, &dest, false, method) == Skipped);
+        assert_eq!(fs::read(&dest).unwrap(), b"abc");
+        // Unlike a link, modifying the destination leaves the source as is.
+        fs::write(&dest, "abcd").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"abc");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+// Given `--modified-since`, only the objects whose lastModifiedDate is after
+// the given date are migrated along with their datastreams, e.g. to sync the
+// changes made during a cutover window after a full migration. Fedora updates
+// the lastModifiedDate of an object whenever one of its datastreams changes.
+use super::identifiers::ObjectPathMap;
+use chrono::{DateTime, FixedOffset};
+use log::{info, warn};
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+// Removes the object files which have not been modified since the given date,
+// returning the PIDs of those that remain. Those whose lastModifiedDate can not
+// be read are kept, so they are not silently left behind.
+pub fn retain_modified(
+    object_files: &mut ObjectPathMap,
+    since: DateTime<FixedOffset>,
+) -> HashSet<String> {
+    let total = object_files.len();
+    let unreadable = Mutex::new(Vec::new());
+    let modified = object_files
+        .par_iter()
+        .filter(|(identifier, path)| {
+            let modified = foxml::Foxml::metadata_from_path(path)
+                .and_then(|foxml| foxml.properties.modified_date());
+            match modified {
+                Ok(modified) => modified > since,
+                Err(error) => {
+                    unreadable
+                        .lock()
+                        .unwrap()
+                        .push(format!("{}: {}", identifier.pid, error));
+                    true
+                }
+            }
+        })
+        .map(|(identifier, _)| identifier.pid.clone())
+        .collect::<HashSet<_>>();
+    let mut unreadable = unreadable.into_inner().unwrap();
+    if !unreadable.is_empty() {
+        unreadable.sort_unstable();
+        warn!(
+            "Failed to read the lastModifiedDate of the following objects, they are migrated regardless:\n\t{}",
+            unreadable.join("\n\t")
+        );
+    }
+    object_files.retain(|identifier, _| modified.contains(&identifier.pid));
+    info!(
+        "Migrating {} of {} objects modified since {}",
+        object_files.len(),
+        total,
+        since.to_rfc3339()
+    );
+    modified
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::identifiers::ObjectIdentifier;
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn modified_since() {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../assets/fixtures");
+        let object = |pid: &str, name: &str| {
+            (
+                ObjectIdentifier {
+                    pid: pid.to_string(),
+                },
+                fixture.join(name).into_boxed_path(),
+            )
+        };
+        let object_files = || {
+            vec![
+                object("demo:1", "valid.foxml.xml"),
+                object("demo:2", "invalid.foxml.xml"),
+            ]
+            .into_iter()
+            .collect::<ObjectPathMap>()
+        };
+        let date = |date: &str| DateTime::parse_from_rfc3339(date).unwrap();
+        let modified = foxml::Foxml::from_path(&fixture.join("valid.foxml.xml"))
+            .unwrap()
+            .properties
+            .modified_date()
+            .unwrap();
+        let mut files = object_files();
+        let pids = retain_modified(&mut files, modified);
+        // Unreadable objects are kept.
+        assert_eq!(
+            files
+                .keys()
+                .map(|identifier| identifier.pid.as_str())
+                .collect::<Vec<_>>(),
+            vec!["demo:2"]
+        );
+        assert!(!pids.contains("demo:1"));
+        let mut files = object_files();
+        let pids = retain_modified(&mut files, date("2000-01-01T00:00:00Z"));
+        assert_eq!(files.len(), 2);
+        assert!(pids.contains("demo:1"));
+    }
+}
@@ -0,0 +1,67 @@
+// Files in the datastreamStore which no object file references are usually
+// left behind by failed ingests or purges, but can be the only copy of content
+// whose object file was lost. Given `--orphans` they are migrated (copied or
+// moved like any other file) into a quarantine directory organized by PID and
+// DSID for review, rather than only being reported.
+use super::identifiers::{DatastreamIdentifier, DatastreamPathMap, PathMap};
+use super::migrate::{migrate_files, CopyMethod};
+use super::MigrateOptions;
+use log::info;
+use std::path::{Path, PathBuf};
+
+// The source file name is kept, as it identifies the version (if any) and can
+// be traced back to the datastreamStore.
+fn quarantine_path(directory: &Path, identifier: &DatastreamIdentifier, src: &Path) -> PathBuf {
+    directory
+        .join(&identifier.pid)
+        .join(&identifier.dsid)
+        .join(src.file_name().unwrap())
+}
+
+// Migrates the given orphaned datastreams to the quarantine directory, if any.
+pub fn quarantine(
+    orphans: &[&DatastreamIdentifier],
+    files: &DatastreamPathMap,
+    method: CopyMethod,
+    options: &MigrateOptions,
+) {
+    let directory = match &options.orphans_directory {
+        Some(directory) => directory,
+        None => return,
+    };
+    let files = orphans
+        .iter()
+        .map(|&identifier| {
+            let src = files[identifier].clone();
+            let dest = quarantine_path(directory, identifier, &src);
+            (src, dest.into_boxed_path())
+        })
+        .collect::<PathMap>();
+    let results = migrate_files("orphaned datastreams", &files, method, options);
+    info!(
+        "Finished quarantining orphaned datastreams in {}: {}",
+        directory.display(),
+        results
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarantine_paths() {
+        let identifier = DatastreamIdentifier {
+            pid: "archden:999".to_string(),
+            dsid: "OBJ".to_string(),
+            version: "OBJ.0".to_string(),
+        };
+        let src = Path::new(
+            "/fedora/data/datastreamStore/ab/info%3Afedora%2Farchden%3A999%2FOBJ%2FOBJ.0",
+        );
+        assert_eq!(
+            quarantine_path(Path::new("/orphans"), &identifier, src),
+            Path::new("/orphans/archden:999/OBJ/info%3Afedora%2Farchden%3A999%2FOBJ%2FOBJ.0")
+        );
+    }
+}
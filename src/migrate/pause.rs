@@ -0,0 +1,103 @@
+// Migrations sharing production storage with Fedora can be paused during peak
+// hours without killing the process and losing progress. While the
+// `--pause-file` exists, or from one SIGUSR1 until the next, no new files are
+// started, those in flight are finished first, so a cron job can pause and
+// resume the run by touching and removing the file.
+use log::info;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// How often to check whether to resume.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+static PAUSED_BY_SIGNAL: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    // Held by the thread waiting for the run to resume, which the others wait on.
+    static ref WAITING: Mutex<()> = Mutex::new(());
+}
+
+// Toggles pausing on SIGUSR1.
+#[cfg(unix)]
+pub fn pause_on_signal() {
+    extern "C" fn toggle(_: libc::c_int) {
+        PAUSED_BY_SIGNAL.fetch_xor(true, Ordering::SeqCst);
+    }
+    let handler = toggle as extern "C" fn(libc::c_int);
+    unsafe {
+        libc::signal(libc::SIGUSR1, handler as libc::sighandler_t);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn pause_on_signal() {}
+
+fn is_paused(pause_file: Option<&Path>) -> bool {
+    PAUSED_BY_SIGNAL.load(Ordering::SeqCst) || pause_file.is_some_and(Path::exists)
+}
+
+// Blocks while paused, should be called before starting each file.
+pub fn wait(pause_file: Option<&Path>) {
+    if !is_paused(pause_file) {
+        return;
+    }
+    // Only the first thread to notice logs, the rest wait for it.
+    let _waiting = WAITING.lock().unwrap();
+    if !is_paused(pause_file) {
+        return;
+    }
+    info!("Paused, the files in progress will be finished");
+    let paused = Instant::now();
+    while is_paused(pause_file) {
+        thread::sleep(POLL_INTERVAL);
+    }
+    info!(
+        "Resumed after being paused for {} seconds",
+        paused.elapsed().as_secs()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    lazy_static! {
+        // Held while SIGUSR1 may be raised, as it pauses the other tests.
+        static ref SIGNAL: Mutex<()> = Mutex::new(());
+    }
+
+    #[test]
+    fn pause_while_file_exists() {
+        let _signal = SIGNAL.lock().unwrap();
+        let path = std::env::temp_dir().join(format!("pause-{}", std::process::id()));
+        fs::write(&path, "").unwrap();
+        assert!(is_paused(Some(&path)));
+        let remove = {
+            let path = path.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(100));
+                fs::remove_file(path).unwrap();
+            })
+        };
+        let started = Instant::now();
+        wait(Some(&path));
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        remove.join().unwrap();
+        assert!(!is_paused(Some(&path)));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pause_on_sigusr1() {
+        let _signal = SIGNAL.lock().unwrap();
+        pause_on_signal();
+        unsafe { libc::raise(libc::SIGUSR1) };
+        assert!(PAUSED_BY_SIGNAL.load(Ordering::SeqCst));
+        unsafe { libc::raise(libc::SIGUSR1) };
+        assert!(!PAUSED_BY_SIGNAL.load(Ordering::SeqCst));
+    }
+}
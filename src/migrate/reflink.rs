@@ -0,0 +1,43 @@
+// On copy-on-write filesystems (Btrfs, XFS, APFS) given `--reflink` files are
+// cloned rather than copied, sharing their extents with Fedora until either is
+// modified, so terabytes of datastreams are "copied" in moments without the
+// risks of hard links. Elsewhere the files are copied as they would be without.
+use std::fs;
+use std::io;
+use std::path::Path;
+
+// Clones the file, the destination must not exist.
+#[cfg(target_os = "linux")]
+pub fn clone_file(path: &Path, dest: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    let source = fs::File::open(path)?;
+    let file = fs::File::create(dest)?;
+    let result = unsafe { libc::ioctl(file.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) };
+    if result == -1 {
+        let error = io::Error::last_os_error();
+        drop(file);
+        fs::remove_file(dest)?;
+        return Err(error);
+    }
+    fs::set_permissions(dest, source.metadata()?.permissions())
+}
+
+#[cfg(target_os = "macos")]
+pub fn clone_file(path: &Path, dest: &Path) -> io::Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let dest = CString::new(dest.as_os_str().as_bytes())?;
+    // Permissions are cloned along with the content.
+    match unsafe { libc::clonefile(path.as_ptr(), dest.as_ptr(), 0) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn clone_file(_: &Path, _: &Path) -> io::Result<()> {
+    Err(io::Error::other(
+        "Cloning files is not supported on this platform",
+    ))
+}
@@ -0,0 +1,192 @@
+// Resumable copies of large files (e.g. video), so that an interrupted copy
+// continues where it left off rather than starting over. Files are copied to
+// `<name>.partial`, and periodically the number of bytes written along with
+// their checksum is recorded in `<name>.partial.checkpoint`. When resuming, the
+// partial file is checked against the checkpoint and copying continues from the
+// last verified offset, the partial file is renamed once complete.
+use crc32fast::Hasher;
+use log::{info, warn};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+// Smaller files are copied as a whole, as restarting them is cheap.
+const RESUMABLE_SIZE: u64 = 64 * 1024 * 1024;
+
+// Bytes copied between checkpoints.
+const CHECKPOINT_INTERVAL: u64 = 64 * 1024 * 1024;
+
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+fn sibling(dest: &Path, extension: &str) -> PathBuf {
+    let mut name = dest.file_name().unwrap().to_os_string();
+    name.push(extension);
+    dest.with_file_name(name)
+}
+
+// The source is identified by its size and modified time, should either change
+// the partial file is discarded.
+pub fn source_stamp(path: &Path) -> io::Result<String> {
+    let metadata = path.metadata()?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(format!(
+        "{} {} {}",
+        metadata.len(),
+        modified.as_secs(),
+        modified.subsec_nanos()
+    ))
+}
+
+// Checks the partial file against the checkpoint, returning the verified
+// offset and the checksum of the bytes up to it.
+fn verified_offset(stamp: &str, partial: &Path, checkpoint: &Path) -> Option<(u64, Hasher)> {
+    let content = fs::read_to_string(checkpoint).ok()?;
+    let (checkpoint_stamp, rest) = content.trim().rsplit_once(' ')?;
+    let (checkpoint_stamp, offset) = checkpoint_stamp.rsplit_once(' ')?;
+    let (offset, checksum) = (offset.parse::<u64>().ok()?, rest.parse::<u32>().ok()?);
+    if checkpoint_stamp != stamp || partial.metadata().ok()?.len() < offset {
+        return None;
+    }
+    let mut hasher = Hasher::new();
+    let mut file = File::open(partial).ok()?.take(offset);
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let read = file.read(&mut buffer).ok()?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    if hasher.clone().finalize() == checksum {
+        Some((offset, hasher))
+    } else {
+        None
+    }
+}
+
+fn copy_resumable(path: &Path, dest: &Path, interval: u64) -> io::Result<()> {
+    let partial = sibling(dest, ".partial");
+    let checkpoint = sibling(dest, ".partial.checkpoint");
+    let stamp = source_stamp(path)?;
+    let (mut offset, mut hasher) = match verified_offset(&stamp, &partial, &checkpoint) {
+        Some((offset, hasher)) => {
+            info!("Resuming copy of {} from byte {}", path.display(), offset);
+            (offset, hasher)
+        }
+        None => {
+            if partial.exists() {
+                warn!(
+                    "Restarting copy of {} as {} could not be verified",
+                    path.display(),
+                    partial.display()
+                );
+            }
+            (0, Hasher::new())
+        }
+    };
+    let mut src = File::open(path)?;
+    src.seek(SeekFrom::Start(offset))?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&partial)?;
+    file.set_len(offset)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buffer = vec![0; BUFFER_SIZE];
+    let mut last_checkpoint = offset;
+    loop {
+        let read = src.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        file.write_all(&buffer[..read])?;
+        hasher.update(&buffer[..read]);
+        offset += read as u64;
+        if offset - last_checkpoint >= interval {
+            // Only record what is known to be on disk.
+            file.sync_data()?;
+            let checksum = hasher.clone().finalize();
+            fs::write(&checkpoint, format!("{} {} {}", stamp, offset, checksum))?;
+            last_checkpoint = offset;
+        }
+    }
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&partial, dest)?;
+    // As fs::copy does for smaller files.
+    fs::set_permissions(dest, path.metadata()?.permissions())?;
+    if checkpoint.exists() {
+        fs::remove_file(&checkpoint)?;
+    }
+    Ok(())
+}
+
+// Copies the file at path to dest, large files are copied such that they can
+// be resumed if interrupted.
+pub fn copy(path: &Path, dest: &Path) -> io::Result<()> {
+    if path.metadata()?.len() < RESUMABLE_SIZE {
+        fs::copy(path, dest).map(|_| ())
+    } else {
+        copy_resumable(path, dest, CHECKPOINT_INTERVAL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume() {
+        let directory = std::env::temp_dir().join("migrate_resume_test");
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("source");
+        let dest = directory.join("dest");
+        fs::write(&path, b"0123456789").unwrap();
+        let stamp = source_stamp(&path).unwrap();
+        let checksum = |bytes: &[u8]| {
+            let mut hasher = Hasher::new();
+            hasher.update(bytes);
+            hasher.finalize()
+        };
+        // Interrupted after the checkpoint at 4 bytes, the rest is discarded.
+        fs::write(sibling(&dest, ".partial"), b"0123XX").unwrap();
+        fs::write(
+            sibling(&dest, ".partial.checkpoint"),
+            format!("{} 4 {}", stamp, checksum(b"0123")),
+        )
+        .unwrap();
+        let (offset, _) = verified_offset(
+            &stamp,
+            &sibling(&dest, ".partial"),
+            &sibling(&dest, ".partial.checkpoint"),
+        )
+        .unwrap();
+        assert_eq!(offset, 4);
+        copy_resumable(&path, &dest, 3).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"0123456789");
+        assert!(!sibling(&dest, ".partial").exists());
+        assert!(!sibling(&dest, ".partial.checkpoint").exists());
+        // A partial file that does not match its checkpoint is not resumed.
+        fs::write(sibling(&dest, ".partial"), b"XXXX").unwrap();
+        fs::write(
+            sibling(&dest, ".partial.checkpoint"),
+            format!("{} 4 {}", stamp, checksum(b"0123")),
+        )
+        .unwrap();
+        copy_resumable(&path, &dest, 3).unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"0123456789");
+        // Permissions are copied from the source.
+        let mut permissions = path.metadata().unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions).unwrap();
+        fs::remove_file(&dest).unwrap();
+        copy_resumable(&path, &dest, 3).unwrap();
+        assert!(dest.metadata().unwrap().permissions().readonly());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+// Large repositories take days to migrate, so given `--resume` the files
+// completed by each batch are appended to migrate_state.csv in the output
+// directory. Should the run be interrupted, the next run skips the files whose
+// source is unchanged since they were completed without comparing them to
+// their destination, which given `--checksum` would otherwise read both again.
+use super::resume::source_stamp;
+use log::info;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+static STATE_FILE: &str = "migrate_state";
+
+// Files migrated between updates of the state file.
+pub const BATCH_SIZE: usize = 10_000;
+
+#[derive(Clone)]
+struct Entry {
+    source: String,
+    // The size and modified time of the source, see `resume::source_stamp()`.
+    stamp: String,
+}
+
+#[derive(Default)]
+struct State {
+    // By destination.
+    completed: HashMap<String, Entry>,
+    writer: Option<csv::Writer<File>>,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State::default());
+}
+
+// Shards share the output directory.
+fn state_file(output_directory: &Path) -> PathBuf {
+    match foxml::pids::shard() {
+        Some(shard) => output_directory.join(format!("{}-{}.csv", STATE_FILE, shard)),
+        None => output_directory.join(format!("{}.csv", STATE_FILE)),
+    }
+}
+
+// Loads the files completed by previous runs, subsequently completed files are
+// appended to the same file.
+pub fn load(output_directory: &Path) {
+    let path = state_file(output_directory);
+    let mut state = STATE.lock().unwrap();
+    let exists = path.exists();
+    if exists {
+        let read = || -> Result<HashMap<String, Entry>, csv::Error> {
+            let mut reader = csv::Reader::from_path(&path)?;
+            let mut completed = HashMap::new();
+            for record in reader.records() {
+                let record = record?;
+                completed.insert(
+                    record[4].to_string(),
+                    Entry {
+                        source: record[0].to_string(),
+                        stamp: format!("{} {}", &record[1], record[2].replacen('.', " ", 1)),
+                    },
+                );
+            }
+            Ok(completed)
+        };
+        state.completed =
+            read().unwrap_or_else(|error| panic!("Failed to read {}: {}", path.display(), error));
+        info!(
+            "Resuming, {} files were completed by previous runs according to {}",
+            state.completed.len(),
+            path.display()
+        );
+    }
+    let open = || -> Result<csv::Writer<File>, csv::Error> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let mut writer = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(file);
+        if !exists {
+            writer.write_record(["source", "size", "modified", "crc32", "destination"])?;
+            writer.flush()?;
+        }
+        Ok(writer)
+    };
+    state.writer =
+        Some(open().unwrap_or_else(|error| panic!("Failed to open {}: {}", path.display(), error)));
+}
+
+// The destination was completed by a previous run, and its source has not
+// changed since.
+pub fn is_completed(src: &Path, dest: &Path) -> bool {
+    // Released before reading the metadata, which would otherwise serialise
+    // the threads checking files.
+    let entry = STATE
+        .lock()
+        .unwrap()
+        .completed
+        .get(dest.to_string_lossy().as_ref())
+        .cloned();
+    match entry {
+        Some(entry) => {
+            entry.source == src.to_string_lossy()
+                && source_stamp(src).is_ok_and(|stamp| stamp == entry.stamp)
+                && dest.exists()
+        }
+        None => false,
+    }
+}
+
+// Records the files of a batch once they have all been migrated, along with
+// the CRC32 of the destination given `--checksum`.
+pub fn complete<'a, I>(files: I)
+where
+    I: Iterator<Item = (&'a Path, &'a Path, Option<u32>)>,
+{
+    let rows = files
+        .filter_map(|(src, dest, crc32)| {
+            let stamp = source_stamp(src).ok()?;
+            let (size, modified) = stamp.split_once(' ')?;
+            let crc32 = crc32.map_or_else(String::new, |crc32| format!("{:08x}", crc32));
+            Some([
+                src.to_string_lossy().to_string(),
+                size.to_string(),
+                // Seconds and nanoseconds since the epoch.
+                modified.replacen(' ', ".", 1),
+                crc32,
+                dest.to_string_lossy().to_string(),
+            ])
+        })
+        .collect::<Vec<_>>();
+    let mut state = STATE.lock().unwrap();
+    if let Some(writer) = state.writer.as_mut() {
+        let mut write = || -> Result<(), csv::Error> {
+            for row in &rows {
+                writer.write_record(row)?;
+            }
+            // Flushed per batch, so that little is lost should the run be interrupted.
+            writer.flush()?;
+            Ok(())
+        };
+        write().unwrap_or_else(|error| panic!("Failed to update the migrate state: {}", error));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn resume() {
+        let directory = std::env::temp_dir().join("migrate_state_test");
+        fs::create_dir_all(&directory).unwrap();
+        let (src, dest) = (directory.join("source.xml"), directory.join("dest.xml"));
+        fs::write(&src, "abc").unwrap();
+        fs::write(&dest, "abc").unwrap();
+        load(&directory);
+        assert!(!is_completed(&src, &dest));
+        let crc32 = crate::manifest::crc32(&dest);
+        complete(vec![(src.as_path(), dest.as_path(), crc32)].into_iter());
+        // Loaded again as by a subsequent run.
+        load(&directory);
+        assert!(is_completed(&src, &dest));
+        assert!(!is_completed(&dest, &src));
+        let content = fs::read_to_string(directory.join("migrate_state.csv")).unwrap();
+        assert_eq!(content.lines().count(), 2);
+        assert!(content.contains(",352441c2,"));
+        // Changing the source invalidates the entry.
+        fs::write(&src, "abcd").unwrap();
+        assert!(!is_completed(&src, &dest));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
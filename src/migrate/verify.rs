@@ -0,0 +1,348 @@
+// Re-checks the output of the `migrate` command once it has completed, e.g.
+// before Fedora is decommissioned. Every object file and managed datastream is
+// compared against its source in Fedora (if still present), and every
+// datastream referenced by the migrated object files must exist on disk.
+// Each check is listed in verification.csv, and the command fails if any did.
+use super::deleted;
+use super::digests::{self, Outcome};
+use super::filesystem;
+use super::identifiers::*;
+use super::manifest::crc32;
+use super::volumes::Volumes;
+use super::{Limits, MigrateOptions, DATASTREAM_STORE, OBJECT_STORE};
+use foxml::FoxmlControlGroup;
+use log::{info, warn};
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::path::Path;
+
+static REPORT_FILE: &str = "verification.csv";
+
+// A migrated file and its source in Fedora, if any. Sources are absent when
+// they were moved, or for datastreams extracted from the object files.
+struct Check {
+    check: &'static str,
+    id: String,
+    source: Option<Box<Path>>,
+    destination: Box<Path>,
+}
+
+struct Row {
+    check: &'static str,
+    id: String,
+    source: String,
+    destination: String,
+    result: &'static str,
+    problem: String,
+}
+
+impl Row {
+    fn new(check: &'static str, id: String, source: Option<&Path>, destination: &Path) -> Self {
+        Row {
+            check,
+            id,
+            source: source.map_or_else(String::new, |source| source.to_string_lossy().to_string()),
+            destination: destination.to_string_lossy().to_string(),
+            result: "passed",
+            problem: String::new(),
+        }
+    }
+
+    fn with_result(mut self, result: &'static str, problem: String) -> Self {
+        self.result = result;
+        self.problem = problem;
+        self
+    }
+}
+
+// Compares the migrated file against its source as the `migrate` command does
+// when deciding whether to migrate it again.
+fn compare(source: &Path, destination: &Path, checksum: bool) -> Result<(), String> {
+    let destination_metadata = destination.metadata().map_err(|_| "missing".to_string())?;
+    let source_metadata = source
+        .metadata()
+        .map_err(|error| format!("source could not be read: {}", error))?;
+    if source_metadata.len() != destination_metadata.len() {
+        return Err(format!(
+            "size {} != {}",
+            destination_metadata.len(),
+            source_metadata.len()
+        ));
+    }
+    if source_metadata.modified().ok() != destination_metadata.modified().ok() {
+        return Err("modified time differs".to_string());
+    }
+    if checksum && crc32(source) != crc32(destination) {
+        return Err("content differs".to_string());
+    }
+    Ok(())
+}
+
+fn check_file(check: &Check, checksum: bool) -> Row {
+    let row = Row::new(
+        check.check,
+        check.id.clone(),
+        check.source.as_deref(),
+        &check.destination,
+    );
+    let result = match &check.source {
+        Some(source) => compare(source, &check.destination, checksum),
+        None if check.destination.exists() => Ok(()),
+        None => Err("missing".to_string()),
+    };
+    match result {
+        Ok(()) => row,
+        Err(problem) => row.with_result("failed", problem),
+    }
+}
+
+fn check_digest(digest: &digests::Digest) -> Row {
+    let row = Row::new("digest", digest.id.clone(), None, &digest.path);
+    match digests::verify(digest) {
+        Outcome::Verified => row,
+        Outcome::Mismatched(actual) => row.with_result(
+            "failed",
+            format!("{} {} != {}", digest.algorithm, actual, digest.expected),
+        ),
+        Outcome::Missing => row.with_result("failed", "missing".to_string()),
+        Outcome::Unsupported => row.with_result(
+            "skipped",
+            format!("unsupported algorithm {}", digest.algorithm),
+        ),
+    }
+}
+
+fn object_checks(
+    src: &Path,
+    dest: &Path,
+    limits: &mut Limits,
+    options: &MigrateOptions,
+) -> (Vec<Check>, HashSet<String>) {
+    let mut object_files: ObjectPathMap =
+        identify_files(src, dest, &options.namespaces, &options.pid_map);
+    object_files.retain(|identifier, _| limits.contains(&identifier.pid));
+    // Deleted objects were left behind by the `migrate` command.
+    if options.exclude_deleted {
+        limits.deleted = deleted::remove_deleted(&mut object_files, &options.pid_map);
+    }
+    // Colliding objects were skipped by the `migrate` command.
+    let colliding = filesystem::remove_collisions(
+        &mut object_files,
+        |identifier, _| identifier.pid.clone(),
+        "colliding_objects",
+    )
+    .into_iter()
+    .collect::<HashSet<_>>();
+    let checks = object_files
+        .into_iter()
+        .map(|(identifier, source)| Check {
+            check: "object file",
+            destination: dest
+                .join(format!("{}.xml", identifier.pid))
+                .into_boxed_path(),
+            id: identifier.pid,
+            source: Some(source),
+        })
+        .collect();
+    (checks, colliding)
+}
+
+// Every datastream of the given control group referenced by the migrated
+// object files, along with its source in the datastreamStore if any.
+fn datastream_checks(
+    check: &'static str,
+    objects: &Vec<Box<Path>>,
+    group: FoxmlControlGroup,
+    volumes: &Volumes,
+    sources: &DatastreamPathMap,
+    options: &MigrateOptions,
+) -> Vec<Check> {
+    datastreams(objects, group, volumes, options)
+        .into_iter()
+        .map(|(identifier, destination)| Check {
+            check,
+            source: sources.get(&identifier).cloned(),
+            id: format!(
+                "{}/{}/{}",
+                identifier.pid, identifier.dsid, identifier.version
+            ),
+            destination,
+        })
+        .collect()
+}
+
+fn write_report(output_directory: &Path, rows: &[Row]) {
+    let report = output_directory.join(REPORT_FILE);
+    let write = || -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_path(&report)?;
+        writer.write_record(["check", "id", "source", "destination", "result", "problem"])?;
+        for row in rows {
+            writer.write_record([
+                row.check,
+                &row.id,
+                &row.source,
+                &row.destination,
+                row.result,
+                &row.problem,
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    };
+    write().unwrap_or_else(|error| panic!("Failed to write {}: {}", report.display(), error));
+    logger::summary::add_report(&report);
+}
+
+// Returns the number of failed checks.
+pub fn verify_migration(
+    fedora_directory: &Path,
+    output_directory: &Path,
+    spread_across: Vec<&Path>,
+    datastream_store: bool,
+    limit_to_pids: Vec<String>,
+    options: &MigrateOptions,
+) -> usize {
+    info!(
+        "Verifying the migration of {} to {}.",
+        fedora_directory.display(),
+        output_directory.display()
+    );
+    let mut limits = Limits::new(limit_to_pids);
+    let mut destinations = vec![output_directory];
+    destinations.extend(&spread_across);
+    filesystem::probe_destinations(&destinations);
+
+    info!("Searching Fedora for object files");
+    let dest = output_directory.join("objects");
+    let (mut checks, colliding) = object_checks(
+        &fedora_directory.join(OBJECT_STORE),
+        &dest,
+        &mut limits,
+        options,
+    );
+
+    info!("Building list of migrated object files.");
+    let objects = files(&dest, vec![])
+        .into_iter()
+        .filter(|path| {
+            path.file_stem()
+                .is_some_and(|pid| limits.contains(&pid.to_string_lossy()))
+        })
+        .collect::<Vec<_>>();
+
+    let volumes = Volumes::new(output_directory, spread_across);
+    let sources = if datastream_store {
+        info!("Searching Fedora datastream store for files.");
+        let mut files: DatastreamPathMap = identify_files(
+            &fedora_directory.join(DATASTREAM_STORE),
+            &volumes.default_directory(),
+            &options.namespaces,
+            &options.pid_map,
+        );
+        files.retain(|identifier, _| !colliding.contains(&identifier.pid));
+        files
+    } else {
+        DatastreamPathMap::new()
+    };
+    checks.extend(datastream_checks(
+        "managed datastream",
+        &objects,
+        FoxmlControlGroup::M,
+        &volumes,
+        &sources,
+        options,
+    ));
+    checks.extend(datastream_checks(
+        "inline datastream",
+        &objects,
+        FoxmlControlGroup::X,
+        &volumes,
+        &DatastreamPathMap::new(),
+        options,
+    ));
+
+    info!("Verifying {} files.", checks.len());
+    let progress_bar = logger::progress_bar(checks.len() as u64);
+    // Each file is its own task, as their sizes vary greatly.
+    let mut rows = checks
+        .par_iter()
+        .with_max_len(1)
+        .map(|check| {
+            let row = check_file(check, options.checksum);
+            progress_bar.inc(1);
+            row
+        })
+        .collect::<Vec<_>>();
+    progress_bar.finish_and_clear();
+
+    if options.verify_digests {
+        info!("Reading the digests recorded for managed datastreams.");
+        let digests = objects
+            .par_iter()
+            .flat_map_iter(|path| digests::digests(path, &volumes, options))
+            .collect::<Vec<_>>();
+        info!("Verifying {} digests.", digests.len());
+        let progress_bar = logger::progress_bar(digests.len() as u64);
+        rows.par_extend(digests.par_iter().with_max_len(1).map(|digest| {
+            let row = check_digest(digest);
+            progress_bar.inc(1);
+            row
+        }));
+        progress_bar.finish_and_clear();
+    }
+
+    let failed = rows.iter().filter(|row| row.result == "failed").count();
+    for row in rows.iter().filter(|row| row.result == "failed") {
+        logger::summary::warning(
+            "verification_failures",
+            &format!("{} {} ({})", row.check, row.id, row.problem),
+        );
+    }
+    write_report(output_directory, &rows);
+    if failed > 0 {
+        warn!(
+            "{} of {} checks failed, see {}",
+            failed,
+            rows.len(),
+            output_directory.join(REPORT_FILE).display()
+        );
+    } else {
+        info!("All {} checks passed.", rows.len());
+    }
+    failed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn compare_files() {
+        let directory = std::env::temp_dir().join("migrate_verify_test");
+        fs::create_dir_all(&directory).unwrap();
+        let (source, dest) = (directory.join("source.xml"), directory.join("dest.xml"));
+        fs::write(&source, "abc").unwrap();
+        let _ = fs::remove_file(&dest);
+        assert_eq!(compare(&source, &dest, false), Err("missing".to_string()));
+        fs::write(&dest, "abcd").unwrap();
+        assert_eq!(
+            compare(&source, &dest, false),
+            Err("size 4 != 3".to_string())
+        );
+        fs::write(&dest, "abd").unwrap();
+        let mtime = filetime::FileTime::from_last_modification_time(&source.metadata().unwrap());
+        filetime::set_file_mtime(&dest, mtime).unwrap();
+        assert_eq!(compare(&source, &dest, false), Ok(()));
+        assert_eq!(
+            compare(&source, &dest, true),
+            Err("content differs".to_string())
+        );
+        filetime::set_file_mtime(&dest, filetime::FileTime::zero()).unwrap();
+        assert_eq!(
+            compare(&source, &dest, false),
+            Err("modified time differs".to_string())
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,173 @@
+// Spreads datastreams across multiple volumes, for sites whose repository does
+// not fit on a single destination volume. Objects are assigned to a volume as a
+// whole, balancing the size of their managed datastreams. Assignments are
+// recorded in volumes.csv so that subsequent runs and the `csv` command agree.
+use log::info;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+pub static VOLUMES_MANIFEST: &str = "volumes.csv";
+
+pub struct Volumes {
+    output: PathBuf,
+    volumes: Vec<PathBuf>,
+    // Index of the assigned volume by PID.
+    assignments: BTreeMap<String, usize>,
+}
+
+// Volumes are referred to by their position in `--spread-across`.
+fn volume_name(index: usize) -> String {
+    format!("volume{}", index + 1)
+}
+
+impl Volumes {
+    // Loads the assignments of any previous run from the output directory.
+    pub fn new(output: &Path, volumes: Vec<&Path>) -> Self {
+        let volumes = volumes
+            .into_iter()
+            .map(|volume| {
+                volume.canonicalize().unwrap_or_else(|error| {
+                    panic!("Failed to find volume {}: {}", volume.display(), error)
+                })
+            })
+            .collect::<Vec<_>>();
+        let manifest = output.join(VOLUMES_MANIFEST);
+        let mut assignments = BTreeMap::new();
+        if manifest.exists() {
+            if volumes.is_empty() {
+                panic!(
+                    "Datastreams were previously spread across volumes, --spread-across must be given again (see {})",
+                    manifest.display()
+                );
+            }
+            let mut reader = csv::Reader::from_path(&manifest)
+                .unwrap_or_else(|error| panic!("Failed to read {}: {}", manifest.display(), error));
+            for record in reader.records() {
+                let record = record.unwrap_or_else(|error| {
+                    panic!("Failed to read {}: {}", manifest.display(), error)
+                });
+                let (pid, name, path) = (&record[0], &record[1], Path::new(&record[2]));
+                let index = volumes
+                    .iter()
+                    .position(|volume| volume == path)
+                    .filter(|index| volume_name(*index) == name)
+                    .unwrap_or_else(|| {
+                        panic!(
+                            "Volume {} ({}) listed in {} must be given to --spread-across in the same order",
+                            name,
+                            path.display(),
+                            manifest.display()
+                        )
+                    });
+                assignments.insert(pid.to_string(), index);
+            }
+        }
+        Volumes {
+            output: output.to_path_buf(),
+            volumes,
+            assignments,
+        }
+    }
+
+    pub fn is_spread(&self) -> bool {
+        !self.volumes.is_empty()
+    }
+
+    // Assigns unassigned objects to the least used volume, largest first, so
+    // the volumes fill up evenly.
+    pub fn assign(&mut self, sizes: BTreeMap<String, u64>) {
+        if !self.is_spread() {
+            return;
+        }
+        let mut used = vec![0; self.volumes.len()];
+        let mut unassigned = Vec::new();
+        for (pid, size) in sizes {
+            match self.assignments.get(&pid) {
+                Some(index) => used[*index] += size,
+                None => unassigned.push((pid, size)),
+            }
+        }
+        unassigned.sort_by(|(a, a_size), (b, b_size)| b_size.cmp(a_size).then_with(|| a.cmp(b)));
+        for (pid, size) in unassigned {
+            let index = (0..used.len()).min_by_key(|index| used[*index]).unwrap();
+            used[index] += size;
+            self.assignments.insert(pid, index);
+        }
+        for (index, used) in used.iter().enumerate() {
+            info!(
+                "Assigned {} bytes of managed datastreams to {} ({})",
+                used,
+                volume_name(index),
+                self.volumes[index].display()
+            );
+        }
+    }
+
+    // Directory in which the datastreams of the given object are stored.
+    pub fn datastreams_directory(&self, pid: &str) -> PathBuf {
+        match self.assignments.get(pid) {
+            Some(index) => self.volumes[*index].join("datastreams"),
+            None => self.default_directory(),
+        }
+    }
+
+    // Used when not spreading across volumes.
+    pub fn default_directory(&self) -> PathBuf {
+        self.output.join("datastreams")
+    }
+
+    // All directories datastreams may be stored in.
+    pub fn datastreams_directories(&self) -> Vec<PathBuf> {
+        if self.is_spread() {
+            self.volumes
+                .iter()
+                .map(|volume| volume.join("datastreams"))
+                .collect()
+        } else {
+            vec![self.default_directory()]
+        }
+    }
+
+    pub fn write_manifest(&self) {
+        if !self.is_spread() {
+            return;
+        }
+        let manifest = self.output.join(VOLUMES_MANIFEST);
+        let write = || -> Result<(), csv::Error> {
+            let mut writer = csv::Writer::from_path(&manifest)?;
+            writer.write_record(["pid", "volume", "path"])?;
+            for (pid, index) in &self.assignments {
+                writer.write_record([
+                    pid.as_str(),
+                    &volume_name(*index),
+                    &self.volumes[*index].to_string_lossy(),
+                ])?;
+            }
+            writer.flush()?;
+            Ok(())
+        };
+        write().unwrap_or_else(|error| panic!("Failed to write {}: {}", manifest.display(), error));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assign() {
+        let directory = std::env::temp_dir();
+        let mut volumes = Volumes::new(&directory, vec![&directory, &directory]);
+        volumes.assignments.insert("a:1".to_string(), 1);
+        let sizes = vec![("a:1", 10), ("a:2", 30), ("a:3", 20), ("a:4", 5)]
+            .into_iter()
+            .map(|(pid, size)| (pid.to_string(), size))
+            .collect::<BTreeMap<_, _>>();
+        volumes.assign(sizes);
+        // Existing assignments are kept, the rest are balanced largest first.
+        assert_eq!(volumes.assignments["a:1"], 1);
+        assert_eq!(volumes.assignments["a:2"], 0);
+        assert_eq!(volumes.assignments["a:3"], 1);
+        assert_eq!(volumes.assignments["a:4"], 0);
+    }
+}
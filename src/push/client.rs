@@ -0,0 +1,175 @@
+// A minimal client for Drupal's JSON:API, authenticating every request with
+// HTTP basic authentication (requires the core `basic_auth` module).
+use base64::Engine;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+static JSON_API: &str = "application/vnd.api+json";
+
+pub struct Client {
+    agent: ureq::Agent,
+    url: String,
+    authorization: String,
+    // Terms by vocabulary and external URI, `None` if there is no such term.
+    terms: Mutex<HashMap<(String, String), Option<String>>>,
+}
+
+// A reference to an entity in a JSON:API relationship.
+pub fn reference(entity_type: &str, bundle: &str, uuid: &str) -> Value {
+    json!({ "type": format!("{}--{}", entity_type, bundle), "id": uuid })
+}
+
+// Describes a failed request, including the details given by Drupal if any.
+fn request_error(url: &str, error: ureq::Error) -> String {
+    match error {
+        ureq::Error::Status(status, response) => {
+            let body = response.into_string().unwrap_or_default();
+            let detail = serde_json::from_str::<Value>(&body)
+                .ok()
+                .and_then(|body| {
+                    body["errors"][0]["detail"]
+                        .as_str()
+                        .map(|detail| detail.to_string())
+                })
+                .unwrap_or(body);
+            format!("{} returned {}: {}", url, status, detail.trim())
+        }
+        error => format!("Failed to request {}: {}", url, error),
+    }
+}
+
+fn body(url: &str, response: ureq::Response) -> Result<Value, String> {
+    response
+        .into_string()
+        .map_err(|error| error.to_string())
+        .and_then(|body| serde_json::from_str(&body).map_err(|error| error.to_string()))
+        .map_err(|error| format!("Failed to read the response of {}: {}", url, error))
+}
+
+// The UUID of the entity in the response to a request.
+fn uuid(url: &str, response: ureq::Response) -> Result<String, String> {
+    body(url, response)?["data"]["id"]
+        .as_str()
+        .map(|id| id.to_string())
+        .ok_or_else(|| format!("The response of {} did not include an id", url))
+}
+
+impl Client {
+    pub fn new(url: &str, username: &str, password: &str, timeout: Duration) -> Self {
+        let credentials = format!("{}:{}", username, password);
+        Client {
+            agent: ureq::AgentBuilder::new().timeout(timeout).build(),
+            url: url.trim_end_matches('/').to_string(),
+            authorization: format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(credentials)
+            ),
+            terms: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn request(&self, method: &str, path: &str) -> (String, ureq::Request) {
+        let url = format!("{}/jsonapi/{}", self.url, path);
+        let request = self
+            .agent
+            .request(method, &url)
+            .set("Authorization", &self.authorization)
+            .set("Accept", JSON_API);
+        (url, request)
+    }
+
+    // Creates an entity, returning its UUID.
+    pub fn create(
+        &self,
+        entity_type: &str,
+        bundle: &str,
+        attributes: Value,
+        relationships: Value,
+    ) -> Result<String, String> {
+        let body = json!({
+            "data": {
+                "type": format!("{}--{}", entity_type, bundle),
+                "attributes": attributes,
+                "relationships": relationships,
+            }
+        });
+        let (url, request) = self.request("POST", &format!("{}/{}", entity_type, bundle));
+        let response = request
+            .set("Content-Type", JSON_API)
+            .send_string(&body.to_string())
+            .map_err(|error| request_error(&url, error))?;
+        uuid(&url, response)
+    }
+
+    // Uploads the file for the given field of a bundle, returning the UUID of
+    // the file entity created, which must then be referenced by an entity.
+    pub fn upload(
+        &self,
+        entity_type: &str,
+        bundle: &str,
+        field: &str,
+        path: &Path,
+        filename: &str,
+    ) -> Result<String, String> {
+        let file = File::open(path)
+            .and_then(|file| Ok((file.metadata()?.len(), file)))
+            .map_err(|error| format!("Failed to open {}: {}", path.display(), error));
+        let (size, file) = file?;
+        let (url, request) = self.request("POST", &format!("{}/{}/{}", entity_type, bundle, field));
+        let response = request
+            .set("Content-Type", "application/octet-stream")
+            // Otherwise the file is sent chunked, which not all servers accept.
+            .set("Content-Length", &size.to_string())
+            .set(
+                "Content-Disposition",
+                &format!("file; filename=\"{}\"", filename.replace('"', "")),
+            )
+            .send(file)
+            .map_err(|error| request_error(&url, error))?;
+        uuid(&url, response)
+    }
+
+    // The UUID of the term in the given vocabulary with the given external URI
+    // (e.g. models and display hints), if any.
+    pub fn term(&self, vocabulary: &str, uri: &str) -> Result<Option<String>, String> {
+        let key = (vocabulary.to_string(), uri.to_string());
+        if let Some(term) = self.terms.lock().unwrap().get(&key) {
+            return Ok(term.clone());
+        }
+        let (url, request) = self.request("GET", &format!("taxonomy_term/{}", vocabulary));
+        let response = request
+            .query("filter[field_external_uri.uri]", uri)
+            .call()
+            .map_err(|error| request_error(&url, error))?;
+        let term = body(&url, response)?["data"][0]["id"]
+            .as_str()
+            .map(|id| id.to_string());
+        self.terms.lock().unwrap().insert(key, term.clone());
+        Ok(term)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references() {
+        assert_eq!(
+            reference("node", "islandora_object", "4f3c"),
+            json!({ "type": "node--islandora_object", "id": "4f3c" })
+        );
+        let client = Client::new(
+            "https://example.com/",
+            "admin",
+            "secret",
+            Duration::from_secs(1),
+        );
+        assert_eq!(client.url, "https://example.com");
+        assert_eq!(client.authorization, "Basic YWRtaW46c2VjcmV0");
+    }
+}
@@ -0,0 +1,489 @@
+// Experimental: creates the nodes, files and media described by the output of
+// the `csv` command directly over Drupal's JSON:API, for small to medium sites
+// that want to skip importing the CSV files with migrate_plus entirely.
+//
+// Nodes are created first, parents before their children, so that
+// `field_member_of` can reference them. Then the latest version of every
+// datastream is uploaded as a file and its media created, referencing the file
+// and its node via `field_media_of`. Previous versions (media_revisions.csv)
+// are not pushed.
+//
+// Every entity created is recorded in the state file, entities already
+// recorded are skipped, so a push can be rerun until every entity succeeds.
+#[macro_use]
+extern crate log;
+
+mod client;
+mod state;
+
+use client::{reference, Client};
+use csv::{FileRow, MediaRow, NodeRow};
+use csv_other::StringRecord;
+use rayon::prelude::*;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use state::{Kind, State};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Maximum length of a node title or media name in Drupal.
+const MAX_TITLE_LENGTH: usize = 255;
+
+// Default source field of each media bundle, as installed by Islandora.
+static SOURCE_FIELDS: &[(&str, &str)] = &[
+    ("audio", "field_media_audio_file"),
+    ("document", "field_media_document"),
+    ("extracted_text", "field_media_file"),
+    ("file", "field_media_file"),
+    ("fits_technical_metadata", "field_media_file"),
+    ("image", "field_media_image"),
+    ("video", "field_media_video_file"),
+];
+
+// Media use of well known datastreams, by external URI in the
+// `islandora_media_use` vocabulary.
+static MEDIA_USES: &[(&str, &str)] = &[
+    ("FULL_TEXT", "http://pcdm.org/use#ExtractedText"),
+    ("OBJ", "http://pcdm.org/use#OriginalFile"),
+    ("OCR", "http://pcdm.org/use#ExtractedText"),
+    ("TN", "http://pcdm.org/use#ThumbnailImage"),
+];
+
+pub struct PushOptions<'a> {
+    // Base URL of the Drupal site.
+    pub url: &'a str,
+    pub username: &'a str,
+    pub password: &'a str,
+    // The output directory of the `migrate` command, which the paths of
+    // files.csv are relative to.
+    pub files: &'a Path,
+    pub node_type: &'a str,
+    // Maximum number of concurrent requests.
+    pub concurrency: usize,
+    pub timeout: Duration,
+    // Overrides the source field of the given media bundles.
+    pub source_fields: Vec<(&'a str, &'a str)>,
+    // Defaults to push_state.jsonl in the input directory.
+    pub state_file: Option<&'a Path>,
+}
+
+fn media_id(media: &MediaRow) -> String {
+    format!("{}/{}/{}", media.pid, media.dsid, media.version)
+}
+
+// Only CSV output can be pushed, not that of `--output-format parquet`.
+pub fn valid_source_directory(path: &Path) -> Result<(), String> {
+    if !path.is_dir() {
+        return Err(format!("The directory '{}' does not exist", path.display()));
+    }
+    for file in &["files.csv", "media.csv", "nodes.csv"] {
+        let path = path.join(file);
+        let parquet = path.with_extension("parquet");
+        if parquet.is_file() {
+            return Err(format!(
+                "The file '{}' is Parquet, only CSV output can be pushed",
+                parquet.display()
+            ));
+        }
+        if !path.is_file() {
+            return Err(format!("The file '{}' does not exist", path.display()));
+        }
+    }
+    Ok(())
+}
+
+// The records of a CSV file, which the rows read from it borrow.
+struct Table {
+    path: PathBuf,
+    headers: StringRecord,
+    records: Vec<StringRecord>,
+}
+
+impl Table {
+    fn read(path: &Path) -> Self {
+        let read = || -> Result<(StringRecord, Vec<StringRecord>), csv_other::Error> {
+            let mut reader = csv_other::Reader::from_path(path)?;
+            let headers = reader.headers()?.clone();
+            let records = reader.records().collect::<Result<_, _>>()?;
+            Ok((headers, records))
+        };
+        let (headers, records) =
+            read().unwrap_or_else(|error| panic!("Failed to read {}: {}", path.display(), error));
+        Table {
+            path: path.to_path_buf(),
+            headers,
+            records,
+        }
+    }
+
+    fn rows<'a, T: Deserialize<'a>>(&'a self) -> Vec<T> {
+        self.records
+            .iter()
+            .map(|record| record.deserialize(Some(&self.headers)))
+            .collect::<Result<_, _>>()
+            .unwrap_or_else(|error| panic!("Failed to read {}: {}", self.path.display(), error))
+    }
+}
+
+// Drupal expects RFC 3339 dates, the CSV files give seconds since the epoch.
+fn timestamp(seconds: i64) -> Option<String> {
+    chrono::DateTime::from_timestamp(seconds, 0).map(|date| date.to_rfc3339())
+}
+
+fn truncate(value: &str) -> String {
+    if value.chars().count() > MAX_TITLE_LENGTH {
+        csv::truncate(value, MAX_TITLE_LENGTH)
+    } else {
+        value.to_string()
+    }
+}
+
+// Groups the nodes so that every node comes after its parents, nodes within a
+// group can be created concurrently. Parents that are not in nodes.csv are
+// expected to have been created by a previous push, or to not exist.
+fn generations(nodes: Vec<NodeRow>) -> Vec<Vec<NodeRow>> {
+    let mut remaining = nodes;
+    let mut pending = remaining
+        .iter()
+        .map(|node| node.pid.to_string())
+        .collect::<HashSet<_>>();
+    let mut generations = Vec::new();
+    while !remaining.is_empty() {
+        let (mut ready, blocked): (Vec<_>, Vec<_>) = remaining.into_iter().partition(|node| {
+            parents(node)
+                .iter()
+                .all(|parent| *parent == node.pid || !pending.contains(*parent))
+        });
+        remaining = blocked;
+        // Nodes with cyclic parents are created without waiting on each other.
+        if ready.is_empty() {
+            ready = std::mem::take(&mut remaining);
+        }
+        for node in &ready {
+            pending.remove(node.pid);
+        }
+        generations.push(ready);
+    }
+    generations
+}
+
+fn parents<'a>(node: &'a NodeRow) -> Vec<&'a str> {
+    node.parents
+        .split('|')
+        .filter(|parent| !parent.is_empty())
+        .collect()
+}
+
+struct Pusher<'a> {
+    client: Client,
+    state: State,
+    options: &'a PushOptions<'a>,
+    source_fields: HashMap<&'a str, &'a str>,
+    // Source files by 'pid/dsid/version'.
+    files: HashMap<String, PathBuf>,
+    // Parents that are not in nodes.csv and were not pushed previously, only
+    // logged once.
+    unknown_parents: Mutex<HashSet<String>>,
+    failures: Mutex<Vec<String>>,
+}
+
+impl<'a> Pusher<'a> {
+    fn fail(&self, id: &str, error: String) {
+        error!("Failed to push {}: {}", id, error);
+        self.failures
+            .lock()
+            .unwrap()
+            .push(format!("{}: {}", id, error));
+    }
+
+    // A reference to the term with the given external URI, if any.
+    fn term(&self, vocabulary: &str, uri: &str) -> Result<Option<Value>, String> {
+        if uri.is_empty() {
+            return Ok(None);
+        }
+        Ok(self
+            .client
+            .term(vocabulary, uri)?
+            .map(|uuid| reference("taxonomy_term", vocabulary, &uuid)))
+    }
+
+    fn push_node(&self, node: &NodeRow) -> Result<String, String> {
+        let mut attributes = Map::new();
+        attributes.insert("title".into(), truncate(node.label).into());
+        attributes.insert("status".into(), (node.state == "Active").into());
+        if let Some(created) = timestamp(node.created_date) {
+            attributes.insert("created".into(), created.into());
+        }
+        let mut relationships = Map::new();
+        let mut members = Vec::new();
+        for parent in parents(node) {
+            match self.state.get(Kind::Node, parent) {
+                Some(uuid) => members.push(reference("node", self.options.node_type, &uuid)),
+                None => {
+                    if self
+                        .unknown_parents
+                        .lock()
+                        .unwrap()
+                        .insert(parent.to_string())
+                    {
+                        warn!("The parent {} of {} has not been pushed", parent, node.pid);
+                    }
+                }
+            }
+        }
+        if !members.is_empty() {
+            relationships.insert("field_member_of".into(), json!({ "data": members }));
+        }
+        if let Some(model) = self.term("islandora_models", node.model)? {
+            relationships.insert("field_model".into(), json!({ "data": model }));
+        }
+        if let Some(hint) = self.term("islandora_display", node.display_hint)? {
+            relationships.insert("field_display_hints".into(), json!({ "data": [hint] }));
+        }
+        self.client.create(
+            "node",
+            self.options.node_type,
+            attributes.into(),
+            relationships.into(),
+        )
+    }
+
+    fn push_media(&self, media: &MediaRow) -> Result<String, String> {
+        let node = self
+            .state
+            .get(Kind::Node, media.pid)
+            .ok_or_else(|| format!("Its node {} has not been pushed", media.pid))?;
+        let field = *self
+            .source_fields
+            .get(media.bundle.as_str())
+            .ok_or_else(|| format!("No source field is known for the bundle {}", media.bundle))?;
+        let id = media_id(media);
+        let file = match self.state.get(Kind::File, &id) {
+            Some(uuid) => uuid,
+            None => {
+                let path = self
+                    .files
+                    .get(&id)
+                    .ok_or_else(|| format!("{} is not listed in files.csv", id))?;
+                let uuid = self
+                    .client
+                    .upload("media", &media.bundle, field, path, &media.name)?;
+                self.state.insert(Kind::File, &id, &uuid);
+                uuid
+            }
+        };
+        let mut attributes = Map::new();
+        attributes.insert("name".into(), truncate(media.label).into());
+        if let Some(created) = timestamp(media.created_date) {
+            attributes.insert("created".into(), created.into());
+        }
+        let mut relationships = Map::new();
+        relationships.insert(
+            field.into(),
+            json!({ "data": reference("file", "file", &file) }),
+        );
+        relationships.insert(
+            "field_media_of".into(),
+            json!({ "data": reference("node", self.options.node_type, &node) }),
+        );
+        let media_use = MEDIA_USES
+            .iter()
+            .find(|(dsid, _)| *dsid == media.dsid)
+            .map(|(_, uri)| *uri)
+            .unwrap_or_default();
+        if let Some(media_use) = self.term("islandora_media_use", media_use)? {
+            relationships.insert("field_media_use".into(), json!({ "data": [media_use] }));
+        }
+        self.client.create(
+            "media",
+            &media.bundle,
+            attributes.into(),
+            relationships.into(),
+        )
+    }
+
+    // Pushes the given rows concurrently, skipping those already pushed.
+    fn push<T, F>(&self, kind: Kind, rows: &[T], id: fn(&T) -> String, push: F)
+    where
+        T: Sync,
+        F: Fn(&T) -> Result<String, String> + Sync,
+    {
+        let progress_bar = logger::progress_bar(rows.len() as u64);
+        rows.par_iter().for_each(|row| {
+            let id = id(row);
+            if self.state.get(kind, &id).is_none() {
+                match push(row) {
+                    Ok(uuid) => self.state.insert(kind, &id, &uuid),
+                    Err(error) => self.fail(&id, error),
+                }
+            }
+            progress_bar.inc(1);
+        });
+        progress_bar.finish_and_clear();
+    }
+}
+
+pub fn push(input: &Path, options: PushOptions) {
+    let state_file = options
+        .state_file
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| input.join("push_state.jsonl"));
+    let state = State::open(&state_file);
+    info!(
+        "Found {} entities pushed previously in {}",
+        state.len(),
+        state_file.display()
+    );
+    let files = Table::read(&input.join("files.csv"))
+        .rows::<FileRow>()
+        .into_iter()
+        .map(|file| {
+            let id = format!("{}/{}/{}", file.pid, file.dsid, file.version);
+            let relative = file.path.trim_start_matches("private://fedora/");
+            (id, options.files.join(relative))
+        })
+        .collect();
+    let mut source_fields = SOURCE_FIELDS.iter().cloned().collect::<HashMap<_, _>>();
+    source_fields.extend(options.source_fields.iter().cloned());
+    let pusher = Pusher {
+        client: Client::new(
+            options.url,
+            options.username,
+            options.password,
+            options.timeout,
+        ),
+        state,
+        options: &options,
+        source_fields,
+        files,
+        unknown_parents: Mutex::new(HashSet::new()),
+        failures: Mutex::new(Vec::new()),
+    };
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.concurrency)
+        .build()
+        .expect("Failed to create thread pool");
+    pool.install(|| {
+        let nodes = Table::read(&input.join("nodes.csv"));
+        let nodes = nodes.rows::<NodeRow>();
+        info!("Pushing {} nodes", nodes.len());
+        for generation in generations(nodes) {
+            pusher.push(
+                Kind::Node,
+                &generation,
+                |node| node.pid.to_string(),
+                |node| pusher.push_node(node),
+            );
+        }
+        let media = Table::read(&input.join("media.csv"));
+        let media = media
+            .rows::<MediaRow>()
+            .into_iter()
+            .filter(|media| {
+                if media.missing {
+                    warn!("Skipping {} as its file is missing", media_id(media));
+                }
+                !media.missing
+            })
+            .collect::<Vec<_>>();
+        info!("Pushing {} media", media.len());
+        pusher.push(Kind::Media, &media, media_id, |media| {
+            pusher.push_media(media)
+        });
+    });
+    let failures = pusher.failures.into_inner().unwrap();
+    if !failures.is_empty() {
+        panic!(
+            "Failed to push {} entities, rerun to retry them:\n\t{}",
+            failures.len(),
+            failures
+                .iter()
+                .take(logger::summary::EXAMPLES)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join("\n\t")
+        );
+    }
+    info!("Pushed every entity to {}", options.url);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A row of nodes.csv as written by the `csv` command.
+    fn node(pid: &str, parents: &str) -> StringRecord {
+        StringRecord::from(vec![
+            pid,
+            "1481127176",
+            "Label",
+            "",
+            "https://schema.org/DigitalDocument",
+            "1481127176",
+            "Active",
+            "admin",
+            "",
+            parents,
+            "",
+            "1",
+            "0",
+            "false",
+            "false",
+        ])
+    }
+
+    #[test]
+    fn parents_first() {
+        let table = Table {
+            path: PathBuf::from("nodes.csv"),
+            headers: StringRecord::from(vec![
+                "pid",
+                "created_date",
+                "label",
+                "weight",
+                "model",
+                "modified_date",
+                "state",
+                "user",
+                "display_hint",
+                "parents",
+                "edtf_date",
+                "datastream_count",
+                "total_bytes",
+                "has_ocr",
+                "has_preservation_master",
+            ]),
+            records: vec![
+                node("archden:3", "archden:2"),
+                node("archden:2", "archden:1|archden:southern"),
+                node("archden:1", "archden:southern"),
+                node("archden:4", "archden:5"),
+                node("archden:5", "archden:4"),
+            ],
+        };
+        let pids = generations(table.rows::<NodeRow>())
+            .into_iter()
+            .map(|generation| {
+                generation
+                    .into_iter()
+                    .map(|node| node.pid)
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            pids,
+            vec![
+                vec!["archden:1"],
+                vec!["archden:2"],
+                vec!["archden:3"],
+                vec!["archden:4", "archden:5"],
+            ]
+        );
+        assert_eq!(
+            timestamp(1481127176),
+            Some("2016-12-07T16:12:56+00:00".to_string())
+        );
+    }
+}
@@ -0,0 +1,135 @@
+// Records the Drupal entity created for each source row, so that a push that
+// was interrupted or partially failed can be rerun without creating duplicates.
+// Entities are appended as JSON lines as soon as they are created, for example:
+// {"kind":"node","id":"archden:1","uuid":"4f3c8d6e-..."}
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    Node,
+    File,
+    Media,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Entry {
+    kind: Kind,
+    // The source ID, the PID for nodes and 'pid/dsid/version' for files and
+    // media.
+    id: String,
+    uuid: String,
+}
+
+// True if the file is empty or its last byte is a newline.
+fn ends_with_newline(file: &mut File) -> bool {
+    let mut last = [0; 1];
+    match file.seek(SeekFrom::End(-1)) {
+        Ok(_) => file.read_exact(&mut last).is_err() || last[0] == b'\n',
+        Err(_) => true,
+    }
+}
+
+pub struct State {
+    path: PathBuf,
+    entities: Mutex<HashMap<(Kind, String), String>>,
+    file: Mutex<File>,
+}
+
+impl State {
+    // Loads any entities recorded by previous runs, a truncated last line (e.g.
+    // if the process was killed while writing it) is ignored.
+    pub fn open(path: &Path) -> Self {
+        let mut entities = HashMap::new();
+        if path.exists() {
+            let file = File::open(path)
+                .unwrap_or_else(|error| panic!("Failed to open {}: {}", path.display(), error));
+            for line in BufReader::new(file).lines() {
+                let line = line
+                    .unwrap_or_else(|error| panic!("Failed to read {}: {}", path.display(), error));
+                if let Ok(entry) = serde_json::from_str::<Entry>(&line) {
+                    entities.insert((entry.kind, entry.id), entry.uuid);
+                }
+            }
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .unwrap_or_else(|error| panic!("Failed to open {}: {}", path.display(), error));
+        // Start a new line after a truncated one.
+        if !ends_with_newline(&mut file) {
+            file.write_all(b"\n")
+                .unwrap_or_else(|error| panic!("Failed to write {}: {}", path.display(), error));
+        }
+        State {
+            path: path.to_path_buf(),
+            entities: Mutex::new(entities),
+            file: Mutex::new(file),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.lock().unwrap().len()
+    }
+
+    pub fn get(&self, kind: Kind, id: &str) -> Option<String> {
+        self.entities
+            .lock()
+            .unwrap()
+            .get(&(kind, id.to_string()))
+            .cloned()
+    }
+
+    pub fn insert(&self, kind: Kind, id: &str, uuid: &str) {
+        let entry = Entry {
+            kind,
+            id: id.to_string(),
+            uuid: uuid.to_string(),
+        };
+        let line = serde_json::to_string(&entry).unwrap() + "\n";
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())
+            .and_then(|_| file.flush())
+            .unwrap_or_else(|error| panic!("Failed to write {}: {}", self.path.display(), error));
+        self.entities
+            .lock()
+            .unwrap()
+            .insert((kind, entry.id), entry.uuid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume() {
+        let path = std::env::temp_dir().join(format!("push-state-{}.jsonl", std::process::id()));
+        {
+            let state = State::open(&path);
+            state.insert(Kind::Node, "archden:1", "a");
+            state.insert(Kind::Media, "archden:1/OBJ/OBJ.0", "b");
+        }
+        // Interrupted while writing the last entry.
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"kind":"file","id":"arch"#).unwrap();
+        let state = State::open(&path);
+        state.insert(Kind::File, "archden:1/TN/TN.0", "c");
+        let state = State::open(&path);
+        assert_eq!(state.len(), 3);
+        assert_eq!(state.get(Kind::Node, "archden:1"), Some("a".to_string()));
+        assert_eq!(state.get(Kind::File, "archden:1/OBJ/OBJ.0"), None);
+        assert_eq!(
+            state.get(Kind::Media, "archden:1/OBJ/OBJ.0"),
+            Some("b".to_string())
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -950,6 +950,19 @@ fn create_tables_preamble() -> String {
     .join("\n")
 }
 
+// Creates the output directory, unless told not to in which case it must
+// already exist.
+pub fn create_output_directory(path: &Path, create: bool) {
+    if !create && !path.is_dir() {
+        panic!(
+            "The directory '{}' does not exist, and --no-create was given",
+            path.display()
+        );
+    }
+    fs::create_dir_all(path)
+        .unwrap_or_else(|error| panic!("Failed to create {}: {}", path.display(), error));
+}
+
 pub fn generate_sql(input: &Path, dest: &Path) {
     let mut file = fs::File::create(dest.join("migrate.sql")).unwrap();
     file.write_all(&create_tables_preamble().as_bytes())